@@ -0,0 +1,22 @@
+use tusk_rs::{json::json_string, Request, Response, RouteError, DatabaseConnection, Route};
+use tusk_rs_derive::route;
+
+use crate::models::RouteData;
+
+/// Plaintext and JSON endpoints for load testing, in the
+/// style of the TechEmpower benchmarks. Point `wrk` at
+/// `/bench/plaintext` or `/bench/json`.
+pub fn routes() -> Vec<Route<RouteData>> {
+    vec![plaintext(), json()]
+}
+
+#[route(Get /plaintext)]
+pub async fn plaintext(_req: Request, _db: DatabaseConnection, _params: RouteData) -> Result<Response, RouteError> {
+    Ok(Response::string("Hello, World!"))
+}
+
+#[route(Get /json)]
+pub async fn json(_req: Request, _db: DatabaseConnection, _params: RouteData) -> Result<Response, RouteError> {
+    let body = format!("{{\"message\":{}}}", json_string("Hello, World!"));
+    Ok(Response::data(body.into_bytes()).header("Content-Type", "application/json; charset=utf-8"))
+}
@@ -1,6 +1,7 @@
 use models::{RouteData};
 use tusk_rs::{DatabaseConfig, Request, RouteError, DatabaseConnection, Response};
 use tusk_rs_derive::{treatment, ToJson, embed_binary, route, PostgresJoins};
+mod bench;
 mod models;
 mod util;
 
@@ -27,6 +28,7 @@ async fn main() {
     let config = DatabaseConfig::new();
     let mut server = tusk_rs::Server::new(9000, config, treat_user_data(), User { email: String::new() }).await;
     server.register(pdf());
+    server.module("bench", bench::routes());
     server.set_cors(
         "*",
         "Origin, X-Requested-With, Content-Type, Accept, Authorization",
@@ -1,12 +1,13 @@
 use tusk_rs::PostgresTable;
-use tusk_rs_derive::{FromPostgres, PostgresReadFields, PostgresWriteFields, PostgresWriteable, PostgresJoins};
+use tusk_rs_derive::{FromPostgres, PostgresReadFields, PostgresWriteFields, PostgresWriteable, PostgresJoins, ToJson};
 
 
 pub struct RouteData {}
 
-#[derive(FromPostgres, PostgresReadFields,  PostgresWriteFields, PostgresWriteable, PostgresJoins)]
+#[derive(FromPostgres, PostgresReadFields,  PostgresWriteFields, PostgresWriteable, PostgresJoins, ToJson)]
 pub struct TestFromPostgres {
 	pub username: String,
+	#[tusk(redact)]
 	pub password: String
 }
 impl PostgresTable for TestFromPostgres {
@@ -6,13 +6,19 @@
 
 #[macro_export] macro_rules! json_string {
     ($body:ident, $field:literal) => {
-        $body.validate_get::<String>($field, &format!("{} is a required field.", $field))?
+        {
+            use tusk_rs::ValidateJson as _;
+            $body.validate::<String>($field, &format!("{} is a required field.", $field))?
+        }
     };
 }
 
 #[macro_export] macro_rules! json_i32 {
     ($body:ident, $field:literal) => {
-        $body.validate<i32>($field, &format!("{} is a required field.", $field))?
+        {
+            use tusk_rs::ValidateJson as _;
+            $body.validate::<i32>($field, &format!("{} is a required field.", $field))?
+        }
     };
 }
 
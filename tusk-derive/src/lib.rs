@@ -27,7 +27,10 @@ use syn::{parse_macro_input, ItemFn, ItemStruct};
 /// - `T`, where `T` is the type provided by treatment function.
 ///   for more information, see the `tusk_rs::Server` documentation
 ///
-/// It should return a `Result<Response, RouteError>`.
+/// It should return a `Result<T, RouteError>`, where `T`
+/// implements `IntoResponse`, such as a `Response`, `()`,
+/// `String` or `Option<T>`. `Result<impl IntoResponse, RouteError>`
+/// may also be used.
 ///
 /// The route macro all supports security middleware by
 /// adding `: your_secure_fn` to the end of the macro.
@@ -94,7 +97,9 @@ pub fn route(args: TokenStream, input: TokenStream) -> TokenStream {
             tusk_rs::Route::new(
                 #route_name.to_string(),
                 tusk_rs::RequestType::#route_type_ident,
-                Box::new(move |a,b,c| Box::pin(#int_fn_name(a,b,c)))
+                Box::new(move |a,b,c| Box::pin(async move {
+                    #int_fn_name(a,b,c).await.map(tusk_rs::IntoResponse::into_response)
+                }))
             )
         }
         async fn #int_fn_name(#data_args) #data_out {
@@ -207,68 +212,401 @@ pub fn treatment(_args: TokenStream, input: TokenStream) -> TokenStream {
     }.into()
 }
 
-#[proc_macro_derive(FromPostgres)]
+/// Derives `FromPostgres`, reading each field from the column
+/// with the same name.
+///
+/// `Option` fields are `None` when their column is absent from
+/// the row, and fields marked `#[tusk(default)]` use their
+/// `Default` value, so a struct can be reused across queries
+/// which select different columns.
+///
+/// With `#[tusk(after_read)]` on the struct, the struct's
+/// `AfterRead` implementation runs on every value read.
+#[proc_macro_derive(FromPostgres, attributes(tusk))]
 pub fn derive_from_postgres(item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as ItemStruct);
+    let after_read = match has_hook(&input.attrs, "after_read") {
+        Ok(true) => quote! { tusk_rs::AfterRead::after_read(&mut value); },
+        Ok(false) => quote! {},
+        Err(err) => return err.to_compile_error().into(),
+    };
     let struct_name = input.ident;
+    let struct_name_string = struct_name.to_string();
     
-    let from_postgres_fields = input.fields.iter().map(|field| {
-        let field_name = field.ident.as_ref().unwrap();
-        let field_name_string = field.ident.as_ref().unwrap().to_string();
-        quote! {
-            #field_name: row.get(#field_name_string)
-        }
-    }).collect::<Vec<_>>();
-    
-    let try_from_postgres_fields = input.fields.iter().map(|field| {
+    let mut try_from_postgres_fields = Vec::new();
+    for field in input.fields.iter() {
         let field_name = field.ident.as_ref().unwrap();
         let field_name_string = field.ident.as_ref().unwrap().to_string();
-        quote! {
-            #field_name: row.try_get(#field_name_string).map_err(|_| tusk_rs::FromPostgresError::MissingColumn(#field_name_string))?
-        }
-    }).collect::<Vec<_>>();
+        let optional = match has_default(field) {
+            Ok(default) => default || is_option(&field.ty),
+            Err(err) => return err.to_compile_error().into(),
+        };
+        try_from_postgres_fields.push(if optional {
+            quote! {
+                #field_name: match tusk_rs::query::try_get(row, #field_name_string) {
+                    Err(tusk_rs::FromPostgresError::MissingColumn(_)) => Default::default(),
+                    value => value?,
+                }
+            }
+        } else {
+            quote! {
+                #field_name: tusk_rs::query::try_get(row, #field_name_string)?
+            }
+        });
+    }
     
     quote! {
         impl tusk_rs::FromPostgres for #struct_name {
             fn from_postgres(row: &tusk_rs::Row) -> #struct_name {
-                #struct_name {
-                    #(#from_postgres_fields),*
+                match Self::try_from_postgres(row) {
+                    Ok(value) => value,
+                    Err(err) => panic!("Could not read {}: {}", #struct_name_string, err),
                 }
             }
             fn try_from_postgres(row: &tusk_rs::Row) -> Result<#struct_name, tusk_rs::FromPostgresError> {
-                Ok(#struct_name {
+                #[allow(unused_mut)]
+                let mut value = #struct_name {
                     #(#try_from_postgres_fields),*
+                };
+                #after_read
+                Ok(value)
+            }
+        }
+    }.into()
+}
+
+/// Derives `FromRequest`, reading each field from the request's
+/// query parameters, headers or cookies:
+///
+/// ```ignore
+/// #[derive(FromRequest)]
+/// struct ListUsers {
+///     #[tusk(query)]
+///     page: Option<i32>,
+///     #[tusk(query = "q", default)]
+///     search: String,
+///     #[tusk(header = "x-tenant")]
+///     tenant: String,
+///     #[tusk(cookie = "session")]
+///     session: Option<String>,
+/// }
+/// ```
+///
+/// `query`, `header` and `cookie` take the name to read, and
+/// default to the field's name (with `_` replaced by `-` for
+/// headers). Fields without one are read from the query.
+/// `Option` fields and fields marked `#[tusk(default)]` may be
+/// missing.
+#[proc_macro_derive(FromRequest, attributes(tusk))]
+pub fn derive_from_request(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as ItemStruct);
+    let struct_name = input.ident;
+
+    let mut fields = Vec::new();
+    for field in input.fields.iter() {
+        let field_name = field.ident.as_ref().unwrap();
+        let (source, name, default) = match parse_request_source(field) {
+            Ok(x) => x,
+            Err(err) => return err.to_compile_error().into(),
+        };
+        let label = if source == "query" { "query parameter" } else { source.as_str() };
+        let missing = format!("Missing {} {}.", label, name);
+        fields.push(match option_inner(&field.ty) {
+            Some(inner) => quote! {
+                #field_name: request.request_field::<#inner>(#source, #name)?
+            },
+            None if default => quote! {
+                #field_name: request.request_field(#source, #name)?.unwrap_or_default()
+            },
+            None => quote! {
+                #field_name: request
+                    .request_field(#source, #name)?
+                    .ok_or_else(|| tusk_rs::RouteError::bad_request(#missing))?
+            },
+        });
+    }
+
+    quote! {
+        impl tusk_rs::FromRequest for #struct_name {
+            fn from_request(request: &tusk_rs::Request) -> Result<#struct_name, tusk_rs::RouteError> {
+                Ok(#struct_name {
+                    #(#fields),*
                 })
             }
         }
     }.into()
 }
 
-#[proc_macro_derive(PostgresJoins)]
+/// Reads a field's `query`, `header` or `cookie` attribute,
+/// returning the source, the name and whether it has a default.
+fn parse_request_source(field: &syn::Field) -> syn::Result<(String, String, bool)> {
+    let field_name = field.ident.as_ref().unwrap().to_string();
+    let mut source: Option<(String, String)> = None;
+    let mut default = false;
+    for attr in field.attrs.iter().filter(|x| x.path().is_ident("tusk")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("default") {
+                default = true;
+                return Ok(());
+            }
+            let kind = ["query", "header", "cookie"]
+                .into_iter()
+                .find(|x| meta.path.is_ident(x))
+                .ok_or_else(|| meta.error("expected `query`, `header`, `cookie` or `default`"))?;
+            if source.is_some() {
+                return Err(meta.error("a field can only have one of `query`, `header` or `cookie`"));
+            }
+            let name = if meta.input.peek(syn::Token![=]) {
+                meta.value()?.parse::<syn::LitStr>()?.value()
+            } else if kind == "header" {
+                field_name.replace('_', "-")
+            } else {
+                field_name.clone()
+            };
+            source = Some((kind.to_string(), name));
+            Ok(())
+        })?;
+    }
+    let (source, name) = source.unwrap_or_else(|| ("query".to_string(), field_name));
+    Ok((source, name, default))
+}
+
+/// The `T` of an `Option<T>`.
+fn option_inner(ty: &syn::Type) -> Option<&syn::Type> {
+    let syn::Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last().filter(|x| x.ident == "Option")?;
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => match args.args.first()? {
+            syn::GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether a field is marked `#[tusk(default)]`.
+fn has_default(field: &syn::Field) -> syn::Result<bool> {
+    let mut default = false;
+    for attr in field.attrs.iter().filter(|x| x.path().is_ident("tusk")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("default") {
+                default = true;
+            } else if meta.input.peek(syn::Token![=]) {
+                // Other attributes are checked by the derives using them.
+                meta.value()?.parse::<syn::LitStr>()?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(default)
+}
+
+/// Whether a type is an `Option`.
+fn is_option(ty: &syn::Type) -> bool {
+    matches!(ty, syn::Type::Path(x) if x.path.segments.last().is_some_and(|x| x.ident == "Option"))
+}
+
+/// Derives `PostgresJoins`. Joins are declared on the struct:
+///
+/// ```ignore
+/// #[derive(PostgresJoins)]
+/// #[tusk(join(name = "org_join", table = "organizations", on = "organization_id = id", kind = "inner"))]
+/// pub struct UserDirectory { .. }
+/// ```
+///
+/// `on` compares a field of the local table with a field of the
+/// joined table. `kind` may be `inner` (the default), `left`,
+/// `right` or `full`, and `name` defaults to the table name.
+/// Without any joins, the derive returns an empty slice.
+#[proc_macro_derive(PostgresJoins, attributes(tusk))]
 pub fn derive_postgres_joins(item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as ItemStruct);
     let struct_name = input.ident;
+    let joins = match parse_joins(&input.attrs) {
+        Ok(joins) => joins,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let joins = joins.iter().map(|join| {
+        let JoinAttribute { table, kind, local_field, condition, foreign_field, .. } = join;
+        quote! {
+            &tusk_rs::PostgresJoin {
+                join_type: #kind,
+                table: #table,
+                local_field: #local_field,
+                foreign_field: #foreign_field,
+                condition: #condition,
+            }
+        }
+    }).collect::<Vec<_>>();
     
     quote! {
         impl tusk_rs::PostgresJoins for #struct_name {
             fn joins() -> &'static [&'static tusk_rs::PostgresJoin] {
-                &[]
+                &[#(#joins),*]
             }
         }
     }.into()
 }
 
-#[proc_macro_derive(PostgresReadFields)]
+/// A join declared with `#[tusk(join(...))]`.
+struct JoinAttribute {
+    name: String,
+    table: String,
+    kind: String,
+    local_field: String,
+    condition: String,
+    foreign_field: String,
+}
+
+/// Struct attributes enabling model lifecycle hooks.
+const HOOKS: [&str; 2] = ["after_read", "before_write"];
+
+/// Whether a struct has a hook attribute, such as `#[tusk(after_read)]`.
+fn has_hook(attrs: &[syn::Attribute], hook: &str) -> syn::Result<bool> {
+    let mut found = false;
+    for attr in attrs.iter().filter(|x| x.path().is_ident("tusk")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident(hook) {
+                found = true;
+            } else if meta.input.peek(syn::Token![=]) {
+                meta.value()?.parse::<syn::LitStr>()?;
+            } else if meta.input.peek(syn::token::Paren) {
+                // Joins are checked by the derives using them.
+                meta.parse_nested_meta(|inner| {
+                    inner.value()?.parse::<syn::LitStr>()?;
+                    Ok(())
+                })?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(found)
+}
+
+/// Reads every `#[tusk(join(...))]` attribute on a struct.
+fn parse_joins(attrs: &[syn::Attribute]) -> syn::Result<Vec<JoinAttribute>> {
+    let mut joins = Vec::new();
+    for attr in attrs.iter().filter(|x| x.path().is_ident("tusk")) {
+        attr.parse_nested_meta(|meta| {
+            if HOOKS.iter().any(|x| meta.path.is_ident(x)) {
+                return Ok(());
+            }
+            if meta.path.is_ident("rename_all") {
+                meta.value()?.parse::<syn::LitStr>()?;
+                return Ok(());
+            }
+            if !meta.path.is_ident("join") {
+                return Err(meta.error("expected `join(...)`, `after_read`, `before_write` or `rename_all`"));
+            }
+            let (mut name, mut table, mut kind, mut on) = (None, None, None, None);
+            meta.parse_nested_meta(|inner| {
+                let value = inner.value()?.parse::<syn::LitStr>()?;
+                if inner.path.is_ident("name") {
+                    name = Some(value.value());
+                } else if inner.path.is_ident("table") {
+                    table = Some(value.value());
+                } else if inner.path.is_ident("kind") {
+                    kind = Some(match value.value().to_lowercase().as_str() {
+                        "inner" => "INNER JOIN",
+                        "left" => "LEFT JOIN",
+                        "right" => "RIGHT JOIN",
+                        "full" => "FULL JOIN",
+                        _ => return Err(syn::Error::new(value.span(), "expected `inner`, `left`, `right` or `full`")),
+                    });
+                } else if inner.path.is_ident("on") {
+                    on = Some(value);
+                } else {
+                    return Err(inner.error("expected `name`, `table`, `on` or `kind`"));
+                }
+                Ok(())
+            })?;
+            let table = table.ok_or_else(|| meta.error("missing `table`"))?;
+            let on = on.ok_or_else(|| meta.error("missing `on`"))?;
+            let on_value = on.value();
+            let (local_field, condition, foreign_field) = ["<=", ">=", "<>", "!=", "=", "<", ">"]
+                .iter()
+                .find_map(|op| {
+                    let (local, foreign) = on_value.split_once(op)?;
+                    Some((local.trim().to_string(), op.to_string(), foreign.trim().to_string()))
+                })
+                .filter(|(local, _, foreign)| !local.is_empty() && !foreign.is_empty())
+                .ok_or_else(|| syn::Error::new(on.span(), "expected `local_field = foreign_field`"))?;
+            let name = name.unwrap_or_else(|| table.clone());
+            if joins.iter().any(|x: &JoinAttribute| x.name == name) {
+                return Err(meta.error(format!("duplicate join `{}`, give each join a distinct `name`", name)));
+            }
+            // Joins aren't aliased, so joining a table twice
+            // would make its columns ambiguous.
+            if joins.iter().any(|x: &JoinAttribute| x.table == table) {
+                return Err(meta.error(format!("`{}` is already joined", table)));
+            }
+            joins.push(JoinAttribute {
+                name,
+                table,
+                kind: kind.unwrap_or("INNER JOIN").to_string(),
+                local_field,
+                condition,
+                foreign_field,
+            });
+            Ok(())
+        })?;
+    }
+    Ok(joins)
+}
+
+/// Derives `PostgresReadFields`, reading every field from the
+/// local table by default. Fields can instead be read from:
+///
+/// - an expression, with `#[tusk(expr = "count(*) over ()")]`.
+///   `{}` is replaced with the local table's name.
+/// - a joined table, with `#[tusk(from_join = "org_join.name")]`,
+///   naming a join declared with `#[tusk(join(...))]`.
+///
+/// Either way, the column is aliased to the field's name.
+#[proc_macro_derive(PostgresReadFields, attributes(tusk))]
 pub fn derive_postgres_read_fields(item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as ItemStruct);
     let struct_name = input.ident;
+    let joins = match parse_joins(&input.attrs) {
+        Ok(joins) => joins,
+        Err(err) => return err.to_compile_error().into(),
+    };
     
-    let fields = input.fields.iter().map(|field| {
+    let mut fields = Vec::new();
+    for field in input.fields.iter() {
         let field_name = field.ident.as_ref().unwrap().to_string();
-        quote! {
-            tusk_rs::local!(#field_name)
-        }
-    }).collect::<Vec<_>>();
+        let source = match parse_field_source(field) {
+            Ok(source) => source,
+            Err(err) => return err.to_compile_error().into(),
+        };
+        fields.push(match source {
+            Some(FieldSource::Expression(expr)) => quote! {
+                tusk_rs::expression!(#expr, #field_name)
+            },
+            Some(FieldSource::Join(join, column, span)) => {
+                let Some(table) = joins.iter().find(|x| x.name == join).map(|x| &x.table) else {
+                    let declared = joins.iter().map(|x| format!("`{}`", x.name)).collect::<Vec<_>>();
+                    let message = if declared.is_empty() {
+                        format!("unknown join `{}`, declare it with `#[tusk(join(...))]`", join)
+                    } else {
+                        format!("unknown join `{}`, expected one of {}", join, declared.join(", "))
+                    };
+                    return syn::Error::new(span, message).to_compile_error().into();
+                };
+                quote! {
+                    &tusk_rs::PostgresField {
+                        alias: #field_name,
+                        location: tusk_rs::PostgresFieldLocation::Join(#table, #column),
+                    }
+                }
+            }
+            None => quote! {
+                tusk_rs::local!(#field_name)
+            },
+        });
+    }
     
     quote! {
         impl tusk_rs::PostgresReadFields for #struct_name {
@@ -279,6 +617,128 @@ pub fn derive_postgres_read_fields(item: TokenStream) -> TokenStream {
     }.into()
 }
 
+/// Derives `PostgresSchema`, describing the columns of the
+/// local table this struct reads and writes. The column types
+/// are inferred from the field types; unknown types accept
+/// any column type.
+#[proc_macro_derive(PostgresSchema, attributes(tusk))]
+pub fn derive_postgres_schema(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as ItemStruct);
+    let struct_name = input.ident;
+    
+    let columns = input.fields.iter().filter(|x| is_writeable(x)).map(|field| {
+        let name = field.ident.as_ref().unwrap().to_string();
+        let (types, nullable) = postgres_types(&field.ty);
+        quote! {
+            tusk_rs::schema::ExpectedColumn {
+                name: #name,
+                types: &[#(#types),*],
+                nullable: #nullable,
+            }
+        }
+    }).collect::<Vec<_>>();
+    
+    quote! {
+        impl tusk_rs::schema::PostgresSchema for #struct_name {
+            fn expected_columns() -> &'static [tusk_rs::schema::ExpectedColumn] {
+                &[#(#columns),*]
+            }
+        }
+    }.into()
+}
+
+/// The `information_schema` data types a Rust type can be read
+/// from, and whether it can hold NULL.
+fn postgres_types(ty: &syn::Type) -> (Vec<&'static str>, bool) {
+    let syn::Type::Path(path) = ty else {
+        return (Vec::new(), false);
+    };
+    let Some(segment) = path.path.segments.last() else {
+        return (Vec::new(), false);
+    };
+    let inner = match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => args.args.iter().find_map(|x| match x {
+            syn::GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        }),
+        _ => None,
+    };
+    let types = match segment.ident.to_string().as_str() {
+        "Option" => return inner.map(|x| (postgres_types(x).0, true)).unwrap_or((Vec::new(), true)),
+        "String" => vec!["text", "character varying", "character"],
+        "bool" => vec!["boolean"],
+        "i8" => vec!["\"char\""],
+        "i16" => vec!["smallint"],
+        "i32" => vec!["integer"],
+        "i64" => vec!["bigint"],
+        "u32" => vec!["oid"],
+        "f32" => vec!["real"],
+        "f64" => vec!["double precision"],
+        "DateTime" => vec!["timestamp with time zone"],
+        "NaiveDateTime" => vec!["timestamp without time zone"],
+        "NaiveDate" => vec!["date"],
+        "NaiveTime" => vec!["time without time zone"],
+        "Interval" => vec!["interval"],
+        "Ulid" => vec!["uuid", "bytea"],
+        "Encrypted" => vec!["bytea"],
+        "Vec" if matches!(inner, Some(syn::Type::Path(x)) if x.path.is_ident("u8")) => vec!["bytea"],
+        "Vec" => vec!["ARRAY"],
+        _ => Vec::new(),
+    };
+    (types, false)
+}
+
+/// Where a field is read from, if not the local table.
+enum FieldSource {
+    Expression(String),
+    /// (Join name, column, span of the attribute value)
+    Join(String, String, proc_macro2::Span),
+}
+
+/// Reads the `#[tusk(expr = "...")]` or `#[tusk(from_join = "...")]`
+/// attribute on a field.
+fn parse_field_source(field: &syn::Field) -> syn::Result<Option<FieldSource>> {
+    let mut source = None;
+    for attr in field.attrs.iter().filter(|x| x.path().is_ident("tusk")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("default") || meta.path.is_ident("no_patch") || meta.path.is_ident("flatten") {
+                return Ok(());
+            }
+            if meta.path.is_ident("redact") {
+                return parse_redact_mask(&meta).map(|_| ());
+            }
+            let value = meta.value()?.parse::<syn::LitStr>()?;
+            if meta.path.is_ident("serialize_with") || meta.path.is_ident("deserialize_with") {
+                return Ok(());
+            }
+            if source.is_some() {
+                return Err(meta.error("a field can only have one of `expr` or `from_join`"));
+            }
+            if meta.path.is_ident("expr") {
+                source = Some(FieldSource::Expression(value.value()));
+            } else if meta.path.is_ident("from_join") {
+                let (join, column) = value
+                    .value()
+                    .split_once('.')
+                    .map(|(join, column)| (join.trim().to_string(), column.trim().to_string()))
+                    .filter(|(join, column)| !join.is_empty() && !column.is_empty())
+                    .ok_or_else(|| syn::Error::new(value.span(), "expected `join_name.column`"))?;
+                source = Some(FieldSource::Join(join, column, value.span()));
+            } else {
+                return Err(meta.error("expected `expr`, `from_join` or `default`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(source)
+}
+
+/// Whether a field is stored in the local table,
+/// and so should be written.
+fn is_writeable(field: &syn::Field) -> bool {
+    matches!(parse_field_source(field), Ok(None))
+}
+
 #[proc_macro_derive(PostgresReadable)]
 pub fn derive_postgres_readable(item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as ItemStruct);
@@ -289,12 +749,14 @@ pub fn derive_postgres_readable(item: TokenStream) -> TokenStream {
     }.into()
 }
 
-#[proc_macro_derive(PostgresWriteFields)]
+/// Derives `PostgresWriteFields`. Fields read from an
+/// expression or a join are skipped.
+#[proc_macro_derive(PostgresWriteFields, attributes(tusk))]
 pub fn derive_postgres_write_fields(item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as ItemStruct);
     let struct_name = input.ident;
     
-    let fields = input.fields.iter().map(|field| {
+    let fields = input.fields.iter().filter(|x| is_writeable(x)).map(|field| {
         field.ident.as_ref().unwrap().to_string()
     }).collect::<Vec<_>>();
     
@@ -306,24 +768,45 @@ pub fn derive_postgres_write_fields(item: TokenStream) -> TokenStream {
         }
     }.into()
 }
-#[proc_macro_derive(PostgresWriteable)]
+/// Derives `PostgresWriteable` and `PostgresWriteableRef`. With
+/// `#[tusk(before_write)]` on the struct, its `BeforeWrite`
+/// implementation runs in `write`, and `PostgresWriteableRef`
+/// isn't derived, since borrowed writes couldn't run it.
+#[proc_macro_derive(PostgresWriteable, attributes(tusk))]
 pub fn derive_postgres_writeable(item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as ItemStruct);
+    let has_before_write = match has_hook(&input.attrs, "before_write") {
+        Ok(x) => x,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let before_write = if has_before_write {
+        quote! { tusk_rs::BeforeWrite::before_write(&mut self); }
+    } else {
+        quote! {}
+    };
     let struct_name = input.ident;
     
-    let fields = input.fields.iter().map(|field| {
+    let fields = input.fields.iter().filter(|x| is_writeable(x)).map(|field| {
         let f = field.ident.as_ref().unwrap();
         let f_name = field.ident.as_ref().unwrap().to_string();
         quote! {
             #f_name => Box::new(std::mem::take(&mut self.#f))
         }
     }).collect::<Vec<_>>();
+    let ref_fields = input.fields.iter().filter(|x| is_writeable(x)).map(|field| {
+        let f = field.ident.as_ref().unwrap();
+        let f_name = field.ident.as_ref().unwrap().to_string();
+        quote! {
+            #f_name => &self.#f
+        }
+    }).collect::<Vec<_>>();
     
-    quote! {
+    let writeable = quote! {
         impl tusk_rs::PostgresWriteable for #struct_name {
             fn write(mut self) -> tusk_rs::PostgresWrite {
-                let mut arguments: Vec<Box<(dyn tusk_rs::ToSql + Sync)>> = vec![];
+                #before_write
                 let fields = <Self as tusk_rs::PostgresWriteFields>::write_fields();
+                let mut arguments: Vec<Box<(dyn tusk_rs::ToSql + Sync)>> = Vec::with_capacity(fields.len());
                 for f in fields {
                     arguments.push(
                         match *f {
@@ -338,9 +821,639 @@ pub fn derive_postgres_writeable(item: TokenStream) -> TokenStream {
                 }
             }
         }
+    };
+    // Borrowed writes can't run `before_write`, so they aren't
+    // offered to models which have it.
+    if has_before_write {
+        return writeable.into();
+    }
+    quote! {
+        #writeable
+        impl tusk_rs::PostgresWriteableRef for #struct_name {
+            fn write_ref(&self) -> tusk_rs::PostgresWriteRef<'_> {
+                let fields = <Self as tusk_rs::PostgresWriteFields>::write_fields();
+                let mut arguments: Vec<&(dyn tusk_rs::ToSql + Sync)> = Vec::with_capacity(fields.len());
+                for f in fields {
+                    arguments.push(
+                        match *f {
+                            #(#ref_fields),*,
+                            _ => panic!("Unknown field {}!", f)
+                        }
+                    )
+                }
+                tusk_rs::PostgresWriteRef {
+                    fields,
+                    arguments
+                }
+            }
+        }
+    }.into()
+}
+
+/// Derives `PostgresPatchable`, reading JSON Merge Patches of
+/// the struct's write fields. `Option` fields are cleared by
+/// `null`. Fields marked `#[tusk(no_patch)]` can't be patched.
+/// Keys are renamed by `#[tusk(rename_all = "...")]` like `ToJson`.
+#[proc_macro_derive(PostgresPatchable, attributes(tusk))]
+pub fn derive_postgres_patchable(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as ItemStruct);
+    let struct_name = input.ident;
+    let rename_all = match parse_rename_all(&input.attrs) {
+        Ok(x) => x,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    let mut fields = Vec::new();
+    for field in input.fields.iter().filter(|x| is_writeable(x)) {
+        match has_flag(field, "no_patch") {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(err) => return err.to_compile_error().into(),
+        }
+        let name = field.ident.as_ref().unwrap().to_string();
+        let key = rename_all.apply(&name);
+        let value = match option_inner(&field.ty) {
+            Some(inner) => quote! { tusk_rs::PostgresPatch::read_optional::<#inner>(#key, value)? },
+            None => {
+                let ty = &field.ty;
+                quote! { tusk_rs::PostgresPatch::read::<#ty>(#key, value)? }
+            }
+        };
+        fields.push(quote! {
+            #key => patch.set(#name, #value)
+        });
+    }
+
+    quote! {
+        impl tusk_rs::PostgresPatchable for #struct_name {
+            fn patch_from_json(json: &tusk_rs::JsonNode) -> Result<tusk_rs::PostgresPatch, tusk_rs::RouteError> {
+                let entries = json
+                    .as_object()
+                    .ok_or_else(|| tusk_rs::RouteError::bad_request("Expected a JSON object."))?;
+                let mut patch = tusk_rs::PostgresPatch::new();
+                for (key, value) in entries {
+                    match key.as_str() {
+                        #(#fields,)*
+                        _ => return Err(tusk_rs::RouteError::bad_request(&format!("{} can't be changed.", key))),
+                    }
+                }
+                if patch.is_empty() {
+                    return Err(tusk_rs::RouteError::bad_request("Nothing to change."));
+                }
+                Ok(patch)
+            }
+        }
+    }.into()
+}
+
+/// How `#[tusk(rename_all = "...")]` renames fields in JSON.
+#[derive(Clone, Copy, PartialEq)]
+enum RenameRule {
+    None,
+    CamelCase,
+    PascalCase,
+    KebabCase,
+    ScreamingSnakeCase,
+    LowerCase,
+    UpperCase,
+}
+impl RenameRule {
+    /// The JSON key for a snake_case field name.
+    fn apply(self, name: &str) -> String {
+        let words = name.split('_').filter(|x| !x.is_empty());
+        let capitalized = |word: &str| {
+            let mut chars = word.chars();
+            chars.next().map_or(String::new(), |x| x.to_uppercase().chain(chars).collect())
+        };
+        match self {
+            RenameRule::None => name.to_string(),
+            RenameRule::CamelCase => words
+                .enumerate()
+                .map(|(ix, x)| if ix == 0 { x.to_string() } else { capitalized(x) })
+                .collect(),
+            RenameRule::PascalCase => words.map(capitalized).collect(),
+            RenameRule::KebabCase => words.collect::<Vec<_>>().join("-"),
+            RenameRule::ScreamingSnakeCase => name.to_uppercase(),
+            RenameRule::LowerCase => name.replace('_', ""),
+            RenameRule::UpperCase => name.replace('_', "").to_uppercase(),
+        }
+    }
+}
+
+/// Reads a struct's `#[tusk(rename_all = "...")]` attribute.
+fn parse_rename_all(attrs: &[syn::Attribute]) -> syn::Result<RenameRule> {
+    let mut rule = RenameRule::None;
+    for attr in attrs.iter().filter(|x| x.path().is_ident("tusk")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename_all") {
+                let value = meta.value()?.parse::<syn::LitStr>()?;
+                rule = match value.value().as_str() {
+                    "snake_case" => RenameRule::None,
+                    "camelCase" => RenameRule::CamelCase,
+                    "PascalCase" => RenameRule::PascalCase,
+                    "kebab-case" => RenameRule::KebabCase,
+                    "SCREAMING_SNAKE_CASE" => RenameRule::ScreamingSnakeCase,
+                    "lowercase" => RenameRule::LowerCase,
+                    "UPPERCASE" => RenameRule::UpperCase,
+                    _ => {
+                        return Err(syn::Error::new(
+                            value.span(),
+                            "expected `camelCase`, `PascalCase`, `snake_case`, `kebab-case`, `SCREAMING_SNAKE_CASE`, `lowercase` or `UPPERCASE`",
+                        ))
+                    }
+                };
+            } else if meta.input.peek(syn::Token![=]) {
+                // Other attributes are checked by the derives using them.
+                meta.value()?.parse::<syn::LitStr>()?;
+            } else if meta.input.peek(syn::token::Paren) {
+                meta.parse_nested_meta(|inner| {
+                    inner.value()?.parse::<syn::LitStr>()?;
+                    Ok(())
+                })?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(rule)
+}
+
+/// Whether a field has a flag attribute, such as `#[tusk(no_patch)]`.
+fn has_flag(field: &syn::Field, flag: &str) -> syn::Result<bool> {
+    let mut found = false;
+    for attr in field.attrs.iter().filter(|x| x.path().is_ident("tusk")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident(flag) {
+                found = true;
+            } else if meta.input.peek(syn::Token![=]) {
+                // Other attributes are checked by the derives using them.
+                meta.value()?.parse::<syn::LitStr>()?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(found)
+}
+
+/// Derives `ToJson` and `ToJsonInto`, writing each field under
+/// its own name, or renamed with `#[tusk(rename_all = "camelCase")]`
+/// on the struct. Fields are appended to one buffer, through their
+/// own `ToJsonInto` where they implement it.
+///
+/// Fields marked `#[tusk(flatten)]` have their own fields written
+/// into the parent object instead of under a key. They must
+/// implement `ToJsonInto`, as models deriving `ToJson` do, and
+/// `None` writes no fields. `#[derive(JsonField)]` reads them back.
+///
+/// Fields marked `#[tusk(serialize_with = "path::to::fn")]` are
+/// written by that function instead, called as
+/// `fn(&FieldType, &mut String)` to append the field's JSON value,
+/// for fields with nonstandard formats such as unix timestamps.
+/// `deserialize_with` on `#[derive(JsonField)]` reads them back.
+///
+/// Fields marked `#[tusk(redact)]` are left out, and fields
+/// marked `#[tusk(redact = "***")]` are written as that string
+/// instead, so secrets such as password hashes aren't sent to
+/// clients. The full record is still available internally
+/// through `tusk_rs::Unredacted`.
+#[proc_macro_derive(ToJson, attributes(tusk))]
+pub fn derive_to_json(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as ItemStruct);
+    let struct_name = input.ident;
+    let rename_all = match parse_rename_all(&input.attrs) {
+        Ok(x) => x,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    let mut redacted = Vec::new();
+    let mut unredacted = Vec::new();
+    // Room for the keys, and a short value for each field.
+    let mut capacity = 2;
+    for field in input.fields.iter() {
+        let field_name = field.ident.as_ref().unwrap();
+        let key = rename_all.apply(&syn::ext::IdentExt::unraw(field_name).to_string());
+        let redaction = match parse_redaction(field) {
+            Ok(x) => x,
+            Err(err) => return err.to_compile_error().into(),
+        };
+        let flatten = match has_flag(field, "flatten") {
+            Ok(x) => x,
+            Err(err) => return err.to_compile_error().into(),
+        };
+        let serialize_with = match parse_with(field, "serialize_with") {
+            Ok(x) => x,
+            Err(err) => return err.to_compile_error().into(),
+        };
+        let value = if flatten {
+            if serialize_with.is_some() {
+                return syn::Error::new_spanned(field, "a flattened field can't use `serialize_with`")
+                    .to_compile_error()
+                    .into();
+            }
+            if let Some(Some(_)) = redaction {
+                return syn::Error::new_spanned(field, "a flattened field can't be masked, only left out with `redact`")
+                    .to_compile_error()
+                    .into();
+            }
+            quote! { tusk_rs::ToJsonInto::fields_to_json_into(&self.#field_name, output); }
+        } else if let Some(serialize_with) = serialize_with {
+            let key = format!("\"{}\":", key);
+            quote! {
+                tusk_rs::json::write_json_key(output, #key);
+                #serialize_with(&self.#field_name, output);
+            }
+        } else {
+            let key = format!("\"{}\":", key);
+            quote! {
+                tusk_rs::json::write_json_key(output, #key);
+                {
+                    use tusk_rs::json::{WriteJsonInto as _, WriteToJson as _};
+                    (&tusk_rs::json::JsonValue(&self.#field_name)).write_json_value(output);
+                }
+            }
+        };
+        capacity += key.len() + 12;
+        match redaction {
+            None => redacted.push(value.clone()),
+            Some(Some(mask)) => {
+                let key = format!("\"{}\":", key);
+                redacted.push(quote! {
+                    tusk_rs::json::write_json_key(output, #key);
+                    tusk_rs::json::write_json_string(output, #mask);
+                });
+            }
+            Some(None) => {}
+        }
+        unredacted.push(value);
+    }
+
+    quote! {
+        impl tusk_rs::ToJsonInto for #struct_name {
+            fn to_json_into(&self, output: &mut String) {
+                output.push('{');
+                tusk_rs::ToJsonInto::fields_to_json_into(self, output);
+                output.push('}');
+            }
+
+            fn fields_to_json_into(&self, output: &mut String) {
+                #(#redacted)*
+            }
+        }
+        impl tusk_rs::ToJson for #struct_name {
+            fn to_json(&self) -> String {
+                let mut output = String::with_capacity(#capacity);
+                tusk_rs::ToJsonInto::to_json_into(self, &mut output);
+                output
+            }
+        }
+        impl tusk_rs::UnredactedJson for #struct_name {
+            fn to_unredacted_json(&self) -> String {
+                let mut output = String::with_capacity(#capacity);
+                tusk_rs::UnredactedJson::to_unredacted_json_into(self, &mut output);
+                output
+            }
+
+            fn to_unredacted_json_into(&self, output: &mut String) {
+                output.push('{');
+                #(#unredacted)*
+                output.push('}');
+            }
+        }
     }.into()
 }
 
+/// Reads a field's `redact` attribute: `None` if it isn't
+/// redacted, otherwise the mask to write in its place, if any.
+fn parse_redaction(field: &syn::Field) -> syn::Result<Option<Option<String>>> {
+    let mut redaction = None;
+    for attr in field.attrs.iter().filter(|x| x.path().is_ident("tusk")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("redact") {
+                redaction = Some(parse_redact_mask(&meta)?);
+            } else if meta.input.peek(syn::Token![=]) {
+                // Other attributes are checked by the derives using them.
+                meta.value()?.parse::<syn::LitStr>()?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(redaction)
+}
+
+/// Reads a field's `serialize_with = "path::to::fn"` or
+/// `deserialize_with` attribute, named by `name`.
+fn parse_with(field: &syn::Field, name: &str) -> syn::Result<Option<syn::Path>> {
+    let mut path = None;
+    for attr in field.attrs.iter().filter(|x| x.path().is_ident("tusk")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident(name) {
+                path = Some(meta.value()?.parse::<syn::LitStr>()?.parse::<syn::Path>()?);
+            } else if meta.input.peek(syn::Token![=]) {
+                // Other attributes are checked by the derives using them.
+                meta.value()?.parse::<syn::LitStr>()?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(path)
+}
+
+/// The mask of `redact = "..."`, or `None` for a bare `redact`.
+fn parse_redact_mask(meta: &syn::meta::ParseNestedMeta) -> syn::Result<Option<String>> {
+    if meta.input.peek(syn::Token![=]) {
+        Ok(Some(meta.value()?.parse::<syn::LitStr>()?.value()))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Derives `JsonField`, reading the struct from a JSON object
+/// with the same keys `ToJson` writes, so it can be read with
+/// `validate`, `get_path` or as an RPC argument. `Option` fields
+/// may be missing or `null`.
+///
+/// Fields marked `#[tusk(flatten)]` are read from the parent
+/// object itself, the reverse of `ToJson`. An `Option` flattened
+/// field is `None` if its fields can't be read.
+///
+/// Fields marked `#[tusk(deserialize_with = "path::to::fn")]` are
+/// read by that function instead, called as
+/// `fn(&JsonNode) -> Option<FieldType>` with the field's value, or
+/// `null` if it's missing, the reverse of `serialize_with`.
+#[proc_macro_derive(JsonField, attributes(tusk))]
+pub fn derive_json_field(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as ItemStruct);
+    let struct_name = input.ident;
+    let rename_all = match parse_rename_all(&input.attrs) {
+        Ok(x) => x,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    let mut fields = Vec::new();
+    for field in input.fields.iter() {
+        let field_name = field.ident.as_ref().unwrap();
+        let key = rename_all.apply(&syn::ext::IdentExt::unraw(field_name).to_string());
+        let flatten = match has_flag(field, "flatten") {
+            Ok(x) => x,
+            Err(err) => return err.to_compile_error().into(),
+        };
+        let deserialize_with = match parse_with(field, "deserialize_with") {
+            Ok(x) => x,
+            Err(err) => return err.to_compile_error().into(),
+        };
+        let ty = &field.ty;
+        let value = if let Some(deserialize_with) = deserialize_with {
+            if flatten {
+                return syn::Error::new_spanned(field, "a flattened field can't use `deserialize_with`")
+                    .to_compile_error()
+                    .into();
+            }
+            quote! { #deserialize_with(json.get(#key).unwrap_or(&tusk_rs::JsonNode::Null))? }
+        } else {
+            match (flatten, option_inner(ty)) {
+                (true, Some(inner)) => quote! { <#inner as tusk_rs::JsonField>::from_json_node(json) },
+                (true, None) => quote! { <#ty as tusk_rs::JsonField>::from_json_node(json)? },
+                (false, _) => quote! { <#ty as tusk_rs::JsonField>::from_json_entry(json, #key)? },
+            }
+        };
+        fields.push(quote! { #field_name: #value });
+    }
+
+    quote! {
+        impl tusk_rs::JsonField for #struct_name {
+            fn from_json_node(json: &tusk_rs::JsonNode) -> Option<Self> {
+                json.as_object()?;
+                Some(#struct_name {
+                    #(#fields),*
+                })
+            }
+        }
+    }.into()
+}
+
+/// Serves a trait of async methods as RPC routes, for internal
+/// service-to-service calls:
+///
+/// ```ignore
+/// #[rpc(client)]
+/// pub trait Accounts {
+///     async fn balance(&self, req: &Request, db: &DatabaseConnection, account: String) -> Result<f64, RouteError>;
+/// }
+///
+/// server.add_service(MyAccounts::new());
+/// server.module("", AccountsRpc::routes::<MyAccounts, _>());
+/// ```
+///
+/// Each method is served as `POST /rpc/{method}`, by the service
+/// of type `S` registered with `Server::add_service`. After `&self`,
+/// methods take the request and its database connection, then
+/// their arguments, which are read from the JSON object in the
+/// request body by name using `JsonField`. `Option` arguments may
+/// be missing. The output is sent using `ToJson`.
+///
+/// With `client`, a `{Trait}Client` is generated too, which sends
+/// calls through a `RpcTransport`. Its arguments must implement
+/// `ToJson`, and its outputs `JsonField`.
+#[proc_macro_attribute]
+pub fn rpc(args: TokenStream, input: TokenStream) -> TokenStream {
+    let mut client = false;
+    let args_parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("client") {
+            client = true;
+            Ok(())
+        } else {
+            Err(meta.error("expected `client`"))
+        }
+    });
+    parse_macro_input!(args with args_parser);
+    let item = parse_macro_input!(input as syn::ItemTrait);
+    match rpc_items(&item, client) {
+        Ok(items) => quote! {
+            #[allow(async_fn_in_trait)]
+            #item
+            #items
+        }
+        .into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// The routes, and optionally the client, of an `#[rpc]` trait.
+fn rpc_items(item: &syn::ItemTrait, client: bool) -> syn::Result<proc_macro2::TokenStream> {
+    let trait_name = &item.ident;
+    let vis = &item.vis;
+    let rpc_name = format_ident!("{}Rpc", trait_name);
+    let client_name = format_ident!("{}Client", trait_name);
+
+    let mut routes = Vec::new();
+    let mut handlers = Vec::new();
+    let mut calls = Vec::new();
+    for trait_item in item.items.iter() {
+        let syn::TraitItem::Fn(method) = trait_item else {
+            continue;
+        };
+        let sig = &method.sig;
+        if sig.asyncness.is_none() {
+            return Err(syn::Error::new_spanned(sig, "RPC methods must be async"));
+        }
+        let name = &sig.ident;
+        let path = format!("/rpc/{}", name);
+        let mut inputs = sig.inputs.iter();
+        if !matches!(inputs.next(), Some(syn::FnArg::Receiver(_))) {
+            return Err(syn::Error::new_spanned(sig, "RPC methods must take `&self`"));
+        }
+        if inputs.len() < 2 {
+            return Err(syn::Error::new_spanned(
+                sig,
+                "RPC methods must take the request and database connection after `&self`",
+            ));
+        }
+        let arguments = inputs
+            .skip(2)
+            .map(|x| match x {
+                syn::FnArg::Typed(typed) => match typed.pat.as_ref() {
+                    syn::Pat::Ident(pat) => Ok((pat.ident.clone(), typed.ty.as_ref().clone())),
+                    _ => Err(syn::Error::new_spanned(typed, "RPC arguments must be named")),
+                },
+                _ => Err(syn::Error::new_spanned(x, "unexpected receiver")),
+            })
+            .collect::<syn::Result<Vec<_>>>()?;
+        let output = rpc_output(&sig.output)?;
+
+        let names = arguments.iter().map(|(name, _)| name).collect::<Vec<_>>();
+        let reads = arguments.iter().map(|(name, ty)| {
+            let key = name.to_string();
+            match option_inner(ty) {
+                Some(inner) => quote! { let #name = tusk_rs::rpc::optional_arg::<#inner>(&body, #key)?; },
+                None => quote! { let #name = tusk_rs::rpc::arg::<#ty>(&body, #key)?; },
+            }
+        });
+        let body = if arguments.is_empty() {
+            quote! {}
+        } else {
+            quote! { let body = tusk_rs::rpc::body(&req)?; }
+        };
+        routes.push(quote! {
+            tusk_rs::Route::new(
+                #path.to_string(),
+                tusk_rs::RequestType::Post,
+                Box::new(|a, b, c| Box::pin(Self::#name::<S, T>(a, b, c))),
+            )
+        });
+        handlers.push(quote! {
+            async fn #name<S: #trait_name + 'static, T: 'static>(
+                req: tusk_rs::Request,
+                db: tusk_rs::DatabaseConnection,
+                _data: T,
+            ) -> Result<tusk_rs::Response, tusk_rs::RouteError> {
+                #body
+                #(#reads)*
+                let service = req.service::<S>()?;
+                let output = service.#name(&req, &db, #(#names),*).await?;
+                Ok(tusk_rs::Response::json(&output))
+            }
+        });
+
+        let params = arguments.iter().map(|(name, ty)| quote! { #name: #ty });
+        let writes = arguments.iter().map(|(name, ty)| {
+            let key = name.to_string();
+            if option_inner(ty).is_some() {
+                quote! {
+                    if let Some(value) = &#name {
+                        tusk_rs::rpc::write_arg(&mut body, #key, value);
+                    }
+                }
+            } else {
+                quote! { tusk_rs::rpc::write_arg(&mut body, #key, &#name); }
+            }
+        });
+        calls.push(quote! {
+            pub async fn #name(&self, #(#params),*) -> Result<#output, tusk_rs::RpcError> {
+                let mut body = String::from("{");
+                #(#writes)*
+                body.push('}');
+                tusk_rs::rpc::read_response(self.transport.post(#path, body).await?)
+            }
+        });
+    }
+
+    let client = if client {
+        quote! {
+            /// Calls the RPC routes of a remote service.
+            #vis struct #client_name<C> {
+                pub transport: C,
+            }
+            impl<C: tusk_rs::RpcTransport> #client_name<C> {
+                pub fn new(transport: C) -> #client_name<C> {
+                    #client_name { transport }
+                }
+                #(#calls)*
+            }
+        }
+    } else {
+        quote! {}
+    };
+    Ok(quote! {
+        /// The RPC routes of the trait.
+        #vis struct #rpc_name;
+        impl #rpc_name {
+            /// The routes, served by the service `S`.
+            pub fn routes<S: #trait_name + 'static, T: 'static>() -> Vec<tusk_rs::Route<T>> {
+                vec![#(#routes),*]
+            }
+            #(#handlers)*
+        }
+        #client
+    })
+}
+
+/// The `T` of an RPC method returning `Result<T, RouteError>`.
+fn rpc_output(output: &syn::ReturnType) -> syn::Result<&syn::Type> {
+    let error = || syn::Error::new_spanned(output, "RPC methods must return `Result<T, RouteError>`");
+    let syn::ReturnType::Type(_, ty) = output else {
+        return Err(error());
+    };
+    let syn::Type::Path(path) = ty.as_ref() else {
+        return Err(error());
+    };
+    let segment = path.path.segments.last().filter(|x| x.ident == "Result").ok_or_else(error)?;
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => match args.args.first() {
+            Some(syn::GenericArgument::Type(ty)) => Ok(ty),
+            _ => Err(error()),
+        },
+        _ => Err(error()),
+    }
+}
+
+/// This macro is used to define a postfix function, which
+/// runs after every route and may modify the response.
+///
+/// The function should be async with arguments for:
+/// - [`Request`], the original request without its body
+/// - [`Response`], the response produced by the route
+/// - [`std::time::Duration`], the time elapsed since the request was read
+///
+/// and should return a `Response`. Register it using
+/// `Server::set_postfix(your_postfix())`.
+#[proc_macro_attribute]
+pub fn postfix(_args: TokenStream, input: TokenStream) -> TokenStream {
+    let data = parse_macro_input!(input as ItemFn);
+    let data_name = &data.sig.ident;
+    let int_fn_name = format_ident!("_int_{}", data_name);
+
+    let data_args = &data.sig.inputs;
+    let data_out = &data.sig.output;
+    let data_block = &data.block;
+
+    quote! {
+        pub fn #data_name() -> Box<fn(tusk_rs::Request, tusk_rs::Response, std::time::Duration) -> std::pin::Pin<Box<dyn std::future::Future<Output = tusk_rs::Response>>>> {
+            Box::new(move |a,b,c| Box::pin(#int_fn_name(a,b,c)))
+        }
+        async fn #int_fn_name(#data_args) #data_out #data_block
+    }
+    .into()
+}
+
 /// Embed a file into the binary as a string.
 /// This is useful for HTML files or other static files
 /// that need to be represented as a string.
@@ -374,3 +1487,36 @@ pub fn embed_binary(item: TokenStream) -> TokenStream {
         &[#(#contents),*]
     }.into()
 }
+
+/// Embed a file into the binary as a `tusk_rs::assets::EmbeddedAsset`,
+/// along with the `.br` and `.gz` files next to it, if they exist.
+/// Compress assets as part of the build, and the compressed version
+/// the client accepts is sent.
+///
+/// The path is resolved like [`embed_binary`].
+#[proc_macro]
+pub fn embed_asset(item: TokenStream) -> TokenStream {
+    let path = item.to_string().replace('\"', "");
+    let resolved_path = std::fs::canonicalize(path).expect("Invalid path!");
+    let contents = std::fs::read(&resolved_path).unwrap_or_else(|_| panic!("Could not read contents at {}", resolved_path.display()));
+    let compressed = |extension: &str| {
+        let mut path = resolved_path.clone().into_os_string();
+        path.push(".");
+        path.push(extension);
+        match std::fs::read(&path) {
+            Ok(contents) => quote! { Some(&[#(#contents),*]) },
+            Err(_) => quote! { None },
+        }
+    };
+    let brotli = compressed("br");
+    let gzip = compressed("gz");
+    let name = resolved_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    quote! {
+        tusk_rs::assets::EmbeddedAsset {
+            name: #name,
+            data: &[#(#contents),*],
+            brotli: #brotli,
+            gzip: #gzip,
+        }
+    }.into()
+}
@@ -0,0 +1,36 @@
+use brackets::{JsonArray, JsonObject};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use tusk_rs::json::write_json_string;
+
+const OBJECT: &str = r#"{"id":1042,"email":"person@example.com","name":"Example Person","active":true,"score":98.5,"tags":["admin","beta"],"address":{"city":"Portland","zip":"97201"}}"#;
+
+fn encode(c: &mut Criterion) {
+    let clean = "The quick brown fox jumps over the lazy dog. ".repeat(16);
+    let escaped = "Line \"one\"\n\tLine \\two\\\r\n".repeat(16);
+    let mut output = String::new();
+    c.bench_function("json_encode_string_clean", |b| {
+        b.iter(|| {
+            output.clear();
+            write_json_string(&mut output, black_box(&clean));
+        })
+    });
+    c.bench_function("json_encode_string_escaped", |b| {
+        b.iter(|| {
+            output.clear();
+            write_json_string(&mut output, black_box(&escaped));
+        })
+    });
+}
+
+fn decode(c: &mut Criterion) {
+    let array = format!("[{}]", vec![OBJECT; 100].join(","));
+    c.bench_function("json_decode_object", |b| {
+        b.iter(|| JsonObject::from_string(black_box(OBJECT)))
+    });
+    c.bench_function("json_decode_array_100", |b| {
+        b.iter(|| JsonArray::from_string(black_box(&array)))
+    });
+}
+
+criterion_group!(benches, encode, decode);
+criterion_main!(benches);
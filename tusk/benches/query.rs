@@ -0,0 +1,58 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use tusk_rs::{
+    query::IntoSyntax, PostgresField, PostgresFieldLocation, PostgresJoin, PostgresWrite, ToSql,
+};
+
+const FIELDS: &[&PostgresField] = &[
+    &PostgresField { alias: "id", location: PostgresFieldLocation::Local("id") },
+    &PostgresField { alias: "email", location: PostgresFieldLocation::Local("email") },
+    &PostgresField { alias: "name", location: PostgresFieldLocation::Local("name") },
+    &PostgresField { alias: "org_name", location: PostgresFieldLocation::Join("orgs", "name") },
+];
+const JOINS: &[&PostgresJoin] = &[&PostgresJoin {
+    join_type: "INNER JOIN",
+    table: "orgs",
+    local_field: "org_id",
+    foreign_field: "id",
+    condition: "=",
+}];
+const WRITE_FIELDS: &[&str] = &["email", "name", "org_id", "active"];
+
+fn write(rows: usize) -> PostgresWrite {
+    let mut arguments: Vec<Box<dyn ToSql + Sync>> = Vec::new();
+    for ix in 0..rows {
+        arguments.push(Box::new(format!("user{}@example.com", ix)));
+        arguments.push(Box::new(format!("User {}", ix)));
+        arguments.push(Box::new(ix as i32));
+        arguments.push(Box::new(true));
+    }
+    PostgresWrite { fields: WRITE_FIELDS, arguments }
+}
+
+fn select(c: &mut Criterion) {
+    c.bench_function("sql_select", |b| {
+        b.iter(|| {
+            format!(
+                "SELECT {} FROM users {} WHERE users.id = $1",
+                black_box(FIELDS).as_syntax("users"),
+                black_box(JOINS).as_syntax("users")
+            )
+        })
+    });
+}
+
+fn insert(c: &mut Criterion) {
+    let single = write(1);
+    let bulk = write(100);
+    c.bench_function("sql_insert", |b| b.iter(|| black_box(&single).into_insert("users").0));
+    c.bench_function("sql_insert_ref", |b| {
+        b.iter(|| black_box(&single).as_write_ref().into_insert("users").0)
+    });
+    c.bench_function("sql_insert_bulk_100", |b| {
+        b.iter(|| black_box(&bulk).into_bulk_insert("users").0)
+    });
+    c.bench_function("sql_update", |b| b.iter(|| black_box(&single).into_update("users", 1).0));
+}
+
+criterion_group!(benches, select, insert);
+criterion_main!(benches);
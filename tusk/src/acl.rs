@@ -0,0 +1,185 @@
+use std::{fmt::Display, net::IpAddr, str::FromStr};
+
+use crate::{Response, RouteError};
+
+/// A range of IP addresses, such as `10.0.0.0/8` or `fd00::/8`.
+/// A single address is a range of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    address: IpAddr,
+    prefix: u8,
+}
+impl Cidr {
+    /// Whether `ip` is in the range. IPv4 addresses mapped into
+    /// IPv6, as seen on dual-stack sockets, match IPv4 ranges.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (range, ip, bits) = match (self.address, ip.to_canonical()) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => (u32::from(range) as u128, u32::from(ip) as u128, 32),
+            (IpAddr::V6(range), IpAddr::V6(ip)) => (u128::from(range), u128::from(ip), 128),
+            _ => return false,
+        };
+        Self::masked(range, bits, self.prefix) == Self::masked(ip, bits, self.prefix)
+    }
+
+    /// Keeps the first `prefix` bits of a `bits` long address.
+    fn masked(address: u128, bits: u8, prefix: u8) -> u128 {
+        if prefix == 0 {
+            0
+        } else {
+            address >> (bits - prefix)
+        }
+    }
+}
+
+/// Why a string couldn't be read as a [`Cidr`].
+#[derive(Debug)]
+pub struct CidrParseError;
+impl Display for CidrParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected an IP address or a range such as 10.0.0.0/8")
+    }
+}
+impl std::error::Error for CidrParseError {}
+
+impl FromStr for Cidr {
+    type Err = CidrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.trim().split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| CidrParseError)?;
+        let bits = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().ok().filter(|x| *x <= bits).ok_or(CidrParseError)?,
+            None => bits,
+        };
+        Ok(Cidr { address, prefix })
+    }
+}
+
+/// Restricts which client addresses may connect, and which may
+/// reach particular paths. Register it with
+/// [`crate::Server::network_acl`].
+///
+/// Denied addresses are disconnected as soon as they're accepted,
+/// before anything is read. Restricted paths are checked once the
+/// request line and headers are read, before the body, and get
+/// a 403.
+///
+/// Addresses are those of the connected peer. Behind a reverse
+/// proxy that's the proxy, so filter at the proxy instead.
+///
+/// ```ignore
+/// server.network_acl(
+///     NetworkAcl::new()
+///         .deny("203.0.113.0/24")
+///         .restrict("/admin", &["10.0.0.0/8", "127.0.0.1"]),
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct NetworkAcl {
+    deny: Vec<Cidr>,
+    restricted: Vec<(String, Vec<Cidr>)>,
+}
+impl NetworkAcl {
+    pub fn new() -> NetworkAcl {
+        NetworkAcl::default()
+    }
+
+    /// Disconnect clients in `range`. Can be chained.
+    ///
+    /// Panics if `range` isn't a valid address or range.
+    pub fn deny(mut self, range: &str) -> NetworkAcl {
+        self.deny.push(Self::parse(range));
+        self
+    }
+
+    /// Only allow clients in `ranges` to reach paths starting
+    /// with `prefix`. Can be chained.
+    ///
+    /// Panics if any range isn't a valid address or range.
+    pub fn restrict(mut self, prefix: &str, ranges: &[&str]) -> NetworkAcl {
+        self.restricted
+            .push((prefix.to_string(), ranges.iter().map(|x| Self::parse(x)).collect()));
+        self
+    }
+
+    fn parse(range: &str) -> Cidr {
+        range
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid IP range {}", range))
+    }
+
+    /// Whether a client is denied outright.
+    pub fn denies(&self, ip: IpAddr) -> bool {
+        self.deny.iter().any(|x| x.contains(ip))
+    }
+
+    /// Whether a client may reach `path`.
+    pub fn allows(&self, ip: IpAddr, path: &str) -> bool {
+        !self.denies(ip)
+            && self
+                .restricted
+                .iter()
+                .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+                .all(|(_, ranges)| ranges.iter().any(|x| x.contains(ip)))
+    }
+
+    pub(crate) fn forbidden() -> Response {
+        RouteError::forbidden("Access denied.").to_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(range: &str, ip: &str) -> bool {
+        range.parse::<Cidr>().unwrap().contains(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn ipv4_ranges_match_their_prefix() {
+        assert!(contains("10.0.0.0/8", "10.255.3.4"));
+        assert!(!contains("10.0.0.0/8", "11.0.0.0"));
+        assert!(contains("192.168.1.0/23", "192.168.0.255"));
+        assert!(!contains("192.168.1.0/23", "192.168.2.0"));
+        assert!(contains("203.0.113.7/31", "203.0.113.6"));
+        assert!(!contains("203.0.113.7/31", "203.0.113.8"));
+    }
+
+    #[test]
+    fn single_addresses_and_zero_prefixes() {
+        assert!(contains("127.0.0.1", "127.0.0.1"));
+        assert!(!contains("127.0.0.1", "127.0.0.2"));
+        assert!(contains("0.0.0.0/0", "198.51.100.1"));
+        assert!(contains("::/0", "2001:db8::1"));
+        assert!(contains("::1", "::1"));
+        assert!(!contains("::1/128", "::2"));
+    }
+
+    #[test]
+    fn ipv6_ranges_match_their_prefix() {
+        assert!(contains("fd00::/8", "fdff:1234::1"));
+        assert!(!contains("fd00::/8", "fe80::1"));
+        assert!(contains("2001:db8::/33", "2001:db8:7fff::1"));
+        assert!(!contains("2001:db8::/33", "2001:db8:8000::1"));
+    }
+
+    #[test]
+    fn mapped_ipv4_matches_ipv4_ranges_only() {
+        assert!(contains("10.0.0.0/8", "::ffff:10.1.2.3"));
+        assert!(!contains("10.0.0.0/8", "::ffff:11.1.2.3"));
+        assert!(!contains("::/0", "10.1.2.3"));
+        assert!(!contains("0.0.0.0/0", "::1"));
+    }
+
+    #[test]
+    fn malformed_ranges_are_refused() {
+        for range in ["10.0.0.0/33", "::/129", "10.0.0.0/", "10.0.0.0/x", "10.0.0/8", "example.com"] {
+            assert!(range.parse::<Cidr>().is_err(), "{}", range);
+        }
+    }
+}
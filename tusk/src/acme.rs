@@ -0,0 +1,489 @@
+use std::path::Path;
+
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    ec::{EcGroup, EcKey},
+    ecdsa::EcdsaSig,
+    hash::{hash, MessageDigest},
+    nid::Nid,
+    pkey::{PKey, Private},
+    sign::Signer,
+    stack::Stack,
+    x509::{extension::SubjectAlternativeName, X509NameBuilder, X509ReqBuilder, X509},
+};
+
+use crate::{
+    auth::encode_base64_url,
+    json::write_json_string,
+    DatabaseConnection, JsonField, JsonNode,
+};
+
+/// The production Let's Encrypt directory.
+pub const LETS_ENCRYPT: &str = "https://acme-v02.api.letsencrypt.org/directory";
+/// The Let's Encrypt staging directory, for testing
+/// without hitting rate limits.
+pub const LETS_ENCRYPT_STAGING: &str = "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+/// Errors that can occur while provisioning a certificate.
+#[derive(Debug)]
+pub enum AcmeError {
+    Crypto(openssl::error::ErrorStack),
+    /// The ACME server returned a problem. Syntax is (type, detail).
+    Problem(String, String),
+    /// A response was missing a field, or `body` if it wasn't
+    /// JSON. Syntax is (field).
+    MalformedResponse(&'static str),
+    Io(std::io::Error),
+    Database(tokio_postgres::Error),
+}
+impl From<openssl::error::ErrorStack> for AcmeError {
+    fn from(value: openssl::error::ErrorStack) -> Self {
+        AcmeError::Crypto(value)
+    }
+}
+impl From<std::io::Error> for AcmeError {
+    fn from(value: std::io::Error) -> Self {
+        AcmeError::Io(value)
+    }
+}
+impl From<tokio_postgres::Error> for AcmeError {
+    fn from(value: tokio_postgres::Error) -> Self {
+        AcmeError::Database(value)
+    }
+}
+
+/// Parses an ACME response, returning the problem if it is one.
+fn read_response(json: &str) -> Result<JsonNode, AcmeError> {
+    let json = JsonNode::parse(json).map_err(|_| AcmeError::MalformedResponse("body"))?;
+    match String::from_json_entry(&json, "type") {
+        Some(kind) if kind.starts_with("urn:ietf:params:acme:error:") => Err(AcmeError::Problem(
+            kind,
+            String::from_json_entry(&json, "detail").unwrap_or_default(),
+        )),
+        _ => Ok(json),
+    }
+}
+
+/// The endpoints of an ACME server, read from its directory.
+///
+/// Certificates are provisioned with the HTTP-01 challenge:
+/// 1. `GET` the directory (such as [`LETS_ENCRYPT`]) and parse it
+///    with [`AcmeDirectory::from_json`]. `HEAD` its `new_nonce`
+///    URL for the first nonce; every response carries the next
+///    one in its `Replay-Nonce` header.
+/// 2. Send [`AcmeAccount::new_account`] and store the `Location`
+///    header with [`AcmeAccount::set_kid`].
+/// 3. Send [`AcmeAccount::new_order`], then fetch each of the
+///    order's authorizations with [`AcmeAccount::post_as_get`].
+/// 4. Add each challenge to the server's [`crate::AcmeChallenges`]
+///    with [`AcmeAccount::key_authorization`], and send
+///    [`AcmeAccount::respond_to_challenge`]. Poll the authorization
+///    until it's valid.
+/// 5. Create a [`CertificateKey`] and send [`AcmeAccount::finalize`].
+///    Poll the order until it's valid, then fetch its certificate
+///    with [`AcmeAccount::post_as_get`].
+/// 6. Keep the result in a [`StoredCertificate`], and repeat when
+///    [`StoredCertificate::needs_renewal`].
+///
+/// This only builds and reads the messages. Tusk has no HTTP
+/// client and doesn't serve TLS, so the app sends each request,
+/// schedules renewals itself, and serves the certificate from
+/// a TLS-terminating proxy.
+#[derive(Debug, Clone)]
+pub struct AcmeDirectory {
+    pub new_nonce: String,
+    pub new_account: String,
+    pub new_order: String,
+}
+impl AcmeDirectory {
+    pub fn from_json(json: &str) -> Result<AcmeDirectory, AcmeError> {
+        let json = read_response(json)?;
+        Ok(AcmeDirectory {
+            new_nonce: String::from_json_entry(&json, "newNonce").ok_or(AcmeError::MalformedResponse("newNonce"))?,
+            new_account: String::from_json_entry(&json, "newAccount")
+                .ok_or(AcmeError::MalformedResponse("newAccount"))?,
+            new_order: String::from_json_entry(&json, "newOrder").ok_or(AcmeError::MalformedResponse("newOrder"))?,
+        })
+    }
+}
+
+/// A signed request to an ACME server. Send it as a `POST` to
+/// `url` with `Content-Type: application/jose+json`.
+#[derive(Debug, Clone)]
+pub struct AcmeRequest {
+    pub url: String,
+    pub body: String,
+}
+
+/// An ACME account, identified by an ES256 key.
+pub struct AcmeAccount {
+    key: EcKey<Private>,
+    kid: Option<String>,
+}
+impl AcmeAccount {
+    /// Create an account with a new key. Keep the key with
+    /// [`AcmeAccount::private_key_pem`] to reuse the account.
+    pub fn generate() -> Result<AcmeAccount, AcmeError> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        Ok(AcmeAccount {
+            key: EcKey::generate(&group)?,
+            kid: None,
+        })
+    }
+
+    /// Load an account's P-256 key, and its URL if known.
+    pub fn from_pem(pem: &str, kid: Option<String>) -> Result<AcmeAccount, AcmeError> {
+        Ok(AcmeAccount {
+            key: EcKey::private_key_from_pem(pem.as_bytes())?,
+            kid,
+        })
+    }
+
+    pub fn private_key_pem(&self) -> Result<String, AcmeError> {
+        Ok(String::from_utf8_lossy(&self.key.private_key_to_pem()?).to_string())
+    }
+
+    /// The account URL, used to sign requests after the account
+    /// is created.
+    pub fn kid(&self) -> Option<&str> {
+        self.kid.as_deref()
+    }
+
+    /// Define the account URL, from the `Location` header of the
+    /// response to [`AcmeAccount::new_account`].
+    pub fn set_kid(&mut self, kid: String) {
+        self.kid = Some(kid);
+    }
+
+    /// The public key as a JWK, with its members in the order
+    /// required for thumbprints.
+    fn jwk(&self) -> Result<String, AcmeError> {
+        let mut x = BigNum::new()?;
+        let mut y = BigNum::new()?;
+        let mut ctx = BigNumContext::new()?;
+        self.key
+            .public_key()
+            .affine_coordinates(self.key.group(), &mut x, &mut y, &mut ctx)?;
+        Ok(format!(
+            "{{\"crv\":\"P-256\",\"kty\":\"EC\",\"x\":\"{}\",\"y\":\"{}\"}}",
+            encode_base64_url(&x.to_vec_padded(32)?),
+            encode_base64_url(&y.to_vec_padded(32)?)
+        ))
+    }
+
+    /// The key's JWK thumbprint.
+    pub fn thumbprint(&self) -> Result<String, AcmeError> {
+        Ok(encode_base64_url(&hash(MessageDigest::sha256(), self.jwk()?.as_bytes())?))
+    }
+
+    /// The response for the HTTP-01 challenge `token`.
+    pub fn key_authorization(&self, token: &str) -> Result<String, AcmeError> {
+        Ok(format!("{}.{}", token, self.thumbprint()?))
+    }
+
+    /// Create the account (or find it, if the key is already
+    /// registered), agreeing to the server's terms of service.
+    pub fn new_account(
+        &self,
+        directory: &AcmeDirectory,
+        nonce: &str,
+        contact_email: Option<&str>,
+    ) -> Result<AcmeRequest, AcmeError> {
+        let mut payload = String::from("{\"termsOfServiceAgreed\":true");
+        if let Some(email) = contact_email {
+            payload += ",\"contact\":[";
+            write_json_string(&mut payload, &format!("mailto:{}", email));
+            payload += "]";
+        }
+        payload += "}";
+        self.sign(&directory.new_account, nonce, Some(&payload), true)
+    }
+
+    /// Order a certificate for `domains`.
+    pub fn new_order(
+        &self,
+        directory: &AcmeDirectory,
+        nonce: &str,
+        domains: &[&str],
+    ) -> Result<AcmeRequest, AcmeError> {
+        let mut payload = String::from("{\"identifiers\":[");
+        for (ix, domain) in domains.iter().enumerate() {
+            if ix != 0 {
+                payload += ",";
+            }
+            payload += "{\"type\":\"dns\",\"value\":";
+            write_json_string(&mut payload, domain);
+            payload += "}";
+        }
+        payload += "]}";
+        self.sign(&directory.new_order, nonce, Some(&payload), false)
+    }
+
+    /// Fetch an order, authorization or certificate.
+    pub fn post_as_get(&self, url: &str, nonce: &str) -> Result<AcmeRequest, AcmeError> {
+        self.sign(url, nonce, None, false)
+    }
+
+    /// Tell the server a challenge is ready to be checked.
+    pub fn respond_to_challenge(&self, url: &str, nonce: &str) -> Result<AcmeRequest, AcmeError> {
+        self.sign(url, nonce, Some("{}"), false)
+    }
+
+    /// Request the certificate for a ready order.
+    pub fn finalize(
+        &self,
+        order: &AcmeOrder,
+        nonce: &str,
+        key: &CertificateKey,
+    ) -> Result<AcmeRequest, AcmeError> {
+        let payload = format!("{{\"csr\":\"{}\"}}", encode_base64_url(&key.csr));
+        self.sign(&order.finalize, nonce, Some(&payload), false)
+    }
+
+    /// Signs a JWS with the account's JWK (for new accounts)
+    /// or its URL. A missing payload is a POST-as-GET.
+    fn sign(
+        &self,
+        url: &str,
+        nonce: &str,
+        payload: Option<&str>,
+        use_jwk: bool,
+    ) -> Result<AcmeRequest, AcmeError> {
+        let mut protected = String::from("{\"alg\":\"ES256\",\"nonce\":");
+        write_json_string(&mut protected, nonce);
+        protected += ",\"url\":";
+        write_json_string(&mut protected, url);
+        match (&self.kid, use_jwk) {
+            (Some(kid), false) => {
+                protected += ",\"kid\":";
+                write_json_string(&mut protected, kid);
+            }
+            _ => protected += &format!(",\"jwk\":{}", self.jwk()?),
+        }
+        protected += "}";
+
+        let protected = encode_base64_url(protected.as_bytes());
+        let payload = payload.map(|x| encode_base64_url(x.as_bytes())).unwrap_or_default();
+        let key = PKey::from_ec_key(self.key.clone())?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+        signer.update(format!("{}.{}", protected, payload).as_bytes())?;
+        // JWS uses the raw r || s form rather than DER.
+        let signature = EcdsaSig::from_der(&signer.sign_to_vec()?)?;
+        let mut raw = signature.r().to_vec_padded(32)?;
+        raw.extend(signature.s().to_vec_padded(32)?);
+        Ok(AcmeRequest {
+            url: url.to_string(),
+            body: format!(
+                "{{\"protected\":\"{}\",\"payload\":\"{}\",\"signature\":\"{}\"}}",
+                protected,
+                payload,
+                encode_base64_url(&raw)
+            ),
+        })
+    }
+}
+
+/// An order for a certificate.
+#[derive(Debug, Clone)]
+pub struct AcmeOrder {
+    /// `pending`, `ready`, `processing`, `valid` or `invalid`.
+    pub status: String,
+    pub authorizations: Vec<String>,
+    pub finalize: String,
+    /// The certificate URL, once the order is valid.
+    pub certificate: Option<String>,
+}
+impl AcmeOrder {
+    pub fn from_json(json: &str) -> Result<AcmeOrder, AcmeError> {
+        let json = read_response(json)?;
+        Ok(AcmeOrder {
+            status: String::from_json_entry(&json, "status").ok_or(AcmeError::MalformedResponse("status"))?,
+            authorizations: Vec::from_json_entry(&json, "authorizations").unwrap_or_default(),
+            finalize: String::from_json_entry(&json, "finalize").ok_or(AcmeError::MalformedResponse("finalize"))?,
+            certificate: String::from_json_entry(&json, "certificate"),
+        })
+    }
+}
+
+/// An authorization for one domain of an order.
+#[derive(Debug, Clone)]
+pub struct AcmeAuthorization {
+    /// `pending`, `valid`, `invalid`, `deactivated`, `expired` or `revoked`.
+    pub status: String,
+    pub domain: String,
+    /// The HTTP-01 challenge, if the server offered one.
+    pub challenge: Option<AcmeChallenge>,
+}
+impl AcmeAuthorization {
+    pub fn from_json(json: &str) -> Result<AcmeAuthorization, AcmeError> {
+        let json = read_response(json)?;
+        Ok(AcmeAuthorization {
+            status: String::from_json_entry(&json, "status").ok_or(AcmeError::MalformedResponse("status"))?,
+            domain: json
+                .get("identifier")
+                .and_then(|x| String::from_json_entry(x, "value"))
+                .ok_or(AcmeError::MalformedResponse("identifier"))?,
+            challenge: json
+                .get("challenges")
+                .and_then(JsonNode::as_array)
+                .unwrap_or_default()
+                .iter()
+                .filter(|x| x.get("type").and_then(JsonNode::as_str) == Some("http-01"))
+                .find_map(|x| {
+                    Some(AcmeChallenge {
+                        url: String::from_json_entry(x, "url")?,
+                        token: String::from_json_entry(x, "token")?,
+                    })
+                }),
+        })
+    }
+}
+
+/// An HTTP-01 challenge.
+#[derive(Debug, Clone)]
+pub struct AcmeChallenge {
+    pub url: String,
+    pub token: String,
+}
+
+/// A certificate's private key and its signing request.
+pub struct CertificateKey {
+    key: PKey<Private>,
+    csr: Vec<u8>,
+}
+impl CertificateKey {
+    /// Create a P-256 key and a request for `domains`. The first
+    /// domain is used as the common name.
+    pub fn generate(domains: &[&str]) -> Result<CertificateKey, AcmeError> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+
+        let mut request = X509ReqBuilder::new()?;
+        let mut name = X509NameBuilder::new()?;
+        if let Some(domain) = domains.first() {
+            name.append_entry_by_nid(Nid::COMMONNAME, domain)?;
+        }
+        request.set_subject_name(&name.build())?;
+        let mut names = SubjectAlternativeName::new();
+        for domain in domains {
+            names.dns(domain);
+        }
+        let mut extensions = Stack::new()?;
+        extensions.push(names.build(&request.x509v3_context(None))?)?;
+        request.add_extensions(&extensions)?;
+        request.set_pubkey(&key)?;
+        request.sign(&key, MessageDigest::sha256())?;
+
+        Ok(CertificateKey {
+            csr: request.build().to_der()?,
+            key,
+        })
+    }
+
+    pub fn private_key_pem(&self) -> Result<String, AcmeError> {
+        Ok(String::from_utf8_lossy(&self.key.private_key_to_pem_pkcs8()?).to_string())
+    }
+}
+
+/// A provisioned certificate chain and its key, in PEM form.
+///
+/// Certificates can be kept on disk, or in a Postgres table
+/// so every instance of an app shares them.
+#[derive(Debug, Clone)]
+pub struct StoredCertificate {
+    pub domain: String,
+    pub certificate_pem: String,
+    pub private_key_pem: String,
+}
+impl StoredCertificate {
+    pub fn new(domain: &str, certificate_pem: String, key: &CertificateKey) -> Result<StoredCertificate, AcmeError> {
+        Ok(StoredCertificate {
+            domain: domain.to_string(),
+            certificate_pem,
+            private_key_pem: key.private_key_pem()?,
+        })
+    }
+
+    /// Seconds until the leaf certificate expires (negative once
+    /// it has).
+    pub fn expires_in(&self) -> Result<i64, AcmeError> {
+        let certificate = X509::from_pem(self.certificate_pem.as_bytes())?;
+        let now = openssl::asn1::Asn1Time::days_from_now(0)?;
+        let remaining = now.diff(certificate.not_after())?;
+        Ok(remaining.days as i64 * 86400 + remaining.secs as i64)
+    }
+
+    /// Whether the certificate expires within `days` days. Let's
+    /// Encrypt recommends renewing with 30 days left.
+    pub fn needs_renewal(&self, days: u32) -> Result<bool, AcmeError> {
+        Ok(self.expires_in()? < days as i64 * 86400)
+    }
+
+    /// Write `<domain>.crt` and `<domain>.key` into `directory`.
+    pub fn save_to_disk<P: AsRef<Path>>(&self, directory: P) -> Result<(), AcmeError> {
+        let directory = directory.as_ref();
+        std::fs::create_dir_all(directory)?;
+        std::fs::write(directory.join(format!("{}.crt", self.domain)), &self.certificate_pem)?;
+        std::fs::write(directory.join(format!("{}.key", self.domain)), &self.private_key_pem)?;
+        Ok(())
+    }
+
+    /// Read a certificate written with [`StoredCertificate::save_to_disk`].
+    /// Returns `None` if it hasn't been saved.
+    pub fn load_from_disk<P: AsRef<Path>>(directory: P, domain: &str) -> Result<Option<StoredCertificate>, AcmeError> {
+        let directory = directory.as_ref();
+        let read = |extension: &str| match std::fs::read_to_string(directory.join(format!("{}.{}", domain, extension))) {
+            Ok(x) => Ok(Some(x)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        };
+        Ok(read("crt")?.zip(read("key")?).map(|(certificate_pem, private_key_pem)| StoredCertificate {
+            domain: domain.to_string(),
+            certificate_pem,
+            private_key_pem,
+        }))
+    }
+
+    /// The SQL needed to create a table for [`StoredCertificate::save`].
+    pub fn create_table_sql(table: &str) -> String {
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                domain TEXT PRIMARY KEY,
+                certificate TEXT NOT NULL,
+                private_key TEXT NOT NULL,
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )",
+            table
+        )
+    }
+
+    /// Insert or replace the certificate in `table`.
+    pub async fn save(&self, db: &DatabaseConnection, table: &str) -> Result<(), AcmeError> {
+        db.query(
+            format!(
+                "INSERT INTO {} (domain, certificate, private_key, updated_at) VALUES ($1, $2, $3, now())
+                ON CONFLICT (domain) DO UPDATE SET certificate = EXCLUDED.certificate,
+                private_key = EXCLUDED.private_key, updated_at = EXCLUDED.updated_at",
+                table
+            ),
+            &[&self.domain, &self.certificate_pem, &self.private_key_pem],
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Read the certificate for `domain` from `table`.
+    pub async fn load(db: &DatabaseConnection, table: &str, domain: &str) -> Result<Option<StoredCertificate>, AcmeError> {
+        let rows = db
+            .query(
+                format!("SELECT certificate, private_key FROM {} WHERE domain = $1", table),
+                &[&domain],
+            )
+            .await?;
+        Ok(rows.first().map(|row| StoredCertificate {
+            domain: domain.to_string(),
+            certificate_pem: row.get(0),
+            private_key_pem: row.get(1),
+        }))
+    }
+}
@@ -0,0 +1,289 @@
+use std::{marker::PhantomData, str::FromStr};
+
+use brackets::ToJson;
+use tokio_postgres::types::ToSql;
+
+use crate::{
+    database::PostgresWriteError, urlencoded::decode_url_component, DatabaseConnection, FromPostgres,
+    PostgresPatchable, PostgresReadable, PostgresTable, Request, RequestType, Response, ResponseStatusCode, Route,
+    RouteError, RouteModule,
+};
+
+/// A model managed by an [`AdminModule`]. Every method has a
+/// default, so naming the id's type is enough to start:
+///
+/// ```ignore
+/// impl AdminModel for User {
+///     type Id = i32;
+/// }
+/// ```
+///
+/// Creates and updates are read with [`PostgresPatchable`], so
+/// fields marked `#[tusk(no_patch)]` can't be set by either.
+pub trait AdminModel:
+    FromPostgres + PostgresReadable + PostgresTable + PostgresPatchable + ToJson + 'static
+{
+    /// The type of [`AdminModel::id_column`], which `?id=` is
+    /// parsed into, so lookups can use the column's index.
+    type Id: FromStr + ToSql + Sync + 'static;
+
+    /// The column identifying each row. Defaults to `id`.
+    fn id_column() -> &'static str {
+        "id"
+    }
+
+    /// The columns the list can be filtered by, with query
+    /// parameters such as `?role=admin`, each with its Postgres
+    /// type, such as `("role", "text")`. Values are cast to the
+    /// column's type, so its indexes can be used.
+    fn filter_columns() -> &'static [(&'static str, &'static str)] {
+        &[]
+    }
+
+    /// The columns the list can be sorted by, with `?sort=name`
+    /// or `?sort=-name` for descending order.
+    fn sort_columns() -> &'static [&'static str] {
+        &[]
+    }
+
+    /// The number of rows in a page, unless `?per_page=` is sent.
+    fn page_size() -> i64 {
+        50
+    }
+
+    /// The largest `?per_page=` accepted.
+    fn max_page_size() -> i64 {
+        200
+    }
+}
+
+/// JSON routes to list, read, create, update and delete the rows
+/// of a model, for back-office APIs. Register it with
+/// [`crate::Server::mount`]:
+///
+/// | Route | |
+/// |---|---|
+/// | `GET /{prefix}` | A page of rows, filtered and sorted |
+/// | `GET /{prefix}/detail?id=` | One row |
+/// | `POST /{prefix}` | Creates a row from a JSON object |
+/// | `PATCH /{prefix}?id=` | Updates a row from a JSON Merge Patch |
+/// | `DELETE /{prefix}?id=` | Deletes a row |
+///
+/// Lists respond with `{"items":[...],"total":0,"page":1,"per_page":50}`,
+/// and take `?page=` and `?per_page=`.
+///
+/// The routes have no access control of their own, so mount
+/// the module behind a treatment which only admits admins,
+/// such as with [`crate::Server::mount_app`].
+///
+/// ```ignore
+/// impl AdminModel for User {
+///     type Id = i32;
+///     fn filter_columns() -> &'static [(&'static str, &'static str)] { &[("role", "text")] }
+/// }
+/// server.mount(AdminModule::<User>::new("admin/users"));
+/// ```
+pub struct AdminModule<M> {
+    prefix: String,
+    read_only: bool,
+    model: PhantomData<M>,
+}
+impl<M: AdminModel> AdminModule<M> {
+    pub fn new(prefix: &str) -> AdminModule<M> {
+        AdminModule {
+            prefix: prefix.to_string(),
+            read_only: false,
+            model: PhantomData,
+        }
+    }
+
+    /// Only register the list and detail routes. Can be chained.
+    pub fn read_only(mut self) -> AdminModule<M> {
+        self.read_only = true;
+        self
+    }
+
+    async fn list<T>(req: Request, db: DatabaseConnection, _data: T) -> Result<Response, RouteError> {
+        let table = M::table_name();
+        let mut filters = Vec::new();
+        let mut args: Vec<String> = Vec::new();
+        for (column, column_type) in M::filter_columns() {
+            if let Some(value) = req.query.get(*column) {
+                args.push(decode_url_component(value));
+                filters.push(format!("{table}.{column} = ${}::text::{column_type}", args.len()));
+            }
+        }
+        let condition = if filters.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", filters.join(" AND "))
+        };
+        let order = match req.query.get("sort").map(|x| decode_url_component(x)) {
+            Some(sort) => {
+                let (column, direction) = match sort.strip_prefix('-') {
+                    Some(column) => (column.to_string(), "DESC"),
+                    None => (sort, "ASC"),
+                };
+                if !M::sort_columns().contains(&column.as_str()) {
+                    return Err(RouteError::bad_request(&format!("Can't sort by {}.", column)));
+                }
+                format!("{table}.{column} {direction}, {table}.{}", M::id_column())
+            }
+            None => format!("{table}.{}", M::id_column()),
+        };
+        let page = Self::number(&req, "page", 1)?.max(1);
+        let per_page = Self::number(&req, "per_page", M::page_size())?.clamp(1, M::max_page_size());
+        let offset = (page - 1)
+            .checked_mul(per_page)
+            .ok_or_else(|| RouteError::bad_request("page is too large."))?;
+        let args = args.iter().map(|x| x as &(dyn ToSql + Sync)).collect::<Vec<_>>();
+
+        let items: Vec<M> = db
+            .select_all(
+                &format!("{condition} ORDER BY {order} LIMIT {per_page} OFFSET {offset}"),
+                &args,
+            )
+            .await?;
+        let total: i64 = db
+            .query(format!("SELECT count(*) FROM {table} {condition}"), &args)
+            .await
+            .map_err(|err| RouteError::server_error("Could not read data.").caused_by(err))?
+            .first()
+            .map(|x| x.get(0))
+            .unwrap_or_default();
+
+        let mut output = String::from("{\"items\":[");
+        for (ix, item) in items.iter().enumerate() {
+            if ix > 0 {
+                output.push(',');
+            }
+            output.push_str(&item.to_json());
+        }
+        output.push_str(&format!("],\"total\":{},\"page\":{},\"per_page\":{}}}", total, page, per_page));
+        Ok(Response::data(output.into_bytes()).header("Content-Type", "application/json; charset=utf-8"))
+    }
+
+    async fn detail<T>(req: Request, db: DatabaseConnection, _data: T) -> Result<Response, RouteError> {
+        let id = Self::id(&req)?;
+        let item: M = db.select_one(&format!("WHERE {}", Self::id_condition()), &[&id]).await?;
+        Ok(Response::json(&item))
+    }
+
+    async fn create<T>(req: Request, db: DatabaseConnection, _data: T) -> Result<Response, RouteError> {
+        let patch = req.body.to_postgres_patch::<M>()?;
+        let item: M = db.insert_ref(patch.as_write_ref()).await.map_err(write_error)?;
+        Ok(Response::json(&item).status(ResponseStatusCode::Created))
+    }
+
+    async fn update<T>(req: Request, db: DatabaseConnection, _data: T) -> Result<Response, RouteError> {
+        let id = Self::id(&req)?;
+        let patch = req.body.to_postgres_patch::<M>()?;
+        let outcome = db
+            .update_returning::<M>(patch.as_write_ref(), &Self::id_condition(), &[&id])
+            .await
+            .map_err(write_error)?;
+        match outcome.returned.first() {
+            Some(item) => Ok(Response::json(item)),
+            None => Err(RouteError::not_found("Not found.")),
+        }
+    }
+
+    async fn delete<T>(req: Request, db: DatabaseConnection, _data: T) -> Result<Response, RouteError> {
+        let id = Self::id(&req)?;
+        let deleted = db
+            .delete::<M>(&format!("WHERE {}", Self::id_condition()), &[&id])
+            .await
+            .map_err(write_error)?;
+        if deleted == 0 {
+            return Err(RouteError::not_found("Not found."));
+        }
+        Ok(Response::new().status(ResponseStatusCode::NoContent))
+    }
+
+    /// The row's id, parsed as [`AdminModel::Id`].
+    fn id(req: &Request) -> Result<M::Id, RouteError> {
+        req.query
+            .get("id")
+            .map(|x| decode_url_component(x))
+            .filter(|x| !x.is_empty())
+            .ok_or_else(|| RouteError::bad_request("id is required."))?
+            .parse()
+            .map_err(|_| RouteError::bad_request("id is invalid."))
+    }
+
+    fn id_condition() -> String {
+        format!("{}.{} = $1", M::table_name(), M::id_column())
+    }
+
+    fn number(req: &Request, name: &str, default: i64) -> Result<i64, RouteError> {
+        match req.query.get(name) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| RouteError::bad_request(&format!("{} must be a number.", name))),
+            None => Ok(default),
+        }
+    }
+}
+impl<M: AdminModel, T: 'static> RouteModule<T> for AdminModule<M> {
+    fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    fn routes(&self) -> Vec<Route<T>> {
+        let mut routes = vec![
+            Route::new(
+                String::new(),
+                RequestType::Get,
+                Box::new(|a, b, c| Box::pin(Self::list(a, b, c))),
+            ),
+            Route::new(
+                "detail".to_string(),
+                RequestType::Get,
+                Box::new(|a, b, c| Box::pin(Self::detail(a, b, c))),
+            ),
+        ];
+        if !self.read_only {
+            routes.push(
+                Route::new(
+                    String::new(),
+                    RequestType::Post,
+                    Box::new(|a, b, c| Box::pin(Self::create(a, b, c))),
+                )
+                .accept_json(),
+            );
+            routes.push(
+                Route::new(
+                    String::new(),
+                    RequestType::Patch,
+                    Box::new(|a, b, c| Box::pin(Self::update(a, b, c))),
+                )
+                .accept_json(),
+            );
+            routes.push(Route::new(
+                String::new(),
+                RequestType::Delete,
+                Box::new(|a, b, c| Box::pin(Self::delete(a, b, c))),
+            ));
+        }
+        routes
+    }
+}
+
+/// Responds to a failed write with the error a client can act on.
+fn write_error(err: PostgresWriteError) -> RouteError {
+    match err {
+        PostgresWriteError::NoRows => RouteError::not_found("Not found."),
+        PostgresWriteError::UniqueConstraintViolation(_, detail) => RouteError::conflict(&detail),
+        PostgresWriteError::NotNullConstraintViolation(column) => {
+            RouteError::bad_request(&format!("{} is required.", column))
+        }
+        PostgresWriteError::Conversion(err) => {
+            eprintln!("[ERROR] Could not read row: {}", err);
+            RouteError::server_error("Could not write data.").caused_by(err)
+        }
+        err => {
+            eprintln!("[ERROR] Could not write: {:?}", err);
+            RouteError::server_error("Could not write data.")
+        }
+    }
+}
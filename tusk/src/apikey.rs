@@ -0,0 +1,252 @@
+use chrono::{DateTime, Utc};
+use tokio_postgres::Row;
+
+use crate::{
+    database::{PostgresReadError, PostgresWriteError},
+    DatabaseConnection, Request, RouteError,
+};
+
+/// An API key as stored in Postgres. The key itself
+/// is never stored, only its SHA-256 hash.
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    pub id: i32,
+    /// The identity this key authenticates as, for example a user or service id.
+    pub identity: String,
+    /// The first characters of the key, useful for showing
+    /// which key is which without revealing it.
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+impl ApiKey {
+    /// Whether this key was granted a scope.
+    pub fn has_scope<S: AsRef<str>>(&self, scope: S) -> bool {
+        self.scopes.iter().any(|x| x == scope.as_ref())
+    }
+
+    /// Returns a 403 error if this key was not granted a scope.
+    pub fn require_scope<S: AsRef<str>>(&self, scope: S) -> Result<(), RouteError> {
+        if self.has_scope(&scope) {
+            Ok(())
+        } else {
+            Err(RouteError::forbidden(&format!(
+                "API key is missing scope {}.",
+                scope.as_ref()
+            )))
+        }
+    }
+
+    fn from_row(row: &Row) -> ApiKey {
+        ApiKey {
+            id: row.get("id"),
+            identity: row.get("identity"),
+            prefix: row.get("prefix"),
+            scopes: row.get("scopes"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+/// A Postgres-backed store of API keys.
+///
+/// Keys are generated randomly, returned once on creation
+/// or rotation, and hashed at rest. Create the table using
+/// the statement from [`ApiKeyStore::create_table_sql`].
+///
+/// The store is usually checked in a treatment function:
+///
+/// ```ignore
+/// const API_KEYS: ApiKeyStore = ApiKeyStore::new("api_keys");
+///
+/// #[treatment]
+/// pub async fn treat(req: Request, db: DatabaseConnection, _init: &()) -> RouteData {
+///     RouteData {
+///         api_key: API_KEYS.authenticate(&req, &db).await?,
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct ApiKeyStore {
+    table: &'static str,
+}
+impl ApiKeyStore {
+    const HEADER: &'static str = "x-api-key";
+    const KEY_PREFIX: &'static str = "tk_";
+    const DISPLAY_PREFIX_LEN: usize = 10;
+    const COLUMNS: &'static str = "id, identity, prefix, scopes, created_at";
+
+    /// Create a store backed by `table`.
+    pub const fn new(table: &'static str) -> ApiKeyStore {
+        ApiKeyStore { table }
+    }
+
+    /// The SQL needed to create the backing table.
+    pub fn create_table_sql(&self) -> String {
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                id SERIAL PRIMARY KEY,
+                identity TEXT NOT NULL,
+                prefix TEXT NOT NULL,
+                key_hash TEXT NOT NULL UNIQUE,
+                scopes TEXT[] NOT NULL DEFAULT '{{}}',
+                created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                revoked_at TIMESTAMPTZ
+            )",
+            self.table
+        )
+    }
+
+    /// Create a new key for an identity. Returns the key,
+    /// which can't be retrieved again, and its record.
+    pub async fn create<S: AsRef<str>>(
+        &self,
+        db: &DatabaseConnection,
+        identity: S,
+        scopes: &[&str],
+    ) -> Result<(String, ApiKey), PostgresWriteError> {
+        let key = Self::generate_key();
+        let scopes = scopes.iter().map(|x| x.to_string()).collect::<Vec<String>>();
+        let row = db
+            .query(
+                format!(
+                    "INSERT INTO {} (identity, prefix, key_hash, scopes) VALUES ($1, $2, $3, $4) RETURNING {}",
+                    self.table,
+                    Self::COLUMNS
+                ),
+                &[&identity.as_ref(), &Self::display_prefix(&key), &Self::hash(&key), &scopes],
+            )
+            .await?;
+        let record = row.first().map(ApiKey::from_row).ok_or(PostgresWriteError::NoRows)?;
+        Ok((key, record))
+    }
+
+    /// Replace the key for an existing, unrevoked record.
+    /// The previous key stops working immediately.
+    pub async fn rotate(
+        &self,
+        db: &DatabaseConnection,
+        id: i32,
+    ) -> Result<(String, ApiKey), PostgresWriteError> {
+        let key = Self::generate_key();
+        let row = db
+            .query(
+                format!(
+                    "UPDATE {} SET prefix = $1, key_hash = $2 WHERE id = $3 AND revoked_at IS NULL RETURNING {}",
+                    self.table,
+                    Self::COLUMNS
+                ),
+                &[&Self::display_prefix(&key), &Self::hash(&key), &id],
+            )
+            .await?;
+        let record = row.first().map(ApiKey::from_row).ok_or(PostgresWriteError::NoRows)?;
+        Ok((key, record))
+    }
+
+    /// Revoke a key. Revoked keys are kept for auditing
+    /// but can no longer be used.
+    pub async fn revoke(&self, db: &DatabaseConnection, id: i32) -> Result<(), PostgresWriteError> {
+        let rows = db
+            .query(
+                format!(
+                    "UPDATE {} SET revoked_at = now() WHERE id = $1 AND revoked_at IS NULL RETURNING id",
+                    self.table
+                ),
+                &[&id],
+            )
+            .await?;
+        if rows.is_empty() {
+            return Err(PostgresWriteError::NoRows);
+        }
+        Ok(())
+    }
+
+    /// List all unrevoked keys for an identity.
+    pub async fn list<S: AsRef<str>>(
+        &self,
+        db: &DatabaseConnection,
+        identity: S,
+    ) -> Result<Vec<ApiKey>, PostgresReadError> {
+        Ok(db
+            .query(
+                format!(
+                    "SELECT {} FROM {} WHERE identity = $1 AND revoked_at IS NULL ORDER BY id",
+                    Self::COLUMNS,
+                    self.table
+                ),
+                &[&identity.as_ref()],
+            )
+            .await?
+            .iter()
+            .map(ApiKey::from_row)
+            .collect())
+    }
+
+    /// Look up the record for a key. Returns
+    /// [`PostgresReadError::NoResults`] if the key is unknown or revoked.
+    pub async fn verify<S: AsRef<str>>(
+        &self,
+        db: &DatabaseConnection,
+        key: S,
+    ) -> Result<ApiKey, PostgresReadError> {
+        db.query(
+            format!(
+                "SELECT {} FROM {} WHERE key_hash = $1 AND revoked_at IS NULL",
+                Self::COLUMNS,
+                self.table
+            ),
+            &[&Self::hash(key.as_ref())],
+        )
+        .await?
+        .first()
+        .map(ApiKey::from_row)
+        .ok_or(PostgresReadError::NoResults)
+    }
+
+    /// Check the `X-Api-Key` header of a request, returning
+    /// a 401 error if it is missing, unknown or revoked.
+    pub async fn authenticate(
+        &self,
+        req: &Request,
+        db: &DatabaseConnection,
+    ) -> Result<ApiKey, RouteError> {
+        let key = req
+            .headers
+            .get(Self::HEADER)
+            .ok_or_else(|| RouteError::unauthorized("API key required."))?;
+        self.verify(db, key).await.map_err(|x| match x {
+            PostgresReadError::NoResults => RouteError::unauthorized("Invalid API key."),
+            _ => RouteError::server_error("Could not verify API key."),
+        })
+    }
+
+    /// Like [`ApiKeyStore::authenticate`], but also places the
+    /// [`ApiKey`] in the request's extensions.
+    pub async fn resolve(
+        &self,
+        req: &mut Request,
+        db: &DatabaseConnection,
+    ) -> Result<ApiKey, RouteError> {
+        let record = self.authenticate(req, db).await?;
+        req.extensions.insert(record.clone());
+        Ok(record)
+    }
+
+    fn generate_key() -> String {
+        let mut bytes = [0u8; 32];
+        openssl::rand::rand_bytes(&mut bytes).expect("Could not generate random bytes!");
+        format!("{}{}", Self::KEY_PREFIX, to_hex(&bytes))
+    }
+
+    fn display_prefix(key: &str) -> String {
+        key.chars().take(Self::DISPLAY_PREFIX_LEN).collect()
+    }
+
+    fn hash(key: &str) -> String {
+        to_hex(&openssl::sha::sha256(key.as_bytes()))
+    }
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
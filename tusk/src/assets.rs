@@ -0,0 +1,120 @@
+use std::path::Path;
+
+use crate::{mime, Request, Response, RouteError};
+
+/// A file embedded with `embed_asset!`, along with the `.br`
+/// and `.gz` files next to it, if there were any when it was
+/// embedded. Send it with [`EmbeddedAsset::response`].
+///
+/// ```ignore
+/// const APP_JS: EmbeddedAsset = embed_asset!("public/app.js");
+///
+/// #[route(Get /app.js)]
+/// async fn app_js(req: Request, _db: DatabaseConnection, _data: RouteData) -> Result<Response, RouteError> {
+///     Ok(APP_JS.response(&req))
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct EmbeddedAsset {
+    /// The file's name, which its `Content-Type` is
+    /// inferred from.
+    pub name: &'static str,
+    pub data: &'static [u8],
+    /// The file compressed with Brotli.
+    pub brotli: Option<&'static [u8]>,
+    /// The file compressed with gzip.
+    pub gzip: Option<&'static [u8]>,
+}
+impl EmbeddedAsset {
+    /// The asset, compressed if the client accepts one of
+    /// its compressed versions.
+    pub fn response(&self, request: &Request) -> Response {
+        let available = [("br", self.brotli), ("gzip", self.gzip)];
+        let offered = available.iter().filter(|(_, x)| x.is_some()).map(|(x, _)| *x).collect::<Vec<_>>();
+        let response = match preferred_encoding(request, &offered) {
+            Some(encoding) => {
+                let data = available.iter().find(|(x, _)| *x == encoding).and_then(|(_, x)| *x).unwrap();
+                Response::data(data.to_vec()).header("Content-Encoding", encoding)
+            }
+            None => Response::data(self.data.to_vec()),
+        };
+        vary(response, !offered.is_empty()).header("Content-Type", mime::from_path(self.name))
+    }
+}
+
+impl Response {
+    /// Reads a file, sending the `.br` or `.gz` file next to it
+    /// instead if there is one and the client accepts it. The
+    /// `Content-Type` is inferred with [`mime::from_path`].
+    /// Missing files are 404 errors.
+    ///
+    /// The path is read as given, so don't build it from the
+    /// request's path without checking it stays in its directory.
+    pub async fn file<P: AsRef<Path>>(request: &Request, path: P) -> Result<Response, RouteError> {
+        let path = path.as_ref();
+        let mut offered = Vec::new();
+        for (encoding, extension) in [("br", "br"), ("gzip", "gz")] {
+            let mut compressed = path.as_os_str().to_owned();
+            compressed.push(".");
+            compressed.push(extension);
+            if tokio::fs::metadata(&compressed).await.is_ok_and(|x| x.is_file()) {
+                offered.push((encoding, compressed));
+            }
+        }
+        let encodings = offered.iter().map(|(x, _)| *x).collect::<Vec<_>>();
+        let response = match preferred_encoding(request, &encodings) {
+            Some(encoding) => {
+                let (_, compressed) = offered.iter().find(|(x, _)| *x == encoding).unwrap();
+                Response::data(read_file(compressed.as_ref()).await?).header("Content-Encoding", encoding)
+            }
+            None => Response::data(read_file(path).await?),
+        };
+        Ok(vary(response, !offered.is_empty()).header("Content-Type", mime::from_path(path)))
+    }
+}
+
+async fn read_file(path: &Path) -> Result<Vec<u8>, RouteError> {
+    tokio::fs::read(path).await.map_err(|err| match err.kind() {
+        std::io::ErrorKind::NotFound => RouteError::not_found("Not found."),
+        _ => RouteError::server_error("Could not read file.").caused_by(err),
+    })
+}
+
+/// Marks responses which depend on `Accept-Encoding`, so caches
+/// keep each version apart.
+fn vary(response: Response, varies: bool) -> Response {
+    if varies {
+        response.append_header("Vary", "Accept-Encoding")
+    } else {
+        response
+    }
+}
+
+/// The encoding from `offered` the client prefers, by the
+/// quality values of its `Accept-Encoding` header. Ties go to
+/// the encoding offered first.
+fn preferred_encoding<'a>(request: &Request, offered: &[&'a str]) -> Option<&'a str> {
+    let header = request.headers.get("accept-encoding")?;
+    let quality = |name: &str| {
+        header.split(',').find_map(|entry| {
+            let mut parts = entry.split(';');
+            if !parts.next()?.trim().eq_ignore_ascii_case(name) {
+                return None;
+            }
+            Some(
+                parts
+                    .find_map(|x| x.trim().strip_prefix("q="))
+                    .and_then(|x| x.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0),
+            )
+        })
+    };
+    let mut preferred = None;
+    for encoding in offered {
+        let q = quality(encoding).or_else(|| quality("*")).unwrap_or(0.0);
+        if q > 0.0 && preferred.is_none_or(|(_, best)| q > best) {
+            preferred = Some((*encoding, q));
+        }
+    }
+    preferred.map(|(encoding, _)| encoding)
+}
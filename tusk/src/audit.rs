@@ -0,0 +1,190 @@
+use std::{cell::RefCell, rc::Rc, time::{Duration, Instant}};
+
+use brackets::ToJson;
+use chrono::{DateTime, Utc};
+use tokio_postgres::types::ToSql;
+
+use crate::{database::PostgresWriteError, ApiKey, DatabaseConnection, Request};
+
+/// The actor recorded for audit entries. Place this in
+/// the request's extensions (usually in a treatment function)
+/// so [`Request::audit`] knows who performed an action.
+///
+/// If no actor is set, the identity of a resolved [`ApiKey`]
+/// is used instead.
+#[derive(Debug, Clone)]
+pub struct AuditActor(pub String);
+
+/// A single audit record.
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub actor: Option<String>,
+    pub action: String,
+    pub entity: String,
+    /// JSON of the entity before the change.
+    pub before: String,
+    /// JSON of the entity after the change.
+    pub after: String,
+    pub request_id: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Records audit entries into a Postgres table.
+///
+/// Entries are buffered and written in batches, either
+/// when `batch_size` entries are pending or when the oldest
+/// pending entry is older than `flush_interval`. Register the
+/// log using [`crate::Server::audit_log`] and record entries
+/// from handlers with [`Request::audit`].
+#[derive(Clone)]
+pub struct AuditLog {
+    table: &'static str,
+    batch_size: usize,
+    flush_interval: Duration,
+    pending: Rc<RefCell<Vec<AuditEntry>>>,
+    oldest: Rc<RefCell<Option<Instant>>>,
+}
+impl AuditLog {
+    const COLUMNS: usize = 8;
+
+    /// Create a log backed by `table`.
+    pub fn new(table: &'static str) -> AuditLog {
+        AuditLog {
+            table,
+            batch_size: 50,
+            flush_interval: Duration::from_secs(5),
+            pending: Rc::new(RefCell::new(Vec::new())),
+            oldest: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    /// Define how many entries are buffered before writing. Can be chained.
+    pub fn batch_size(mut self, batch_size: usize) -> AuditLog {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Define the longest an entry is buffered before writing. Can be chained.
+    pub fn flush_interval(mut self, interval: Duration) -> AuditLog {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// The SQL needed to create the backing table.
+    pub fn create_table_sql(&self) -> String {
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                id BIGSERIAL PRIMARY KEY,
+                actor TEXT,
+                action TEXT NOT NULL,
+                entity TEXT NOT NULL,
+                before JSONB,
+                after JSONB,
+                request_id TEXT,
+                ip TEXT,
+                created_at TIMESTAMPTZ NOT NULL
+            )",
+            self.table
+        )
+    }
+
+    /// Buffer an entry to be written with the next batch.
+    pub fn record(&self, entry: AuditEntry) {
+        self.oldest.borrow_mut().get_or_insert_with(Instant::now);
+        self.pending.borrow_mut().push(entry);
+    }
+
+    /// Whether the pending entries should be written.
+    pub fn should_flush(&self) -> bool {
+        let pending = self.pending.borrow().len();
+        pending >= self.batch_size
+            || (pending > 0
+                && self
+                    .oldest
+                    .borrow()
+                    .is_some_and(|x| x.elapsed() >= self.flush_interval))
+    }
+
+    /// Write all pending entries in a single insert.
+    /// If the insert fails, the entries are kept for the next flush.
+    pub async fn flush(&self, db: &DatabaseConnection) -> Result<(), PostgresWriteError> {
+        let entries = std::mem::take(&mut *self.pending.borrow_mut());
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let placeholders = (0..entries.len())
+            .map(|ix| {
+                let o = ix * Self::COLUMNS;
+                format!(
+                    "(${}, ${}, ${}, (${}::text)::jsonb, (${}::text)::jsonb, ${}, ${}, ${})",
+                    o + 1, o + 2, o + 3, o + 4, o + 5, o + 6, o + 7, o + 8
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+        let args = entries
+            .iter()
+            .flat_map(|x| {
+                [
+                    &x.actor as &(dyn ToSql + Sync),
+                    &x.action,
+                    &x.entity,
+                    &x.before,
+                    &x.after,
+                    &x.request_id,
+                    &x.ip,
+                    &x.created_at,
+                ]
+            })
+            .collect::<Vec<&(dyn ToSql + Sync)>>();
+        let result = db
+            .query(
+                format!(
+                    "INSERT INTO {} (actor, action, entity, before, after, request_id, ip, created_at) VALUES {}",
+                    self.table, placeholders
+                ),
+                &args,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                *self.oldest.borrow_mut() = None;
+                Ok(())
+            }
+            Err(err) => {
+                let mut pending = self.pending.borrow_mut();
+                let newer = std::mem::replace(&mut *pending, entries);
+                pending.extend(newer);
+                Err(err.into())
+            }
+        }
+    }
+}
+
+impl Request {
+    /// Record an audit entry for an action, such as `user.update`.
+    /// The entity is the part of the action before the first `.`.
+    ///
+    /// Entries are only recorded if an [`AuditLog`] was registered
+    /// with the server.
+    pub fn audit<B: ToJson, A: ToJson>(&self, action: &str, before: &B, after: &A) {
+        let Some(log) = self.extensions.get::<AuditLog>() else {
+            return;
+        };
+        log.record(AuditEntry {
+            actor: self
+                .extensions
+                .get::<AuditActor>()
+                .map(|x| x.0.clone())
+                .or_else(|| self.extensions.get::<ApiKey>().map(|x| x.identity.clone())),
+            action: action.to_string(),
+            entity: action.split('.').next().unwrap_or(action).to_string(),
+            before: before.to_json(),
+            after: after.to_json(),
+            request_id: self.headers.get("x-request-id").cloned(),
+            ip: self.remote_addr.map(|x| x.ip().to_string()),
+            created_at: Utc::now(),
+        });
+    }
+}
@@ -0,0 +1,102 @@
+/// A parsed `Authorization` header.
+///
+/// Retrieve this from a request with [`crate::Request::authorization`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Authorization {
+    /// `Basic` credentials, decoded from base64.
+    Basic { user: String, pass: String },
+    /// A `Bearer` token.
+    Bearer(String),
+    /// Any other scheme. Syntax is (scheme, credentials).
+    Other(String, String),
+}
+impl Authorization {
+    /// Parses the value of an `Authorization` header.
+    /// Returns `None` if the value is malformed, including
+    /// `Basic` credentials which are not valid base64 or UTF-8.
+    pub fn from_header(value: &str) -> Option<Authorization> {
+        let value = value.trim();
+        let (scheme, credentials) = match value.split_once(' ') {
+            Some((scheme, credentials)) => (scheme, credentials.trim()),
+            None => (value, ""),
+        };
+        if scheme.is_empty() {
+            return None;
+        }
+        if scheme.eq_ignore_ascii_case("basic") {
+            let decoded = String::from_utf8(decode_base64(credentials)?).ok()?;
+            let (user, pass) = decoded.split_once(':')?;
+            Some(Authorization::Basic {
+                user: user.to_string(),
+                pass: pass.to_string(),
+            })
+        } else if scheme.eq_ignore_ascii_case("bearer") {
+            if credentials.is_empty() {
+                return None;
+            }
+            Some(Authorization::Bearer(credentials.to_string()))
+        } else {
+            Some(Authorization::Other(scheme.to_string(), credentials.to_string()))
+        }
+    }
+
+    /// The bearer token, if this is a `Bearer` authorization.
+    pub fn bearer(&self) -> Option<&str> {
+        match self {
+            Authorization::Bearer(token) => Some(token),
+            _ => None,
+        }
+    }
+
+    /// The user and password, if this is a `Basic` authorization.
+    pub fn basic(&self) -> Option<(&str, &str)> {
+        match self {
+            Authorization::Basic { user, pass } => Some((user, pass)),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes standard or URL-safe base64, with or without padding.
+pub(crate) fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=');
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in input.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    // A single leftover character can't encode a full byte.
+    if bits >= 6 {
+        return None;
+    }
+    Some(output)
+}
+
+/// Encodes URL-safe base64 without padding, as used by
+/// JWTs and PKCE.
+pub(crate) fn encode_base64_url(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let buffer = chunk.iter().fold(0u32, |acc, x| (acc << 8) | *x as u32) << (8 * (3 - chunk.len()));
+        for ix in 0..=chunk.len() {
+            output.push(ALPHABET[((buffer >> (18 - ix * 6)) & 63) as usize] as char);
+        }
+    }
+    output
+}
@@ -1,3 +1,20 @@
+use std::time::Duration;
+
+use crate::PostgresOwned;
+
+/// How the connection pooler in front of Postgres, if any,
+/// shares server connections. See [`DatabaseConfig::pool_mode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PoolMode {
+    /// Each connection is a whole session, as when connecting to
+    /// Postgres directly or with PgBouncer in session mode.
+    #[default]
+    Session,
+    /// Server connections are shared between transactions, as
+    /// with PgBouncer in transaction mode.
+    Transaction,
+}
+
 /// Defines a connection to a Postgres server.
 pub struct DatabaseConfig {
 	pub host: String,
@@ -7,6 +24,16 @@ pub struct DatabaseConfig {
 	pub database: String,
 	pub ssl: bool,
     pub debug: bool,
+    /// How many rows [`crate::DatabaseConnection::select_batched`]
+    /// fetches at a time.
+    pub fetch_size: i32,
+    /// Tables which may only be read and written through
+    /// [`crate::DatabaseConnection::owned_by`].
+    pub owned_tables: Vec<&'static str>,
+    /// Queries slower than this have their plan logged. See
+    /// [`DatabaseConfig::explain_slow_queries`].
+    pub explain_threshold: Option<Duration>,
+    pub pool_mode: PoolMode,
 }
 impl DatabaseConfig {
 	/// Creates a new database connection config.
@@ -21,6 +48,10 @@ impl DatabaseConfig {
 			database: "postgres".to_string(),
 			ssl: false,
             debug: false,
+            fetch_size: 1000,
+            owned_tables: Vec::new(),
+            explain_threshold: None,
+            pool_mode: PoolMode::Session,
 		}
 	}
 
@@ -121,6 +152,74 @@ impl DatabaseConfig {
         self.debug = debug;
         self
     }
+
+    /// Define how many rows are fetched at a time by
+    /// batched selects. Can be chained.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tusk_rs::config::DatabaseConfig;
+    ///
+    /// DatabaseConfig::new().username("username").password("password").fetch_size(500)
+    /// ```
+    pub fn fetch_size(mut self, fetch_size: i32) -> DatabaseConfig {
+        self.fetch_size = fetch_size.max(1);
+        self
+    }
+
+    /// Log the plan of queries slower than `threshold` while
+    /// [`DatabaseConfig::debug()`] is on. Only the plan is logged,
+    /// without running the query again, so use
+    /// [`crate::DatabaseConnection::explain`] for actual times.
+    /// Can be chained.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use tusk_rs::config::DatabaseConfig;
+    ///
+    /// DatabaseConfig::new().debug(true).explain_slow_queries(Duration::from_millis(100))
+    /// ```
+    pub fn explain_slow_queries(mut self, threshold: Duration) -> DatabaseConfig {
+        self.explain_threshold = Some(threshold);
+        self
+    }
+
+    /// Define how the connection pooler shares connections.
+    /// Defaults to [`PoolMode::Session`]. Can be chained.
+    ///
+    /// With [`PoolMode::Transaction`], the statement a query is
+    /// prepared as and the query itself could otherwise reach
+    /// different server connections, so queries outside of a
+    /// transaction are each run in one, costing two round trips.
+    /// Session settings, like the tenant from [`crate::Tenancy`],
+    /// are set again at the start of every transaction instead.
+    /// PgBouncer 1.21 and later can track prepared statements
+    /// itself with `max_prepared_statements`, which avoids this.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tusk_rs::config::{DatabaseConfig, PoolMode};
+    ///
+    /// DatabaseConfig::new().port(6432).pool_mode(PoolMode::Transaction)
+    /// ```
+    pub fn pool_mode(mut self, pool_mode: PoolMode) -> DatabaseConfig {
+        self.pool_mode = pool_mode;
+        self
+    }
+
+    /// Refuse reads and writes of `T` which aren't scoped to an
+    /// owner with [`crate::DatabaseConnection::owned_by`]. They fail
+    /// with an `Unscoped` error instead, including inserts, which
+    /// go through [`crate::OwnedScope::insert`]. Raw queries aren't
+    /// checked. Can be chained.
+    pub fn enforce_ownership<T: PostgresOwned>(mut self) -> DatabaseConfig {
+        self.owned_tables.push(T::table_name());
+        self
+    }
 }
 impl Default for DatabaseConfig {
 	fn default() -> Self {
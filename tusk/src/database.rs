@@ -1,10 +1,22 @@
+use std::{
+    collections::HashSet,
+    future::Future,
+    ops::{Deref, DerefMut},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
 use deadpool_postgres::{Object, Pool};
 use openssl::ssl::{SslConnector, SslMethod};
 use postgres_openssl::MakeTlsConnector;
 use tokio_postgres::{types::ToSql, NoTls, Row};
 
 use crate::{
-    config::DatabaseConfig, query::{PostgresReadable, IntoSyntax}, FromPostgres, PostgresTable, PostgresWrite, PostgresReadFields
+    config::{DatabaseConfig, PoolMode}, hooks::ConnectionLeaks, query::{offset_placeholders, select_prefix, select_prefix_as, PostgresJoins, PostgresReadable, PostgresVersioned, IntoSyntax, RowLock}, FromPostgres, FromPostgresError, PostgresTable, RouteError, PostgresWrite, PostgresWriteRef, PostgresReadFields
 };
 
 
@@ -14,6 +26,13 @@ use crate::{
 pub struct Database {
     pool: Pool,
     debug: bool,
+    fetch_size: i32,
+    owned_tables: Arc<[&'static str]>,
+    explain_threshold: Option<Duration>,
+    pool_mode: PoolMode,
+    leaks: Option<ConnectionLeaks>,
+    /// Statements to prepare once per connection. See [`crate::Warmup`].
+    warm: Arc<RwLock<HashSet<String>>>,
 }
 
 impl Database {
@@ -23,27 +42,56 @@ impl Database {
         cfg.user = Some(config.username);
         cfg.password = Some(config.password);
         cfg.host = Some(config.host);
+        cfg.port = Some(config.port as u16);
         cfg.dbname = Some(config.database);
+        let owned_tables: Arc<[&'static str]> = config.owned_tables.into();
+        let explain_threshold = config.explain_threshold;
+        let pool_mode = config.pool_mode;
 
         if config.ssl {
             let mut builder = SslConnector::builder(SslMethod::tls()).ok()?;
             let _ = builder.set_ca_file("/etc/ssl/cert.pem");
             let connector = MakeTlsConnector::new(builder.build());
             let pool = cfg.create_pool(None, connector).ok()?;
-            Some(Database { pool, debug: config.debug })
+            Some(Database { pool, debug: config.debug, fetch_size: config.fetch_size, owned_tables, explain_threshold, pool_mode, leaks: None, warm: Arc::default() })
         } else {
             let pool = cfg.create_pool(None, NoTls).ok()?;
-            Some(Database { pool, debug: config.debug })
+            Some(Database { pool, debug: config.debug, fetch_size: config.fetch_size, owned_tables, explain_threshold, pool_mode, leaks: None, warm: Arc::default() })
         }
     }
 
     /// Gets a connection within the pool.
     pub async fn get_connection(&self) -> Result<DatabaseConnection, deadpool_postgres::PoolError> {
+        let cn = self.pool.get().await?;
         Ok(DatabaseConnection {
-            cn: self.pool.get().await?,
+            cn: PooledClient(Some(cn)),
             debug: self.debug,
+            fetch_size: self.fetch_size,
+            owned_tables: self.owned_tables.clone(),
+            explain_threshold: self.explain_threshold,
+            pool_mode: self.pool_mode,
+            in_transaction: AtomicBool::new(false),
+            local_settings: Mutex::default(),
+            warm: self.warm.clone(),
+            checkout: self.leaks.as_ref().map(|leaks| (leaks.clone(), leaks.check_out())),
         })
     }
+
+    /// Track how long connections are held. See [`ConnectionLeaks`].
+    pub(crate) fn track_leaks(&mut self, leaks: ConnectionLeaks) {
+        self.leaks = Some(leaks);
+    }
+
+    pub fn pool_mode(&self) -> PoolMode {
+        self.pool_mode
+    }
+
+    /// Runs `statements` as prepared statements from now on,
+    /// preparing them once per connection.
+    pub(crate) fn keep_prepared(&self, statements: &[String]) {
+        let mut warm = self.warm.write().unwrap_or_else(|x| x.into_inner());
+        warm.extend(statements.iter().cloned());
+    }
 }
 
 #[derive(Debug)]
@@ -54,6 +102,11 @@ pub enum PostgresReadError {
     AmbigiousColumn(String),
     // (Table)
     PermissionDenied(String),
+    /// A row could not be converted into the requested type.
+    Conversion(FromPostgresError),
+    /// The table is owned and the read wasn't scoped to an owner.
+    /// See [`crate::DatabaseConfig::enforce_ownership`].
+    Unscoped(String),
 }
 impl PostgresReadError {
     pub fn from_pg_err(err: tokio_postgres::Error) -> PostgresReadError {
@@ -82,6 +135,30 @@ impl From<tokio_postgres::Error> for PostgresReadError {
         PostgresReadError::from_pg_err(value)
     }
 }
+impl From<FromPostgresError> for PostgresReadError {
+    fn from(value: FromPostgresError) -> Self {
+        PostgresReadError::Conversion(value)
+    }
+}
+impl From<PostgresReadError> for RouteError {
+    fn from(value: PostgresReadError) -> Self {
+        match value {
+            PostgresReadError::NoResults => RouteError::not_found("Not found."),
+            PostgresReadError::Conversion(err) => {
+                eprintln!("[ERROR] Could not read row: {}", err);
+                RouteError::server_error("Could not read data.").caused_by(err)
+            }
+            PostgresReadError::Unknown(err) => {
+                eprintln!("[ERROR] Could not read: {:?}", err);
+                RouteError::server_error("Could not read data.").caused_by(err)
+            }
+            err => {
+                eprintln!("[ERROR] Could not read: {:?}", err);
+                RouteError::server_error("Could not read data.")
+            }
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum PostgresWriteError {
@@ -92,7 +169,18 @@ pub enum PostgresWriteError {
     NotNullConstraintViolation(String),
     // (Table)
     PermissionDenied(String),
+    /// The write matched no rows, such as an update
+    /// whose condition matched nothing.
     NoRows,
+    /// A versioned row was changed since it was read. See
+    /// [`DatabaseConnection::update_versioned`]. Usually
+    /// answered with a 409.
+    Conflict,
+    /// The table is owned and the write wasn't scoped to an owner.
+    /// See [`crate::DatabaseConfig::enforce_ownership`].
+    Unscoped(String),
+    /// A written row could not be converted into the requested type.
+    Conversion(FromPostgresError),
     Unknown(tokio_postgres::Error),
 }
 impl PostgresWriteError {
@@ -121,12 +209,175 @@ impl From<tokio_postgres::Error> for PostgresWriteError {
         PostgresWriteError::from_pg_err(value)
     }
 }
+impl From<FromPostgresError> for PostgresWriteError {
+    fn from(value: FromPostgresError) -> Self {
+        PostgresWriteError::Conversion(value)
+    }
+}
+
+/// A client from the pool, which can be closed instead of
+/// returned to it.
+struct PooledClient(Option<Object>);
+impl Deref for PooledClient {
+    type Target = Object;
+    fn deref(&self) -> &Object {
+        self.0.as_ref().unwrap()
+    }
+}
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut Object {
+        self.0.as_mut().unwrap()
+    }
+}
 
 pub struct DatabaseConnection {
-    cn: Object,
+    cn: PooledClient,
     debug: bool,
+    fetch_size: i32,
+    owned_tables: Arc<[&'static str]>,
+    explain_threshold: Option<Duration>,
+    pool_mode: PoolMode,
+    /// Whether a transaction may be open, from
+    /// [`DatabaseConnection::begin`] or around a single statement.
+    in_transaction: AtomicBool,
+    /// Settings made at the start of each transaction in
+    /// [`PoolMode::Transaction`], as a `SELECT set_config(...)`.
+    local_settings: Mutex<String>,
+    warm: Arc<RwLock<HashSet<String>>>,
+    /// The tracker and id of this checkout, when tracking leaks.
+    checkout: Option<(ConnectionLeaks, u64)>,
+}
+impl Drop for DatabaseConnection {
+    fn drop(&mut self) {
+        if let Some((leaks, id)) = &self.checkout {
+            leaks.check_in(*id);
+        }
+        // Dropped inside a transaction, such as by a route timing
+        // out, so the next request would run inside it. Closing
+        // the connection rolls it back.
+        if self.in_transaction.load(Ordering::Relaxed) {
+            if let Some(cn) = self.cn.0.take() {
+                drop(Object::take(cn));
+            }
+        }
+    }
 }
 impl DatabaseConnection {
+    pub fn pool_mode(&self) -> PoolMode {
+        self.pool_mode
+    }
+
+    /// Sets connection settings, such as `search_path`. In
+    /// [`PoolMode::Transaction`] they're set at the start of every
+    /// transaction, since the server connection may change between
+    /// them; otherwise they're set for the session.
+    pub(crate) async fn set_settings(&self, settings: &[(&str, &str)]) -> Result<(), tokio_postgres::Error> {
+        if settings.is_empty() {
+            return Ok(());
+        }
+        let statement = settings
+            .iter()
+            .map(|(name, value)| {
+                format!(
+                    "set_config({}, {}, {})",
+                    quote_literal(name),
+                    quote_literal(value),
+                    self.pool_mode == PoolMode::Transaction
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let statement = format!("SELECT {}", statement);
+        match self.pool_mode {
+            PoolMode::Session => self.cn.batch_execute(&statement).await,
+            PoolMode::Transaction => {
+                let result = match self.in_transaction.load(Ordering::Relaxed) {
+                    true => self.cn.batch_execute(&statement).await,
+                    false => Ok(()),
+                };
+                *self.local_settings.lock().unwrap_or_else(|x| x.into_inner()) = statement;
+                result
+            }
+        }
+    }
+
+    /// The statement starting a transaction, with the settings
+    /// it needs in [`PoolMode::Transaction`].
+    fn begin_statement(&self) -> String {
+        let settings = self.local_settings.lock().unwrap_or_else(|x| x.into_inner());
+        match settings.is_empty() {
+            true => "BEGIN".to_string(),
+            false => format!("BEGIN; {}", settings),
+        }
+    }
+
+    /// Prepares `statement`, keeping it for the connection's
+    /// next uses.
+    pub(crate) async fn prepare_cached(&self, statement: &str) -> Result<(), tokio_postgres::Error> {
+        self.cn.prepare_cached(statement).await.map(|_| ())
+    }
+
+    /// Runs `query`, as a prepared statement if it was warmed
+    /// up by [`crate::Warmup`].
+    async fn query_rows(&self, query: &str, args: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, tokio_postgres::Error> {
+        let warm = self.warm.read().unwrap_or_else(|x| x.into_inner()).contains(query);
+        if warm {
+            let statement = self.cn.prepare_cached(query).await?;
+            return self.cn.query(&statement, args).await;
+        }
+        self.pinned(self.cn.query(query, args)).await
+    }
+
+    /// Runs `statement` in a transaction of its own in
+    /// [`PoolMode::Transaction`], unless one is open, so it's
+    /// prepared and run on the same server connection.
+    async fn pinned<R>(
+        &self,
+        statement: impl Future<Output = Result<R, tokio_postgres::Error>>,
+    ) -> Result<R, tokio_postgres::Error> {
+        if self.pool_mode == PoolMode::Session || self.in_transaction.swap(true, Ordering::Relaxed) {
+            return statement.await;
+        }
+        if let Err(err) = self.cn.batch_execute(&self.begin_statement()).await {
+            self.in_transaction.store(false, Ordering::Relaxed);
+            return Err(err);
+        }
+        let result = statement.await;
+        // Ends the transaction as a rollback if the statement failed.
+        let committed = self.cn.batch_execute("COMMIT").await;
+        self.in_transaction.store(false, Ordering::Relaxed);
+        let result = result?;
+        committed?;
+        Ok(result)
+    }
+
+    /// Attributes the connection to `route` in [`ConnectionLeaks`].
+    pub(crate) fn assign_route(&self, route: impl FnOnce() -> String) {
+        if let Some((leaks, id)) = &self.checkout {
+            leaks.assign(*id, route());
+        }
+    }
+
+    /// Whether `table` may only be used through
+    /// [`DatabaseConnection::owned_by`].
+    fn enforces_owner(&self, table: &str) -> bool {
+        self.owned_tables.contains(&table)
+    }
+
+    fn check_read_scope(&self, table: &str) -> Result<(), PostgresReadError> {
+        if self.enforces_owner(table) {
+            return Err(PostgresReadError::Unscoped(table.to_string()));
+        }
+        Ok(())
+    }
+
+    fn check_write_scope(&self, table: &str) -> Result<(), PostgresWriteError> {
+        if self.enforces_owner(table) {
+            return Err(PostgresWriteError::Unscoped(table.to_string()));
+        }
+        Ok(())
+    }
+
     pub async fn query<T: AsRef<str>>(
         &self,
         query: T,
@@ -136,91 +387,449 @@ impl DatabaseConnection {
             println!("[DEBUG: QUERY] {}", query.as_ref());
             println!("[DEBUG: ARGS] Args: {:?}", args);
         }
-        self.cn.query(query.as_ref(), args).await
+        self.run(query.as_ref(), args).await
     }
 
-    pub async fn select_all<T: FromPostgres + PostgresReadable + PostgresTable>(
+    /// Runs a statement, logging its plan if it's slower than
+    /// [`DatabaseConfig::explain_slow_queries`] allows and debugging
+    /// is on. Only the plan is logged, from `EXPLAIN`, so a slow
+    /// statement isn't run a second time.
+    async fn run(&self, query: &str, args: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, tokio_postgres::Error> {
+        let Some(threshold) = self.explain_threshold.filter(|_| self.debug) else {
+            return self.query_rows(query, args).await;
+        };
+        let started = Instant::now();
+        let rows = self.query_rows(query, args).await?;
+        let elapsed = started.elapsed();
+        if elapsed > threshold {
+            println!("[DEBUG: SLOW QUERY] {}ms: {}", elapsed.as_millis(), query);
+            match self.plan("EXPLAIN", query, args).await {
+                Ok(plan) => println!("{}", plan),
+                Err(err) => println!("[DEBUG: SLOW QUERY] Could not explain: {}", err),
+            }
+        }
+        Ok(rows)
+    }
+
+    /// The plan Postgres chooses for a statement, from
+    /// `EXPLAIN (ANALYZE, BUFFERS)`, to find missing indexes.
+    ///
+    /// `SELECT` statements are run again to measure them, so
+    /// the plan shows actual times and rows. Other statements are
+    /// only planned, with `EXPLAIN`, so they aren't applied twice.
+    pub async fn explain<T: AsRef<str>>(
+        &self,
+        query: T,
+        args: &[&(dyn ToSql + Sync)],
+    ) -> Result<String, tokio_postgres::Error> {
+        let query = query.as_ref().trim_start();
+        let reads = query.get(..6).is_some_and(|x| x.eq_ignore_ascii_case("select"));
+        let explain = if reads { "EXPLAIN (ANALYZE, BUFFERS)" } else { "EXPLAIN" };
+        self.plan(explain, query, args).await
+    }
+
+    async fn plan(&self, explain: &str, query: &str, args: &[&(dyn ToSql + Sync)]) -> Result<String, tokio_postgres::Error> {
+        let explain = format!("{} {}", explain, query);
+        Ok(self
+            .pinned(self.cn.query(&explain, args))
+            .await?
+            .iter()
+            .map(|x| x.get::<_, String>(0))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    /// Explains the statement [`DatabaseConnection::select_all`]
+    /// runs for `query`. See [`DatabaseConnection::explain`].
+    ///
+    /// ```ignore
+    /// println!("{}", db.explain_select::<User>("WHERE email = $1", &[&email]).await?);
+    /// ```
+    pub async fn explain_select<T: PostgresReadable + PostgresTable + 'static>(
+        &self,
+        query: &str,
+        args: &[&(dyn ToSql + Sync)],
+    ) -> Result<String, tokio_postgres::Error> {
+        self.explain(Self::select_query::<T>(query), args).await
+    }
+
+    pub async fn select_all<T: FromPostgres + PostgresReadable + PostgresTable + 'static>(
         &self,
         query: &str,
         args: &[&(dyn ToSql + Sync)],
     ) -> Result<Vec<T>, PostgresReadError> {
+        self.check_read_scope(T::table_name())?;
+        let select = Self::select_query::<T>(query);
         if self.debug {
-            println!("[DEBUG: QUERY] (select_all) SELECT {} FROM {} {} {}", T::read_fields().as_syntax(T::table_name()), T::table_name(), T::joins().iter().map(|j| j.to_read(T::table_name())).collect::<Vec<String>>().join(" "), query);
+            println!("[DEBUG: QUERY] (select_all) {}", select);
             println!("[DEBUG: ARGS] (select_all) Args: {:?}", args);
         }
         Ok(self
-            .cn
-            .query(
-                &format!(
-                    "SELECT {} FROM {} {} {}",
-                    T::read_fields().as_syntax(T::table_name()),
-                    T::table_name(),
-                    T::joins().iter().map(|j| j.to_read(T::table_name())).collect::<Vec<String>>().join(" "),
-                    query
-                ),
-                args,
-            )
+            .run(&select, args)
             .await?
             .iter()
-            .map(|x| T::from_postgres(x))
-            .collect::<Vec<_>>())
+            .map(|x| T::try_from_postgres(x))
+            .collect::<Result<Vec<_>, _>>()?)
     }
 
-    pub async fn select_one<T: FromPostgres + PostgresReadable + PostgresTable>(
+    pub async fn select_one<T: FromPostgres + PostgresReadable + PostgresTable + 'static>(
         &self,
         query: &str,
         args: &[&(dyn ToSql + Sync)],
     ) -> Result<T, PostgresReadError> {
+        self.check_read_scope(T::table_name())?;
+        let select = Self::select_query::<T>(query);
         if self.debug {
-            println!("[DEBUG: QUERY] (select_one) SELECT {} FROM {} {} {}",
-                    T::read_fields().as_syntax(T::table_name()),
-                    T::table_name(),
-                    T::joins().iter().map(|j| j.to_read(T::table_name())).collect::<Vec<String>>().join(" "),
-                    query);
+            println!("[DEBUG: QUERY] (select_one) {}", select);
             println!("[DEBUG: ARGS] (select_one) Args: {:?}", args);
         }
         self
-            .cn
-            .query(
-                &format!(
-                    "SELECT {} FROM {} {} {}",
-                    T::read_fields().as_syntax(T::table_name()),
-                    T::table_name(),
-                    T::joins().iter().map(|j| j.to_read(T::table_name())).collect::<Vec<String>>().join(" "),
-                    query
-                ),
-                args,
-            )
+            .run(&select, args)
+            .await?
+            .first()
+            .ok_or(PostgresReadError::NoResults)
+            .and_then(|x| Ok(T::try_from_postgres(x)?))
+    }
+
+    /// Read the fields of `P` from the table and joins of `M`.
+    /// This allows slim projections, such as for list endpoints,
+    /// without redefining the table and its joins. Fields of `P`
+    /// read from a join must declare the same join as `M`.
+    pub async fn select_as<
+        M: PostgresJoins + PostgresTable + 'static,
+        P: FromPostgres + PostgresReadFields + 'static,
+    >(
+        &self,
+        query: &str,
+        args: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<P>, PostgresReadError> {
+        self.check_read_scope(M::table_name())?;
+        let select = Self::with_prefix(select_prefix_as::<M, P>(), query);
+        if self.debug {
+            println!("[DEBUG: QUERY] (select_as) {}", select);
+            println!("[DEBUG: ARGS] (select_as) Args: {:?}", args);
+        }
+        Ok(self
+            .run(&select, args)
+            .await?
+            .iter()
+            .map(|x| P::try_from_postgres(x))
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Like [`DatabaseConnection::select_as`], but for a single row.
+    pub async fn select_one_as<
+        M: PostgresJoins + PostgresTable + 'static,
+        P: FromPostgres + PostgresReadFields + 'static,
+    >(
+        &self,
+        query: &str,
+        args: &[&(dyn ToSql + Sync)],
+    ) -> Result<P, PostgresReadError> {
+        self.check_read_scope(M::table_name())?;
+        let select = Self::with_prefix(select_prefix_as::<M, P>(), query);
+        if self.debug {
+            println!("[DEBUG: QUERY] (select_one_as) {}", select);
+            println!("[DEBUG: ARGS] (select_one_as) Args: {:?}", args);
+        }
+        self
+            .run(&select, args)
+            .await?
+            .first()
+            .ok_or(PostgresReadError::NoResults)
+            .and_then(|x| Ok(P::try_from_postgres(x)?))
+    }
+
+    /// Like [`DatabaseConnection::select_all`], but the selected
+    /// rows are locked until the transaction ends. The lock is
+    /// added after `query`, so it may end in `ORDER BY` or `LIMIT`.
+    pub async fn select_all_locked<T: FromPostgres + PostgresReadable + PostgresTable + 'static>(
+        &self,
+        query: &str,
+        args: &[&(dyn ToSql + Sync)],
+        lock: RowLock,
+    ) -> Result<Vec<T>, PostgresReadError> {
+        self.check_read_scope(T::table_name())?;
+        let select = format!("{} {}", Self::select_query::<T>(query), lock.clause(T::table_name()));
+        if self.debug {
+            println!("[DEBUG: QUERY] (select_all_locked) {}", select);
+            println!("[DEBUG: ARGS] (select_all_locked) Args: {:?}", args);
+        }
+        Ok(self
+            .run(&select, args)
+            .await?
+            .iter()
+            .map(|x| T::try_from_postgres(x))
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Like [`DatabaseConnection::select_one`], but the selected
+    /// row is locked until the transaction ends.
+    pub async fn select_one_locked<T: FromPostgres + PostgresReadable + PostgresTable + 'static>(
+        &self,
+        query: &str,
+        args: &[&(dyn ToSql + Sync)],
+        lock: RowLock,
+    ) -> Result<T, PostgresReadError> {
+        self.check_read_scope(T::table_name())?;
+        let select = format!("{} {}", Self::select_query::<T>(query), lock.clause(T::table_name()));
+        if self.debug {
+            println!("[DEBUG: QUERY] (select_one_locked) {}", select);
+            println!("[DEBUG: ARGS] (select_one_locked) Args: {:?}", args);
+        }
+        self
+            .run(&select, args)
+            .await?
+            .first()
+            .ok_or(PostgresReadError::NoResults)
+            .and_then(|x| Ok(T::try_from_postgres(x)?))
+    }
+
+    /// Starts a transaction. Every query on this connection is
+    /// part of it until [`DatabaseConnection::commit`] or
+    /// [`DatabaseConnection::rollback`] is called.
+    ///
+    /// A connection dropped before the transaction ends, such as
+    /// when its route times out, is closed rather than returned
+    /// to the pool, which rolls the transaction back.
+    pub async fn begin(&self) -> Result<(), PostgresWriteError> {
+        self.in_transaction.store(true, Ordering::Relaxed);
+        if let Err(err) = self.cn.batch_execute(&self.begin_statement()).await {
+            self.in_transaction.store(false, Ordering::Relaxed);
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    /// Commits the current transaction.
+    pub async fn commit(&self) -> Result<(), PostgresWriteError> {
+        let result = self.cn.batch_execute("COMMIT").await;
+        self.in_transaction.store(false, Ordering::Relaxed);
+        Ok(result?)
+    }
+
+    /// Rolls back the current transaction.
+    pub async fn rollback(&self) -> Result<(), PostgresWriteError> {
+        let result = self.cn.batch_execute("ROLLBACK").await;
+        self.in_transaction.store(false, Ordering::Relaxed);
+        Ok(result?)
+    }
+
+    /// Like [`DatabaseConnection::select_all`], but duplicate
+    /// rows are removed with `SELECT DISTINCT`.
+    pub async fn select_distinct<T: FromPostgres + PostgresReadable + PostgresTable + 'static>(
+        &self,
+        query: &str,
+        args: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<T>, PostgresReadError> {
+        self.check_read_scope(T::table_name())?;
+        let select = Self::select_query::<T>(query).replacen("SELECT ", "SELECT DISTINCT ", 1);
+        if self.debug {
+            println!("[DEBUG: QUERY] (select_distinct) {}", select);
+            println!("[DEBUG: ARGS] (select_distinct) Args: {:?}", args);
+        }
+        Ok(self
+            .run(&select, args)
+            .await?
+            .iter()
+            .map(|x| T::try_from_postgres(x))
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Like [`DatabaseConnection::select_all`], for types with
+    /// expression fields which take arguments. `field_args` are
+    /// bound to the expressions' `$1`, `$2`, ... placeholders.
+    pub async fn select_all_with<T: FromPostgres + PostgresReadable + PostgresTable + 'static>(
+        &self,
+        query: &str,
+        args: &[&(dyn ToSql + Sync)],
+        field_args: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<T>, PostgresReadError> {
+        self.check_read_scope(T::table_name())?;
+        let select = Self::select_query_with::<T>(query, args.len());
+        let args = [args, field_args].concat();
+        if self.debug {
+            println!("[DEBUG: QUERY] (select_all_with) {}", select);
+            println!("[DEBUG: ARGS] (select_all_with) Args: {:?}", args);
+        }
+        Ok(self
+            .run(&select, &args)
             .await?
             .iter()
-            .map(|x| T::from_postgres(x))
-            .next()
-            .ok_or_else(|| PostgresReadError::NoResults)
+            .map(|x| T::try_from_postgres(x))
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Like [`DatabaseConnection::select_one`], for types with
+    /// expression fields which take arguments. `field_args` are
+    /// bound to the expressions' `$1`, `$2`, ... placeholders.
+    pub async fn select_one_with<T: FromPostgres + PostgresReadable + PostgresTable + 'static>(
+        &self,
+        query: &str,
+        args: &[&(dyn ToSql + Sync)],
+        field_args: &[&(dyn ToSql + Sync)],
+    ) -> Result<T, PostgresReadError> {
+        self.check_read_scope(T::table_name())?;
+        let select = Self::select_query_with::<T>(query, args.len());
+        let args = [args, field_args].concat();
+        if self.debug {
+            println!("[DEBUG: QUERY] (select_one_with) {}", select);
+            println!("[DEBUG: ARGS] (select_one_with) Args: {:?}", args);
+        }
+        self
+            .run(&select, &args)
+            .await?
+            .first()
+            .ok_or(PostgresReadError::NoResults)
+            .and_then(|x| Ok(T::try_from_postgres(x)?))
+    }
+
+    /// Select rows in batches, for result sets too large to
+    /// hold in memory at once. Rows are fetched through a portal,
+    /// [`crate::DatabaseConfig::fetch_size`] at a time, and each
+    /// batch is passed to `batch`.
+    ///
+    /// The portal is read in a transaction of its own, or inside
+    /// the one from [`DatabaseConnection::begin`] if it's open,
+    /// which is left open.
+    ///
+    /// The same buffer is reused for every batch and is cleared
+    /// before it is filled, so take ownership of rows with
+    /// `drain` if they need to outlive the callback.
+    pub async fn select_batched<T, F>(
+        &mut self,
+        query: &str,
+        args: &[&(dyn ToSql + Sync)],
+        mut batch: F,
+    ) -> Result<(), PostgresReadError>
+    where
+        T: FromPostgres + PostgresReadable + PostgresTable + 'static,
+        F: FnMut(&mut Vec<T>),
+    {
+        self.check_read_scope(T::table_name())?;
+        let select = Self::select_query::<T>(query);
+        if self.debug {
+            println!("[DEBUG: QUERY] (select_batched) {}", select);
+            println!("[DEBUG: ARGS] (select_batched) Args: {:?}", args);
+        }
+        let fetch_size = self.fetch_size;
+        let mut buffer = Vec::with_capacity(fetch_size as usize);
+        if self.in_transaction.load(Ordering::Relaxed) {
+            // Inside DatabaseConnection::begin, so the rows are read
+            // through a cursor in that transaction, leaving it open.
+            self.cn
+                .execute(&format!("DECLARE tusk_batched NO SCROLL CURSOR FOR {}", select), args)
+                .await?;
+            let fetch = format!("FETCH {} FROM tusk_batched", fetch_size);
+            let read = async {
+                loop {
+                    let rows = self.cn.query(&fetch, &[]).await?;
+                    if Self::next_batch(&rows, &mut buffer, &mut batch, fetch_size)? {
+                        return Ok::<(), PostgresReadError>(());
+                    }
+                }
+            }
+            .await;
+            let closed = self.cn.batch_execute("CLOSE tusk_batched").await;
+            read?;
+            closed?;
+            return Ok(());
+        }
+        // Portals only live as long as their transaction.
+        let settings = self.local_settings.lock().unwrap_or_else(|x| x.into_inner()).clone();
+        let transaction = self.cn.transaction().await?;
+        if !settings.is_empty() {
+            transaction.batch_execute(&settings).await?;
+        }
+        let portal = transaction.bind(select.as_str(), args).await?;
+        loop {
+            let rows = transaction.query_portal(&portal, fetch_size).await?;
+            if Self::next_batch(&rows, &mut buffer, &mut batch, fetch_size)? {
+                break;
+            }
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Converts `rows` into `buffer` and passes it to `batch`,
+    /// returning whether they were the last.
+    fn next_batch<T: FromPostgres, F: FnMut(&mut Vec<T>)>(
+        rows: &[Row],
+        buffer: &mut Vec<T>,
+        batch: &mut F,
+        fetch_size: i32,
+    ) -> Result<bool, PostgresReadError> {
+        if rows.is_empty() {
+            return Ok(true);
+        }
+        buffer.clear();
+        for row in rows {
+            buffer.push(T::try_from_postgres(row)?);
+        }
+        batch(buffer);
+        Ok(rows.len() < fetch_size as usize)
+    }
+
+    /// Like [`DatabaseConnection::select_query`], but expression
+    /// arguments are numbered after the query's `arg_count` arguments.
+    fn select_query_with<T: PostgresReadable + PostgresTable + 'static>(query: &str, arg_count: usize) -> String {
+        let prefix = offset_placeholders(&select_prefix::<T>(), arg_count);
+        let mut select = String::with_capacity(prefix.len() + query.len() + 1);
+        select.push_str(&prefix);
+        select.push(' ');
+        select.push_str(query);
+        select
+    }
+
+    /// Appends the where clause to the cached select prefix.
+    pub(crate) fn select_query<T: PostgresReadable + PostgresTable + 'static>(query: &str) -> String {
+        Self::with_prefix(select_prefix::<T>(), query)
+    }
+
+    fn with_prefix(prefix: Rc<str>, query: &str) -> String {
+        let mut select = String::with_capacity(prefix.len() + query.len() + 1);
+        select.push_str(&prefix);
+        select.push(' ');
+        select.push_str(query);
+        select
     }
 
     pub async fn insert<T: FromPostgres + PostgresTable + PostgresReadFields>(
         &self,
         write: PostgresWrite,
     ) -> Result<T, PostgresWriteError> {
+        self.insert_ref(write.as_write_ref()).await
+    }
+
+    /// Like [`DatabaseConnection::insert`], but writes borrowed
+    /// values from [`crate::PostgresWriteableRef::write_ref`].
+    pub async fn insert_ref<T: FromPostgres + PostgresTable + PostgresReadFields>(
+        &self,
+        write: PostgresWriteRef<'_>,
+    ) -> Result<T, PostgresWriteError> {
+        self.check_write_scope(T::table_name())?;
         let (insert_q, insert_a) = write.into_insert(T::table_name());
+        let insert_q = Self::insert_query::<T>(&insert_q);
         if self.debug {
-            println!("[DEBUG: QUERY] (insert) {} RETURNING {}", insert_q, T::read_fields().as_syntax(T::table_name()));
+            println!("[DEBUG: QUERY] (insert) {}", insert_q);
             println!("[DEBUG: ARGS] (insert) Args: {:?}", insert_a);
         }
-        Ok(self
-            .cn
-            .query(&format!("{} RETURNING {}", insert_q, T::read_fields().as_syntax(T::table_name())), insert_a.as_slice())
-            .await?
-            .iter()
-            .map(|x| T::from_postgres(x))
-            .next()
-            .unwrap())
+        let rows = self.run(&insert_q, insert_a.as_slice()).await?;
+        let row = rows.first().ok_or(PostgresWriteError::NoRows)?;
+        Ok(T::try_from_postgres(row)?)
+    }
+
+    /// Appends the returned fields to an insert statement.
+    pub(crate) fn insert_query<T: PostgresTable + PostgresReadFields>(insert: &str) -> String {
+        format!("{} RETURNING {}", insert, T::read_fields().as_syntax(T::table_name()))
     }
 
     pub async fn insert_vec<T: FromPostgres + PostgresTable + PostgresReadable>(
         &self,
         write: PostgresWrite,
     ) -> Result<Vec<T>, PostgresWriteError> {
+        self.check_write_scope(T::table_name())?;
         let (insert_q, insert_a) = write.into_bulk_insert(T::table_name());
         if insert_a.is_empty() {
             return Err(PostgresWriteError::NoRows);
@@ -232,12 +841,11 @@ impl DatabaseConnection {
             println!("[DEBUG: ARGS] (insert_vec) Args: {:?}", insert_a);
         }
         Ok(self
-            .cn
-            .query(&format!("WITH {} AS ({} RETURNING *) SELECT {} FROM {} {}", temp_table, insert_q, T::read_fields().as_syntax(&temp_table), temp_table, join_str), insert_a.as_slice())
+            .run(&format!("WITH {} AS ({} RETURNING *) SELECT {} FROM {} {}", temp_table, insert_q, T::read_fields().as_syntax(&temp_table), temp_table, join_str), insert_a.as_slice())
             .await?
             .iter()
-            .map(|x| T::from_postgres(x))
-            .collect())
+            .map(|x| T::try_from_postgres(x))
+            .collect::<Result<Vec<_>, _>>()?)
     }
 
     pub async fn update<T: FromPostgres + PostgresTable + PostgresReadable + std::fmt::Debug>(
@@ -246,30 +854,119 @@ impl DatabaseConnection {
         condition: &str,
         args: &[&(dyn ToSql + Sync)],
     ) -> Result<T, PostgresWriteError> {
+        self.update_ref(write.as_write_ref(), condition, args).await
+    }
+
+    /// Like [`DatabaseConnection::update`], but writes borrowed
+    /// values from [`crate::PostgresWriteableRef::write_ref`].
+    pub async fn update_ref<T: FromPostgres + PostgresTable + PostgresReadable + std::fmt::Debug>(
+        &self,
+        write: PostgresWriteRef<'_>,
+        condition: &str,
+        args: &[&(dyn ToSql + Sync)],
+    ) -> Result<T, PostgresWriteError> {
+        let outcome = self.update_returning::<T>(write, condition, args).await?;
+        outcome.returned.into_iter().next().ok_or(PostgresWriteError::NoRows)
+    }
+
+    /// Updates every row matching `condition`, returning all
+    /// updated rows and how many there were. Use
+    /// [`PostgresWrite::as_write_ref`] to pass an owned write.
+    pub async fn update_returning<T: FromPostgres + PostgresTable + PostgresReadable>(
+        &self,
+        write: PostgresWriteRef<'_>,
+        condition: &str,
+        args: &[&(dyn ToSql + Sync)],
+    ) -> Result<WriteOutcome<T>, PostgresWriteError> {
+        self.check_write_scope(T::table_name())?;
         let temp_table = format!("write_{}", T::table_name());
         let (insert_q, insert_a) = write.into_update(T::table_name(), args.len());
+        let query = format!(
+            "WITH {} AS ({} WHERE {} RETURNING *) {}",
+            temp_table,
+            insert_q,
+            condition,
+            Self::returning_query::<T>(&temp_table)
+        );
         if self.debug {
-            println!("[DEBUG: QUERY] (update) WITH {} AS ({} WHERE {} RETURNING *) SELECT {} FROM {} {}", temp_table, insert_q, condition, T::read_fields().as_syntax(&temp_table), temp_table, T::joins().as_syntax(&temp_table));
+            println!("[DEBUG: QUERY] (update) {}", query);
             println!("[DEBUG: ARGS] (update) Args: {:?}", [args, insert_a.as_slice()].concat());
         }
-        let next = self
-            .cn
-            .query(
-                &format!("WITH {} AS ({} WHERE {} RETURNING *) SELECT {} FROM {} {}",
-                    temp_table,
-                    insert_q,
-                    condition,
-                    T::read_fields().as_syntax(&temp_table),
-                    temp_table,
-                    T::joins().as_syntax(&temp_table)
-                ),
-                [args, insert_a.as_slice()].concat().as_slice(),
-            )
-            .await?
-            .iter()
-            .map(|x| T::from_postgres(x))
-            .collect::<Vec<_>>();
-        Ok(next.into_iter().next().unwrap())
+        let rows = self
+            .run(&query, [args, insert_a.as_slice()].concat().as_slice())
+            .await?;
+        Ok(WriteOutcome::from_rows(&rows)?)
+    }
+
+    /// Selects the rows written into `temp_table`, alongside how
+    /// many there were, so rows a join leaves out or repeats don't
+    /// change the count. Read with [`WriteOutcome::from_rows`].
+    pub(crate) fn returning_query<T: PostgresReadable + PostgresTable>(temp_table: &str) -> String {
+        format!(
+            "SELECT tusk_written.count AS tusk_rows_affected, tusk_returned.* FROM (SELECT count(*) FROM {}) tusk_written \
+            LEFT JOIN (SELECT true AS tusk_returned, {} FROM {} {}) tusk_returned ON true",
+            temp_table,
+            T::read_fields().as_syntax(temp_table),
+            temp_table,
+            T::joins().as_syntax(temp_table)
+        )
+    }
+
+    /// Updates the row matching `condition` if its version is still
+    /// `version`, moving it to the next version. Fails with
+    /// [`PostgresWriteError::Conflict`] if the row was changed in
+    /// the meantime, and [`PostgresWriteError::NoRows`] if nothing
+    /// matches `condition` at all.
+    ///
+    /// ```ignore
+    /// match db.update_versioned::<Note>(note.write_ref(), "id = $1", &[&id], &note.version).await {
+    ///     Err(PostgresWriteError::Conflict) => Err(RouteError::conflict("Note was edited by someone else.")),
+    ///     ...
+    /// }
+    /// ```
+    pub async fn update_versioned<T: FromPostgres + PostgresVersioned + PostgresReadable>(
+        &self,
+        write: PostgresWriteRef<'_>,
+        condition: &str,
+        args: &[&(dyn ToSql + Sync)],
+        version: &(dyn ToSql + Sync),
+    ) -> Result<T, PostgresWriteError> {
+        self.check_write_scope(T::table_name())?;
+        let table = T::table_name();
+        let temp_table = format!("write_{}", table);
+        let (update_q, update_a) = write.into_update(table, args.len());
+        let update_args = [args, update_a.as_slice(), &[version]].concat();
+        let query = format!(
+            "WITH {} AS ({}, {} = {} WHERE ({}) AND {}.{} = ${} RETURNING *) SELECT {} FROM {} {}",
+            temp_table,
+            update_q,
+            T::version_column(),
+            T::next_version(),
+            condition,
+            table,
+            T::version_column(),
+            update_args.len(),
+            T::read_fields().as_syntax(&temp_table),
+            temp_table,
+            T::joins().as_syntax(&temp_table)
+        );
+        if self.debug {
+            println!("[DEBUG: QUERY] (update_versioned) {}", query);
+            println!("[DEBUG: ARGS] (update_versioned) Args: {:?}", update_args);
+        }
+        if let Some(row) = self.run(&query, &update_args).await?.first() {
+            return Ok(T::try_from_postgres(row)?);
+        }
+        // Nothing was written, either because the row is gone
+        // or because its version moved on.
+        let exists = self
+            .run(&format!("SELECT 1 FROM {} WHERE {}", table, condition), args)
+            .await?;
+        if exists.is_empty() {
+            Err(PostgresWriteError::NoRows)
+        } else {
+            Err(PostgresWriteError::Conflict)
+        }
     }
 
     pub async fn update_set<T: FromPostgres + PostgresTable + PostgresReadable>(
@@ -277,14 +974,14 @@ impl DatabaseConnection {
         query: &str,
         args: &[&(dyn ToSql + Sync)],
     ) -> Result<T, PostgresWriteError> {
+        self.check_write_scope(T::table_name())?;
         let temp_table = format!("write_{}", T::table_name());
         if self.debug {
             println!("[DEBUG: QUERY] (update_set) WITH {} AS (UPDATE {} SET {} RETURNING *) SELECT {} FROM {} {}", temp_table, T::table_name(), query, T::read_fields().as_syntax(&temp_table), temp_table, T::joins().as_syntax(&temp_table));
             println!("[DEBUG: ARGS] (update_set) Args: {:?}", args);
         }
-        Ok(self
-            .cn
-            .query(
+        let rows = self
+            .run(
                 &format!("with {} as (update {} set {} returning *) select {} from {} {}",
                     temp_table,
                     T::table_name(),
@@ -295,22 +992,72 @@ impl DatabaseConnection {
                 ),
                 args,
             )
-            .await?
-            .iter()
-            .map(|x| T::from_postgres(x))
-            .next()
-            .unwrap())
+            .await?;
+        let row = rows.first().ok_or(PostgresWriteError::NoRows)?;
+        Ok(T::try_from_postgres(row)?)
     }
 
-    pub async fn delete<T: PostgresTable>(&self, condition: &str, args: &[&(dyn ToSql + Sync)]) {
+    /// Deletes the rows matching `condition`, returning how many
+    /// were deleted, so a handler can respond with a 404 when
+    /// nothing matched.
+    pub async fn delete<T: PostgresTable>(
+        &self,
+        condition: &str,
+        args: &[&(dyn ToSql + Sync)],
+    ) -> Result<u64, PostgresWriteError> {
+        self.check_write_scope(T::table_name())?;
         if self.debug {
             println!("[DEBUG: QUERY] (delete) DELETE FROM {} {}", T::table_name(), condition);
             println!("[DEBUG: ARGS] (delete) Args: {:?}", args);
         }
-        let _ = self.cn.query(
-            &format!("DELETE FROM {} {}", T::table_name(), condition),
-            args,
-        ).await;
+        let delete = format!("DELETE FROM {} {}", T::table_name(), condition);
+        Ok(self.pinned(self.cn.execute(&delete, args)).await?)
+    }
+
+    /// Deletes the rows matching `condition`, returning
+    /// the deleted rows and how many there were.
+    pub async fn delete_returning<T: FromPostgres + PostgresTable + PostgresReadable>(
+        &self,
+        condition: &str,
+        args: &[&(dyn ToSql + Sync)],
+    ) -> Result<WriteOutcome<T>, PostgresWriteError> {
+        self.check_write_scope(T::table_name())?;
+        let temp_table = format!("write_{}", T::table_name());
+        let query = format!(
+            "WITH {} AS (DELETE FROM {} {} RETURNING *) {}",
+            temp_table,
+            T::table_name(),
+            condition,
+            Self::returning_query::<T>(&temp_table)
+        );
+        if self.debug {
+            println!("[DEBUG: QUERY] (delete_returning) {}", query);
+            println!("[DEBUG: ARGS] (delete_returning) Args: {:?}", args);
+        }
+        let rows = self.run(&query, args).await?;
+        Ok(WriteOutcome::from_rows(&rows)?)
+    }
+}
+
+/// The result of a write which returns rows.
+#[derive(Debug)]
+pub struct WriteOutcome<T> {
+    /// The number of rows written or deleted.
+    pub rows_affected: u64,
+    /// The affected rows, as they were returned by Postgres.
+    pub returned: Vec<T>,
+}
+impl<T: FromPostgres> WriteOutcome<T> {
+    /// Reads the rows of a [`DatabaseConnection::returning_query`].
+    /// With nothing returned, a single row holds only the count.
+    pub(crate) fn from_rows(rows: &[Row]) -> Result<WriteOutcome<T>, FromPostgresError> {
+        let rows_affected = rows.first().map_or(0, |x| x.get::<_, i64>("tusk_rows_affected")) as u64;
+        let returned = rows
+            .iter()
+            .filter(|x| x.get::<_, Option<bool>>("tusk_returned").is_some())
+            .map(|x| T::try_from_postgres(x))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(WriteOutcome { rows_affected, returned })
     }
 }
 
@@ -319,3 +1066,8 @@ pub enum DatabaseError {
     ForeignKey(String),
     NoResults,
 }
+
+/// Quotes `value` as an SQL string literal.
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
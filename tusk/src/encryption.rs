@@ -0,0 +1,255 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    ops::Deref,
+    sync::{Arc, RwLock},
+};
+
+use brackets::ToJson;
+use bytes::{BufMut, BytesMut};
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use tokio_postgres::types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
+
+use crate::{json::json_string, DatabaseConnection};
+
+/// The keys installed with [`EncryptionKeys::install`].
+static INSTALLED: RwLock<Option<Arc<EncryptionKeys>>> = RwLock::new(None);
+
+/// Errors that can occur while encrypting or decrypting a value.
+#[derive(Debug)]
+pub enum EncryptionError {
+    /// No keys were installed with [`EncryptionKeys::install`].
+    NotConfigured,
+    /// The value was encrypted with a key that isn't installed.
+    /// Syntax is (key id).
+    UnknownKey(u32),
+    /// The value is malformed, or was tampered with.
+    Invalid,
+    Crypto(openssl::error::ErrorStack),
+    Database(tokio_postgres::Error),
+}
+impl Display for EncryptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncryptionError::NotConfigured => write!(f, "no encryption keys are installed"),
+            EncryptionError::UnknownKey(id) => write!(f, "encryption key {} is not installed", id),
+            EncryptionError::Invalid => write!(f, "encrypted value is malformed or was tampered with"),
+            EncryptionError::Crypto(err) => write!(f, "{}", err),
+            EncryptionError::Database(err) => write!(f, "{}", err),
+        }
+    }
+}
+impl Error for EncryptionError {}
+impl From<openssl::error::ErrorStack> for EncryptionError {
+    fn from(value: openssl::error::ErrorStack) -> Self {
+        EncryptionError::Crypto(value)
+    }
+}
+impl From<tokio_postgres::Error> for EncryptionError {
+    fn from(value: tokio_postgres::Error) -> Self {
+        EncryptionError::Database(value)
+    }
+}
+
+/// The AES-256 keys used by [`Encrypted`] columns, each with an
+/// id which is stored alongside the values it encrypts.
+///
+/// Values are always encrypted with the current key, and can be
+/// decrypted with any installed key. To rotate keys, install a
+/// new current key with the old one added using
+/// [`EncryptionKeys::previous`], then re-encrypt existing values
+/// with [`EncryptionKeys::rotate`].
+///
+/// ```ignore
+/// server.encryption_keys(EncryptionKeys::new(2, &new_key).previous(1, &old_key));
+/// ```
+#[derive(Clone)]
+pub struct EncryptionKeys {
+    current: u32,
+    keys: HashMap<u32, [u8; 32]>,
+}
+impl EncryptionKeys {
+    const VERSION: u8 = 1;
+    const HEADER_LEN: usize = 5;
+    const NONCE_LEN: usize = 12;
+    const TAG_LEN: usize = 16;
+
+    /// Use `key` to encrypt values.
+    ///
+    /// Panics if `key` isn't 32 bytes long.
+    pub fn new(id: u32, key: &[u8]) -> EncryptionKeys {
+        EncryptionKeys {
+            current: id,
+            keys: HashMap::from([(id, Self::key(key))]),
+        }
+    }
+
+    /// Add an older key, which is only used to decrypt values.
+    /// Can be chained.
+    ///
+    /// Panics if `key` isn't 32 bytes long.
+    pub fn previous(mut self, id: u32, key: &[u8]) -> EncryptionKeys {
+        self.keys.entry(id).or_insert(Self::key(key));
+        self
+    }
+
+    fn key(key: &[u8]) -> [u8; 32] {
+        key.try_into().expect("Encryption keys must be 32 bytes long!")
+    }
+
+    /// Use these keys for every [`Encrypted`] value read or written
+    /// by this process, replacing any installed before.
+    pub fn install(self) {
+        *INSTALLED.write().unwrap() = Some(Arc::new(self));
+    }
+
+    /// The keys installed with [`EncryptionKeys::install`].
+    pub fn installed() -> Result<Arc<EncryptionKeys>, EncryptionError> {
+        INSTALLED.read().unwrap().clone().ok_or(EncryptionError::NotConfigured)
+    }
+
+    /// Encrypts `plaintext` with the current key.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        let mut header = vec![Self::VERSION];
+        header.extend_from_slice(&self.current.to_be_bytes());
+        let mut nonce = [0u8; Self::NONCE_LEN];
+        openssl::rand::rand_bytes(&mut nonce)?;
+        let mut tag = [0u8; Self::TAG_LEN];
+        let ciphertext = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.keys[&self.current],
+            Some(&nonce),
+            &header,
+            plaintext,
+            &mut tag,
+        )?;
+        let mut output = header;
+        output.extend_from_slice(&nonce);
+        output.extend_from_slice(&tag);
+        output.extend_from_slice(&ciphertext);
+        Ok(output)
+    }
+
+    /// Decrypts a value written by [`EncryptionKeys::encrypt`].
+    pub fn decrypt(&self, value: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        let id = Self::key_id(value)?;
+        let key = self.keys.get(&id).ok_or(EncryptionError::UnknownKey(id))?;
+        let (header, rest) = value.split_at(Self::HEADER_LEN);
+        let (nonce, rest) = rest.split_at(Self::NONCE_LEN);
+        let (tag, ciphertext) = rest.split_at(Self::TAG_LEN);
+        decrypt_aead(Cipher::aes_256_gcm(), key, Some(nonce), header, ciphertext, tag)
+            .map_err(|_| EncryptionError::Invalid)
+    }
+
+    /// The id of the key a value was encrypted with.
+    pub fn key_id(value: &[u8]) -> Result<u32, EncryptionError> {
+        if value.len() < Self::HEADER_LEN + Self::NONCE_LEN + Self::TAG_LEN || value[0] != Self::VERSION {
+            return Err(EncryptionError::Invalid);
+        }
+        Ok(u32::from_be_bytes(value[1..Self::HEADER_LEN].try_into().unwrap()))
+    }
+
+    /// Whether a value was encrypted with an older key.
+    pub fn needs_rotation(&self, value: &[u8]) -> bool {
+        Self::key_id(value).is_ok_and(|id| id != self.current)
+    }
+
+    /// Re-encrypts the values of `column` in `table` which were
+    /// encrypted with an older key, identifying rows by
+    /// `id_column`. Returns the number of values re-encrypted.
+    ///
+    /// Rows are updated in batches, so this can run while the
+    /// table is in use. Once it returns, older keys can be removed.
+    pub async fn rotate(
+        &self,
+        db: &DatabaseConnection,
+        table: &str,
+        id_column: &str,
+        column: &str,
+    ) -> Result<u64, EncryptionError> {
+        const BATCH: i64 = 500;
+        let current = self.current.to_be_bytes().to_vec();
+        let mut rotated = 0;
+        loop {
+            let rows = db
+                .query(
+                    format!(
+                        "SELECT {id_column}::text, {column} FROM {table} WHERE substring({column} from 2 for 4) <> $1 LIMIT $2"
+                    ),
+                    &[&current, &BATCH],
+                )
+                .await?;
+            for row in rows.iter() {
+                let id: String = row.get(0);
+                let value = self.encrypt(&self.decrypt(row.get(1))?)?;
+                db.query(
+                    format!("UPDATE {table} SET {column} = $1 WHERE {id_column}::text = $2"),
+                    &[&value, &id],
+                )
+                .await?;
+            }
+            rotated += rows.len() as u64;
+            if (rows.len() as i64) < BATCH {
+                return Ok(rotated);
+            }
+        }
+    }
+}
+
+/// A column value which is encrypted at rest, using the keys
+/// installed with [`EncryptionKeys::install`]. Stored in Postgres
+/// as `bytea`; in Rust, it's the plain value.
+///
+/// Encrypted values can't be searched or sorted in SQL. The
+/// value is written in full by `ToJson`, so mark fields with
+/// `#[tusk(redact)]` where it shouldn't be sent to clients.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Encrypted<T>(pub T);
+impl<T> Deref for Encrypted<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+impl<T> From<T> for Encrypted<T> {
+    fn from(value: T) -> Self {
+        Encrypted(value)
+    }
+}
+impl<T> std::fmt::Debug for Encrypted<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Encrypted(..)")
+    }
+}
+
+impl ToSql for Encrypted<String> {
+    fn to_sql(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        out.put_slice(&EncryptionKeys::installed()?.encrypt(self.0.as_bytes())?);
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::BYTEA
+    }
+
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for Encrypted<String> {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Encrypted<String>, Box<dyn Error + Sync + Send>> {
+        let plaintext = EncryptionKeys::installed()?.decrypt(raw)?;
+        Ok(Encrypted(String::from_utf8(plaintext)?))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::BYTEA
+    }
+}
+
+impl ToJson for Encrypted<String> {
+    fn to_json(&self) -> String {
+        json_string(&self.0)
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use crate::{database::PostgresReadError, ApiKey, DatabaseConnection, Request};
+
+/// The stable id used for percentage rollouts. Place this
+/// in the request's extensions (usually in a treatment function)
+/// so a user consistently sees the same variant.
+///
+/// If no subject is set, the identity of a resolved [`ApiKey`]
+/// is used instead.
+#[derive(Debug, Clone)]
+pub struct FeatureSubject(pub String);
+
+/// A feature flag as stored in Postgres.
+#[derive(Debug, Clone)]
+pub struct FeatureFlag {
+    pub name: String,
+    pub enabled: bool,
+    /// The percentage of subjects, from 0 to 100,
+    /// who see this feature while it is enabled.
+    pub rollout_percent: i32,
+}
+impl FeatureFlag {
+    /// Whether the flag is on for a subject. Without a subject,
+    /// only fully rolled out flags are on.
+    pub fn is_enabled_for(&self, subject: Option<&str>) -> bool {
+        if !self.enabled || self.rollout_percent <= 0 {
+            return false;
+        }
+        if self.rollout_percent >= 100 {
+            return true;
+        }
+        match subject {
+            Some(subject) => {
+                let hash = openssl::sha::sha256(format!("{}:{}", self.name, subject).as_bytes());
+                let bucket = u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]) % 100;
+                (bucket as i32) < self.rollout_percent
+            }
+            None => false,
+        }
+    }
+}
+
+struct FlagCache {
+    flags: HashMap<String, FeatureFlag>,
+    loaded_at: Option<Instant>,
+}
+
+/// Feature flags backed by a Postgres table and cached in memory.
+///
+/// Register the flags using [`crate::Server::feature_flags`]; the
+/// cache is refreshed from the database once it is older than the TTL.
+/// Check flags from handlers with [`Request::feature`].
+#[derive(Clone)]
+pub struct FeatureFlags {
+    table: &'static str,
+    ttl: Duration,
+    cache: Rc<RefCell<FlagCache>>,
+}
+impl FeatureFlags {
+    /// Create flags backed by `table`, refreshed every 30 seconds.
+    pub fn new(table: &'static str) -> FeatureFlags {
+        FeatureFlags {
+            table,
+            ttl: Duration::from_secs(30),
+            cache: Rc::new(RefCell::new(FlagCache {
+                flags: HashMap::new(),
+                loaded_at: None,
+            })),
+        }
+    }
+
+    /// Define how long flags are cached. Can be chained.
+    pub fn ttl(mut self, ttl: Duration) -> FeatureFlags {
+        self.ttl = ttl;
+        self
+    }
+
+    /// The SQL needed to create the backing table.
+    pub fn create_table_sql(&self) -> String {
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                name TEXT PRIMARY KEY,
+                enabled BOOLEAN NOT NULL DEFAULT false,
+                rollout_percent INTEGER NOT NULL DEFAULT 100
+            )",
+            self.table
+        )
+    }
+
+    /// Whether the cache has expired.
+    pub fn needs_refresh(&self) -> bool {
+        self.cache
+            .borrow()
+            .loaded_at
+            .is_none_or(|x| x.elapsed() >= self.ttl)
+    }
+
+    /// Reload all flags from the database.
+    pub async fn refresh(&self, db: &DatabaseConnection) -> Result<(), PostgresReadError> {
+        let flags = db
+            .query(
+                format!("SELECT name, enabled, rollout_percent FROM {}", self.table),
+                &[],
+            )
+            .await?
+            .iter()
+            .map(|row| {
+                let flag = FeatureFlag {
+                    name: row.get("name"),
+                    enabled: row.get("enabled"),
+                    rollout_percent: row.get("rollout_percent"),
+                };
+                (flag.name.clone(), flag)
+            })
+            .collect();
+        let mut cache = self.cache.borrow_mut();
+        cache.flags = flags;
+        cache.loaded_at = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Get a cached flag.
+    pub fn get(&self, name: &str) -> Option<FeatureFlag> {
+        self.cache.borrow().flags.get(name).cloned()
+    }
+
+    /// Whether a flag is on for a subject. Unknown flags are off.
+    pub fn is_enabled(&self, name: &str, subject: Option<&str>) -> bool {
+        self.cache
+            .borrow()
+            .flags
+            .get(name)
+            .is_some_and(|x| x.is_enabled_for(subject))
+    }
+}
+
+impl Request {
+    /// Whether a feature flag is on for this request.
+    ///
+    /// The subject for percentage rollouts is taken from
+    /// [`FeatureSubject`] or a resolved [`ApiKey`]. Returns `false`
+    /// if no [`FeatureFlags`] were registered with the server.
+    pub fn feature(&self, name: &str) -> bool {
+        let Some(flags) = self.services.get::<FeatureFlags>() else {
+            return false;
+        };
+        let subject = self
+            .extensions
+            .get::<FeatureSubject>()
+            .map(|x| x.0.as_str())
+            .or_else(|| self.extensions.get::<ApiKey>().map(|x| x.identity.as_str()));
+        flags.is_enabled(name, subject)
+    }
+}
@@ -0,0 +1,340 @@
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+
+use crate::{
+    apikey::to_hex, auth::encode_base64_url, Request, Response, RouteError, UrlEncoded,
+};
+
+/// Appends `value` to `output`, escaped for HTML text and
+/// quoted attribute values.
+pub fn write_html_escaped(output: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            c => output.push(c),
+        }
+    }
+}
+
+/// Returns `value` escaped for HTML.
+pub fn escape_html(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    write_html_escaped(&mut output, value);
+    output
+}
+
+/// Protects forms against cross-site request forgery, using
+/// signed double-submit tokens.
+///
+/// Each browser gets a random value in a cookie, and forms carry
+/// an HMAC of it as a hidden field. Another site can make the
+/// browser send the cookie, but can't read it to produce the
+/// matching field.
+///
+/// ```ignore
+/// let token = csrf.token(&req);
+/// let page = Response::html(form.csrf(&token).render().into_bytes());
+/// Ok(token.apply(page))
+/// // When the form is posted:
+/// csrf.verify(&req, req.body.url_encoded()?)?;
+/// ```
+#[derive(Clone)]
+pub struct CsrfProtection {
+    secret: Vec<u8>,
+    cookie: String,
+}
+impl CsrfProtection {
+    /// The form field holding the token.
+    pub const FIELD: &'static str = "_csrf";
+
+    /// Create a protection signing tokens with `secret`,
+    /// which should be at least 32 random bytes.
+    pub fn new(secret: &[u8]) -> CsrfProtection {
+        CsrfProtection {
+            secret: secret.to_vec(),
+            cookie: "tusk_csrf".to_string(),
+        }
+    }
+
+    /// Define the cookie holding each browser's value.
+    /// Defaults to `tusk_csrf`. Can be chained.
+    pub fn cookie(mut self, name: &str) -> CsrfProtection {
+        self.cookie = name.to_string();
+        self
+    }
+
+    /// The token for forms sent in response to `request`.
+    pub fn token(&self, request: &Request) -> CsrfToken {
+        match request.cookie(&self.cookie).filter(|x| !x.is_empty()) {
+            Some(value) => CsrfToken {
+                value: self.sign(&value),
+                cookie: None,
+            },
+            None => {
+                let mut bytes = [0u8; 32];
+                openssl::rand::rand_bytes(&mut bytes).expect("Could not generate random bytes!");
+                let value = encode_base64_url(&bytes);
+                CsrfToken {
+                    value: self.sign(&value),
+                    cookie: Some(format!(
+                        "{}={}; Path=/; HttpOnly; Secure; SameSite=Lax",
+                        self.cookie, value
+                    )),
+                }
+            }
+        }
+    }
+
+    /// Checks the token posted with a form, responding with
+    /// a 403 if it's missing or doesn't match.
+    pub fn verify(&self, request: &Request, form: &UrlEncoded) -> Result<(), RouteError> {
+        let forbidden = || RouteError::forbidden("Invalid CSRF token. Reload the page and try again.");
+        let cookie = request.cookie(&self.cookie).ok_or_else(forbidden)?;
+        let sent: String = form.get(Self::FIELD).ok_or_else(forbidden)?;
+        let expected = self.sign(&cookie);
+        if sent.len() != expected.len() || !openssl::memcmp::eq(sent.as_bytes(), expected.as_bytes()) {
+            return Err(forbidden());
+        }
+        Ok(())
+    }
+
+    fn sign(&self, value: &str) -> String {
+        let key = PKey::hmac(&self.secret).unwrap();
+        let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+        signer.update(value.as_bytes()).unwrap();
+        to_hex(&signer.sign_to_vec().unwrap())
+    }
+}
+
+/// A CSRF token, from [`CsrfProtection::token`].
+#[derive(Debug, Clone)]
+pub struct CsrfToken {
+    pub value: String,
+    /// The `Set-Cookie` value for browsers without a cookie yet.
+    cookie: Option<String>,
+}
+impl CsrfToken {
+    /// Sets the cookie the token is checked against, if the
+    /// browser doesn't have one yet.
+    pub fn apply(&self, response: Response) -> Response {
+        match &self.cookie {
+            Some(cookie) => response.append_header("Set-Cookie", cookie),
+            None => response,
+        }
+    }
+}
+
+/// One input of a [`Form`].
+#[derive(Debug, Clone)]
+pub struct FormField {
+    name: String,
+    label: String,
+    kind: &'static str,
+    value: String,
+    required: bool,
+    error: Option<String>,
+}
+impl FormField {
+    /// An `<input>` of any `type`, such as `date`.
+    pub fn input(kind: &'static str, name: &str, label: &str) -> FormField {
+        FormField {
+            name: name.to_string(),
+            label: label.to_string(),
+            kind,
+            value: String::new(),
+            required: false,
+            error: None,
+        }
+    }
+
+    pub fn text(name: &str, label: &str) -> FormField {
+        Self::input("text", name, label)
+    }
+
+    pub fn email(name: &str, label: &str) -> FormField {
+        Self::input("email", name, label)
+    }
+
+    /// A password, which is never rendered with its value.
+    pub fn password(name: &str, label: &str) -> FormField {
+        Self::input("password", name, label)
+    }
+
+    pub fn number(name: &str, label: &str) -> FormField {
+        Self::input("number", name, label)
+    }
+
+    pub fn textarea(name: &str, label: &str) -> FormField {
+        Self::input("textarea", name, label)
+    }
+
+    /// A checkbox, which is checked when its value is `on`.
+    pub fn checkbox(name: &str, label: &str) -> FormField {
+        Self::input("checkbox", name, label)
+    }
+
+    /// Require a value. Can be chained.
+    pub fn required(mut self) -> FormField {
+        self.required = true;
+        self
+    }
+
+    /// Set the initial value. Can be chained.
+    pub fn value(mut self, value: &str) -> FormField {
+        self.value = value.to_string();
+        self
+    }
+
+    fn render(&self, output: &mut String) {
+        let id = format!("field-{}", escape_html(&self.name));
+        let name = escape_html(&self.name);
+        let required = if self.required { " required" } else { "" };
+        let invalid = if self.error.is_some() { " aria-invalid=\"true\"" } else { "" };
+        output.push_str("<div class=\"field\">");
+        output.push_str(&format!("<label for=\"{}\">", id));
+        write_html_escaped(output, &self.label);
+        output.push_str("</label>");
+        match self.kind {
+            "textarea" => {
+                output.push_str(&format!("<textarea id=\"{}\" name=\"{}\"{}{}>", id, name, required, invalid));
+                write_html_escaped(output, &self.value);
+                output.push_str("</textarea>");
+            }
+            "checkbox" => {
+                let checked = if self.value == "on" { " checked" } else { "" };
+                output.push_str(&format!(
+                    "<input type=\"checkbox\" id=\"{}\" name=\"{}\"{}{}{}>",
+                    id, name, checked, required, invalid
+                ));
+            }
+            kind => {
+                output.push_str(&format!("<input type=\"{}\" id=\"{}\" name=\"{}\" value=\"", kind, id, name));
+                if kind != "password" {
+                    write_html_escaped(output, &self.value);
+                }
+                output.push_str(&format!("\"{}{}>", required, invalid));
+            }
+        }
+        if let Some(error) = &self.error {
+            output.push_str("<p class=\"error\">");
+            write_html_escaped(output, error);
+            output.push_str("</p>");
+        }
+        output.push_str("</div>");
+    }
+}
+
+/// Renders an HTML form, and re-renders it with the submitted
+/// values and field errors when validation fails:
+///
+/// ```ignore
+/// fn signup_form() -> Form {
+///     Form::new("/signup")
+///         .field(FormField::email("email", "Email").required())
+///         .field(FormField::password("password", "Password").required())
+/// }
+///
+/// // When the form is posted:
+/// let values = req.body.url_encoded()?;
+/// let mut form = signup_form().fill(values).validate();
+/// if taken { form = form.error("email", "This email is already registered."); }
+/// if form.has_errors() {
+///     return Ok(Response::html(form.csrf(&token).render().into_bytes())
+///         .status(ResponseStatusCode::UnprocessableEntity));
+/// }
+/// ```
+///
+/// Values are read as with [`crate::FromUrlEncoded`], so the
+/// form's field names match the struct it's parsed into.
+#[derive(Debug, Clone)]
+pub struct Form {
+    action: String,
+    submit: String,
+    fields: Vec<FormField>,
+    csrf: Option<String>,
+}
+impl Form {
+    /// Create a form which is posted to `action`.
+    pub fn new(action: &str) -> Form {
+        Form {
+            action: action.to_string(),
+            submit: "Submit".to_string(),
+            fields: Vec::new(),
+            csrf: None,
+        }
+    }
+
+    /// Add a field. Can be chained.
+    pub fn field(mut self, field: FormField) -> Form {
+        self.fields.push(field);
+        self
+    }
+
+    /// Define the label of the submit button. Can be chained.
+    pub fn submit(mut self, label: &str) -> Form {
+        self.submit = label.to_string();
+        self
+    }
+
+    /// Include a CSRF token. Can be chained.
+    pub fn csrf(mut self, token: &CsrfToken) -> Form {
+        self.csrf = Some(token.value.clone());
+        self
+    }
+
+    /// Fill the fields with submitted values. Passwords are
+    /// validated, but not rendered again. Can be chained.
+    pub fn fill(mut self, values: &UrlEncoded) -> Form {
+        for field in self.fields.iter_mut() {
+            field.value = values.get(&field.name).unwrap_or_default();
+        }
+        self
+    }
+
+    /// Mark empty required fields. Call after [`Form::fill`].
+    /// Can be chained.
+    pub fn validate(mut self) -> Form {
+        for field in self.fields.iter_mut() {
+            if field.required && field.value.trim().is_empty() && field.error.is_none() {
+                field.error = Some(format!("{} is required.", field.label));
+            }
+        }
+        self
+    }
+
+    /// Show an error under a field. Can be chained.
+    pub fn error(mut self, field: &str, message: &str) -> Form {
+        if let Some(field) = self.fields.iter_mut().find(|x| x.name == field) {
+            field.error = Some(message.to_string());
+        }
+        self
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.fields.iter().any(|x| x.error.is_some())
+    }
+
+    /// The form as HTML.
+    pub fn render(&self) -> String {
+        let mut output = String::from("<form method=\"post\" action=\"");
+        write_html_escaped(&mut output, &self.action);
+        output.push_str("\">");
+        if let Some(token) = &self.csrf {
+            output.push_str(&format!(
+                "<input type=\"hidden\" name=\"{}\" value=\"{}\">",
+                CsrfProtection::FIELD,
+                escape_html(token)
+            ));
+        }
+        for field in self.fields.iter() {
+            field.render(&mut output);
+        }
+        output.push_str("<button type=\"submit\">");
+        write_html_escaped(&mut output, &self.submit);
+        output.push_str("</button></form>");
+        output
+    }
+}
@@ -0,0 +1,265 @@
+use std::error::Error;
+
+use brackets::ToJson;
+use bytes::{BufMut, BytesMut};
+use tokio_postgres::types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
+
+/// The SRID used for longitude/latitude coordinates (WGS 84).
+pub const WGS84: u32 = 4326;
+
+const EWKB_POINT: u32 = 1;
+const EWKB_POLYGON: u32 = 3;
+const EWKB_SRID: u32 = 0x2000_0000;
+const EWKB_Z: u32 = 0x8000_0000;
+const EWKB_M: u32 = 0x4000_0000;
+
+/// A PostGIS point, read from and written to `geometry` or
+/// `geography` columns. Serializes to a GeoJSON point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    /// The longitude, for WGS 84 points.
+    pub x: f64,
+    /// The latitude, for WGS 84 points.
+    pub y: f64,
+    pub srid: Option<u32>,
+}
+impl Point {
+    /// Create a WGS 84 point.
+    pub fn new(longitude: f64, latitude: f64) -> Point {
+        Point {
+            x: longitude,
+            y: latitude,
+            srid: Some(WGS84),
+        }
+    }
+
+    /// Define the point's SRID. Can be chained.
+    pub fn srid(mut self, srid: Option<u32>) -> Point {
+        self.srid = srid;
+        self
+    }
+}
+
+/// A PostGIS polygon, read from and written to `geometry` or
+/// `geography` columns. Serializes to a GeoJSON polygon.
+///
+/// The first ring is the exterior; any others are holes.
+/// Rings should be closed, ending with their first point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polygon {
+    pub rings: Vec<Vec<(f64, f64)>>,
+    pub srid: Option<u32>,
+}
+impl Polygon {
+    /// Create a WGS 84 polygon from its exterior ring.
+    pub fn new(exterior: Vec<(f64, f64)>) -> Polygon {
+        Polygon {
+            rings: vec![exterior],
+            srid: Some(WGS84),
+        }
+    }
+
+    /// Add a hole. Can be chained.
+    pub fn hole(mut self, ring: Vec<(f64, f64)>) -> Polygon {
+        self.rings.push(ring);
+        self
+    }
+
+    /// Define the polygon's SRID. Can be chained.
+    pub fn srid(mut self, srid: Option<u32>) -> Polygon {
+        self.srid = srid;
+        self
+    }
+}
+
+/// A condition matching rows where the geometry in `column` is
+/// within `meters_arg` meters of the point in `point_arg`, such
+/// as `where_within_radius("location", 1, 2)` with a [`Point`]
+/// and an `f64` as the first two arguments.
+///
+/// Distances are measured on the spheroid, so points should
+/// be WGS 84.
+pub fn where_within_radius(column: &str, point_arg: usize, meters_arg: usize) -> String {
+    format!(
+        "ST_DWithin({}::geography, ${}::geography, ${})",
+        column, point_arg, meters_arg
+    )
+}
+
+/// A condition matching rows where the geometry in `column` is
+/// inside the [`Polygon`] in `polygon_arg`.
+pub fn where_within(column: &str, polygon_arg: usize) -> String {
+    format!("ST_Within({}, ${}::geometry)", column, polygon_arg)
+}
+
+/// Whether a Postgres type is a PostGIS spatial type.
+fn is_spatial(ty: &Type) -> bool {
+    matches!(ty.name(), "geometry" | "geography")
+}
+
+fn write_header(out: &mut BytesMut, kind: u32, srid: Option<u32>) {
+    out.put_u8(1);
+    match srid {
+        Some(srid) => {
+            out.put_u32_le(kind | EWKB_SRID);
+            out.put_u32_le(srid);
+        }
+        None => out.put_u32_le(kind),
+    }
+}
+
+/// Reads EWKB in either byte order.
+struct EwkbReader<'a> {
+    raw: &'a [u8],
+    little_endian: bool,
+}
+impl<'a> EwkbReader<'a> {
+    /// Read the header, returning the reader, the geometry kind and its SRID.
+    fn new(raw: &'a [u8]) -> Result<(EwkbReader<'a>, u32, Option<u32>), Box<dyn Error + Sync + Send>> {
+        let (&order, raw) = raw.split_first().ok_or("empty geometry")?;
+        let mut reader = EwkbReader {
+            raw,
+            little_endian: order == 1,
+        };
+        let kind = reader.u32()?;
+        if kind & (EWKB_Z | EWKB_M) != 0 {
+            return Err("geometries with Z or M coordinates are not supported".into());
+        }
+        let srid = if kind & EWKB_SRID != 0 {
+            Some(reader.u32()?)
+        } else {
+            None
+        };
+        Ok((reader, kind & 0xFF, srid))
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], Box<dyn Error + Sync + Send>> {
+        if self.raw.len() < N {
+            return Err("truncated geometry".into());
+        }
+        let (bytes, rest) = self.raw.split_at(N);
+        self.raw = rest;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error + Sync + Send>> {
+        let bytes = self.take::<4>()?;
+        Ok(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn f64(&mut self) -> Result<f64, Box<dyn Error + Sync + Send>> {
+        let bytes = self.take::<8>()?;
+        Ok(if self.little_endian {
+            f64::from_le_bytes(bytes)
+        } else {
+            f64::from_be_bytes(bytes)
+        })
+    }
+}
+
+impl ToSql for Point {
+    fn to_sql(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        write_header(out, EWKB_POINT, self.srid);
+        out.put_f64_le(self.x);
+        out.put_f64_le(self.y);
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        is_spatial(ty)
+    }
+
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for Point {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Point, Box<dyn Error + Sync + Send>> {
+        let (mut reader, kind, srid) = EwkbReader::new(raw)?;
+        if kind != EWKB_POINT {
+            return Err(format!("expected a point, found geometry type {}", kind).into());
+        }
+        Ok(Point {
+            x: reader.f64()?,
+            y: reader.f64()?,
+            srid,
+        })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        is_spatial(ty)
+    }
+}
+
+impl ToSql for Polygon {
+    fn to_sql(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        write_header(out, EWKB_POLYGON, self.srid);
+        out.put_u32_le(self.rings.len() as u32);
+        for ring in &self.rings {
+            out.put_u32_le(ring.len() as u32);
+            for (x, y) in ring {
+                out.put_f64_le(*x);
+                out.put_f64_le(*y);
+            }
+        }
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        is_spatial(ty)
+    }
+
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for Polygon {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Polygon, Box<dyn Error + Sync + Send>> {
+        let (mut reader, kind, srid) = EwkbReader::new(raw)?;
+        if kind != EWKB_POLYGON {
+            return Err(format!("expected a polygon, found geometry type {}", kind).into());
+        }
+        let ring_count = reader.u32()?;
+        let mut rings = Vec::with_capacity(ring_count.min(64) as usize);
+        for _ in 0..ring_count {
+            let point_count = reader.u32()?;
+            let mut ring = Vec::with_capacity(point_count.min(1024) as usize);
+            for _ in 0..point_count {
+                ring.push((reader.f64()?, reader.f64()?));
+            }
+            rings.push(ring);
+        }
+        Ok(Polygon { rings, srid })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        is_spatial(ty)
+    }
+}
+
+impl ToJson for Point {
+    fn to_json(&self) -> String {
+        format!("{{\"type\":\"Point\",\"coordinates\":[{},{}]}}", self.x, self.y)
+    }
+}
+
+impl ToJson for Polygon {
+    fn to_json(&self) -> String {
+        let rings = self
+            .rings
+            .iter()
+            .map(|ring| {
+                let points = ring
+                    .iter()
+                    .map(|(x, y)| format!("[{},{}]", x, y))
+                    .collect::<Vec<String>>()
+                    .join(",");
+                format!("[{}]", points)
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+        format!("{{\"type\":\"Polygon\",\"coordinates\":[{}]}}", rings)
+    }
+}
@@ -0,0 +1,154 @@
+use std::fmt::Display;
+
+use chrono::{DateTime, Utc};
+
+use crate::{httpdate, Request};
+
+/// A parsed media type, such as `text/html; charset=utf-8`.
+/// The type, subtype and parameter names are lowercase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaType {
+    pub kind: String,
+    pub subtype: String,
+    pub parameters: Vec<(String, String)>,
+}
+impl MediaType {
+    /// Parses the value of a `Content-Type` header.
+    pub fn parse(value: &str) -> Option<MediaType> {
+        let mut parts = value.split(';');
+        let (kind, subtype) = parts.next()?.trim().split_once('/')?;
+        if kind.is_empty() || subtype.is_empty() {
+            return None;
+        }
+        let parameters = parts
+            .filter_map(|x| {
+                let (name, value) = x.split_once('=')?;
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|x| x.strip_suffix('"'))
+                    .unwrap_or(value);
+                Some((name.trim().to_lowercase(), value.to_string()))
+            })
+            .collect();
+        Some(MediaType {
+            kind: kind.to_lowercase(),
+            subtype: subtype.to_lowercase(),
+            parameters,
+        })
+    }
+
+    /// The type without parameters, such as `text/html`.
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.kind, self.subtype)
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(x, _)| x.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.parameter("charset")
+    }
+
+    /// Whether this is `application/json` or a `+json` type,
+    /// such as `application/problem+json`.
+    pub fn is_json(&self) -> bool {
+        self.kind == "application" && (self.subtype == "json" || self.subtype.ends_with("+json"))
+    }
+}
+impl Display for MediaType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.kind, self.subtype)?;
+        for (name, value) in &self.parameters {
+            write!(f, "; {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// A parsed `If-None-Match` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntityTags {
+    /// `*`, matching any current representation.
+    Any,
+    /// The listed tags, with their quotes and `W/` prefixes.
+    Tags(Vec<String>),
+}
+impl EntityTags {
+    pub fn parse(value: &str) -> EntityTags {
+        if value.trim() == "*" {
+            return EntityTags::Any;
+        }
+        EntityTags::Tags(
+            value
+                .split(',')
+                .map(|x| x.trim().to_string())
+                .filter(|x| !x.is_empty())
+                .collect(),
+        )
+    }
+
+    /// Whether `etag` matches, using the weak comparison
+    /// `If-None-Match` calls for.
+    pub fn matches(&self, etag: &str) -> bool {
+        let opaque = |x: &str| x.trim().trim_start_matches("W/").to_string();
+        match self {
+            EntityTags::Any => true,
+            EntityTags::Tags(tags) => tags.iter().any(|x| opaque(x) == opaque(etag)),
+        }
+    }
+}
+
+impl Request {
+    /// The parsed `Content-Type` header.
+    pub fn content_type(&self) -> Option<MediaType> {
+        MediaType::parse(self.headers.get("content-type")?)
+    }
+
+    /// The languages in `Accept-Language`, most preferred first.
+    /// Languages with a quality of 0 are left out.
+    pub fn accept_language(&self) -> Vec<String> {
+        let Some(header) = self.headers.get("accept-language") else {
+            return Vec::new();
+        };
+        let mut languages = header
+            .split(',')
+            .filter_map(|x| {
+                let mut parts = x.split(';');
+                let language = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|x| x.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |x| x.trim().parse::<f32>().ok())?;
+                (!language.is_empty() && quality > 0.0).then(|| (language.to_string(), quality))
+            })
+            .collect::<Vec<(String, f32)>>();
+        // A stable sort keeps the client's order for equal qualities.
+        languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+        languages.into_iter().map(|(language, _)| language).collect()
+    }
+
+    /// The parsed `If-Modified-Since` header.
+    pub fn if_modified_since(&self) -> Option<DateTime<Utc>> {
+        httpdate::parse(self.headers.get("if-modified-since")?)
+    }
+
+    pub fn user_agent(&self) -> Option<&str> {
+        self.headers.get("user-agent").map(String::as_str)
+    }
+
+    /// The parsed `If-None-Match` header.
+    pub fn if_none_match(&self) -> Option<EntityTags> {
+        self.headers.get("if-none-match").map(|x| EntityTags::parse(x))
+    }
+
+    /// The token of a `Bearer` `Authorization` header.
+    pub fn bearer_token(&self) -> Option<&str> {
+        let (scheme, token) = self.headers.get("authorization")?.trim().split_once(' ')?;
+        let token = token.trim();
+        (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+    }
+}
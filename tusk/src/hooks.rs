@@ -0,0 +1,228 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{Request, RequestType, ResponseStatusCode, RouteError};
+
+/// Details of a response once it has been written,
+/// passed to [`crate::Server::on_response_written`].
+#[derive(Debug)]
+pub struct ResponseWritten<'a> {
+    pub request_type: &'a RequestType,
+    pub path: &'a str,
+    pub remote_addr: SocketAddr,
+    pub status: &'a ResponseStatusCode,
+    /// The number of bytes written, including headers.
+    pub bytes_written: usize,
+    /// False if the client disconnected before the
+    /// whole response was written.
+    pub completed: bool,
+    /// The time from reading the request to writing the response.
+    pub elapsed: Duration,
+    /// The route's latency budget, see [`crate::Route::budget`].
+    pub budget: Option<Duration>,
+}
+impl ResponseWritten<'_> {
+    /// Whether the response took longer than its route's budget.
+    pub fn over_budget(&self) -> bool {
+        self.budget.is_some_and(|x| self.elapsed > x)
+    }
+}
+
+/// Counts responses which took longer than their route's
+/// latency budget (see [`crate::Route::budget`]), by method
+/// and path, such as `GET /users`.
+///
+/// Register it with [`crate::Server::budget_alerts`]. Clones
+/// share the same counts, so one can be registered with every
+/// worker and read from a metrics route (it's also available to
+/// handlers as a service).
+#[derive(Debug, Clone, Default)]
+pub struct BudgetAlerts(Arc<RwLock<HashMap<String, u64>>>);
+impl BudgetAlerts {
+    pub fn new() -> BudgetAlerts {
+        BudgetAlerts::default()
+    }
+
+    pub(crate) fn record(&self, route: String) {
+        *self
+            .0
+            .write()
+            .unwrap_or_else(|x| x.into_inner())
+            .entry(route)
+            .or_insert(0) += 1;
+    }
+
+    /// How many responses from `route`, such as `GET /users`,
+    /// were over budget.
+    pub fn count(&self, route: &str) -> u64 {
+        self.0
+            .read()
+            .unwrap_or_else(|x| x.into_inner())
+            .get(route)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Every route with responses over budget, and how many.
+    pub fn counts(&self) -> Vec<(String, u64)> {
+        let mut counts = self
+            .0
+            .read()
+            .unwrap_or_else(|x| x.into_inner())
+            .iter()
+            .map(|(route, count)| (route.clone(), *count))
+            .collect::<Vec<(String, u64)>>();
+        counts.sort();
+        counts
+    }
+}
+
+/// Finds database connections held for longer than a threshold,
+/// which usually means a handler is stuck or is keeping its
+/// connection while it waits on something else, starving the
+/// pool.
+///
+/// Register it with [`crate::Server::connection_leaks`]. Every
+/// connection from the server's pool is tracked, and those held
+/// past the threshold are logged with their route when they're
+/// returned and counted by route. Connections still checked out
+/// are listed by [`ConnectionLeaks::held`], so ones which are
+/// never returned show up too. Like [`BudgetAlerts`], clones
+/// share the same counts, and it's available as a service.
+#[derive(Debug, Clone)]
+pub struct ConnectionLeaks(Arc<LeakState>);
+
+#[derive(Debug)]
+struct LeakState {
+    threshold: Duration,
+    next_id: AtomicU64,
+    /// Connections checked out now, by id, with when and by
+    /// which route.
+    checked_out: Mutex<HashMap<u64, (Instant, Option<String>)>>,
+    leaks: RwLock<HashMap<String, u64>>,
+}
+impl ConnectionLeaks {
+    /// Flag connections held for longer than `threshold`.
+    pub fn new(threshold: Duration) -> ConnectionLeaks {
+        ConnectionLeaks(Arc::new(LeakState {
+            threshold,
+            next_id: AtomicU64::new(0),
+            checked_out: Mutex::default(),
+            leaks: RwLock::default(),
+        }))
+    }
+
+    pub fn threshold(&self) -> Duration {
+        self.0.threshold
+    }
+
+    /// Returns an id to pass to [`ConnectionLeaks::check_in`].
+    pub(crate) fn check_out(&self) -> u64 {
+        let id = self.0.next_id.fetch_add(1, Ordering::Relaxed);
+        self.checked_out().insert(id, (Instant::now(), None));
+        id
+    }
+
+    /// Attributes a checked out connection to `route`.
+    pub(crate) fn assign(&self, id: u64, route: String) {
+        if let Some((_, assigned)) = self.checked_out().get_mut(&id) {
+            *assigned = Some(route);
+        }
+    }
+
+    pub(crate) fn check_in(&self, id: u64) {
+        let Some((since, route)) = self.checked_out().remove(&id) else {
+            return;
+        };
+        let held = since.elapsed();
+        if held <= self.0.threshold {
+            return;
+        }
+        let route = route.unwrap_or_else(|| "(no route)".to_string());
+        eprintln!(
+            "[WARN] {} held a database connection for {}ms, over the {}ms threshold",
+            route,
+            held.as_millis(),
+            self.0.threshold.as_millis()
+        );
+        *self
+            .0
+            .leaks
+            .write()
+            .unwrap_or_else(|x| x.into_inner())
+            .entry(route)
+            .or_insert(0) += 1;
+    }
+
+    fn checked_out(&self) -> std::sync::MutexGuard<'_, HashMap<u64, (Instant, Option<String>)>> {
+        self.0.checked_out.lock().unwrap_or_else(|x| x.into_inner())
+    }
+
+    /// The number of connections checked out now.
+    pub fn in_use(&self) -> usize {
+        self.checked_out().len()
+    }
+
+    /// Connections checked out now for longer than the threshold,
+    /// with their route and how long they've been held, longest
+    /// first. Connections taken outside of a route, such as
+    /// with [`crate::Database::get_connection`], have no route.
+    pub fn held(&self) -> Vec<(Option<String>, Duration)> {
+        let mut held = self
+            .checked_out()
+            .values()
+            .map(|(since, route)| (route.clone(), since.elapsed()))
+            .filter(|(_, held)| *held > self.0.threshold)
+            .collect::<Vec<_>>();
+        held.sort_by_key(|(_, held)| std::cmp::Reverse(*held));
+        held
+    }
+
+    /// How many connections `route`, such as `GET /users`,
+    /// returned after the threshold.
+    pub fn count(&self, route: &str) -> u64 {
+        self.0
+            .leaks
+            .read()
+            .unwrap_or_else(|x| x.into_inner())
+            .get(route)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Every route which returned connections after the
+    /// threshold, and how many.
+    pub fn counts(&self) -> Vec<(String, u64)> {
+        let mut counts = self
+            .0
+            .leaks
+            .read()
+            .unwrap_or_else(|x| x.into_inner())
+            .iter()
+            .map(|(route, count)| (route.clone(), *count))
+            .collect::<Vec<(String, u64)>>();
+        counts.sort();
+        counts
+    }
+}
+
+/// Functions called at each stage of handling a connection.
+/// These are meant for observability and accounting, so
+/// they can't modify the request or response.
+#[derive(Default)]
+pub(crate) struct LifecycleHooks {
+    pub(crate) connection_open: Option<fn(SocketAddr)>,
+    pub(crate) request_parsed: Option<fn(&Request)>,
+    pub(crate) handler_selected: Option<fn(&Request, Option<&str>)>,
+    pub(crate) response_written: Option<fn(&ResponseWritten)>,
+    pub(crate) route_error: Option<fn(&Request, &RouteError)>,
+    pub(crate) connection_error: Option<fn(Option<SocketAddr>, &io::Error)>,
+}
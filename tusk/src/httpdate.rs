@@ -0,0 +1,92 @@
+//! Formatting and parsing of HTTP dates (RFC 7231), as used by
+//! `Date`, `Last-Modified`, `If-Modified-Since` and `Retry-After`.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, NaiveDateTime, Timelike, Utc};
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Formats a time as an IMF-fixdate, such as
+/// `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn format(time: DateTime<Utc>) -> String {
+    format!(
+        "{}, {:0>2} {} {} {:0>2}:{:0>2}:{:0>2} GMT",
+        WEEKDAYS[time.weekday().num_days_from_monday() as usize],
+        time.day(),
+        MONTHS[time.month0() as usize],
+        time.year(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+/// The current time as an IMF-fixdate.
+pub fn now() -> String {
+    format(Utc::now())
+}
+
+/// Parses an HTTP date in any of the three formats recipients
+/// must accept: IMF-fixdate, the obsolete RFC 850 format
+/// (`Sunday, 06-Nov-94 08:49:37 GMT`) and asctime
+/// (`Sun Nov  6 08:49:37 1994`).
+pub fn parse(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    ["%a, %d %b %Y %H:%M:%S GMT", "%A, %d-%b-%y %H:%M:%S GMT", "%a %b %e %H:%M:%S %Y"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .map(|x| x.and_utc())
+}
+
+/// Parses a `Retry-After` value, which is either a number of
+/// seconds or an HTTP date. Dates in the past give zero.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let delay = parse(value)? - Utc::now();
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn example() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(1994, 11, 6, 8, 49, 37).unwrap()
+    }
+
+    #[test]
+    fn all_three_formats_are_parsed() {
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), Some(example()));
+        assert_eq!(parse("Sunday, 06-Nov-94 08:49:37 GMT"), Some(example()));
+        assert_eq!(parse("Sun Nov  6 08:49:37 1994"), Some(example()));
+        assert_eq!(parse("  Sun, 06 Nov 1994 08:49:37 GMT "), Some(example()));
+    }
+
+    #[test]
+    fn two_digit_days_are_parsed_in_asctime() {
+        let expected = Utc.with_ymd_and_hms(1994, 11, 16, 8, 49, 37).unwrap();
+        assert_eq!(parse("Wed Nov 16 08:49:37 1994"), Some(expected));
+    }
+
+    #[test]
+    fn formatted_dates_parse_back() {
+        assert_eq!(format(example()), "Sun, 06 Nov 1994 08:49:37 GMT");
+        let time = Utc.with_ymd_and_hms(2024, 2, 29, 23, 0, 5).unwrap();
+        assert_eq!(parse(&format(time)), Some(time));
+    }
+
+    #[test]
+    fn malformed_dates_are_refused() {
+        for value in ["", "yesterday", "Sun, 06 Nov 1994 08:49:37", "Sun, 32 Nov 1994 08:49:37 GMT", "1994-11-06T08:49:37Z"] {
+            assert_eq!(parse(value), None, "{}", value);
+        }
+    }
+}
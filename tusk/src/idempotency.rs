@@ -0,0 +1,255 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    apikey::to_hex, DatabaseConnection, Request, RequestType, Response, ResponseHeaders,
+    ResponseStatusCode, RouteError,
+};
+
+/// A Postgres-backed store of responses to requests carrying an
+/// `Idempotency-Key` header. Register it with
+/// [`crate::Server::idempotency`], and create the table using the
+/// statement from [`IdempotencyStore::create_table_sql`].
+///
+/// The first `POST` or `PATCH` with a key runs as usual and its
+/// response is stored. Retries with the same key within the TTL
+/// get the stored response, marked with `Idempotent-Replayed: true`,
+/// without running the route again. Retries which arrive while the
+/// first request is still running get a 409, and reusing a key for
+/// a different request gets a 422.
+///
+/// Requests are told apart by method, path, query, `Authorization`
+/// header and body, so one client can't replay another's response.
+/// Server errors aren't stored, so those requests can be retried.
+#[derive(Debug, Clone, Copy)]
+pub struct IdempotencyStore {
+    table: &'static str,
+    ttl: Duration,
+    lock_timeout: Duration,
+}
+impl IdempotencyStore {
+    const HEADER: &'static str = "idempotency-key";
+    const MAX_KEY_LEN: usize = 255;
+
+    /// Create a store backed by `table`, keeping responses
+    /// for a day.
+    pub const fn new(table: &'static str) -> IdempotencyStore {
+        IdempotencyStore {
+            table,
+            ttl: Duration::from_secs(24 * 60 * 60),
+            lock_timeout: Duration::from_secs(60),
+        }
+    }
+
+    /// Define how long responses are replayed for. Can be chained.
+    pub const fn ttl(mut self, ttl: Duration) -> IdempotencyStore {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Define how long a request may run before its key is
+    /// given up on and may be used again, such as after the
+    /// client disconnected. Defaults to a minute. Can be chained.
+    pub const fn lock_timeout(mut self, timeout: Duration) -> IdempotencyStore {
+        self.lock_timeout = timeout;
+        self
+    }
+
+    /// The SQL needed to create the backing table.
+    pub fn create_table_sql(&self) -> String {
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                key TEXT PRIMARY KEY,
+                fingerprint TEXT NOT NULL,
+                status INTEGER,
+                header_names TEXT[],
+                header_values TEXT[],
+                body BYTEA,
+                expires_at TIMESTAMPTZ NOT NULL
+            )",
+            self.table
+        )
+    }
+
+    /// Delete expired responses. Expired keys are replaced when
+    /// reused anyway, so this only keeps the table small.
+    pub async fn purge_expired(&self, db: &DatabaseConnection) -> Result<u64, tokio_postgres::Error> {
+        Ok(db
+            .query(
+                format!("DELETE FROM {} WHERE expires_at < $1 RETURNING key", self.table),
+                &[&Utc::now()],
+            )
+            .await?
+            .len() as u64)
+    }
+
+    /// Whether a request carries a key this store handles.
+    pub(crate) fn applies(&self, request: &Request) -> bool {
+        matches!(request.request_type, RequestType::Post | RequestType::Patch)
+            && request.headers.contains_key(Self::HEADER)
+    }
+
+    /// Claims the request's key. Returns the key if the request
+    /// should run and its response be stored, or the response to
+    /// send instead.
+    pub(crate) async fn begin(
+        &self,
+        db: &DatabaseConnection,
+        request: &Request,
+    ) -> Result<Option<String>, Response> {
+        if !self.applies(request) {
+            return Ok(None);
+        }
+        let key = request.headers[Self::HEADER].trim();
+        if key.is_empty() || key.len() > Self::MAX_KEY_LEN {
+            return Err(RouteError::bad_request("Invalid Idempotency-Key.").to_response());
+        }
+        let fingerprint = Self::fingerprint(request);
+        match self.claim(db, key, &fingerprint).await {
+            Ok(None) => Ok(Some(key.to_string())),
+            Ok(Some(response)) => Err(response),
+            Err(_) => Err(RouteError::server_error("Could not check Idempotency-Key.").to_response()),
+        }
+    }
+
+    async fn claim(
+        &self,
+        db: &DatabaseConnection,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<Option<Response>, tokio_postgres::Error> {
+        let now = Utc::now();
+        db.query(
+            format!("DELETE FROM {} WHERE key = $1 AND expires_at < $2", self.table),
+            &[&key, &now],
+        )
+        .await?;
+        let claimed = db
+            .query(
+                format!(
+                    "INSERT INTO {} (key, fingerprint, expires_at) VALUES ($1, $2, $3) ON CONFLICT (key) DO NOTHING RETURNING key",
+                    self.table
+                ),
+                &[&key, &fingerprint, &Self::after(self.lock_timeout)],
+            )
+            .await?;
+        if !claimed.is_empty() {
+            return Ok(None);
+        }
+        let rows = db
+            .query(
+                format!(
+                    "SELECT fingerprint, status, header_names, header_values, body FROM {} WHERE key = $1",
+                    self.table
+                ),
+                &[&key],
+            )
+            .await?;
+        let Some(row) = rows.first() else {
+            // Expired and removed by another request just now.
+            return Ok(Some(Self::in_progress()));
+        };
+        if row.get::<_, String>("fingerprint") != fingerprint {
+            return Ok(Some(
+                RouteError::custom(
+                    "This Idempotency-Key was used for a different request.",
+                    ResponseStatusCode::UnprocessableEntity,
+                )
+                .to_response(),
+            ));
+        }
+        let Some(status) = row.get::<_, Option<i32>>("status").and_then(ResponseStatusCode::from_code) else {
+            return Ok(Some(Self::in_progress()));
+        };
+        let mut response = Response::new();
+        response.status = status;
+        let names: Vec<String> = row.get("header_names");
+        let values: Vec<String> = row.get("header_values");
+        for (name, value) in names.iter().zip(values.iter()) {
+            response.headers.append(name, value);
+        }
+        response.headers.insert("Date", crate::httpdate::now());
+        response.headers.insert("Idempotent-Replayed", "true");
+        response.data = row.get("body");
+        Ok(Some(response))
+    }
+
+    /// Stores the response to a claimed key, or releases the key
+    /// if the request failed with a server error.
+    pub(crate) async fn finish(
+        &self,
+        db: &DatabaseConnection,
+        key: &str,
+        response: &Response,
+    ) -> Result<(), tokio_postgres::Error> {
+        if response.status.code() >= 500 {
+            db.query(format!("DELETE FROM {} WHERE key = $1", self.table), &[&key])
+                .await?;
+            return Ok(());
+        }
+        let headers = Self::stored_headers(&response.headers);
+        let (names, values): (Vec<&str>, Vec<&str>) = headers.into_iter().unzip();
+        db.query(
+            format!(
+                "UPDATE {} SET status = $2, header_names = $3, header_values = $4, body = $5, expires_at = $6 WHERE key = $1",
+                self.table
+            ),
+            &[
+                &key,
+                &response.status.code(),
+                &names,
+                &values,
+                &response.data,
+                &Self::after(self.ttl),
+            ],
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Headers worth replaying. Framing headers are recomputed
+    /// and the date is refreshed when replaying.
+    fn stored_headers(headers: &ResponseHeaders) -> Vec<(&str, &str)> {
+        headers
+            .iter()
+            .filter(|(name, _)| {
+                !["date", "content-length", "transfer-encoding", "trailer"]
+                    .iter()
+                    .any(|x| name.eq_ignore_ascii_case(x))
+            })
+            .collect()
+    }
+
+    fn fingerprint(request: &Request) -> String {
+        let mut query = request
+            .query
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<String>>();
+        query.sort();
+        let head = format!(
+            "{}\n{}\n{}\n{}\n",
+            request.request_type,
+            request.path,
+            query.join("&"),
+            request.headers.get("authorization").map(String::as_str).unwrap_or("")
+        );
+        let mut hasher = openssl::sha::Sha256::new();
+        hasher.update(head.as_bytes());
+        hasher.update(request.raw_body().unwrap_or_default());
+        to_hex(&hasher.finish())
+    }
+
+    fn after(duration: Duration) -> DateTime<Utc> {
+        Utc::now() + chrono::Duration::from_std(duration).unwrap_or(chrono::Duration::weeks(52))
+    }
+
+    fn in_progress() -> Response {
+        RouteError::custom(
+            "A request with this Idempotency-Key is still in progress.",
+            ResponseStatusCode::Conflict,
+        )
+        .to_response()
+    }
+}
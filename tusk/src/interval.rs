@@ -0,0 +1,204 @@
+use std::{error::Error, fmt::Display};
+
+use brackets::ToJson;
+use bytes::{BufMut, BytesMut};
+use tokio_postgres::types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
+
+use crate::{FromUrlEncoded, JsonField, JsonNode};
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+const MICROS_PER_MINUTE: i64 = 60 * MICROS_PER_SECOND;
+const MICROS_PER_HOUR: i64 = 60 * MICROS_PER_MINUTE;
+const MICROS_PER_DAY: i64 = 24 * MICROS_PER_HOUR;
+
+/// A Postgres `interval`.
+///
+/// Months and days are kept separate from the time, as in
+/// Postgres, since their length depends on when they're
+/// applied. Serializes to an ISO 8601 duration, such as
+/// `P1M2DT3H`, and converts to and from `chrono::Duration`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Interval {
+    pub months: i32,
+    pub days: i32,
+    pub microseconds: i64,
+}
+impl Interval {
+    pub fn new(months: i32, days: i32, microseconds: i64) -> Interval {
+        Interval {
+            months,
+            days,
+            microseconds,
+        }
+    }
+
+    /// The interval as a `chrono::Duration`, counting a month as
+    /// 30 days and a day as 24 hours (as Postgres' `justify_interval`
+    /// does). Returns `None` on overflow.
+    pub fn to_duration(&self) -> Option<chrono::Duration> {
+        let days = self.months as i64 * 30 + self.days as i64;
+        days.checked_mul(MICROS_PER_DAY)
+            .and_then(|x| x.checked_add(self.microseconds))
+            .map(chrono::Duration::microseconds)
+    }
+
+    /// Parse an ISO 8601 duration, such as `P1Y2M3DT4H5M6.5S` or `P2W`.
+    /// Components may be negative, as Postgres writes them.
+    pub fn parse(value: &str) -> Option<Interval> {
+        let value = value.strip_prefix('P')?;
+        let (date, time) = value.split_once('T').unwrap_or((value, ""));
+        if value.is_empty() || value.ends_with('T') {
+            return None;
+        }
+        let mut interval = Interval::default();
+        for (amount, unit) in Self::components(date)? {
+            let amount: i32 = amount.parse().ok()?;
+            match unit {
+                'Y' => interval.months = interval.months.checked_add(amount.checked_mul(12)?)?,
+                'M' => interval.months = interval.months.checked_add(amount)?,
+                'W' => interval.days = interval.days.checked_add(amount.checked_mul(7)?)?,
+                'D' => interval.days = interval.days.checked_add(amount)?,
+                _ => return None,
+            }
+        }
+        for (amount, unit) in Self::components(time)? {
+            let micros = match unit {
+                'H' => amount.parse::<i64>().ok()?.checked_mul(MICROS_PER_HOUR)?,
+                'M' => amount.parse::<i64>().ok()?.checked_mul(MICROS_PER_MINUTE)?,
+                'S' => {
+                    let seconds: f64 = amount.parse().ok()?;
+                    if !seconds.is_finite() {
+                        return None;
+                    }
+                    (seconds * MICROS_PER_SECOND as f64).round() as i64
+                }
+                _ => return None,
+            };
+            interval.microseconds = interval.microseconds.checked_add(micros)?;
+        }
+        Some(interval)
+    }
+
+    /// Splits `1Y-2M` into `[("1", 'Y'), ("-2", 'M')]`.
+    fn components(value: &str) -> Option<Vec<(&str, char)>> {
+        let mut components = Vec::new();
+        let mut start = 0;
+        for (ix, c) in value.char_indices() {
+            if c.is_ascii_alphabetic() {
+                if ix == start {
+                    return None;
+                }
+                components.push((&value[start..ix], c));
+                start = ix + 1;
+            }
+        }
+        if start != value.len() {
+            return None;
+        }
+        Some(components)
+    }
+}
+
+impl Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if *self == Interval::default() {
+            return write!(f, "PT0S");
+        }
+        write!(f, "P")?;
+        if self.months / 12 != 0 {
+            write!(f, "{}Y", self.months / 12)?;
+        }
+        if self.months % 12 != 0 {
+            write!(f, "{}M", self.months % 12)?;
+        }
+        if self.days != 0 {
+            write!(f, "{}D", self.days)?;
+        }
+        if self.microseconds == 0 {
+            return Ok(());
+        }
+        write!(f, "T")?;
+        let hours = self.microseconds / MICROS_PER_HOUR;
+        let minutes = self.microseconds % MICROS_PER_HOUR / MICROS_PER_MINUTE;
+        let micros = self.microseconds % MICROS_PER_MINUTE;
+        if hours != 0 {
+            write!(f, "{}H", hours)?;
+        }
+        if minutes != 0 {
+            write!(f, "{}M", minutes)?;
+        }
+        if micros != 0 {
+            let sign = if micros < 0 { "-" } else { "" };
+            let micros = micros.abs();
+            write!(f, "{}{}", sign, micros / MICROS_PER_SECOND)?;
+            if micros % MICROS_PER_SECOND != 0 {
+                let fraction = format!("{:06}", micros % MICROS_PER_SECOND);
+                write!(f, ".{}", fraction.trim_end_matches('0'))?;
+            }
+            write!(f, "S")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<chrono::Duration> for Interval {
+    /// Converts to a time-only interval. Durations beyond
+    /// roughly 292,000 years saturate.
+    fn from(value: chrono::Duration) -> Self {
+        Interval::new(0, 0, value.num_microseconds().unwrap_or(if value < chrono::Duration::zero() {
+            i64::MIN
+        } else {
+            i64::MAX
+        }))
+    }
+}
+
+impl ToSql for Interval {
+    fn to_sql(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        out.put_i64(self.microseconds);
+        out.put_i32(self.days);
+        out.put_i32(self.months);
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::INTERVAL
+    }
+
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for Interval {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Interval, Box<dyn Error + Sync + Send>> {
+        if raw.len() != 16 {
+            return Err("invalid interval".into());
+        }
+        Ok(Interval {
+            microseconds: i64::from_be_bytes(raw[0..8].try_into().unwrap()),
+            days: i32::from_be_bytes(raw[8..12].try_into().unwrap()),
+            months: i32::from_be_bytes(raw[12..16].try_into().unwrap()),
+        })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::INTERVAL
+    }
+}
+
+impl ToJson for Interval {
+    fn to_json(&self) -> String {
+        format!("\"{}\"", self)
+    }
+}
+
+impl JsonField for Interval {
+    fn from_json_node(json: &JsonNode) -> Option<Self> {
+        Interval::parse(json.as_str()?)
+    }
+}
+
+impl FromUrlEncoded for Interval {
+    fn from_url_encoded(data: &str) -> Option<Self> {
+        Interval::parse(data)
+    }
+}
@@ -0,0 +1,984 @@
+use std::error::Error;
+
+use brackets::{JsonArray, JsonObject, ToJson};
+use bytes::BytesMut;
+use chrono::{DateTime, Utc};
+use tokio_postgres::types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
+
+use crate::{
+    jsoncheck::{parse_json, JsonSyntaxError},
+    RouteError,
+};
+
+/// Appends `value` to `output` as a quoted JSON string.
+///
+/// Clean spans are copied in bulk; the input is scanned eight
+/// bytes at a time for characters which need escaping, so
+/// strings without quotes, backslashes or control characters
+/// are copied with a single `push_str`.
+pub fn write_json_string(output: &mut String, value: &str) {
+    output.reserve(value.len() + 2);
+    output.push('"');
+    let bytes = value.as_bytes();
+    let mut start = 0;
+    let mut ix = 0;
+    while ix < bytes.len() {
+        // Fast path: skip eight clean bytes at a time.
+        if ix + 8 <= bytes.len() {
+            let chunk = u64::from_le_bytes(bytes[ix..ix + 8].try_into().unwrap());
+            if !needs_escape_any(chunk) {
+                ix += 8;
+                continue;
+            }
+        }
+        let b = bytes[ix];
+        if b == b'"' || b == b'\\' || b < 0x20 {
+            // Escaped characters are all ASCII, so this is a char boundary.
+            output.push_str(&value[start..ix]);
+            match b {
+                b'"' => output.push_str("\\\""),
+                b'\\' => output.push_str("\\\\"),
+                b'\n' => output.push_str("\\n"),
+                b'\r' => output.push_str("\\r"),
+                b'\t' => output.push_str("\\t"),
+                0x08 => output.push_str("\\b"),
+                0x0c => output.push_str("\\f"),
+                _ => output.push_str(&format!("\\u{:04x}", b)),
+            }
+            start = ix + 1;
+        }
+        ix += 1;
+    }
+    output.push_str(&value[start..]);
+    output.push('"');
+}
+
+/// Returns `value` as a quoted JSON string.
+pub fn json_string(value: &str) -> String {
+    let mut output = String::new();
+    write_json_string(&mut output, value);
+    output
+}
+
+/// Appends a value's JSON to a buffer, so a response is built
+/// in one `String` instead of one per field. Implemented by the
+/// `ToJson` derive, which uses it for every field whose type
+/// implements it, and `ToJson::to_json` otherwise.
+///
+/// ```ignore
+/// let mut output = String::with_capacity(4096);
+/// for user in &users {
+///     user.to_json_into(&mut output);
+///     output.push('\n');
+/// }
+/// ```
+pub trait ToJsonInto {
+    fn to_json_into(&self, output: &mut String);
+
+    /// Appends the fields of the object this writes, without its
+    /// braces, for `#[tusk(flatten)]`. Values other than objects,
+    /// such as `null`, write no fields.
+    fn fields_to_json_into(&self, output: &mut String) {
+        let start = output.len();
+        self.to_json_into(output);
+        if !output[start..].starts_with('{') || output.len() - start == 2 {
+            output.truncate(start);
+            return;
+        }
+        output.pop();
+        if output[..start].ends_with('{') {
+            output.remove(start);
+        } else {
+            output.replace_range(start..start + 1, ",");
+        }
+    }
+}
+impl ToJsonInto for str {
+    fn to_json_into(&self, output: &mut String) {
+        write_json_string(output, self);
+    }
+}
+impl ToJsonInto for String {
+    fn to_json_into(&self, output: &mut String) {
+        write_json_string(output, self);
+    }
+}
+impl ToJsonInto for bool {
+    fn to_json_into(&self, output: &mut String) {
+        JsonLiteral::write_json(self, output);
+    }
+}
+macro_rules! number_json_into {
+    ($($t:ty),*) => {
+        $(impl ToJsonInto for $t {
+            fn to_json_into(&self, output: &mut String) {
+                JsonLiteral::write_json(self, output);
+            }
+        })*
+    };
+}
+number_json_into!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64);
+impl<T: ToJsonInto> ToJsonInto for Option<T> {
+    fn to_json_into(&self, output: &mut String) {
+        match self {
+            Some(value) => value.to_json_into(output),
+            None => output.push_str("null"),
+        }
+    }
+
+    fn fields_to_json_into(&self, output: &mut String) {
+        if let Some(value) = self {
+            value.fields_to_json_into(output);
+        }
+    }
+}
+impl<T: ToJsonInto> ToJsonInto for [T] {
+    fn to_json_into(&self, output: &mut String) {
+        output.push('[');
+        for (ix, item) in self.iter().enumerate() {
+            if ix > 0 {
+                output.push(',');
+            }
+            item.to_json_into(output);
+        }
+        output.push(']');
+    }
+}
+impl<T: ToJsonInto> ToJsonInto for Vec<T> {
+    fn to_json_into(&self, output: &mut String) {
+        self.as_slice().to_json_into(output);
+    }
+}
+impl<T: ToJsonInto + ?Sized> ToJsonInto for &T {
+    fn to_json_into(&self, output: &mut String) {
+        (**self).to_json_into(output);
+    }
+
+    fn fields_to_json_into(&self, output: &mut String) {
+        (**self).fields_to_json_into(output);
+    }
+}
+impl<T: ToJsonInto + ?Sized> ToJsonInto for Box<T> {
+    fn to_json_into(&self, output: &mut String) {
+        (**self).to_json_into(output);
+    }
+
+    fn fields_to_json_into(&self, output: &mut String) {
+        (**self).fields_to_json_into(output);
+    }
+}
+
+/// Starts an object entry, written by the `ToJson` derive as
+/// `"key":`, after a comma unless it's the object's first.
+#[doc(hidden)]
+pub fn write_json_key(output: &mut String, key: &str) {
+    if !output.ends_with('{') {
+        output.push(',');
+    }
+    output.push_str(key);
+}
+
+/// A field written by the `ToJson` derive. Through autoref,
+/// `(&JsonValue(&field)).write_json_value(output)` uses
+/// [`ToJsonInto`] when the field's type implements it, and
+/// `ToJson` when it doesn't.
+#[doc(hidden)]
+pub struct JsonValue<'a, T>(pub &'a T);
+#[doc(hidden)]
+pub trait WriteJsonInto {
+    fn write_json_value(&self, output: &mut String);
+}
+impl<T: ToJsonInto> WriteJsonInto for JsonValue<'_, T> {
+    fn write_json_value(&self, output: &mut String) {
+        self.0.to_json_into(output);
+    }
+}
+#[doc(hidden)]
+pub trait WriteToJson {
+    fn write_json_value(&self, output: &mut String);
+}
+impl<T: ToJson> WriteToJson for &JsonValue<'_, T> {
+    fn write_json_value(&self, output: &mut String) {
+        output.push_str(&self.0.to_json());
+    }
+}
+
+/// Whether any byte in the word is `"`, `\` or a control character.
+fn needs_escape_any(word: u64) -> bool {
+    const ONES: u64 = 0x0101_0101_0101_0101;
+    const HIGH: u64 = 0x8080_8080_8080_8080;
+    let has_zero = |x: u64| x.wrapping_sub(ONES) & !x & HIGH;
+    let quote = has_zero(word ^ (ONES * b'"' as u64));
+    let backslash = has_zero(word ^ (ONES * b'\\' as u64));
+    // Bytes below 0x20, ignoring bytes with the high bit set.
+    let control = word.wrapping_sub(ONES * 0x20) & !word & HIGH;
+    (quote | backslash | control) != 0
+}
+
+/// A parsed JSON document, for reading values that don't have
+/// a model, such as token claims, provider responses and patches:
+///
+/// ```ignore
+/// let json = JsonNode::parse(&body)?;
+/// let city = json.at_path("customer.addresses[0].city").and_then(JsonNode::as_str);
+/// ```
+///
+/// Strings are decoded, and numbers are kept as they're written,
+/// so integers aren't rounded through `f64`. If an object has a
+/// key twice, the last one wins, as in JavaScript.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonNode {
+    Null,
+    Bool(bool),
+    /// A number, as it's written.
+    Number(String),
+    String(String),
+    Array(Vec<JsonNode>),
+    /// The entries of an object, in order, with each key once.
+    Object(Vec<(String, JsonNode)>),
+}
+impl JsonNode {
+    /// Parses `json`, which must be exactly one JSON value.
+    pub fn parse(json: &str) -> Result<JsonNode, JsonSyntaxError> {
+        parse_json(json, false)
+    }
+
+    /// Parses `json`, refusing duplicate keys. See [`crate::check_strict_json`].
+    pub fn parse_strict(json: &str) -> Result<JsonNode, JsonSyntaxError> {
+        parse_json(json, true)
+    }
+
+    /// The value of `key`, if this is an object which has it.
+    pub fn get(&self, key: &str) -> Option<&JsonNode> {
+        self.as_object()?.iter().find(|(x, _)| x == key).map(|(_, value)| value)
+    }
+
+    /// The item at `index`, if this is an array which has it.
+    pub fn item(&self, index: usize) -> Option<&JsonNode> {
+        self.as_array()?.get(index)
+    }
+
+    /// The value at `path`. See [`JsonPath`].
+    pub fn at_path(&self, path: &str) -> Option<&JsonNode> {
+        parse_path(path)?.iter().try_fold(self, |value, segment| match segment {
+            // JSON Pointers give array indexes as keys.
+            PathSegment::Key(key) => match value {
+                JsonNode::Array(_) => value.item(key.parse().ok()?),
+                _ => value.get(key),
+            },
+            PathSegment::Index(index) => value.item(*index),
+        })
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, JsonNode::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            JsonNode::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            JsonNode::Number(value) => value.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonNode::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[JsonNode]> {
+        match self {
+            JsonNode::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, JsonNode)]> {
+        match self {
+            JsonNode::Object(entries) => Some(entries),
+            _ => None,
+        }
+    }
+
+    /// Applies a JSON Merge Patch (RFC 7386): keys of the patch
+    /// replace those of this value, objects are merged
+    /// recursively, and `null` removes a key.
+    pub fn merge_patch(&mut self, patch: &JsonNode) {
+        let JsonNode::Object(patch) = patch else {
+            *self = patch.clone();
+            return;
+        };
+        if !matches!(self, JsonNode::Object(_)) {
+            *self = JsonNode::Object(Vec::new());
+        }
+        let JsonNode::Object(entries) = self else {
+            return;
+        };
+        for (key, value) in patch {
+            let existing = entries.iter().position(|(x, _)| x == key);
+            match (existing, value) {
+                (Some(ix), JsonNode::Null) => {
+                    entries.remove(ix);
+                }
+                (None, JsonNode::Null) => {}
+                (Some(ix), value) => entries[ix].1.merge_patch(value),
+                (None, value) => {
+                    let mut new = JsonNode::Null;
+                    new.merge_patch(value);
+                    entries.push((key.clone(), new));
+                }
+            }
+        }
+    }
+
+    /// The JSON Merge Patch which turns this value into `to`.
+    ///
+    /// Merge patches can't set a key to `null`, so `null` values
+    /// in `to` remove the key instead.
+    pub fn merge_diff(&self, to: &JsonNode) -> JsonNode {
+        let (JsonNode::Object(from), JsonNode::Object(to)) = (self, to) else {
+            return to.clone();
+        };
+        let mut diff = from
+            .iter()
+            .filter(|(key, _)| !to.iter().any(|(x, _)| x == key))
+            .map(|(key, _)| (key.clone(), JsonNode::Null))
+            .collect::<Vec<_>>();
+        for (key, value) in to {
+            match from.iter().find(|(x, _)| x == key) {
+                Some((_, old)) if old == value => {}
+                Some((_, old @ JsonNode::Object(_))) if matches!(value, JsonNode::Object(_)) => {
+                    let nested = old.merge_diff(value);
+                    if nested.as_object().is_some_and(|x| !x.is_empty()) {
+                        diff.push((key.clone(), nested));
+                    }
+                }
+                _ => diff.push((key.clone(), value.clone())),
+            }
+        }
+        JsonNode::Object(diff)
+    }
+}
+impl ToJsonInto for JsonNode {
+    fn to_json_into(&self, output: &mut String) {
+        match self {
+            JsonNode::Null => output.push_str("null"),
+            JsonNode::Bool(value) => value.to_json_into(output),
+            JsonNode::Number(value) => output.push_str(value),
+            JsonNode::String(value) => write_json_string(output, value),
+            JsonNode::Array(items) => items.to_json_into(output),
+            JsonNode::Object(entries) => {
+                output.push('{');
+                for (ix, (key, value)) in entries.iter().enumerate() {
+                    if ix > 0 {
+                        output.push(',');
+                    }
+                    write_json_string(output, key);
+                    output.push(':');
+                    value.to_json_into(output);
+                }
+                output.push('}');
+            }
+        }
+    }
+}
+impl ToJson for JsonNode {
+    fn to_json(&self) -> String {
+        let mut output = String::new();
+        self.to_json_into(&mut output);
+        output
+    }
+}
+/// Reads the object's JSON, or `null` if it can't be parsed.
+impl From<&JsonObject> for JsonNode {
+    fn from(value: &JsonObject) -> Self {
+        JsonNode::parse(&value.to_json()).unwrap_or(JsonNode::Null)
+    }
+}
+/// Reads the array's JSON, or `null` if it can't be parsed.
+impl From<&JsonArray> for JsonNode {
+    fn from(value: &JsonArray) -> Self {
+        JsonNode::parse(&value.to_json()).unwrap_or(JsonNode::Null)
+    }
+}
+
+/// A value which can be read from a [`JsonNode`], such as a
+/// field of a JSON object with [`ValidateJson`].
+pub trait JsonField: Sized {
+    /// Reads `json`, or `None` if it has the wrong type.
+    fn from_json_node(json: &JsonNode) -> Option<Self>;
+
+    /// Reads `key` from the object `json`, or `None` if it's
+    /// missing or has the wrong type.
+    fn from_json_entry(json: &JsonNode, key: &str) -> Option<Self> {
+        Self::from_json_node(json.get(key)?)
+    }
+}
+impl JsonField for JsonNode {
+    fn from_json_node(json: &JsonNode) -> Option<Self> {
+        Some(json.clone())
+    }
+}
+impl JsonField for String {
+    fn from_json_node(json: &JsonNode) -> Option<Self> {
+        json.as_str().map(|x| x.to_string())
+    }
+}
+impl JsonField for bool {
+    fn from_json_node(json: &JsonNode) -> Option<Self> {
+        json.as_bool()
+    }
+}
+impl JsonField for f64 {
+    fn from_json_node(json: &JsonNode) -> Option<Self> {
+        json.as_f64()
+    }
+}
+/// Read from RFC 3339 strings.
+impl JsonField for DateTime<Utc> {
+    fn from_json_node(json: &JsonNode) -> Option<Self> {
+        DateTime::parse_from_rfc3339(json.as_str()?)
+            .ok()
+            .map(|x| x.with_timezone(&Utc))
+    }
+}
+impl<T: JsonField> JsonField for Vec<T> {
+    fn from_json_node(json: &JsonNode) -> Option<Self> {
+        json.as_array()?.iter().map(T::from_json_node).collect()
+    }
+}
+/// Reads `None` from `null`, or from a missing key.
+impl<T: JsonField> JsonField for Option<T> {
+    fn from_json_node(json: &JsonNode) -> Option<Self> {
+        match json {
+            JsonNode::Null => Some(None),
+            json => T::from_json_node(json).map(Some),
+        }
+    }
+
+    fn from_json_entry(json: &JsonNode, key: &str) -> Option<Self> {
+        json.as_object()?;
+        json.get(key).map_or(Some(None), Self::from_json_node)
+    }
+}
+macro_rules! integer_json_field {
+    ($($t:ty),*) => {
+        $(impl JsonField for $t {
+            /// Fractional or out of range numbers are rejected.
+            fn from_json_node(json: &JsonNode) -> Option<Self> {
+                match json {
+                    JsonNode::Number(number) => parse_json_integer(number),
+                    _ => None,
+                }
+            }
+        })*
+    };
+}
+integer_json_field!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+/// Reads a JSON number as an integer, without rounding it
+/// through `f64`. Numbers with a fraction or exponent, like
+/// `5.0` or `1e3`, are accepted if they're whole and exact.
+fn parse_json_integer<T: std::str::FromStr + TryFrom<i64>>(json: &str) -> Option<T> {
+    let json = json.trim();
+    if !json.starts_with(|c: char| c == '-' || c.is_ascii_digit()) {
+        return None;
+    }
+    if let Ok(n) = json.parse::<T>() {
+        return Some(n);
+    }
+    let n = json.parse::<f64>().ok()?;
+    if n.fract() != 0.0 || n.abs() > MAX_SAFE_INTEGER as f64 {
+        return None;
+    }
+    T::try_from(n as i64).ok()
+}
+
+/// The largest integer every JSON parser reads exactly,
+/// `2^53 - 1`. JavaScript rounds larger numbers.
+pub const MAX_SAFE_INTEGER: i64 = 9_007_199_254_740_991;
+
+impl JsonField for JsonObject {
+    fn from_json_node(json: &JsonNode) -> Option<Self> {
+        json.as_object().map(|_| JsonObject::from_string(&json.to_json()))
+    }
+}
+impl JsonField for JsonArray {
+    fn from_json_node(json: &JsonNode) -> Option<Self> {
+        json.as_array().map(|_| JsonArray::from_string(&json.to_json()))
+    }
+}
+
+/// Reads values nested deep in a JSON document:
+///
+/// ```ignore
+/// let city: String = payload.get_path("customer.addresses[0].city")?;
+/// let city: String = payload.get_path("/customer/addresses/0/city")?;
+/// ```
+///
+/// Paths are either dotted, with array indexes in brackets, or
+/// JSON Pointers (RFC 6901), which start with `/`. Returns `None`
+/// if the path doesn't exist or the value has another type.
+///
+/// A `JsonObject` or `JsonArray` is parsed on every call, so to
+/// read several paths, convert it to a [`JsonNode`] once.
+pub trait JsonPath {
+    fn get_path<T: JsonField>(&self, path: &str) -> Option<T>;
+}
+impl JsonPath for JsonNode {
+    fn get_path<T: JsonField>(&self, path: &str) -> Option<T> {
+        T::from_json_node(self.at_path(path)?)
+    }
+}
+impl JsonPath for JsonObject {
+    fn get_path<T: JsonField>(&self, path: &str) -> Option<T> {
+        JsonNode::from(self).get_path(path)
+    }
+}
+impl JsonPath for JsonArray {
+    fn get_path<T: JsonField>(&self, path: &str) -> Option<T> {
+        JsonNode::from(self).get_path(path)
+    }
+}
+
+/// A step of a [`JsonPath`] path.
+enum PathSegment {
+    /// An object key, or an array index given as a key,
+    /// as in JSON Pointers.
+    Key(String),
+    Index(usize),
+}
+
+fn parse_path(path: &str) -> Option<Vec<PathSegment>> {
+    if path.is_empty() {
+        return Some(Vec::new());
+    }
+    if let Some(pointer) = path.strip_prefix('/') {
+        return Some(
+            pointer
+                .split('/')
+                .map(|x| PathSegment::Key(x.replace("~1", "/").replace("~0", "~")))
+                .collect(),
+        );
+    }
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let (key, mut indexes) = part.split_once('[').map_or((part, ""), |(key, rest)| (key, rest));
+        if !key.is_empty() {
+            segments.push(PathSegment::Key(key.to_string()));
+        } else if indexes.is_empty() {
+            return None;
+        }
+        while !indexes.is_empty() {
+            let (index, rest) = indexes.split_once(']')?;
+            segments.push(PathSegment::Index(index.trim().parse().ok()?));
+            indexes = match rest {
+                "" => "",
+                rest => rest.strip_prefix('[')?,
+            };
+        }
+    }
+    Some(segments)
+}
+
+/// Applies and computes JSON Merge Patches between objects.
+/// See [`JsonNode::merge_patch`] and [`JsonNode::merge_diff`].
+pub trait MergePatch {
+    /// This object with `patch` applied.
+    fn merge_patch(&self, patch: &Self) -> Self;
+
+    /// The patch which turns this object into `other`.
+    fn merge_diff(&self, other: &Self) -> Self;
+}
+impl MergePatch for JsonObject {
+    fn merge_patch(&self, patch: &JsonObject) -> JsonObject {
+        let mut output = JsonNode::from(self);
+        output.merge_patch(&JsonNode::from(patch));
+        JsonObject::from_string(&output.to_json())
+    }
+
+    fn merge_diff(&self, other: &JsonObject) -> JsonObject {
+        JsonObject::from_string(&JsonNode::from(self).merge_diff(&JsonNode::from(other)).to_json())
+    }
+}
+
+/// Reads required fields from a JSON body, responding with
+/// a 400 and a custom message when they're missing or invalid:
+///
+/// ```ignore
+/// let body = req.body.to_json_object()?;
+/// let name: String = body.validate("name", "A name is required.")?;
+/// ```
+pub trait ValidateJson {
+    fn validate<T: JsonField>(&self, key: &str, message: &str) -> Result<T, RouteError>;
+
+    /// The same as [`ValidateJson::validate`].
+    fn validate_get<T: JsonField>(&self, key: &str, message: &str) -> Result<T, RouteError> {
+        self.validate(key, message)
+    }
+}
+impl ValidateJson for JsonNode {
+    fn validate<T: JsonField>(&self, key: &str, message: &str) -> Result<T, RouteError> {
+        T::from_json_entry(self, key).ok_or_else(|| RouteError::bad_request(message))
+    }
+}
+impl ValidateJson for JsonObject {
+    fn validate<T: JsonField>(&self, key: &str, message: &str) -> Result<T, RouteError> {
+        JsonNode::from(self).validate(key, message)
+    }
+}
+
+/// The full JSON of a model deriving `ToJson`, including fields
+/// marked `#[tusk(redact)]`. Implemented by the derive.
+pub trait UnredactedJson {
+    fn to_unredacted_json(&self) -> String;
+
+    /// Appends the full JSON to `output`, like [`ToJsonInto`].
+    fn to_unredacted_json_into(&self, output: &mut String) {
+        output.push_str(&self.to_unredacted_json());
+    }
+}
+
+/// Serializes a model with its redacted fields, for internal
+/// uses such as exports or audit logs. Don't send it to clients.
+///
+/// ```ignore
+/// let full = Unredacted(&user).to_json();
+/// ```
+pub struct Unredacted<'a, T: UnredactedJson>(pub &'a T);
+impl<T: UnredactedJson> ToJson for Unredacted<'_, T> {
+    fn to_json(&self) -> String {
+        self.0.to_unredacted_json()
+    }
+}
+impl<T: UnredactedJson> ToJsonInto for Unredacted<'_, T> {
+    fn to_json_into(&self, output: &mut String) {
+        self.0.to_unredacted_json_into(output);
+    }
+}
+
+/// JSON text which is sent as is, such as the output of
+/// [`crate::tusk_json`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawJson(pub String);
+impl ToJson for RawJson {
+    fn to_json(&self) -> String {
+        self.0.clone()
+    }
+}
+impl ToJsonInto for RawJson {
+    fn to_json_into(&self, output: &mut String) {
+        output.push_str(&self.0);
+    }
+}
+impl std::fmt::Display for RawJson {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// An integer written as a JSON string when it's beyond
+/// [`MAX_SAFE_INTEGER`], so JavaScript clients don't round it,
+/// like IDs from `BIGINT` columns. It's read from either
+/// numbers or numeric strings.
+///
+/// ```ignore
+/// #[derive(ToJson, FromPostgres)]
+/// pub struct Order {
+///     pub id: SafeInteger<i64>,
+/// }
+/// // {"id":"9007199254740993"}
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct SafeInteger<T>(pub T);
+impl<T> std::ops::Deref for SafeInteger<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+impl<T> From<T> for SafeInteger<T> {
+    fn from(value: T) -> Self {
+        SafeInteger(value)
+    }
+}
+impl<T: std::fmt::Display> ToJson for SafeInteger<T> {
+    fn to_json(&self) -> String {
+        let value = self.0.to_string();
+        let digits = value.trim_start_matches('-');
+        // Compared as text, so any integer type works.
+        if digits.len() < 16 || (digits.len() == 16 && digits <= "9007199254740991") {
+            value
+        } else {
+            format!("\"{}\"", value)
+        }
+    }
+}
+impl<T: std::str::FromStr + TryFrom<i64>> JsonField for SafeInteger<T> {
+    fn from_json_node(json: &JsonNode) -> Option<Self> {
+        match json {
+            JsonNode::Number(number) | JsonNode::String(number) => parse_json_integer(number).map(SafeInteger),
+            _ => None,
+        }
+    }
+}
+impl<T: ToSql> ToSql for SafeInteger<T> {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        self.0.to_sql(ty, out)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        T::accepts(ty)
+    }
+
+    to_sql_checked!();
+}
+impl<'a, T: FromSql<'a>> FromSql<'a> for SafeInteger<T> {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<SafeInteger<T>, Box<dyn Error + Sync + Send>> {
+        T::from_sql(ty, raw).map(SafeInteger)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        T::accepts(ty)
+    }
+}
+
+/// A literal in [`crate::tusk_json`], such as `"ok"` or `42`.
+pub trait JsonLiteral {
+    fn write_json(&self, output: &mut String);
+}
+impl JsonLiteral for &str {
+    fn write_json(&self, output: &mut String) {
+        write_json_string(output, self);
+    }
+}
+impl JsonLiteral for bool {
+    fn write_json(&self, output: &mut String) {
+        output.push_str(if *self { "true" } else { "false" });
+    }
+}
+macro_rules! number_json_literal {
+    ($($t:ty),*) => {
+        $(impl JsonLiteral for $t {
+            /// NaN and infinite numbers are written as `null`.
+            #[allow(clippy::unnecessary_cast)]
+            fn write_json(&self, output: &mut String) {
+                if (*self as f64).is_finite() {
+                    output.push_str(&self.to_string());
+                } else {
+                    output.push_str("null");
+                }
+            }
+        })*
+    };
+}
+number_json_literal!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64);
+
+/// Builds JSON without declaring a struct for it, returning
+/// [`RawJson`] which can be sent with [`crate::Response::json`].
+///
+/// Keys are string literals. Values may be `null`, literals,
+/// nested objects and arrays, or any expression whose type
+/// implements `ToJson`:
+///
+/// ```ignore
+/// Response::json(&tusk_json!({
+///     "ok": true,
+///     "tags": ["new", tag],
+///     "owner": { "name": user.name, "team": null },
+///     "user": user,
+/// }))
+/// ```
+#[macro_export]
+macro_rules! tusk_json {
+    (@value $out:ident null) => {
+        $out.push_str("null")
+    };
+    (@value $out:ident [$($items:tt)*]) => {{
+        $out.push('[');
+        $crate::tusk_json!(@array $out "" () $($items)*);
+        $out.push(']');
+    }};
+    (@value $out:ident {$($entries:tt)*}) => {{
+        $out.push('{');
+        $crate::tusk_json!(@object $out "" $($entries)*);
+        $out.push('}');
+    }};
+    (@value $out:ident $value:literal) => {
+        $crate::json::JsonLiteral::write_json(&$value, &mut $out)
+    };
+    (@value $out:ident $value:expr) => {
+        $out.push_str(&$crate::ToJson::to_json(&$value))
+    };
+
+    // Array items are collected token by token up to each comma.
+    (@array $out:ident $sep:literal ()) => {};
+    (@array $out:ident $sep:literal ($($item:tt)+)) => {
+        $out.push_str($sep);
+        $crate::tusk_json!(@value $out $($item)+);
+    };
+    (@array $out:ident $sep:literal ($($item:tt)+) , $($rest:tt)*) => {
+        $out.push_str($sep);
+        $crate::tusk_json!(@value $out $($item)+);
+        $crate::tusk_json!(@array $out "," () $($rest)*);
+    };
+    (@array $out:ident $sep:literal ($($item:tt)*) $next:tt $($rest:tt)*) => {
+        $crate::tusk_json!(@array $out $sep ($($item)* $next) $($rest)*);
+    };
+
+    // Object values are collected the same way, after their key.
+    (@object $out:ident $sep:literal) => {};
+    (@object $out:ident $sep:literal $key:literal : $($rest:tt)+) => {
+        $out.push_str($sep);
+        $crate::json::write_json_string(&mut $out, $key);
+        $out.push(':');
+        $crate::tusk_json!(@entry $out () $($rest)+);
+    };
+    (@entry $out:ident ($($value:tt)+)) => {
+        $crate::tusk_json!(@value $out $($value)+);
+    };
+    (@entry $out:ident ($($value:tt)+) , $($rest:tt)*) => {
+        $crate::tusk_json!(@value $out $($value)+);
+        $crate::tusk_json!(@object $out "," $($rest)*);
+    };
+    (@entry $out:ident ($($value:tt)*) $next:tt $($rest:tt)*) => {
+        $crate::tusk_json!(@entry $out ($($value)* $next) $($rest)*);
+    };
+
+    ($($json:tt)+) => {{
+        let mut output = String::new();
+        $crate::tusk_json!(@value output $($json)+);
+        $crate::json::RawJson(output)
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Escapes one character at a time, to check the fast path against.
+    fn escape_slowly(value: &str) -> String {
+        let mut output = String::from("\"");
+        for c in value.chars() {
+            match c {
+                '"' => output.push_str("\\\""),
+                '\\' => output.push_str("\\\\"),
+                '\n' => output.push_str("\\n"),
+                '\r' => output.push_str("\\r"),
+                '\t' => output.push_str("\\t"),
+                '\u{08}' => output.push_str("\\b"),
+                '\u{0c}' => output.push_str("\\f"),
+                c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+                c => output.push(c),
+            }
+        }
+        output.push('"');
+        output
+    }
+
+    #[test]
+    fn each_escape_is_written() {
+        assert_eq!(json_string("\"\\\n\r\t\u{08}\u{0c}"), r#""\"\\\n\r\t\b\f""#);
+        assert_eq!(json_string("\u{00}\u{01}\u{1f}"), r#""\u0000\u0001\u001f""#);
+        assert_eq!(json_string(" ~\u{7f}"), "\" ~\u{7f}\"");
+    }
+
+    #[test]
+    fn non_ascii_is_written_as_is() {
+        assert_eq!(json_string("café ☕ 𝄞"), "\"café ☕ 𝄞\"");
+        assert_eq!(json_string("ééééé\n"), "\"ééééé\\n\"");
+    }
+
+    #[test]
+    fn escapes_are_found_at_every_position() {
+        let specials = ['"', '\\', '\n', '\u{00}', '\u{1f}', 'é', '☕'];
+        for len in [1, 7, 8, 9, 16, 17] {
+            for position in 0..len {
+                for special in specials {
+                    let value: String = (0..len).map(|ix| if ix == position { special } else { 'a' }).collect();
+                    assert_eq!(json_string(&value), escape_slowly(&value), "{:?}", value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn words_are_checked_byte_by_byte() {
+        assert!(!needs_escape_any(u64::from_le_bytes(*b"abcdefgh")));
+        assert!(!needs_escape_any(u64::from_le_bytes([0x80, 0x9f, 0xa0, 0xff, 0xc3, 0xa9, b' ', b'~'])));
+        for byte in [b'"', b'\\', 0x00, 0x1f] {
+            for ix in 0..8 {
+                let mut word = *b"abcdefgh";
+                word[ix] = byte;
+                assert!(needs_escape_any(u64::from_le_bytes(word)), "{:#x} at {}", byte, ix);
+            }
+        }
+    }
+
+    fn json(value: &str) -> JsonNode {
+        JsonNode::parse(value).unwrap()
+    }
+
+    #[test]
+    fn merge_patches_follow_rfc_7386() {
+        let cases = [
+            (r#"{"a":"b"}"#, r#"{"a":"c"}"#, r#"{"a":"c"}"#),
+            (r#"{"a":"b"}"#, r#"{"b":"c"}"#, r#"{"a":"b","b":"c"}"#),
+            (r#"{"a":"b"}"#, r#"{"a":null}"#, r#"{}"#),
+            (r#"{"a":"b","b":"c"}"#, r#"{"a":null}"#, r#"{"b":"c"}"#),
+            (r#"{"a":["b"]}"#, r#"{"a":"c"}"#, r#"{"a":"c"}"#),
+            (r#"{"a":"c"}"#, r#"{"a":["b"]}"#, r#"{"a":["b"]}"#),
+            (r#"{"a":{"b":"c"}}"#, r#"{"a":{"b":"d","c":null}}"#, r#"{"a":{"b":"d"}}"#),
+            (r#"{"a":[{"b":"c"}]}"#, r#"{"a":[1]}"#, r#"{"a":[1]}"#),
+            (r#"["a","b"]"#, r#"["c","d"]"#, r#"["c","d"]"#),
+            (r#"{"a":"b"}"#, r#"["c"]"#, r#"["c"]"#),
+            (r#"{"a":"foo"}"#, "null", "null"),
+            (r#"{"a":"foo"}"#, r#""bar""#, r#""bar""#),
+            (r#"{"e":null}"#, r#"{"a":1}"#, r#"{"e":null,"a":1}"#),
+            (r#"[1,2]"#, r#"{"a":"b","c":null}"#, r#"{"a":"b"}"#),
+            ("{}", r#"{"a":{"bb":{"ccc":null}}}"#, r#"{"a":{"bb":{}}}"#),
+        ];
+        for (target, patch, expected) in cases {
+            let mut merged = json(target);
+            merged.merge_patch(&json(patch));
+            assert_eq!(merged.to_json(), expected, "{} with {}", target, patch);
+        }
+    }
+
+    #[test]
+    fn merge_diffs_patch_back_to_the_target() {
+        let cases = [
+            (r#"{"a":1,"b":2}"#, r#"{"a":1,"b":3}"#, r#"{"b":3}"#),
+            (r#"{"a":1,"b":2}"#, r#"{"a":1}"#, r#"{"b":null}"#),
+            (r#"{"a":{"x":1,"y":2}}"#, r#"{"a":{"x":1,"y":3},"c":[1]}"#, r#"{"a":{"y":3},"c":[1]}"#),
+            (r#"{"a":{"x":1}}"#, r#"{"a":{"x":1}}"#, "{}"),
+            (r#"{"a":[1,2]}"#, r#"{"a":[2]}"#, r#"{"a":[2]}"#),
+            (r#"{"a":{"x":1}}"#, r#"{"a":5}"#, r#"{"a":5}"#),
+            ("[1]", r#"{"a":1}"#, r#"{"a":1}"#),
+        ];
+        for (from, to, expected) in cases {
+            let diff = json(from).merge_diff(&json(to));
+            assert_eq!(diff.to_json(), expected, "{} to {}", from, to);
+            let mut patched = json(from);
+            patched.merge_patch(&diff);
+            assert_eq!(patched, json(to), "{} to {}", from, to);
+        }
+    }
+
+    #[test]
+    fn merge_diffs_drop_null_values() {
+        let diff = json(r#"{"a":1}"#).merge_diff(&json(r#"{"a":1,"b":null}"#));
+        let mut patched = json(r#"{"a":1}"#);
+        patched.merge_patch(&diff);
+        assert_eq!(patched.to_json(), r#"{"a":1}"#);
+    }
+}
@@ -0,0 +1,467 @@
+use std::{collections::HashSet, fmt::Display};
+
+use brackets::JsonObject;
+
+use crate::{json::JsonNode, RouteError};
+
+/// Why a document failed [`crate::JsonNode::parse`],
+/// [`check_strict_json`] or [`JsonLimits::check`], and where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonSyntaxError {
+    pub message: String,
+    /// The byte offset of the problem.
+    pub offset: usize,
+    /// The line of the problem, from 1.
+    pub line: usize,
+    /// The column of the problem, in characters from 1.
+    pub column: usize,
+}
+impl Display for JsonSyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at line {}, column {}", self.message, self.line, self.column)
+    }
+}
+impl std::error::Error for JsonSyntaxError {}
+/// A 400, with the `line`, `column` and `offset` in `details`.
+impl From<JsonSyntaxError> for RouteError {
+    fn from(val: JsonSyntaxError) -> Self {
+        RouteError::bad_request(&format!("Invalid JSON: {}.", val)).details(JsonObject::from_string(&format!(
+            "{{\"line\":{},\"column\":{},\"offset\":{}}}",
+            val.line, val.column, val.offset
+        )))
+    }
+}
+
+/// Caps on the shape of JSON request bodies, checked before
+/// they're parsed, so crafted payloads can't make the parser and
+/// extractors spend excessive memory or time on them. Bodies over
+/// a limit are refused with a 400. Set them with
+/// [`crate::Server::json_limits`].
+///
+/// ```ignore
+/// server.json_limits(JsonLimits::new().max_depth(16).max_keys(1_000));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JsonLimits {
+    max_depth: usize,
+    max_keys: usize,
+    max_string_length: usize,
+}
+impl Default for JsonLimits {
+    fn default() -> Self {
+        JsonLimits {
+            max_depth: 64,
+            max_keys: 10_000,
+            max_string_length: 1024 * 1024,
+        }
+    }
+}
+impl JsonLimits {
+    pub fn new() -> JsonLimits {
+        JsonLimits::default()
+    }
+
+    /// Define how deeply objects and arrays may be nested.
+    /// Defaults to 64. Can be chained.
+    pub fn max_depth(mut self, depth: usize) -> JsonLimits {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Define how many keys there may be, across every object
+    /// in the document. Defaults to 10,000. Can be chained.
+    pub fn max_keys(mut self, keys: usize) -> JsonLimits {
+        self.max_keys = keys;
+        self
+    }
+
+    /// Define how long strings and keys may be, in bytes as
+    /// sent. Defaults to 1 MiB. Can be chained.
+    pub fn max_string_length(mut self, length: usize) -> JsonLimits {
+        self.max_string_length = length;
+        self
+    }
+
+    /// Checks `json` against the limits in a single pass. Its
+    /// syntax isn't checked; see [`check_strict_json`] for that.
+    pub fn check(&self, json: &str) -> Result<(), JsonSyntaxError> {
+        let bytes = json.as_bytes();
+        let mut depth = 0;
+        let mut keys = 0;
+        let mut ix = 0;
+        while ix < bytes.len() {
+            match bytes[ix] {
+                b'"' => {
+                    let start = ix;
+                    ix += 1;
+                    while ix < bytes.len() && bytes[ix] != b'"' {
+                        if bytes[ix] == b'\\' {
+                            ix += 1;
+                        }
+                        ix += 1;
+                    }
+                    if ix.min(bytes.len()) - start - 1 > self.max_string_length {
+                        return Err(syntax_error(
+                            json,
+                            start,
+                            format!("String is longer than {} bytes", self.max_string_length),
+                        ));
+                    }
+                }
+                b'{' | b'[' => {
+                    depth += 1;
+                    if depth > self.max_depth {
+                        return Err(syntax_error(
+                            json,
+                            ix,
+                            format!("Nested more than {} levels deep", self.max_depth),
+                        ));
+                    }
+                }
+                b'}' | b']' => depth = depth.saturating_sub(1),
+                // Outside of strings, colons only follow keys.
+                b':' => {
+                    keys += 1;
+                    if keys > self.max_keys {
+                        return Err(syntax_error(json, ix, format!("More than {} keys", self.max_keys)));
+                    }
+                }
+                _ => {}
+            }
+            ix += 1;
+        }
+        Ok(())
+    }
+
+    /// Checks a request body, which must also be UTF-8.
+    pub(crate) fn check_body(&self, body: &[u8]) -> Result<(), RouteError> {
+        let json = std::str::from_utf8(body).map_err(|_| RouteError::bad_request("Body is not valid UTF-8."))?;
+        Ok(self.check(json)?)
+    }
+}
+
+/// Checks that `json` is exactly one valid JSON value, as
+/// RFC 8259 defines it, refusing what the lenient body parser
+/// lets through:
+///
+/// - objects with the same key twice, even if one is escaped,
+///   like `{"admin":false,"admin":true}`
+/// - anything after the value, like a second document
+/// - unquoted keys, single quotes, trailing commas and comments
+/// - unescaped control characters and lone surrogates in strings
+///
+/// Parsers disagree on these, such as which duplicate wins, so
+/// a proxy or signature check could see a different document than
+/// the handler. Use [`crate::Route::strict_json`] to check bodies
+/// before the route runs.
+pub fn check_strict_json(json: &str) -> Result<(), JsonSyntaxError> {
+    parse_json(json, true).map(|_| ())
+}
+
+/// How deeply [`parse_json`] lets documents nest, so reading,
+/// writing and dropping the tree can't overflow the stack.
+const MAX_DEPTH: usize = 512;
+
+/// Parses `json` into a [`JsonNode`]. Duplicate keys are refused
+/// if `strict`, and otherwise the last one wins.
+pub(crate) fn parse_json(json: &str, strict: bool) -> Result<JsonNode, JsonSyntaxError> {
+    Parser { json, ix: 0, strict }.parse()
+}
+
+/// A container the parser is inside of.
+enum Frame {
+    /// An object, with its entries so far, the keys seen, and
+    /// the key of the value being read.
+    Object {
+        entries: Vec<(String, JsonNode)>,
+        keys: HashSet<String>,
+        key: String,
+    },
+    Array(Vec<JsonNode>),
+}
+
+struct Parser<'a> {
+    json: &'a str,
+    ix: usize,
+    strict: bool,
+}
+impl Parser<'_> {
+    /// Walks the document with a stack rather than recursion,
+    /// so deep nesting can't overflow the stack.
+    fn parse(&mut self) -> Result<JsonNode, JsonSyntaxError> {
+        let mut stack = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if stack.len() >= MAX_DEPTH && matches!(self.peek(), Some(b'{' | b'[')) {
+                return Err(self.error(self.ix, format!("Nested more than {} levels deep", MAX_DEPTH)));
+            }
+            let mut value = match self.peek() {
+                Some(b'{') => {
+                    self.ix += 1;
+                    self.skip_whitespace();
+                    if self.peek() == Some(b'}') {
+                        self.ix += 1;
+                        JsonNode::Object(Vec::new())
+                    } else {
+                        let keys = HashSet::new();
+                        let key = self.key(&keys)?;
+                        stack.push(Frame::Object {
+                            entries: Vec::new(),
+                            keys,
+                            key,
+                        });
+                        continue;
+                    }
+                }
+                Some(b'[') => {
+                    self.ix += 1;
+                    self.skip_whitespace();
+                    if self.peek() == Some(b']') {
+                        self.ix += 1;
+                        JsonNode::Array(Vec::new())
+                    } else {
+                        stack.push(Frame::Array(Vec::new()));
+                        continue;
+                    }
+                }
+                Some(b'"') => JsonNode::String(self.string()?),
+                Some(b'-' | b'0'..=b'9') => JsonNode::Number(self.number()?),
+                Some(b't' | b'f' | b'n') => self.literal()?,
+                Some(_) => return Err(self.error(self.ix, "Expected a value".to_string())),
+                None => return Err(self.error(self.ix, "Unexpected end of JSON".to_string())),
+            };
+            // A value ended, so close containers until another
+            // value is expected.
+            loop {
+                self.skip_whitespace();
+                match stack.last_mut() {
+                    None => {
+                        if self.ix < self.json.len() {
+                            return Err(self.error(self.ix, "Unexpected data after the JSON value".to_string()));
+                        }
+                        return Ok(value);
+                    }
+                    Some(Frame::Array(items)) => {
+                        items.push(value);
+                        match self.peek() {
+                            Some(b',') => {
+                                self.ix += 1;
+                                break;
+                            }
+                            Some(b']') => {
+                                self.ix += 1;
+                                value = JsonNode::Array(std::mem::take(items));
+                                stack.pop();
+                            }
+                            _ => return Err(self.expected("',' or ']'")),
+                        }
+                    }
+                    Some(Frame::Object { entries, keys, key }) => {
+                        let name = std::mem::take(key);
+                        // Strict parsing refused duplicates already,
+                        // so otherwise the last one wins.
+                        if keys.contains(&name) {
+                            if let Some(entry) = entries.iter_mut().find(|(x, _)| *x == name) {
+                                entry.1 = value;
+                            }
+                        } else {
+                            keys.insert(name.clone());
+                            entries.push((name, value));
+                        }
+                        match self.peek() {
+                            Some(b',') => {
+                                self.ix += 1;
+                                *key = self.key(keys)?;
+                                break;
+                            }
+                            Some(b'}') => {
+                                self.ix += 1;
+                                value = JsonNode::Object(std::mem::take(entries));
+                                stack.pop();
+                            }
+                            _ => return Err(self.expected("',' or '}'")),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads an object key and its colon.
+    fn key(&mut self, keys: &HashSet<String>) -> Result<String, JsonSyntaxError> {
+        self.skip_whitespace();
+        if self.peek() != Some(b'"') {
+            return Err(self.expected("a quoted key"));
+        }
+        let start = self.ix;
+        let key = self.string()?;
+        if self.strict && keys.contains(&key) {
+            return Err(self.error(start, format!("Duplicate key \"{}\"", key)));
+        }
+        self.skip_whitespace();
+        if self.peek() != Some(b':') {
+            return Err(self.expected("':'"));
+        }
+        self.ix += 1;
+        Ok(key)
+    }
+
+    /// Reads and decodes a string.
+    fn string(&mut self) -> Result<String, JsonSyntaxError> {
+        let bytes = self.json.as_bytes();
+        let mut decoded = String::new();
+        self.ix += 1;
+        let mut start = self.ix;
+        loop {
+            match bytes.get(self.ix) {
+                None => return Err(self.error(self.ix, "Unterminated string".to_string())),
+                Some(b'"') => {
+                    decoded.push_str(&self.json[start..self.ix]);
+                    self.ix += 1;
+                    return Ok(decoded);
+                }
+                Some(b'\\') => {
+                    decoded.push_str(&self.json[start..self.ix]);
+                    decoded.push(self.escape()?);
+                    start = self.ix;
+                }
+                Some(c) if *c < 0x20 => {
+                    return Err(self.error(self.ix, "Unescaped control character in string".to_string()));
+                }
+                Some(_) => self.ix += 1,
+            }
+        }
+    }
+
+    /// Reads an escape sequence, starting at its backslash.
+    fn escape(&mut self) -> Result<char, JsonSyntaxError> {
+        let start = self.ix;
+        let invalid = |parser: &Self| parser.error(start, "Invalid escape sequence".to_string());
+        let c = match self.json.as_bytes().get(start + 1) {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                let high = self.hex(start + 2).ok_or_else(|| invalid(self))?;
+                self.ix = start + 6;
+                return match high {
+                    0xD800..=0xDBFF => {
+                        let low = self
+                            .json
+                            .get(self.ix..self.ix + 2)
+                            .filter(|x| *x == "\\u")
+                            .and_then(|_| self.hex(self.ix + 2))
+                            .filter(|x| (0xDC00..=0xDFFF).contains(x))
+                            .ok_or_else(|| invalid(self))?;
+                        self.ix += 6;
+                        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)).ok_or_else(|| invalid(self))
+                    }
+                    high => char::from_u32(high).ok_or_else(|| invalid(self)),
+                };
+            }
+            _ => return Err(invalid(self)),
+        };
+        self.ix = start + 2;
+        Ok(c)
+    }
+
+    /// The four hex digits at `start`.
+    fn hex(&self, start: usize) -> Option<u32> {
+        let digits = self.json.get(start..start + 4)?;
+        if !digits.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return None;
+        }
+        u32::from_str_radix(digits, 16).ok()
+    }
+
+    /// Reads a number, as it's written.
+    fn number(&mut self) -> Result<String, JsonSyntaxError> {
+        let start = self.ix;
+        let invalid = |parser: &Self| parser.error(start, "Invalid number".to_string());
+        if self.peek() == Some(b'-') {
+            self.ix += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.ix += 1,
+            Some(b'1'..=b'9') => self.digits(),
+            _ => return Err(invalid(self)),
+        }
+        if self.peek() == Some(b'.') {
+            self.ix += 1;
+            if !self.peek().is_some_and(|x| x.is_ascii_digit()) {
+                return Err(invalid(self));
+            }
+            self.digits();
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.ix += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.ix += 1;
+            }
+            if !self.peek().is_some_and(|x| x.is_ascii_digit()) {
+                return Err(invalid(self));
+            }
+            self.digits();
+        }
+        Ok(self.json[start..self.ix].to_string())
+    }
+
+    fn digits(&mut self) {
+        while self.peek().is_some_and(|x| x.is_ascii_digit()) {
+            self.ix += 1;
+        }
+    }
+
+    fn literal(&mut self) -> Result<JsonNode, JsonSyntaxError> {
+        for (literal, value) in [
+            ("true", JsonNode::Bool(true)),
+            ("false", JsonNode::Bool(false)),
+            ("null", JsonNode::Null),
+        ] {
+            if self.json[self.ix..].starts_with(literal) {
+                self.ix += literal.len();
+                return Ok(value);
+            }
+        }
+        Err(self.error(self.ix, "Expected a value".to_string()))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.ix += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.json.as_bytes().get(self.ix).copied()
+    }
+
+    fn expected(&self, what: &str) -> JsonSyntaxError {
+        match self.peek() {
+            Some(_) => self.error(self.ix, format!("Expected {}", what)),
+            None => self.error(self.ix, format!("Unexpected end of JSON, expected {}", what)),
+        }
+    }
+
+    fn error(&self, offset: usize, message: String) -> JsonSyntaxError {
+        syntax_error(self.json, offset, message)
+    }
+}
+
+/// An error at byte `offset` of `json`, which must be
+/// on a character boundary.
+fn syntax_error(json: &str, offset: usize, message: String) -> JsonSyntaxError {
+    let before = &json[..offset];
+    let line_start = before.rfind('\n').map_or(0, |x| x + 1);
+    JsonSyntaxError {
+        message,
+        offset,
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    }
+}
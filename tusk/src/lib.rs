@@ -1,23 +1,101 @@
+pub mod acl;
+pub mod acme;
+pub mod admin;
+pub mod apikey;
+pub mod assets;
+pub mod audit;
+pub mod auth;
 pub mod config;
 pub mod database;
+pub mod encryption;
+pub mod features;
+pub mod forms;
+#[cfg(feature = "postgis")]
+pub mod geo;
+pub mod headers;
+pub mod hooks;
+pub mod httpdate;
+pub mod idempotency;
+pub mod interval;
+pub mod json;
+pub mod jsoncheck;
+pub mod maintenance;
+pub mod mime;
+pub mod oauth;
+pub mod ownership;
+pub mod patch;
 pub mod query;
+pub mod redirect;
+pub mod replay;
 pub mod reqres;
+pub mod rpc;
+pub mod schema;
+pub mod security;
 pub mod server;
+pub mod services;
+pub mod shedding;
+pub mod signature;
+pub mod tenancy;
+pub mod ulid;
 pub mod urlencoded;
+pub mod warmup;
+pub mod wellknown;
 /// Re-exports chrono for convience
 pub use chrono;
-pub use config::DatabaseConfig;
-pub use database::{Database, DatabaseError, DatabaseConnection};
+/// Re-exports the JSON trait implemented by the `ToJson` derive.
+pub use brackets::ToJson;
+pub use acl::{Cidr, CidrParseError, NetworkAcl};
+pub use acme::{AcmeAccount, AcmeDirectory, AcmeError, StoredCertificate};
+pub use admin::{AdminModel, AdminModule};
+pub use apikey::{ApiKey, ApiKeyStore};
+pub use assets::EmbeddedAsset;
+pub use audit::{AuditActor, AuditLog};
+pub use auth::Authorization;
+pub use config::{DatabaseConfig, PoolMode};
+pub use database::{Database, DatabaseError, DatabaseConnection, WriteOutcome};
+pub use encryption::{Encrypted, EncryptionError, EncryptionKeys};
+pub use features::{FeatureFlags, FeatureSubject};
+pub use forms::{CsrfProtection, CsrfToken, Form, FormField};
+#[cfg(feature = "postgis")]
+pub use geo::{Point, Polygon};
+pub use headers::{EntityTags, MediaType};
+pub use hooks::{BudgetAlerts, ConnectionLeaks, ResponseWritten};
+pub use idempotency::IdempotencyStore;
+pub use interval::Interval;
+pub use json::{
+    JsonField, JsonLiteral, JsonNode, JsonPath, MergePatch, RawJson, SafeInteger, ToJsonInto, Unredacted,
+    UnredactedJson, ValidateJson,
+};
+pub use jsoncheck::{check_strict_json, JsonLimits, JsonSyntaxError};
+pub use maintenance::MaintenanceMode;
+pub use oauth::{IdTokenValidator, OAuthError, OAuthProvider};
+pub use ownership::{OwnedScope, PostgresOwned};
+pub use patch::{Maybe, PostgresPatch, PostgresPatchable};
 pub use query::{
-    FromPostgres, FromPostgresError, PostgresReadFields, PostgresWrite, PostgresWriteFields,
-    PostgresWriteable, PostgresReadable, PostgresTable, PostgresJoins, PostgresJoin, PostgresField,
-    PostgresFieldLocation
+    AfterRead, BeforeWrite, FromPostgres, FromPostgresError, PostgresReadFields, PostgresWrite, PostgresWriteFields,
+    PostgresWriteRef, PostgresWriteable, PostgresWriteableRef, PostgresReadable, PostgresTable, PostgresJoins, PostgresJoin, PostgresField,
+    PostgresFieldLocation, PostgresVersioned, RowLock
+};
+pub use redirect::{CanonicalRedirects, WwwRedirect};
+pub use replay::ReplayGuard;
+pub use reqres::{
+    BodyContents, BodyReader, CancellationToken, Extensions, FromRequest, HttpVersion, IntoResponse, Json, Request, RequestType, Response, ResponseHeaders,
+    ResponseStatusCode, RouteError,
 };
-pub use reqres::{BodyContents, Request, RequestType, Response, ResponseStatusCode, RouteError};
-pub use server::{IncomingRequest, Route, Server};
+pub use rpc::{RpcError, RpcTransport};
+pub use schema::PostgresSchema;
+pub use security::SecurityHeaders;
+pub use server::{BodyMode, IncomingRequest, Route, RouteExample, RouteModule, RouteRegistry, Server, SubApp};
+pub use services::Services;
+pub use shedding::{ConnectionLimit, LoadShedding};
+pub use signature::SignatureVerifier;
+pub use tenancy::{PostgresTenantScoped, Tenancy, Tenant};
 pub use tokio_postgres::{error::SqlState, types::ToSql, Row};
 pub use tusk_rs_derive::{
-    route, treatment, PostgresReadFields, PostgresReadable, PostgresWriteFields,
-    PostgresWriteable, FromPostgres, PostgresJoins, embed, embed_binary
+    route, rpc, treatment, postfix, FromRequest, PostgresReadFields, PostgresReadable, PostgresWriteFields,
+    PostgresWriteable, FromPostgres, PostgresJoins, PostgresPatchable, PostgresSchema, ToJson, JsonField, embed, embed_asset, embed_binary
 };
+pub use ulid::{new_id, Ulid};
 pub use urlencoded::{FromUrlEncoded, UrlEncoded};
+pub use warmup::Warmup;
+pub use wellknown::AcmeChallenges;
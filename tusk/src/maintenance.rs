@@ -0,0 +1,94 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{FeatureFlags, Response, ResponseStatusCode, RouteError};
+
+/// A switch which answers every request with a 503 and
+/// `Retry-After`, except for allowed paths such as health
+/// checks. Register it with [`crate::Server::maintenance_mode`].
+///
+/// The switch is shared between clones, so keep one to turn
+/// maintenance on and off at runtime (it's also available to
+/// handlers as a service). It can also follow a feature flag,
+/// see [`MaintenanceMode::flag`].
+#[derive(Clone)]
+pub struct MaintenanceMode {
+    enabled: Arc<AtomicBool>,
+    flag: Option<&'static str>,
+    retry_after: Duration,
+    allowed: Vec<String>,
+}
+impl MaintenanceMode {
+    /// Create a switch which is off.
+    pub fn new() -> MaintenanceMode {
+        MaintenanceMode {
+            enabled: Arc::new(AtomicBool::new(false)),
+            flag: None,
+            retry_after: Duration::from_secs(120),
+            allowed: Vec::new(),
+        }
+    }
+
+    /// Define the `Retry-After` sent to clients. Can be chained.
+    pub fn retry_after(mut self, retry_after: Duration) -> MaintenanceMode {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Keep serving paths starting with `path`. Can be chained.
+    pub fn allow(mut self, path: &str) -> MaintenanceMode {
+        self.allowed.push(path.to_string());
+        self
+    }
+
+    /// Also enter maintenance while the feature flag `name` is
+    /// on. This requires [`crate::Server::feature_flags`], and
+    /// follows the flags' cache. Can be chained.
+    pub fn flag(mut self, name: &'static str) -> MaintenanceMode {
+        self.flag = Some(name);
+        self
+    }
+
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+    }
+
+    /// Whether maintenance was turned on with [`MaintenanceMode::enable`].
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// The response for a request to `path`, if it's blocked.
+    pub(crate) fn response(&self, path: &str, flags: Option<&FeatureFlags>) -> Option<Response> {
+        let active = self.is_enabled()
+            || self
+                .flag
+                .zip(flags)
+                .is_some_and(|(name, flags)| flags.is_enabled(name, None));
+        if !active || self.allowed.iter().any(|x| path.starts_with(x.as_str())) {
+            return None;
+        }
+        Some(
+            RouteError::custom(
+                "The service is down for maintenance.",
+                ResponseStatusCode::ServiceUnavailable,
+            )
+            .to_response()
+            .header("Retry-After", self.retry_after.as_secs().to_string()),
+        )
+    }
+}
+impl Default for MaintenanceMode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,111 @@
+use std::path::Path;
+
+/// The media type of a file, from its extension, such as
+/// `text/css; charset=utf-8` for `styles.css`. Unknown
+/// extensions are `application/octet-stream`, so browsers
+/// download them rather than guess.
+pub fn from_path<P: AsRef<Path>>(path: P) -> &'static str {
+    path.as_ref()
+        .extension()
+        .and_then(|x| x.to_str())
+        .and_then(from_extension)
+        .unwrap_or("application/octet-stream")
+}
+
+/// The media type for a file extension, without its dot,
+/// or `None` if it isn't known. Text types include a charset.
+pub fn from_extension(extension: &str) -> Option<&'static str> {
+    let media_type = match extension.to_ascii_lowercase().as_str() {
+        // Text
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" | "cjs" => "text/javascript; charset=utf-8",
+        "txt" | "text" | "log" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "tsv" => "text/tab-separated-values; charset=utf-8",
+        "md" | "markdown" => "text/markdown; charset=utf-8",
+        "xml" => "application/xml; charset=utf-8",
+        "ics" => "text/calendar; charset=utf-8",
+        "vcf" => "text/vcard; charset=utf-8",
+        "vtt" => "text/vtt; charset=utf-8",
+        "yaml" | "yml" => "application/yaml; charset=utf-8",
+        "toml" => "application/toml; charset=utf-8",
+        // Data
+        "json" | "map" => "application/json; charset=utf-8",
+        "jsonld" => "application/ld+json; charset=utf-8",
+        "webmanifest" => "application/manifest+json; charset=utf-8",
+        "geojson" => "application/geo+json; charset=utf-8",
+        "rss" => "application/rss+xml; charset=utf-8",
+        "atom" => "application/atom+xml; charset=utf-8",
+        "wasm" => "application/wasm",
+        // Images
+        "png" => "image/png",
+        "jpg" | "jpeg" | "jpe" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "svg" => "image/svg+xml; charset=utf-8",
+        "ico" => "image/vnd.microsoft.icon",
+        "bmp" => "image/bmp",
+        "tif" | "tiff" => "image/tiff",
+        "heic" => "image/heic",
+        "heif" => "image/heif",
+        "jxl" => "image/jxl",
+        // Fonts
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "eot" => "application/vnd.ms-fontobject",
+        // Audio
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" | "oga" => "audio/ogg",
+        "opus" => "audio/opus",
+        "flac" => "audio/flac",
+        "aac" => "audio/aac",
+        "m4a" => "audio/mp4",
+        "weba" => "audio/webm",
+        "mid" | "midi" => "audio/midi",
+        // Video
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "ogv" => "video/ogg",
+        "mov" => "video/quicktime",
+        "avi" => "video/x-msvideo",
+        "mpeg" | "mpg" => "video/mpeg",
+        "mkv" => "video/x-matroska",
+        "m3u8" => "application/vnd.apple.mpegurl",
+        "ts" => "video/mp2t",
+        // Documents
+        "pdf" => "application/pdf",
+        "rtf" => "application/rtf",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "odt" => "application/vnd.oasis.opendocument.text",
+        "ods" => "application/vnd.oasis.opendocument.spreadsheet",
+        "odp" => "application/vnd.oasis.opendocument.presentation",
+        "epub" => "application/epub+zip",
+        // Archives
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "bz2" => "application/x-bzip2",
+        "xz" => "application/x-xz",
+        "7z" => "application/x-7z-compressed",
+        "rar" => "application/vnd.rar",
+        "br" => "application/x-brotli",
+        "zst" => "application/zstd",
+        // Other
+        "bin" | "exe" | "dll" | "so" | "dmg" | "iso" => "application/octet-stream",
+        "apk" => "application/vnd.android.package-archive",
+        "pem" | "crt" => "application/x-pem-file",
+        "der" | "cer" => "application/pkix-cert",
+        _ => return None,
+    };
+    Some(media_type)
+}
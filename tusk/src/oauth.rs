@@ -0,0 +1,403 @@
+use brackets::{JsonObject, ToJson};
+use chrono::Utc;
+use openssl::{
+    bn::BigNum,
+    hash::MessageDigest,
+    pkey::{PKey, Public},
+    rsa::Rsa,
+    sign::Verifier,
+};
+
+use crate::{
+    auth::{decode_base64, encode_base64_url},
+    urlencoded::encode_url_component,
+    JsonField, JsonNode, Request, Response, ResponseStatusCode, RouteError,
+};
+
+/// Errors that can occur during an OAuth2 / OIDC sign in.
+#[derive(Debug)]
+pub enum OAuthError {
+    /// The `state` sent back by the provider did not match,
+    /// or the sign in cookie was missing.
+    InvalidState,
+    /// The callback did not include a code.
+    MissingCode,
+    /// The provider returned an error. Syntax is (error).
+    ProviderError(String),
+    /// A token or token response could not be parsed.
+    MalformedToken,
+    /// The ID token was signed with an algorithm other than RS256.
+    UnsupportedAlgorithm(String),
+    InvalidSignature,
+    /// A claim did not match. Syntax is (claim).
+    InvalidClaim(&'static str),
+    Expired,
+}
+impl From<OAuthError> for RouteError {
+    fn from(val: OAuthError) -> Self {
+        match val {
+            OAuthError::InvalidState => RouteError::bad_request("Invalid sign in state."),
+            OAuthError::MissingCode => RouteError::bad_request("Missing authorization code."),
+            OAuthError::ProviderError(e) => RouteError::bad_request(&format!("Sign in failed: {}", e)),
+            OAuthError::MalformedToken => RouteError::unauthorized("Malformed token."),
+            OAuthError::UnsupportedAlgorithm(a) => {
+                RouteError::unauthorized(&format!("Unsupported token algorithm {}.", a))
+            }
+            OAuthError::InvalidSignature => RouteError::unauthorized("Invalid token signature."),
+            OAuthError::InvalidClaim(c) => RouteError::unauthorized(&format!("Invalid token claim {}.", c)),
+            OAuthError::Expired => RouteError::unauthorized("Token expired."),
+        }
+    }
+}
+
+/// An OAuth2 provider using the authorization code flow with PKCE.
+///
+/// A sign in is performed in two routes:
+/// 1. Return [`OAuthProvider::redirect`] to send the user to the provider.
+///    The state, PKCE verifier and nonce are stored in a cookie.
+/// 2. On the redirect URI, call [`OAuthProvider::callback`] to check the
+///    state, then send the [`TokenRequest`] from [`OAuthProvider::token_request`]
+///    with your HTTP client and parse the result using [`TokenResponse::from_json`].
+///    For OIDC providers, validate the ID token with an [`IdTokenValidator`].
+pub struct OAuthProvider {
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+}
+impl OAuthProvider {
+    const COOKIE_NAME: &'static str = "tusk_oauth";
+    const COOKIE_MAX_AGE: i32 = 600;
+
+    /// Creates a new provider with no scopes.
+    pub fn new<A: AsRef<str>, B: AsRef<str>, C: AsRef<str>, D: AsRef<str>>(
+        client_id: A,
+        authorization_endpoint: B,
+        token_endpoint: C,
+        redirect_uri: D,
+    ) -> OAuthProvider {
+        OAuthProvider {
+            client_id: client_id.as_ref().to_string(),
+            client_secret: None,
+            authorization_endpoint: authorization_endpoint.as_ref().to_string(),
+            token_endpoint: token_endpoint.as_ref().to_string(),
+            redirect_uri: redirect_uri.as_ref().to_string(),
+            scopes: Vec::new(),
+        }
+    }
+
+    /// Creates a provider for Sign in with Google, requesting
+    /// the `openid`, `email` and `profile` scopes.
+    pub fn google<A: AsRef<str>, B: AsRef<str>>(client_id: A, redirect_uri: B) -> OAuthProvider {
+        OAuthProvider::new(
+            client_id,
+            "https://accounts.google.com/o/oauth2/v2/auth",
+            "https://oauth2.googleapis.com/token",
+            redirect_uri,
+        )
+        .scope("openid")
+        .scope("email")
+        .scope("profile")
+    }
+
+    /// Creates a provider for Sign in with GitHub, requesting
+    /// the `read:user` and `user:email` scopes.
+    pub fn github<A: AsRef<str>, B: AsRef<str>>(client_id: A, redirect_uri: B) -> OAuthProvider {
+        OAuthProvider::new(
+            client_id,
+            "https://github.com/login/oauth/authorize",
+            "https://github.com/login/oauth/access_token",
+            redirect_uri,
+        )
+        .scope("read:user")
+        .scope("user:email")
+    }
+
+    /// Define the client secret. Can be chained.
+    pub fn client_secret<T: AsRef<str>>(mut self, secret: T) -> OAuthProvider {
+        self.client_secret = Some(secret.as_ref().to_string());
+        self
+    }
+
+    /// Add a scope to request. Can be chained.
+    pub fn scope<T: AsRef<str>>(mut self, scope: T) -> OAuthProvider {
+        self.scopes.push(scope.as_ref().to_string());
+        self
+    }
+
+    /// Generates a new state, PKCE verifier and nonce,
+    /// and the URL to send the user to.
+    pub fn authorization_request(&self) -> AuthorizationRequest {
+        let state = random_token();
+        let code_verifier = random_token();
+        let nonce = random_token();
+        let challenge = encode_base64_url(&openssl::sha::sha256(code_verifier.as_bytes()));
+        let separator = if self.authorization_endpoint.contains('?') { '&' } else { '?' };
+        let url = format!(
+            "{}{}response_type=code&client_id={}&redirect_uri={}&scope={}&state={}&nonce={}&code_challenge={}&code_challenge_method=S256",
+            self.authorization_endpoint,
+            separator,
+            encode_url_component(&self.client_id),
+            encode_url_component(&self.redirect_uri),
+            encode_url_component(&self.scopes.join(" ")),
+            state,
+            nonce,
+            challenge
+        );
+        AuthorizationRequest {
+            url,
+            state,
+            code_verifier,
+            nonce,
+        }
+    }
+
+    /// Creates a response which redirects the user to the provider
+    /// and stores the sign in details in a short-lived cookie.
+    pub fn redirect(&self) -> Response {
+        let request = self.authorization_request();
+        Response::data(Vec::new())
+            .status(ResponseStatusCode::Found)
+            .header("Location", &request.url)
+            .append_header(
+                "Set-Cookie",
+                format!(
+                    "{}={}; Max-Age={}; Path=/; HttpOnly; Secure; SameSite=Lax",
+                    Self::COOKIE_NAME,
+                    request.cookie_value(),
+                    Self::COOKIE_MAX_AGE
+                ),
+            )
+    }
+
+    /// Checks the callback request from the provider against
+    /// the cookie set by [`OAuthProvider::redirect`].
+    pub fn callback(&self, req: &Request) -> Result<AuthorizationCallback, OAuthError> {
+        if let Some(error) = req.query.get("error") {
+            return Err(OAuthError::ProviderError(error.to_string()));
+        }
+        let cookie = req.cookie(Self::COOKIE_NAME).ok_or(OAuthError::InvalidState)?;
+        let mut parts = cookie.split('.');
+        let (Some(state), Some(code_verifier), Some(nonce)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(OAuthError::InvalidState);
+        };
+        let sent_state = req.query.get("state").ok_or(OAuthError::InvalidState)?;
+        if sent_state.len() != state.len()
+            || !openssl::memcmp::eq(sent_state.as_bytes(), state.as_bytes())
+        {
+            return Err(OAuthError::InvalidState);
+        }
+        Ok(AuthorizationCallback {
+            code: req.query.get("code").ok_or(OAuthError::MissingCode)?.to_string(),
+            code_verifier: code_verifier.to_string(),
+            nonce: nonce.to_string(),
+        })
+    }
+
+    /// Creates the request which exchanges an authorization
+    /// code for tokens.
+    pub fn token_request(&self, callback: &AuthorizationCallback) -> TokenRequest {
+        let mut body = format!(
+            "grant_type=authorization_code&code={}&redirect_uri={}&client_id={}&code_verifier={}",
+            encode_url_component(&callback.code),
+            encode_url_component(&self.redirect_uri),
+            encode_url_component(&self.client_id),
+            encode_url_component(&callback.code_verifier)
+        );
+        if let Some(secret) = &self.client_secret {
+            body += "&client_secret=";
+            body += &encode_url_component(secret);
+        }
+        TokenRequest {
+            url: self.token_endpoint.clone(),
+            body,
+        }
+    }
+
+    /// Creates a response header value which clears the sign in cookie.
+    pub fn clear_cookie() -> String {
+        format!("{}=; Max-Age=0; Path=/; HttpOnly; Secure; SameSite=Lax", Self::COOKIE_NAME)
+    }
+}
+
+/// A newly started sign in. See [`OAuthProvider::authorization_request`].
+pub struct AuthorizationRequest {
+    pub url: String,
+    pub state: String,
+    pub code_verifier: String,
+    pub nonce: String,
+}
+impl AuthorizationRequest {
+    /// The value stored in the sign in cookie.
+    pub fn cookie_value(&self) -> String {
+        format!("{}.{}.{}", self.state, self.code_verifier, self.nonce)
+    }
+}
+
+/// A verified callback from the provider.
+pub struct AuthorizationCallback {
+    pub code: String,
+    pub code_verifier: String,
+    /// The nonce to check against the ID token.
+    pub nonce: String,
+}
+
+/// A request to the token endpoint. Send it as a `POST`
+/// with `Content-Type: application/x-www-form-urlencoded`
+/// and `Accept: application/json`.
+pub struct TokenRequest {
+    pub url: String,
+    pub body: String,
+}
+
+/// The token endpoint's response.
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: Option<String>,
+    pub expires_in: Option<i64>,
+    pub refresh_token: Option<String>,
+    pub id_token: Option<String>,
+}
+impl TokenResponse {
+    /// Parses a JSON response from the token endpoint.
+    pub fn from_json(json: &str) -> Result<TokenResponse, OAuthError> {
+        let json = JsonNode::parse(json).map_err(|_| OAuthError::MalformedToken)?;
+        if let Some(error) = String::from_json_entry(&json, "error") {
+            return Err(OAuthError::ProviderError(error));
+        }
+        Ok(TokenResponse {
+            access_token: String::from_json_entry(&json, "access_token").ok_or(OAuthError::MalformedToken)?,
+            token_type: String::from_json_entry(&json, "token_type"),
+            expires_in: f64::from_json_entry(&json, "expires_in").map(|x| x as i64),
+            refresh_token: String::from_json_entry(&json, "refresh_token"),
+            id_token: String::from_json_entry(&json, "id_token"),
+        })
+    }
+}
+
+/// Validates RS256-signed OIDC ID tokens.
+pub struct IdTokenValidator {
+    pub issuer: String,
+    pub audience: String,
+    /// Allowed clock skew, in seconds, when checking expiry.
+    pub leeway: i64,
+    key: PKey<Public>,
+}
+impl IdTokenValidator {
+    /// Creates a validator from a PEM encoded public key.
+    pub fn from_pem<A: AsRef<str>, B: AsRef<str>>(
+        issuer: A,
+        audience: B,
+        pem: &[u8],
+    ) -> Option<IdTokenValidator> {
+        let key = PKey::public_key_from_pem(pem).ok()?;
+        Some(Self::from_key(issuer, audience, key))
+    }
+
+    /// Creates a validator from the `n` and `e` values of a JWK,
+    /// as published on the provider's JWKS endpoint.
+    pub fn from_jwk<A: AsRef<str>, B: AsRef<str>>(
+        issuer: A,
+        audience: B,
+        n: &str,
+        e: &str,
+    ) -> Option<IdTokenValidator> {
+        let n = BigNum::from_slice(&decode_base64(n)?).ok()?;
+        let e = BigNum::from_slice(&decode_base64(e)?).ok()?;
+        let key = PKey::from_rsa(Rsa::from_public_components(n, e).ok()?).ok()?;
+        Some(Self::from_key(issuer, audience, key))
+    }
+
+    fn from_key<A: AsRef<str>, B: AsRef<str>>(issuer: A, audience: B, key: PKey<Public>) -> IdTokenValidator {
+        IdTokenValidator {
+            issuer: issuer.as_ref().to_string(),
+            audience: audience.as_ref().to_string(),
+            leeway: 60,
+            key,
+        }
+    }
+
+    /// Validates the signature, issuer, audience, expiry and,
+    /// if provided, the nonce of an ID token.
+    pub fn validate(&self, token: &str, nonce: Option<&str>) -> Result<IdToken, OAuthError> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(OAuthError::MalformedToken);
+        };
+        let header_json = decode_segment(header)?;
+        let alg = String::from_json_entry(&header_json, "alg").ok_or(OAuthError::MalformedToken)?;
+        if alg != "RS256" {
+            return Err(OAuthError::UnsupportedAlgorithm(alg));
+        }
+
+        let signature = decode_base64(signature).ok_or(OAuthError::MalformedToken)?;
+        let mut verifier =
+            Verifier::new(MessageDigest::sha256(), &self.key).map_err(|_| OAuthError::InvalidSignature)?;
+        let signed = format!("{}.{}", header, payload);
+        if !verifier
+            .verify_oneshot(&signature, signed.as_bytes())
+            .unwrap_or(false)
+        {
+            return Err(OAuthError::InvalidSignature);
+        }
+
+        let claims = decode_segment(payload)?;
+        if claims.get("iss").and_then(JsonNode::as_str) != Some(self.issuer.as_str()) {
+            return Err(OAuthError::InvalidClaim("iss"));
+        }
+        let audience = match claims.get("aud") {
+            Some(JsonNode::Array(items)) => items.iter().any(|x| x.as_str() == Some(self.audience.as_str())),
+            aud => aud.and_then(JsonNode::as_str) == Some(self.audience.as_str()),
+        };
+        if !audience {
+            return Err(OAuthError::InvalidClaim("aud"));
+        }
+        let exp = f64::from_json_entry(&claims, "exp").ok_or(OAuthError::InvalidClaim("exp"))? as i64;
+        if exp + self.leeway < Utc::now().timestamp() {
+            return Err(OAuthError::Expired);
+        }
+        if let Some(nonce) = nonce {
+            if claims.get("nonce").and_then(JsonNode::as_str) != Some(nonce) {
+                return Err(OAuthError::InvalidClaim("nonce"));
+            }
+        }
+        Ok(IdToken {
+            subject: String::from_json_entry(&claims, "sub").ok_or(OAuthError::InvalidClaim("sub"))?,
+            email: String::from_json_entry(&claims, "email"),
+            claims,
+        })
+    }
+}
+
+/// A validated ID token.
+pub struct IdToken {
+    /// The `sub` claim, which identifies the user at the provider.
+    pub subject: String,
+    pub email: Option<String>,
+    /// Every claim, such as `claims.get("name")`.
+    pub claims: JsonNode,
+}
+impl IdToken {
+    /// The claims as a [`JsonObject`].
+    pub fn claims_object(&self) -> JsonObject {
+        JsonObject::from_string(&self.claims.to_json())
+    }
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    openssl::rand::rand_bytes(&mut bytes).expect("Could not generate random bytes!");
+    encode_base64_url(&bytes)
+}
+
+/// Decodes and parses a JSON segment of a token. Duplicate
+/// claims are refused, so there's no question which was signed.
+fn decode_segment(segment: &str) -> Result<JsonNode, OAuthError> {
+    let json = String::from_utf8(decode_base64(segment).ok_or(OAuthError::MalformedToken)?)
+        .map_err(|_| OAuthError::MalformedToken)?;
+    JsonNode::parse_strict(&json).map_err(|_| OAuthError::MalformedToken)
+}
@@ -0,0 +1,155 @@
+use std::marker::PhantomData;
+
+use tokio_postgres::types::ToSql;
+
+use crate::{
+    database::{PostgresReadError, PostgresWriteError},
+    query::{insert_statement, IntoSyntax},
+    DatabaseConnection, FromPostgres, PostgresReadable, PostgresTable, PostgresWriteRef,
+    WriteOutcome,
+};
+
+/// A model whose rows each belong to an owner, such as a user.
+/// Read and write it through [`DatabaseConnection::owned_by`],
+/// and enforce that with [`crate::DatabaseConfig::enforce_ownership`].
+pub trait PostgresOwned: PostgresTable {
+    /// The column holding the owner's id.
+    fn owner_column() -> &'static str;
+}
+
+/// Reads and writes of `T` limited to the rows of one owner.
+/// Created by [`DatabaseConnection::owned_by`].
+///
+/// The owner is bound after the query's own arguments, so
+/// queries keep numbering their placeholders from `$1`.
+pub struct OwnedScope<'a, T> {
+    db: &'a DatabaseConnection,
+    owner: &'a (dyn ToSql + Sync),
+    model: PhantomData<T>,
+}
+impl<'a, T: PostgresOwned + FromPostgres + PostgresReadable + 'static> OwnedScope<'a, T> {
+    /// Like [`DatabaseConnection::select_all`], for the owner's rows.
+    pub async fn select_all(
+        &self,
+        query: &str,
+        args: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<T>, PostgresReadError> {
+        let args = [args, &[self.owner]].concat();
+        Ok(self
+            .db
+            .query(self.select_query(query, args.len()), &args)
+            .await?
+            .iter()
+            .map(|x| T::try_from_postgres(x))
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Like [`DatabaseConnection::select_one`], for the owner's rows.
+    pub async fn select_one(
+        &self,
+        query: &str,
+        args: &[&(dyn ToSql + Sync)],
+    ) -> Result<T, PostgresReadError> {
+        let args = [args, &[self.owner]].concat();
+        self.db
+            .query(self.select_query(query, args.len()), &args)
+            .await?
+            .first()
+            .ok_or(PostgresReadError::NoResults)
+            .and_then(|x| Ok(T::try_from_postgres(x)?))
+    }
+
+    /// Like [`DatabaseConnection::insert_ref`], for a row of the
+    /// owner. The owner column is set to the scope's owner, in
+    /// place of any value in `write`.
+    pub async fn insert(&self, write: PostgresWriteRef<'_>) -> Result<T, PostgresWriteError> {
+        let mut fields = Vec::with_capacity(write.fields.len() + 1);
+        let mut args = Vec::with_capacity(write.arguments.len() + 1);
+        for (field, arg) in write.fields.iter().zip(write.arguments) {
+            if *field != T::owner_column() {
+                fields.push(*field);
+                args.push(arg);
+            }
+        }
+        fields.push(T::owner_column());
+        args.push(self.owner);
+        let query = DatabaseConnection::insert_query::<T>(&insert_statement(T::table_name(), &fields, args.len()));
+        let rows = self.db.query(query, &args).await?;
+        let row = rows.first().ok_or(PostgresWriteError::NoRows)?;
+        Ok(T::try_from_postgres(row)?)
+    }
+
+    /// Like [`DatabaseConnection::update_returning`], for the
+    /// owner's rows.
+    pub async fn update(
+        &self,
+        write: PostgresWriteRef<'_>,
+        condition: &str,
+        args: &[&(dyn ToSql + Sync)],
+    ) -> Result<WriteOutcome<T>, PostgresWriteError> {
+        let table = T::table_name();
+        let temp_table = format!("write_{}", table);
+        let (update_q, update_a) = write.into_update(table, args.len());
+        let args = [args, update_a.as_slice(), &[self.owner]].concat();
+        let query = format!(
+            "WITH {} AS ({} WHERE {}.{} = ${} AND ({}) RETURNING *) {}",
+            temp_table,
+            update_q,
+            table,
+            T::owner_column(),
+            args.len(),
+            condition,
+            DatabaseConnection::returning_query::<T>(&temp_table)
+        );
+        let rows = self.db.query(query, &args).await?;
+        Ok(WriteOutcome::from_rows(&rows)?)
+    }
+
+    /// Like [`DatabaseConnection::delete`], for the owner's rows.
+    pub async fn delete(
+        &self,
+        condition: &str,
+        args: &[&(dyn ToSql + Sync)],
+    ) -> Result<u64, PostgresWriteError> {
+        let table = T::table_name();
+        let args = [args, &[self.owner]].concat();
+        // The condition may include its own WHERE, so it
+        // selects the candidate rows in a subquery.
+        let query = format!(
+            "DELETE FROM {table} WHERE {table}.{} = ${} AND {table}.ctid IN (SELECT {table}.ctid FROM {table} {}) RETURNING 1",
+            T::owner_column(),
+            args.len(),
+            condition,
+        );
+        Ok(self.db.query(query, &args).await?.len() as u64)
+    }
+
+    /// The select prefix, reading from only the owner's rows
+    /// under the table's own name, followed by `query`.
+    fn select_query(&self, query: &str, owner_arg: usize) -> String {
+        let table = T::table_name();
+        format!(
+            "SELECT {} FROM (SELECT * FROM {table} WHERE {table}.{} = ${}) AS {table} {} {}",
+            T::read_fields().as_syntax(table),
+            T::owner_column(),
+            owner_arg,
+            T::joins().as_syntax(table),
+            query
+        )
+    }
+}
+
+impl DatabaseConnection {
+    /// Read and write the rows of `T` belonging to `owner`.
+    ///
+    /// ```ignore
+    /// let notes = db.owned_by::<Note>(&user.id).select_all("WHERE archived = false", &[]).await?;
+    /// ```
+    pub fn owned_by<'a, T: PostgresOwned>(&'a self, owner: &'a (dyn ToSql + Sync)) -> OwnedScope<'a, T> {
+        OwnedScope {
+            db: self,
+            owner,
+            model: PhantomData,
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use tokio_postgres::types::ToSql;
+
+use crate::{JsonField, JsonNode, PostgresWriteFields, PostgresWriteRef, RouteError};
+
+/// The fields of a model changed by a JSON Merge Patch, such as
+/// the body of a `PATCH` request. Write it with
+/// [`crate::DatabaseConnection::update_ref`]:
+///
+/// ```ignore
+/// let patch = req.body.to_postgres_patch::<User>()?;
+/// let user: User = db.update_ref(patch.as_write_ref(), "id = $1", &[&id]).await?;
+/// ```
+#[derive(Debug, Default)]
+pub struct PostgresPatch {
+    pub fields: Vec<&'static str>,
+    pub arguments: Vec<Box<dyn ToSql + Sync>>,
+}
+impl PostgresPatch {
+    pub fn new() -> PostgresPatch {
+        PostgresPatch::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Whether the patch changes `field`.
+    pub fn contains(&self, field: &str) -> bool {
+        self.fields.contains(&field)
+    }
+
+    /// Changes `field` to `value`, replacing any earlier value.
+    pub fn set<T: ToSql + Sync + 'static>(&mut self, field: &'static str, value: T) {
+        match self.fields.iter().position(|x| *x == field) {
+            Some(ix) => self.arguments[ix] = Box::new(value),
+            None => {
+                self.fields.push(field);
+                self.arguments.push(Box::new(value));
+            }
+        }
+    }
+
+    /// Borrows the patch for an update.
+    pub fn as_write_ref(&self) -> PostgresWriteRef<'_> {
+        PostgresWriteRef {
+            fields: &self.fields,
+            arguments: self.arguments.iter().map(|x| x.as_ref()).collect(),
+        }
+    }
+
+    /// Changes `field` as a JSON Merge Patch would: a missing
+    /// value is skipped, and `null` sets the column to `NULL`.
+    pub fn set_maybe<T: ToSql + Sync + 'static>(&mut self, field: &'static str, value: Maybe<T>) {
+        match value {
+            Maybe::Missing => {}
+            Maybe::Null => self.set(field, None::<T>),
+            Maybe::Value(value) => self.set(field, Some(value)),
+        }
+    }
+
+    /// Reads the new value of a field, responding with a 400
+    /// if it's invalid or `null`.
+    pub fn read<T: JsonField>(field: &str, json: &JsonNode) -> Result<T, RouteError> {
+        if json.is_null() {
+            return Err(RouteError::bad_request(&format!("{} can't be null.", field)));
+        }
+        T::from_json_node(json).ok_or_else(|| RouteError::bad_request(&format!("{} is invalid.", field)))
+    }
+
+    /// Reads the new value of an `Option` field, where `null`
+    /// clears it.
+    pub fn read_optional<T: JsonField>(field: &str, json: &JsonNode) -> Result<Option<T>, RouteError> {
+        match json {
+            JsonNode::Null => Ok(None),
+            json => Self::read(field, json).map(Some),
+        }
+    }
+}
+
+/// A model which can be changed by a JSON Merge Patch. Derive it
+/// with `#[derive(PostgresPatchable)]`.
+///
+/// Every key of the patch must be a write field of the model.
+/// Fields marked `#[tusk(no_patch)]`, such as ids and owners,
+/// can't be changed, and are refused with a 400 like unknown keys.
+/// Patches which change nothing are refused too, since there'd
+/// be nothing to update.
+pub trait PostgresPatchable: PostgresWriteFields {
+    /// Reads the fields changed by `json`, a JSON object.
+    fn patch_from_json(json: &JsonNode) -> Result<PostgresPatch, RouteError>;
+}
+
+/// A JSON value which tells a missing key apart from an explicit
+/// `null`, such as a field of a `PATCH` body where leaving a key
+/// out keeps the value, but `null` clears it.
+///
+/// ```ignore
+/// let nickname: Maybe<String> = Maybe::from_json_entry(&body, "nickname")
+///     .ok_or_else(|| RouteError::bad_request("nickname is invalid."))?;
+/// patch.set_maybe("nickname", nickname);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Maybe<T> {
+    /// The key wasn't sent.
+    #[default]
+    Missing,
+    /// The key was sent as `null`.
+    Null,
+    Value(T),
+}
+impl<T> Maybe<T> {
+    pub fn is_missing(&self) -> bool {
+        matches!(self, Maybe::Missing)
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Maybe::Null)
+    }
+
+    /// The value, if one was sent.
+    pub fn value(&self) -> Option<&T> {
+        match self {
+            Maybe::Value(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// `None` if the key wasn't sent, otherwise its value,
+    /// which is `None` for `null`.
+    pub fn into_option(self) -> Option<Option<T>> {
+        match self {
+            Maybe::Missing => None,
+            Maybe::Null => Some(None),
+            Maybe::Value(value) => Some(Some(value)),
+        }
+    }
+}
+/// Reads `Missing` rather than `None` for a missing key, so
+/// `None` only means the value has the wrong type.
+impl<T: JsonField> JsonField for Maybe<T> {
+    fn from_json_node(json: &JsonNode) -> Option<Self> {
+        match json {
+            JsonNode::Null => Some(Maybe::Null),
+            json => T::from_json_node(json).map(Maybe::Value),
+        }
+    }
+
+    fn from_json_entry(json: &JsonNode, key: &str) -> Option<Self> {
+        json.as_object()?;
+        json.get(key).map_or(Some(Maybe::Missing), Self::from_json_node)
+    }
+}
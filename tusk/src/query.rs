@@ -1,4 +1,17 @@
-use tokio_postgres::{types::ToSql, Row};
+use std::{
+    any::TypeId,
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    rc::Rc,
+};
+
+use tokio_postgres::{
+    types::{FromSql, ToSql, WasNull},
+    Row,
+};
+
+use crate::EncryptionError;
 
 pub trait IntoSyntax {
     fn as_syntax(&self, local_table: &str) -> String;
@@ -28,9 +41,58 @@ pub trait FromPostgres {
     where
         Self: Sized;
 }
+#[derive(Debug)]
 pub enum FromPostgresError {
-    InvalidType(&'static str),
+    InvalidType {
+        column: &'static str,
+        /// The Rust type of the field.
+        expected: &'static str,
+        /// The Postgres type of the column.
+        actual: String,
+    },
+    /// The column was NULL, but the field isn't an `Option`.
+    UnexpectedNull(&'static str),
     MissingColumn(&'static str),
+    /// The column is `Encrypted`, but couldn't be decrypted.
+    Undecryptable(&'static str),
+}
+impl Display for FromPostgresError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FromPostgresError::InvalidType { column, expected, actual } => write!(
+                f,
+                "column {} is of type {}, which cannot be read as {}",
+                column, actual, expected
+            ),
+            FromPostgresError::UnexpectedNull(column) => {
+                write!(f, "column {} is NULL, but the field is not an Option", column)
+            }
+            FromPostgresError::MissingColumn(column) => write!(f, "column {} is missing", column),
+            FromPostgresError::Undecryptable(column) => write!(f, "column {} could not be decrypted", column),
+        }
+    }
+}
+impl std::error::Error for FromPostgresError {}
+
+/// Reads a column from a row, describing what went wrong if
+/// it can't be read as `T`. Used by the `FromPostgres` derive.
+pub fn try_get<'a, T: FromSql<'a>>(row: &'a Row, column: &'static str) -> Result<T, FromPostgresError> {
+    let Some(ix) = row.columns().iter().position(|x| x.name() == column) else {
+        return Err(FromPostgresError::MissingColumn(column));
+    };
+    row.try_get(ix).map_err(|err| {
+        if std::error::Error::source(&err).is_some_and(|x| x.is::<WasNull>()) {
+            FromPostgresError::UnexpectedNull(column)
+        } else if std::error::Error::source(&err).is_some_and(|x| x.is::<EncryptionError>()) {
+            FromPostgresError::Undecryptable(column)
+        } else {
+            FromPostgresError::InvalidType {
+                column,
+                expected: std::any::type_name::<T>(),
+                actual: row.columns()[ix].type_().name().to_string(),
+            }
+        }
+    })
 }
 
 /// A struct that defines how Tusk should join
@@ -64,7 +126,13 @@ impl PostgresJoin {
 pub enum PostgresFieldLocation {
     /// A field on the table itself.
     Local(&'static str),
-    /// An expression that can be evaluated.
+    /// An expression that can be evaluated. `{}` is replaced
+    /// with the local table's name.
+    ///
+    /// Expressions may reference bound arguments as `$1`, `$2`
+    /// and so on. Pass them to [`crate::DatabaseConnection::select_all_with`]
+    /// or [`crate::DatabaseConnection::select_one_with`], where they are
+    /// numbered independently of the query's own arguments.
     Expression(&'static str),
     /// A field on a foreign table.
     /// Syntax is (table, field).
@@ -187,26 +255,68 @@ pub struct PostgresWrite {
     pub arguments: Vec<Box<(dyn ToSql + Sync)>>,
 }
 impl PostgresWrite {
+    /// Borrows the arguments, without copying them.
+    pub fn as_write_ref(&self) -> PostgresWriteRef<'_> {
+        PostgresWriteRef {
+            fields: self.fields,
+            arguments: self.arguments.iter().map(|x| x.as_ref()).collect(),
+        }
+    }
     /// Converts the write struct into an insert statement
     pub fn into_insert(&self, table_name: &str) -> (String, Vec<&(dyn ToSql + Sync)>) {
-        (
-            format!(
-                "INSERT INTO {} ({}) VALUES ({})",
-                table_name,
-                self.fields.join(","),
-                (0..self.arguments.len())
-                    .map(|x| format!("${}", x + 1))
-                    .collect::<Vec<String>>()
-                    .join(",")
-            ),
-            self.arguments
-                .iter()
-                .map(|x| x.as_ref())
-                .collect::<Vec<&(dyn ToSql + Sync)>>(),
-        )
+        self.as_write_ref().into_insert(table_name)
     }
     /// Converts the write struct into a bulk insert statement
     pub fn into_bulk_insert(&self, table_name: &str) -> (String, Vec<&(dyn ToSql + Sync)>) {
+        self.as_write_ref().into_bulk_insert(table_name)
+    }
+    /// Converts the write struct into an update statement
+    pub fn into_update(
+        &self,
+        table_name: &str,
+        arg_offset: usize,
+    ) -> (String, Vec<&(dyn ToSql + Sync)>) {
+        self.as_write_ref().into_update(table_name, arg_offset)
+    }
+}
+
+/// The statement inserting one row of `fields`, with
+/// `arg_count` placeholders.
+pub(crate) fn insert_statement(table_name: &str, fields: &[&str], arg_count: usize) -> String {
+    format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        table_name,
+        fields.join(","),
+        (0..arg_count)
+            .map(|x| format!("${}", x + 1))
+            .collect::<Vec<String>>()
+            .join(",")
+    )
+}
+
+/// Like [`PostgresWrite`], but the arguments are borrowed
+/// from the struct being written. Creating one only allocates
+/// the argument list, instead of boxing every value, so
+/// prefer it for hot insert paths.
+///
+/// Obtain one with [`PostgresWriteableRef::write_ref`].
+#[derive(Debug)]
+pub struct PostgresWriteRef<'a> {
+    /// The fields that will be provided.
+    pub fields: &'a [&'static str],
+    /// The arguments to insert. This supports either
+    /// a single row or multiple rows.
+    ///
+    /// arguments.len() % fields.len() must always be 0.
+    pub arguments: Vec<&'a (dyn ToSql + Sync)>,
+}
+impl<'a> PostgresWriteRef<'a> {
+    /// Converts the write struct into an insert statement
+    pub fn into_insert(self, table_name: &str) -> (String, Vec<&'a (dyn ToSql + Sync)>) {
+        (insert_statement(table_name, self.fields, self.arguments.len()), self.arguments)
+    }
+    /// Converts the write struct into a bulk insert statement
+    pub fn into_bulk_insert(self, table_name: &str) -> (String, Vec<&'a (dyn ToSql + Sync)>) {
         if self.arguments.len() % self.fields.len() != 0 {
             panic!("For a bulk insert, arguments % fields must be 0.")
         }
@@ -226,18 +336,15 @@ impl PostgresWrite {
                 self.fields.join(","),
                 arg_groups.join(",")
             ),
-            self.arguments
-                .iter()
-                .map(|x| x.as_ref())
-                .collect::<Vec<&(dyn ToSql + Sync)>>(),
+            self.arguments,
         )
     }
     /// Converts the write struct into an update statement
     pub fn into_update(
-        &self,
+        self,
         table_name: &str,
         arg_offset: usize,
-    ) -> (String, Vec<&(dyn ToSql + Sync)>) {
+    ) -> (String, Vec<&'a (dyn ToSql + Sync)>) {
         if self.fields.len() != self.arguments.len() {
             panic!("Field length must equal argument length")
         }
@@ -250,10 +357,7 @@ impl PostgresWrite {
                     .collect::<Vec<String>>()
                     .join(",")
             ),
-            self.arguments
-                .iter()
-                .map(|x| x.as_ref())
-                .collect::<Vec<&(dyn ToSql + Sync)>>(),
+            self.arguments,
         )
     }
 }
@@ -267,6 +371,26 @@ pub trait PostgresTable {
     fn table_name() -> &'static str;
 }
 
+/// A model whose rows carry a version, for optimistic locking.
+/// Update it with [`crate::DatabaseConnection::update_versioned`],
+/// which only writes the row if it still has the version that was
+/// read, and moves it to the next version.
+///
+/// The version is an integer incremented on each write by
+/// default. A timestamp such as `updated_at` can be used instead
+/// by overriding [`PostgresVersioned::next_version`] with `now()`.
+pub trait PostgresVersioned: PostgresTable {
+    /// The column holding the version.
+    fn version_column() -> &'static str {
+        "version"
+    }
+
+    /// The SQL expression for the version after a write.
+    fn next_version() -> String {
+        format!("{} + 1", Self::version_column())
+    }
+}
+
 /// A trait for defining joins in Postgres.
 /// This is used for determining how to join
 /// tables. This is required for all Tusk
@@ -301,6 +425,140 @@ pub trait PostgresReadFields {
 /// For now, it may be implemented by deriving.
 pub trait PostgresReadable: PostgresReadFields + PostgresJoins {}
 
+thread_local! {
+    static SELECT_PREFIXES: RefCell<HashMap<(TypeId, TypeId), Rc<str>>> = RefCell::new(HashMap::new());
+}
+
+/// The `SELECT <fields> FROM <table> <joins>` prefix used to
+/// read `T`. The fields and joins are static, so this is only
+/// generated once per type (and worker thread), and reads just
+/// append their where clause.
+pub fn select_prefix<T: PostgresReadable + PostgresTable + 'static>() -> Rc<str> {
+    select_prefix_as::<T, T>()
+}
+
+/// The prefix used to read the fields of `P` from the table
+/// and joins of `M`. See [`select_prefix`].
+pub fn select_prefix_as<M: PostgresJoins + PostgresTable + 'static, P: PostgresReadFields + 'static>(
+) -> Rc<str> {
+    SELECT_PREFIXES.with(|prefixes| {
+        prefixes
+            .borrow_mut()
+            .entry((TypeId::of::<M>(), TypeId::of::<P>()))
+            .or_insert_with(|| {
+                format!(
+                    "SELECT {} FROM {} {}",
+                    P::read_fields().as_syntax(M::table_name()),
+                    M::table_name(),
+                    M::joins().as_syntax(M::table_name()),
+                )
+                .into()
+            })
+            .clone()
+    })
+}
+
+/// A row-level lock taken by a read, for read-then-write flows
+/// inside a transaction. Pass it to
+/// [`crate::DatabaseConnection::select_all_locked`] or
+/// [`crate::DatabaseConnection::select_one_locked`].
+///
+/// Only rows of the local table are locked, so outer joins
+/// don't prevent the lock.
+#[derive(Debug, Clone, Copy)]
+pub struct RowLock {
+    strength: &'static str,
+    wait: &'static str,
+}
+impl RowLock {
+    /// Lock rows for updating (`FOR UPDATE`).
+    pub fn update() -> RowLock {
+        RowLock { strength: "FOR UPDATE", wait: "" }
+    }
+
+    /// Lock rows against updates by others (`FOR SHARE`).
+    pub fn share() -> RowLock {
+        RowLock { strength: "FOR SHARE", wait: "" }
+    }
+
+    /// Skip rows locked by others instead of waiting. Can be chained.
+    pub fn skip_locked(mut self) -> RowLock {
+        self.wait = " SKIP LOCKED";
+        self
+    }
+
+    /// Fail instead of waiting for rows locked by others. Can be chained.
+    pub fn nowait(mut self) -> RowLock {
+        self.wait = " NOWAIT";
+        self
+    }
+
+    /// The locking clause for `table`.
+    pub fn clause(&self, table: &str) -> String {
+        let table = table.rsplit('.').next().unwrap_or(table);
+        format!("{} OF {}{}", self.strength, table, self.wait)
+    }
+}
+
+/// A `GROUP BY` clause listing every column `T` reads from its
+/// table or its joins, so expression fields can use aggregates.
+/// Place it in the query after the where clause:
+///
+/// ```ignore
+/// db.select_all::<OrgSummary>(&format!("WHERE active {}", group_by::<OrgSummary>()), &[])
+/// ```
+pub fn group_by<T: PostgresReadFields + PostgresTable>() -> String {
+    let columns = T::read_fields()
+        .iter()
+        .filter_map(|x| match x.location {
+            PostgresFieldLocation::Local(field) => Some(format!("{}.{}", T::table_name(), field)),
+            PostgresFieldLocation::Join(table, field) => Some(format!("{}.{}", table, field)),
+            PostgresFieldLocation::Expression(_) => None,
+        })
+        .collect::<Vec<String>>();
+    if columns.is_empty() {
+        String::new()
+    } else {
+        format!("GROUP BY {}", columns.join(","))
+    }
+}
+
+/// Adds `offset` to every `$n` placeholder in `sql`, skipping
+/// string literals and quoted identifiers. Used to number the
+/// arguments of expression fields after a query's own arguments.
+pub fn offset_placeholders(sql: &str, offset: usize) -> String {
+    if offset == 0 {
+        return sql.to_string();
+    }
+    let mut output = String::with_capacity(sql.len() + 8);
+    let mut quote = None;
+    let mut chars = sql.char_indices().peekable();
+    while let Some((ix, c)) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '$') if chars.peek().is_some_and(|(_, x)| x.is_ascii_digit()) => {
+                let start = ix + 1;
+                let mut end = start;
+                while let Some((jx, x)) = chars.peek() {
+                    if !x.is_ascii_digit() {
+                        break;
+                    }
+                    end = jx + 1;
+                    chars.next();
+                }
+                let index: usize = sql[start..end].parse().unwrap_or(0);
+                output.push('$');
+                output.push_str(&(index + offset).to_string());
+                continue;
+            }
+            _ => {}
+        }
+        output.push(c);
+    }
+    output
+}
+
 /// A trait for defining fields to write to
 /// in Postgres. This is required for all
 /// Tusk database operations.
@@ -321,6 +579,32 @@ pub trait PostgresWriteable: PostgresWriteFields + PostgresJoins {
     fn write(self) -> PostgresWrite;
 }
 
+/// Writes a model from borrowed values, without boxing each one.
+/// Derived along with [`PostgresWriteable`].
+pub trait PostgresWriteableRef: PostgresWriteable {
+    fn write_ref(&self) -> PostgresWriteRef<'_>;
+}
+
+/// Runs after a model is read from a row, such as to redact
+/// fields. Enable it with `#[tusk(after_read)]` on a struct
+/// deriving [`FromPostgres`], and it runs on every read.
+pub trait AfterRead {
+    fn after_read(&mut self);
+}
+
+/// Runs before a model is written, such as to normalize emails
+/// or hash passwords. Enable it with `#[tusk(before_write)]` on
+/// a struct deriving [`PostgresWriteable`], and it runs in
+/// [`PostgresWriteable::write`], which every write of the model
+/// goes through.
+///
+/// [`PostgresWriteableRef::write_ref`] only borrows the model, so
+/// it couldn't run the hook, and isn't derived for models with
+/// one.
+pub trait BeforeWrite {
+    fn before_write(&mut self);
+}
+
 /// A trait for defining a struct as bulk writeable.
 /// This is typically defined on collections of structs.
 /// Tusk includes a default implementation for Vec<T> where
@@ -0,0 +1,107 @@
+use crate::{Request, RequestType, Response, ResponseStatusCode};
+
+/// How [`CanonicalRedirects`] treats the `www.` subdomain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WwwRedirect {
+    /// Leave hosts as they are.
+    #[default]
+    Keep,
+    /// Redirect `example.com` to `www.example.com`.
+    Add,
+    /// Redirect `www.example.com` to `example.com`.
+    Remove,
+}
+
+/// Redirects requests to their canonical URL: to HTTPS, and
+/// with or without `www.`. Register it with
+/// [`crate::Server::canonical_redirects`].
+///
+/// The server itself only speaks HTTP, so HTTPS is detected from
+/// a header set by the TLS-terminating proxy (`X-Forwarded-Proto`
+/// by default). Only enable this behind a proxy which always sets
+/// it, since clients can send it themselves.
+///
+/// `GET` requests are redirected with a 301. Other
+/// methods get a 308, so clients repeat the method and body.
+#[derive(Debug, Clone)]
+pub struct CanonicalRedirects {
+    https: bool,
+    proxy_header: String,
+    www: WwwRedirect,
+}
+impl CanonicalRedirects {
+    /// Create redirects which change nothing.
+    pub fn new() -> CanonicalRedirects {
+        CanonicalRedirects {
+            https: false,
+            proxy_header: "x-forwarded-proto".to_string(),
+            www: WwwRedirect::Keep,
+        }
+    }
+
+    /// Redirect plain HTTP requests to HTTPS. Can be chained.
+    pub fn https(mut self) -> CanonicalRedirects {
+        self.https = true;
+        self
+    }
+
+    /// Define the header holding the scheme the client used.
+    /// Can be chained.
+    pub fn proxy_header(mut self, header: &str) -> CanonicalRedirects {
+        self.proxy_header = header.to_lowercase();
+        self
+    }
+
+    /// Define how the `www.` subdomain is treated. Can be chained.
+    pub fn www(mut self, www: WwwRedirect) -> CanonicalRedirects {
+        self.www = www;
+        self
+    }
+
+    /// The redirect for a request, if it isn't canonical.
+    pub(crate) fn response(&self, request: &Request) -> Option<Response> {
+        let host = request.headers.get("host")?.trim();
+        let secure = request
+            .headers
+            .get(&self.proxy_header)
+            .and_then(|x| x.split(',').next())
+            .is_some_and(|x| x.trim().eq_ignore_ascii_case("https"));
+        let has_www = host.len() > 4 && host[..4].eq_ignore_ascii_case("www.");
+        let canonical_host = match self.www {
+            WwwRedirect::Add if !has_www => format!("www.{}", host),
+            WwwRedirect::Remove if has_www => host[4..].to_string(),
+            _ => host.to_string(),
+        };
+        let upgrade = self.https && !secure;
+        if !upgrade && canonical_host == host {
+            return None;
+        }
+
+        let scheme = if self.https || secure { "https" } else { "http" };
+        let mut location = format!("{}://{}{}", scheme, canonical_host, request.path);
+        if request.path.is_empty() {
+            location.push('/');
+        }
+        if !request.query.is_empty() {
+            let mut query = request
+                .query
+                .iter()
+                .map(|(k, v)| if v.is_empty() { k.clone() } else { format!("{}={}", k, v) })
+                .collect::<Vec<String>>();
+            query.sort();
+            location.push('?');
+            location.push_str(&query.join("&"));
+        }
+        let status = if request.request_type == RequestType::Get {
+            ResponseStatusCode::MovedPermanently
+        } else {
+            ResponseStatusCode::PermanentRedirect
+        };
+        Some(Response::data(Vec::new()).status(status).header("Location", location))
+    }
+}
+impl Default for CanonicalRedirects {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,189 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{apikey::to_hex, DatabaseConnection, Request, RouteError};
+
+/// Where a [`ReplayGuard`] remembers the nonces it has seen.
+#[derive(Debug, Clone)]
+enum NonceStore {
+    /// In this process. Workers each have their own.
+    Memory(Arc<Mutex<MemoryNonces>>),
+    /// In a Postgres table, shared by every server.
+    Postgres(&'static str),
+}
+
+#[derive(Debug, Default)]
+struct MemoryNonces {
+    seen: HashMap<String, DateTime<Utc>>,
+    /// Expired nonces are removed once there are this many.
+    purge_at: usize,
+}
+
+/// Refuses requests whose nonce was already received, so a
+/// signed request can't be sent twice. Add it to a route with
+/// [`crate::Route::prevent_replays`], after
+/// [`crate::Route::verify_signature`], or call
+/// [`ReplayGuard::check`] from a handler.
+///
+/// Each request carries a unique nonce in a header, and a nonce
+/// is refused with a 409 for as long as the request could still
+/// be accepted. With a timestamp header, that's until the
+/// timestamp is out of the window; otherwise, for twice the
+/// window after the nonce is first seen.
+///
+/// Senders without a nonce header, like Stripe, sign a new
+/// timestamp into every request, so their signature header can
+/// be used as the nonce:
+///
+/// ```ignore
+/// let guard = ReplayGuard::postgres("webhook_nonces", "stripe-signature");
+/// server.register(stripe_webhook().verify_signature(SignatureVerifier::stripe(secret)).prevent_replays(guard));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReplayGuard {
+    store: NonceStore,
+    header: String,
+    timestamp_header: Option<String>,
+    window: Duration,
+}
+impl ReplayGuard {
+    /// Remember nonces in memory. They're forgotten on restart,
+    /// and with [`crate::Server::start_workers`] each worker
+    /// remembers its own, so use [`ReplayGuard::postgres`]
+    /// when that matters.
+    pub fn memory(header: &str) -> ReplayGuard {
+        ReplayGuard::new(NonceStore::Memory(Arc::default()), header)
+    }
+
+    /// Remember nonces in `table`. Create it using the statement
+    /// from [`ReplayGuard::create_table_sql`].
+    pub fn postgres(table: &'static str, header: &str) -> ReplayGuard {
+        ReplayGuard::new(NonceStore::Postgres(table), header)
+    }
+
+    fn new(store: NonceStore, header: &str) -> ReplayGuard {
+        ReplayGuard {
+            store,
+            header: header.to_lowercase(),
+            timestamp_header: None,
+            window: Duration::from_secs(300),
+        }
+    }
+
+    /// Read the time the request was sent, in Unix seconds,
+    /// from `header`, and refuse requests outside the window.
+    /// Can be chained.
+    pub fn timestamp_header(mut self, header: &str) -> ReplayGuard {
+        self.timestamp_header = Some(header.to_lowercase());
+        self
+    }
+
+    /// Define how far from now timestamps may be. Defaults to
+    /// 5 minutes, like [`crate::SignatureVerifier::tolerance`].
+    /// Can be chained.
+    pub fn window(mut self, window: Duration) -> ReplayGuard {
+        self.window = window;
+        self
+    }
+
+    /// The SQL needed to create the backing table.
+    pub fn create_table_sql(&self) -> String {
+        let table = match &self.store {
+            NonceStore::Postgres(table) => table,
+            NonceStore::Memory(_) => "nonces",
+        };
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                nonce TEXT PRIMARY KEY,
+                expires_at TIMESTAMPTZ NOT NULL
+            )",
+            table
+        )
+    }
+
+    /// Delete expired nonces. Expired nonces are replaced when
+    /// seen again anyway, so this only keeps the table small.
+    pub async fn purge_expired(&self, db: &DatabaseConnection) -> Result<u64, tokio_postgres::Error> {
+        match &self.store {
+            NonceStore::Postgres(table) => Ok(db
+                .query(
+                    format!("DELETE FROM {} WHERE expires_at < $1 RETURNING nonce", table),
+                    &[&Utc::now()],
+                )
+                .await?
+                .len() as u64),
+            NonceStore::Memory(nonces) => {
+                let mut nonces = nonces.lock().unwrap();
+                let count = nonces.seen.len();
+                let now = Utc::now();
+                nonces.seen.retain(|_, expires_at| *expires_at >= now);
+                Ok((count - nonces.seen.len()) as u64)
+            }
+        }
+    }
+
+    /// Records the request's nonce, failing with a 401 if it's
+    /// missing or out of the window, or a 409 if it was seen.
+    pub async fn check(&self, db: &DatabaseConnection, request: &Request) -> Result<(), RouteError> {
+        let nonce = request
+            .headers
+            .get(&self.header)
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .ok_or_else(|| RouteError::unauthorized("Missing request nonce."))?;
+        let now = Utc::now();
+        let window = chrono::Duration::seconds(self.window.as_secs() as i64);
+        let expires_at = match &self.timestamp_header {
+            Some(header) => {
+                let sent = request
+                    .headers
+                    .get(header)
+                    .and_then(|x| x.trim().parse::<i64>().ok())
+                    .ok_or_else(|| RouteError::unauthorized("Missing request timestamp."))?;
+                if now.timestamp().abs_diff(sent) > self.window.as_secs() {
+                    return Err(RouteError::unauthorized("Request timestamp is out of range."));
+                }
+                DateTime::from_timestamp(sent, 0).unwrap_or(now) + window
+            }
+            None => now + window + window,
+        };
+        // Nonces may be long, such as whole signature headers.
+        let nonce = to_hex(&openssl::sha::sha256(nonce.as_bytes()));
+        let claimed = match &self.store {
+            NonceStore::Memory(nonces) => {
+                let mut nonces = nonces.lock().unwrap();
+                if nonces.seen.len() >= nonces.purge_at {
+                    nonces.seen.retain(|_, expires_at| *expires_at >= now);
+                    nonces.purge_at = (nonces.seen.len() * 2).max(1024);
+                }
+                match nonces.seen.get(&nonce) {
+                    Some(seen_until) if *seen_until >= now => false,
+                    _ => {
+                        nonces.seen.insert(nonce, expires_at);
+                        true
+                    }
+                }
+            }
+            NonceStore::Postgres(table) => !db
+                .query(
+                    format!(
+                        "INSERT INTO {table} (nonce, expires_at) VALUES ($1, $2) ON CONFLICT (nonce) DO UPDATE SET expires_at = EXCLUDED.expires_at WHERE {table}.expires_at < $3 RETURNING nonce"
+                    ),
+                    &[&nonce, &expires_at, &now],
+                )
+                .await
+                .map_err(|err| RouteError::server_error("Could not check request nonce.").caused_by(err))?
+                .is_empty(),
+        };
+        if claimed {
+            Ok(())
+        } else {
+            Err(RouteError::conflict("Request was already received."))
+        }
+    }
+}
@@ -1,8 +1,8 @@
 use brackets::{ToJson, JsonArray, JsonObject};
-use chrono::{Utc, Datelike, Timelike};
-use crate::UrlEncoded;
+use crate::{json::write_json_string, urlencoded::decode_url_component, Authorization, FromUrlEncoded, JsonNode, PostgresPatch, PostgresPatchable, Services, UrlEncoded};
 
-use std::{collections::{HashMap, BTreeMap}, fmt::{Display, Formatter}, matches};
+use std::{any::{Any, TypeId}, cell::{Cell, RefCell}, collections::HashMap, fmt::{Debug, Display, Formatter}, future::Future, io, matches, net::SocketAddr, pin::Pin, rc::Rc, task::{ready, Context, Poll, Waker}};
+use tokio::{io::{AsyncRead, ReadBuf}, net::TcpStream};
 
 /// An incoming request. Information is extracted from 
 /// the HTTP request and placed nicely into the following fields.
@@ -12,10 +12,348 @@ use std::{collections::{HashMap, BTreeMap}, fmt::{Display, Formatter}, matches};
 #[derive(Debug)]
 pub struct Request {
     pub request_type: RequestType,
+    pub version: HttpVersion,
     pub path: String,
     pub query: HashMap<String, String>,
     pub headers: HashMap<String, String>,
     pub body: BodyContents,
+    /// The address of the connected client, if known.
+    pub remote_addr: Option<SocketAddr>,
+    /// Shared services registered with the server.
+    pub services: Rc<Services>,
+    /// Typed values attached by treatments or middleware,
+    /// such as the identity resolved from an API key.
+    pub extensions: Extensions,
+    /// Cancelled when the client disconnects before
+    /// the response is written.
+    pub cancellation: CancellationToken,
+    /// The unparsed body, when needed. See [`Request::raw_body`].
+    pub(crate) raw_body: Option<Vec<u8>>,
+    /// Why the body was refused without being parsed, such
+    /// as for exceeding the server's [`crate::JsonLimits`].
+    pub(crate) body_error: Option<RouteError>,
+}
+impl Request {
+    /// Copies everything except the body. Used to keep the
+    /// request details around after the request itself has
+    /// been moved into a route.
+    pub(crate) fn without_body(&self) -> Request {
+        Request {
+            request_type: self.request_type.clone(),
+            version: self.version,
+            path: self.path.clone(),
+            query: self.query.clone(),
+            headers: self.headers.clone(),
+            body: BodyContents::None,
+            remote_addr: self.remote_addr,
+            services: self.services.clone(),
+            extensions: Extensions::new(),
+            cancellation: self.cancellation.clone(),
+            raw_body: None,
+            body_error: None,
+        }
+    }
+
+    /// The body as it was received. Only kept for routes using
+    /// [`crate::Route::verify_signature`], since signatures
+    /// are made over the exact bytes sent, or
+    /// [`crate::Route::strict_json`], and for requests with an
+    /// `Idempotency-Key`.
+    pub fn raw_body(&self) -> Option<&[u8]> {
+        self.raw_body.as_deref()
+    }
+
+    /// Parses the request line and headers of a request.
+    /// The body is left empty and no services are attached;
+    /// the server fills both in once the head is parsed.
+    pub fn from_head(headers_content: &str) -> Request {
+        // Process headers
+        let req: Vec<String> = headers_content
+            .lines()
+            .map(|a| a.to_string())
+            .take_while(|a| !a.is_empty())
+            .collect();
+        let head = &req.first().map(String::as_str).unwrap_or("").split(' ').collect::<Vec<&str>>();
+        let version = HttpVersion::version_for_string(head.get(2).unwrap_or(&""));
+
+        // Proxies may send the absolute form (`http://host/path`),
+        // so split off the authority and keep the origin form.
+        let (authority, head_path) = split_request_target(head.get(1).unwrap_or(&"/"));
+        let path = head_path.split('?').collect::<Vec<&str>>();
+        let wo_query_sect = path[0];
+
+        let mut request = Request {
+            path: wo_query_sect.strip_suffix('/').unwrap_or(wo_query_sect).to_string(),
+            request_type: RequestType::type_for_method(head[0]),
+            version,
+            query: if let Some(q_d) = path.get(1) {
+                q_d.split('&')
+                    .map(|x| {
+                        let q = x.split('=').collect::<Vec<&str>>();
+                        (q[0].to_string(), q.get(1).unwrap_or(&"").to_string())
+                    })
+                    .collect()
+            } else {
+                HashMap::new()
+            },
+            headers: req
+                .iter()
+                .skip(1)
+                .filter_map(|a| {
+                    let (name, value) = a.split_once(':')?;
+                    Some((name.trim().to_lowercase(), value.trim().to_string()))
+                })
+                .collect(),
+            body: BodyContents::None,
+            remote_addr: None,
+            services: Rc::new(Services::new()),
+            extensions: Extensions::new(),
+            cancellation: CancellationToken::new(),
+            raw_body: None,
+            body_error: None,
+        };
+
+        // HTTP/1.0 clients may omit the Host header. When the target
+        // carried an authority, it takes precedence anyway.
+        if let Some(authority) = authority {
+            request.headers.insert("host".to_string(), authority);
+        }
+        request
+    }
+
+    /// Completes once the client disconnects, for work handed
+    /// off from the route. See [`CancellationToken::cancelled`].
+    pub fn cancelled(&self) -> impl Future<Output = ()> + 'static {
+        self.cancellation.cancelled()
+    }
+
+    /// The host the request was sent to, from the `Host`
+    /// header, without its port.
+    pub fn host(&self) -> Option<&str> {
+        let host = self.headers.get("host")?.trim();
+        // IPv6 addresses are bracketed, and contain colons.
+        match host.rfind(':') {
+            Some(ix) if !host[ix..].contains(']') => Some(&host[..ix]),
+            _ => Some(host),
+        }
+    }
+
+    /// Get a shared service registered with
+    /// [`crate::Server::add_service`], or a 500 error if
+    /// no service of this type was registered.
+    pub fn service<T: 'static>(&self) -> Result<&T, RouteError> {
+        self.services.require()
+    }
+
+    /// Get the value of a cookie sent with the request.
+    pub fn cookie<S: AsRef<str>>(&self, name: S) -> Option<String> {
+        self.headers.get("cookie")?.split(';').find_map(|x| {
+            let (key, value) = x.trim().split_once('=')?;
+            if key == name.as_ref() {
+                Some(value.to_string())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Parses the `Authorization` header, if one was sent.
+    /// See [`Authorization`] for the supported schemes.
+    pub fn authorization(&self) -> Option<Authorization> {
+        Authorization::from_header(self.headers.get("authorization")?)
+    }
+
+    /// Build a [`FromRequest`] type, such as a struct of
+    /// query parameters, from the request.
+    pub fn extract<T: FromRequest>(&self) -> Result<T, RouteError> {
+        T::from_request(self)
+    }
+
+    /// Reads one field for `#[derive(FromRequest)]`. `source` is
+    /// `query`, `header` or `cookie`. Returns `None` if it wasn't sent,
+    /// or a 400 if it couldn't be parsed.
+    #[doc(hidden)]
+    pub fn request_field<T: FromUrlEncoded>(&self, source: &str, name: &str) -> Result<Option<T>, RouteError> {
+        let value = match source {
+            "query" => self.query.get(name).map(|x| decode_url_component(x)),
+            "header" => self.headers.get(&name.to_lowercase()).cloned(),
+            _ => self.cookie(name),
+        };
+        match value {
+            Some(value) => T::from_url_encoded(&value)
+                .map(Some)
+                .ok_or_else(|| RouteError::bad_request(&format!("Invalid {} {}.", request_field_label(source), name))),
+            None => Ok(None),
+        }
+    }
+}
+
+/// How a [`Request::request_field`] source is named in errors.
+fn request_field_label(source: &str) -> &str {
+    if source == "query" {
+        "query parameter"
+    } else {
+        source
+    }
+}
+
+/// A type built from a request's query parameters, headers
+/// and cookies, so handlers receive one typed value instead of
+/// looking each up. Derive it and read it with [`Request::extract`]:
+///
+/// ```ignore
+/// #[derive(FromRequest)]
+/// struct ListUsers {
+///     #[tusk(query)]
+///     page: Option<i32>,
+///     #[tusk(query = "q", default)]
+///     search: String,
+///     #[tusk(header = "x-tenant")]
+///     tenant: String,
+/// }
+///
+/// let params: ListUsers = req.extract()?;
+/// ```
+///
+/// Fields are parsed with [`FromUrlEncoded`]. Missing fields
+/// respond with a 400, unless they're an `Option` or marked
+/// `#[tusk(default)]`.
+pub trait FromRequest: Sized {
+    fn from_request(request: &Request) -> Result<Self, RouteError>;
+}
+
+/// Splits a request target into its authority (if the
+/// absolute form was used) and the origin-form path.
+fn split_request_target(target: &str) -> (Option<String>, String) {
+    let without_scheme = if let Some(t) = target.strip_prefix("http://") {
+        t
+    } else if let Some(t) = target.strip_prefix("https://") {
+        t
+    } else {
+        return (None, target.to_string());
+    };
+    match without_scheme.find(['/', '?']) {
+        Some(ix) if without_scheme[ix..].starts_with('/') => (
+            Some(without_scheme[..ix].to_string()),
+            without_scheme[ix..].to_string(),
+        ),
+        Some(ix) => (
+            Some(without_scheme[..ix].to_string()),
+            format!("/{}", &without_scheme[ix..]),
+        ),
+        None => (Some(without_scheme.to_string()), "/".to_string()),
+    }
+}
+
+/// Tracks whether the client behind a [`Request`] is still
+/// connected. Clones share the same state.
+///
+/// Once the client closes or resets the connection, the server
+/// stops polling the route, so work is abandoned at its next
+/// `.await`. Clients which shut down their sending side after the
+/// request are counted as gone too. Streaming routes aren't
+/// watched while they run, since their body is still being read,
+/// so they aren't cancelled until writing the response fails. Check
+/// [`CancellationToken::is_cancelled`] before work that doesn't
+/// yield, or await [`CancellationToken::cancelled`] in work
+/// handed off from the route.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Rc<CancellationState>);
+#[derive(Debug, Default)]
+struct CancellationState {
+    cancelled: Cell<bool>,
+    waiting: RefCell<Vec<Waker>>,
+}
+impl CancellationToken {
+    /// Create a token which is not cancelled.
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Mark the request as abandoned, waking anything
+    /// waiting on [`CancellationToken::cancelled`].
+    pub fn cancel(&self) {
+        self.0.cancelled.set(true);
+        for waker in self.0.waiting.take() {
+            waker.wake();
+        }
+    }
+
+    /// Whether the client has disconnected.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.get()
+    }
+
+    /// Completes once the client disconnects, for use with
+    /// `tokio::select!` to stop long-running work:
+    ///
+    /// ```ignore
+    /// tokio::select! {
+    ///     rows = export(&db) => ...,
+    ///     _ = req.cancelled() => return Err(RouteError::bad_request("Cancelled.")),
+    /// }
+    /// ```
+    pub fn cancelled(&self) -> impl Future<Output = ()> + 'static {
+        let token = self.clone();
+        std::future::poll_fn(move |cx| {
+            if token.is_cancelled() {
+                return Poll::Ready(());
+            }
+            let mut waiting = token.0.waiting.borrow_mut();
+            if !waiting.iter().any(|x| x.will_wake(cx.waker())) {
+                waiting.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+    }
+}
+
+/// A map of values keyed by their type, attached to a [`Request`].
+///
+/// Only one value of each type can be stored, so wrap values
+/// in a dedicated struct when the type alone is ambiguous.
+#[derive(Default)]
+pub struct Extensions {
+    values: HashMap<TypeId, Box<dyn Any>>,
+}
+impl Extensions {
+    /// Create an empty map.
+    pub fn new() -> Extensions {
+        Extensions { values: HashMap::new() }
+    }
+
+    /// Insert a value, returning the previous value of the same type.
+    pub fn insert<T: 'static>(&mut self, value: T) -> Option<T> {
+        self.values
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|x| x.downcast().ok())
+            .map(|x| *x)
+    }
+
+    /// Get a reference to the value of type `T`.
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.values.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    /// Get a mutable reference to the value of type `T`.
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.values.get_mut(&TypeId::of::<T>())?.downcast_mut()
+    }
+
+    /// Remove and return the value of type `T`.
+    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        self.values
+            .remove(&TypeId::of::<T>())
+            .and_then(|x| x.downcast().ok())
+            .map(|x| *x)
+    }
+}
+impl Debug for Extensions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.values.len())
+            .finish()
+    }
 }
 
 /// An outgoing response. This will be converted to HTTP
@@ -28,62 +366,32 @@ pub struct Request {
 pub struct Response {
     pub data: Vec<u8>,
     pub status: ResponseStatusCode,
-    pub headers: BTreeMap<String, String>,
+    pub headers: ResponseHeaders,
+    pub trailers: ResponseHeaders,
 }
 impl Response {
-    const WEEKDAY_MAP: [&'static str;7] = [
-        "Mon",
-        "Tue",
-        "Wed",
-        "Thu",
-        "Fri",
-        "Sat",
-        "Sun"
-    ];
-    const MONTH_MAP: [&'static str;12] = [
-        "Jan",
-        "Feb",
-        "Mar",
-        "Apr",
-        "May",
-        "Jun",
-        "Jul",
-        "Aug",
-        "Sep",
-        "Oct",
-        "Nov",
-        "Dec"
-    ];
-
     /// Create a new, empty response.
     pub fn new() -> Response {
         Response {
             data: Vec::new(),
             status: ResponseStatusCode::Ok,
-            headers: BTreeMap::new(),
+            headers: ResponseHeaders::new(),
+            trailers: ResponseHeaders::new(),
         }
     }
 
     /// Create a new response which transmits the data
     /// passed in as raw bytes.
     pub fn data(data: Vec<u8>) -> Response {
-        let cur_time = Utc::now();
         let len = data.len();
         Response {
             data,
             status: ResponseStatusCode::Ok,
-            headers: BTreeMap::new(),
+            headers: ResponseHeaders::new(),
+            trailers: ResponseHeaders::new(),
         }
         .header("Content-Type", "text/html").header("Content-Length", len.to_string())
-        .header("Date", format!("{}, {} {} {} {:0>2}:{:0>2}:{:0>2} GMT",
-            Self::WEEKDAY_MAP[cur_time.weekday().num_days_from_monday() as usize],
-            cur_time.day(),
-            Self::MONTH_MAP[(cur_time.month() - 1) as usize],
-            cur_time.year(),
-            cur_time.hour(),
-            cur_time.minute(),
-            cur_time.second()
-        ))
+        .header("Date", crate::httpdate::now())
         .header("Connection", "close")
     }
 
@@ -100,6 +408,31 @@ impl Response {
         Response::data(s.to_json().into_bytes()).header("Content-Type", "application/json; charset=utf-8")
     }
 
+    /// Create a new JSON response from a [`JsonObject`].
+    pub fn json_object(object: &JsonObject) -> Response {
+        Response::json(object)
+    }
+
+    /// Create a new JSON response from a [`JsonArray`].
+    pub fn json_array(array: &JsonArray) -> Response {
+        Response::json(array)
+    }
+
+    /// Create a new JSON array response from a slice, array
+    /// or `Vec` of items which implement [`ToJson`], without
+    /// copying them into a new `Vec`.
+    pub fn json_slice<S: ToJson>(items: &[S]) -> Response {
+        let mut output = String::from("[");
+        for (ix, item) in items.iter().enumerate() {
+            if ix > 0 {
+                output.push(',');
+            }
+            output.push_str(&item.to_json());
+        }
+        output.push(']');
+        Response::data(output.into_bytes()).header("Content-Type", "application/json; charset=utf-8")
+    }
+
     /// Create a new response which transmits HTML read
     /// from a file. Sends `Content-Type` as `text/html`.
     pub fn html(s: Vec<u8>) -> Response {
@@ -109,20 +442,24 @@ impl Response {
     /// Used internally to generate header data
     /// in properly formatted HTTP.
     pub fn get_header_data(&self) -> Vec<u8> {
-        let mut output = String::from("HTTP/1.1 ");
-        output += &self.status.http_string();
-        if !self.headers.is_empty() {
-            output += "\r\n";
-            output += &self.headers.iter().map(|x| {
-                let mut o = String::new();
-                o += x.0;
-                o += ": ";
-                o += x.1;
-                o
-            }).collect::<Vec<String>>().join("\n");
-        }
-        output += "\r\n\r\n";
-        output.into_bytes()
+        let mut output = Vec::new();
+        self.write_header_data(&mut output);
+        output
+    }
+
+    /// Appends the header data to an existing buffer,
+    /// so the server can reuse one buffer for every response.
+    pub fn write_header_data(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(b"HTTP/1.1 ");
+        output.extend_from_slice(self.status.http_string().as_bytes());
+        output.extend_from_slice(b"\r\n");
+        for (key, value) in self.headers.iter() {
+            output.extend_from_slice(key.as_bytes());
+            output.extend_from_slice(b": ");
+            output.extend_from_slice(value.as_bytes());
+            output.extend_from_slice(b"\r\n");
+        }
+        output.extend_from_slice(b"\r\n");
     }
 
     /// Set the status. Statuses in Tusk are strongly typed,
@@ -132,17 +469,110 @@ impl Response {
         self
     }
 
-    /// Set header values.
+    /// Set header values. Any existing values for
+    /// this header are replaced.
     pub fn header<S: AsRef<str>, T: AsRef<str>>(mut self, key: S, value: T) -> Response {
-        self.headers.insert(key.as_ref().to_string(), value.as_ref().to_string());
+        self.headers.insert(key, value);
         self
     }
 
+    /// Add a header value without replacing existing
+    /// values of the same name. Use this for headers
+    /// that may be sent multiple times, like `Set-Cookie`.
+    pub fn append_header<S: AsRef<str>, T: AsRef<str>>(mut self, key: S, value: T) -> Response {
+        self.headers.append(key, value);
+        self
+    }
+
+    /// Set a trailer value. Trailers are sent after the
+    /// body, so a response with trailers is sent using
+    /// chunked transfer encoding. HTTP/1.0 clients don't
+    /// support it, so they get the body without trailers.
+    pub fn trailer<S: AsRef<str>, T: AsRef<str>>(mut self, key: S, value: T) -> Response {
+        self.trailers.insert(key, value);
+        self
+    }
+
+    /// Whether this status may include a body.
+    fn allows_body(&self) -> bool {
+        !matches!(
+            self.status,
+            ResponseStatusCode::NoContent | ResponseStatusCode::NotModified
+        )
+    }
+
+    /// Sets the framing headers from the final body.
+    ///
+    /// Postfix functions and error formatters can replace
+    /// the body after it was created, so `Content-Length`
+    /// set by the constructors can't be trusted. Trailers are
+    /// dropped for HTTP/1.0 clients, which can't read chunks.
+    pub fn apply_framing(&mut self, version: HttpVersion) {
+        self.headers.remove("Content-Length");
+        self.headers.remove("Transfer-Encoding");
+        self.headers.remove("Trailer");
+        if version == HttpVersion::Http10 {
+            self.trailers = ResponseHeaders::new();
+        }
+        if !self.allows_body() {
+            self.data.clear();
+            self.trailers = ResponseHeaders::new();
+        } else if self.trailers.is_empty() {
+            self.headers.insert("Content-Length", self.data.len().to_string());
+        } else {
+            self.headers.insert("Transfer-Encoding", "chunked");
+            let names = self.trailers.iter().map(|(k, _)| k).collect::<Vec<&str>>().join(", ");
+            self.headers.insert("Trailer", names);
+        }
+    }
+
+    /// Used internally to serialize the full response,
+    /// including headers, body and any trailers.
+    pub fn into_http(self, version: HttpVersion) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut body = self.into_http_parts(version, &mut output);
+        output.append(&mut body);
+        output
+    }
+
+    /// Used internally to serialize the response without
+    /// copying the body. The headers are appended to `head`
+    /// and the body, including any chunk framing, is returned
+    /// so both can be written in a single vectored write.
+    pub fn into_http_parts(mut self, version: HttpVersion, head: &mut Vec<u8>) -> Vec<u8> {
+        self.apply_framing(version);
+        self.write_header_data(head);
+        if self.trailers.is_empty() {
+            return self.data;
+        }
+        let mut body = Vec::with_capacity(self.data.len() + 32);
+        if !self.data.is_empty() {
+            body.extend_from_slice(format!("{:X}\r\n", self.data.len()).as_bytes());
+            body.append(&mut self.data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"0\r\n");
+        for (key, value) in self.trailers.iter() {
+            body.extend_from_slice(format!("{}: {}\r\n", key, value).as_bytes());
+        }
+        body.extend_from_slice(b"\r\n");
+        body
+    }
+
+    /// Set headers which have not already been set.
+    pub fn apply_default_headers(&mut self, headers: &[(String, String)]) {
+        for (key, value) in headers {
+            if !self.headers.contains_key(key) {
+                self.headers.insert(key, value);
+            }
+        }
+    }
+
     /// Apply CORS values.
     pub fn apply_cors(&mut self, origin: &String, headers: &String) {
-        self.headers.insert("Access-Control-Allow-Origin".to_string(), origin.to_string());
-        self.headers.insert("Access-Control-Allow-Headers".to_string(), headers.to_string());
-        self.headers.insert("Access-Control-Allow-Methods".to_string(), "POST, PATCH, GET, OPTIONS, DELETE, PUT".to_string());
+        self.headers.insert("Access-Control-Allow-Origin", origin);
+        self.headers.insert("Access-Control-Allow-Headers", headers);
+        self.headers.insert("Access-Control-Allow-Methods", "POST, PATCH, GET, OPTIONS, DELETE, PUT");
     }
 
     /// Convert the body of the request into bytes, consuming
@@ -151,12 +581,166 @@ impl Response {
         self.data
     }
 }
+/// Values a route can return in place of a [`Response`],
+/// such as `Ok(())` for an empty 204 response or `Ok(Json(user))`.
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+/// Sends an empty 204 response.
+impl IntoResponse for () {
+    fn into_response(self) -> Response {
+        Response::new().status(ResponseStatusCode::NoContent)
+    }
+}
+/// Sends `text/plain`.
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        Response::string(self)
+    }
+}
+/// Sends `text/plain`.
+impl IntoResponse for &str {
+    fn into_response(self) -> Response {
+        Response::string(self)
+    }
+}
+/// Sends `application/octet-stream`.
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> Response {
+        Response::data(self).header("Content-Type", "application/octet-stream")
+    }
+}
+/// Sends JSON with a status code, such as `(ResponseStatusCode::Created, user)`.
+impl<T: ToJson> IntoResponse for (ResponseStatusCode, T) {
+    fn into_response(self) -> Response {
+        Response::json(&self.1).status(self.0)
+    }
+}
+/// Sends a 404 error for `None`.
+impl<T: IntoResponse> IntoResponse for Option<T> {
+    fn into_response(self) -> Response {
+        match self {
+            Some(value) => value.into_response(),
+            None => RouteError::not_found("Not found.").to_response(),
+        }
+    }
+}
+
+/// Sends any struct which implements [`ToJson`], as
+/// [`Response::json`] does.
+pub struct Json<T: ToJson>(pub T);
+impl<T: ToJson> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        Response::json(&self.0)
+    }
+}
+impl<T: ToJson> From<Json<T>> for Response {
+    fn from(value: Json<T>) -> Self {
+        value.into_response()
+    }
+}
+impl From<()> for Response {
+    fn from(value: ()) -> Self {
+        value.into_response()
+    }
+}
+
 impl Default for Response {
     fn default() -> Self {
         Response::new()
     }
 }
 
+/// The headers of an outgoing [`Response`].
+///
+/// Headers are kept in the order they were added and
+/// a header name may appear more than once, which is
+/// required for headers like `Set-Cookie`. Names are
+/// compared case-insensitively.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseHeaders {
+    values: Vec<(String, String)>,
+}
+impl ResponseHeaders {
+    /// Create an empty set of headers.
+    pub fn new() -> ResponseHeaders {
+        ResponseHeaders { values: Vec::new() }
+    }
+
+    /// Set a header, replacing any existing values
+    /// with the same name. The header keeps the position
+    /// of its first occurrence.
+    pub fn insert<S: AsRef<str>, T: AsRef<str>>(&mut self, key: S, value: T) {
+        let key = key.as_ref();
+        if let Some(ix) = self.position(key) {
+            self.values[ix].1 = value.as_ref().to_string();
+            let mut seen = false;
+            self.values.retain(|(k, _)| {
+                if !k.eq_ignore_ascii_case(key) {
+                    return true;
+                }
+                let keep = !seen;
+                seen = true;
+                keep
+            });
+        } else {
+            self.append(key, value);
+        }
+    }
+
+    /// Add a header value, keeping any existing values
+    /// with the same name.
+    pub fn append<S: AsRef<str>, T: AsRef<str>>(&mut self, key: S, value: T) {
+        self.values.push((key.as_ref().to_string(), value.as_ref().to_string()));
+    }
+
+    /// Get the first value for a header.
+    pub fn get<S: AsRef<str>>(&self, key: S) -> Option<&str> {
+        self.position(key.as_ref()).map(|ix| self.values[ix].1.as_str())
+    }
+
+    /// Get every value for a header, in the order they were added.
+    pub fn get_all<S: AsRef<str>>(&self, key: S) -> Vec<&str> {
+        self.values
+            .iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(key.as_ref()))
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// Whether a header has been set.
+    pub fn contains_key<S: AsRef<str>>(&self, key: S) -> bool {
+        self.position(key.as_ref()).is_some()
+    }
+
+    /// Remove every value for a header.
+    pub fn remove<S: AsRef<str>>(&mut self, key: S) {
+        self.values.retain(|(k, _)| !k.eq_ignore_ascii_case(key.as_ref()));
+    }
+
+    /// Iterate over all headers in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    fn position(&self, key: &str) -> Option<usize> {
+        self.values.iter().position(|(k, _)| k.eq_ignore_ascii_case(key))
+    }
+}
+
 /// RouteError is a struct that lets Tusk know
 /// that something went wrong in your application.
 /// It automatically can handle simple `String`s as errors,
@@ -174,6 +758,11 @@ pub struct RouteError {
     pub message: String,
     pub status_code: ResponseStatusCode,
     pub override_output: bool,
+    /// The underlying error, for logging. This isn't sent to the client.
+    pub source: Option<Box<dyn std::error::Error>>,
+    /// Machine-readable details sent with the message,
+    /// such as field errors or retry hints.
+    pub details: Option<JsonObject>,
 }
 impl RouteError {
     /// Sends a 400 error with a message.
@@ -182,6 +771,19 @@ impl RouteError {
             message: msg.to_string(),
             status_code: ResponseStatusCode::BadRequest,
             override_output: false,
+            source: None,
+            details: None,
+        }
+    }
+
+    /// Sends a 401 error with a message.
+    pub fn unauthorized(msg: &str) -> RouteError {
+        RouteError {
+            message: msg.to_string(),
+            status_code: ResponseStatusCode::Unauthorized,
+            override_output: false,
+            source: None,
+            details: None,
         }
     }
 
@@ -191,6 +793,8 @@ impl RouteError {
             message: msg.to_string(),
             status_code: ResponseStatusCode::Forbidden,
             override_output: false,
+            source: None,
+            details: None,
         }
     }
 
@@ -200,6 +804,8 @@ impl RouteError {
             message: msg.to_string(),
             status_code: ResponseStatusCode::NotFound,
             override_output: false,
+            source: None,
+            details: None,
         }
     }
 
@@ -209,6 +815,19 @@ impl RouteError {
             message: msg.to_string(),
             status_code: ResponseStatusCode::Conflict,
             override_output: false,
+            source: None,
+            details: None,
+        }
+    }
+
+    /// Sends a 415 error with a message.
+    pub fn unsupported_media_type(msg: &str) -> RouteError {
+        RouteError {
+            message: msg.to_string(),
+            status_code: ResponseStatusCode::UnsupportedMediaType,
+            override_output: false,
+            source: None,
+            details: None,
         }
     }
 
@@ -218,6 +837,8 @@ impl RouteError {
             message: msg.to_string(),
             status_code: ResponseStatusCode::InternalServerError,
             override_output: false,
+            source: None,
+            details: None,
         }
     }
 
@@ -229,21 +850,51 @@ impl RouteError {
             message: msg.to_string(),
             status_code,
             override_output: false,
+            source: None,
+            details: None,
         }
     }
 
+    /// Attach the underlying error, keeping the status code
+    /// and message. Can be chained.
+    pub fn caused_by<E: std::error::Error + 'static>(mut self, source: E) -> RouteError {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    /// Attach details, sent to the client as `details`. Can be chained.
+    pub fn details(mut self, details: JsonObject) -> RouteError {
+        self.details = Some(details);
+        self
+    }
+
     pub fn to_response(self) -> Response {
         let mut o = String::new();
         o += "{\n";
         o += "\t\"code\":\"";
         o += &self.status_code.code().to_string();
         o += "\",\n";
-        o += "\t\"message\":\"";
-        o += &self.message;
-        o += "\"\n}";
+        o += "\t\"message\":";
+        write_json_string(&mut o, &self.message);
+        if let Some(details) = &self.details {
+            o += ",\n\t\"details\":";
+            o += &details.to_json();
+        }
+        o += "\n}";
         Response::data(o.as_bytes().to_vec())
             .status(self.status_code)
-            .header("Content-Type", "Content-Type: application/json; charset=utf-8")
+            .header("Content-Type", "application/json; charset=utf-8")
+    }
+}
+
+impl Display for RouteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.status_code.code(), self.message)
+    }
+}
+impl std::error::Error for RouteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.as_deref()
     }
 }
 
@@ -283,6 +934,7 @@ pub enum ResponseStatusCode {
     RangeNotSatisfiable,
     ExpectationFailed,
     ImATeapot,
+    UnprocessableEntity,
     TooEarly,
     PreconditionRequired,
     TooManyRequests,
@@ -328,6 +980,7 @@ impl ResponseStatusCode {
             ResponseStatusCode::RangeNotSatisfiable => 416,
             ResponseStatusCode::ExpectationFailed => 417,
             ResponseStatusCode::ImATeapot => 418,
+            ResponseStatusCode::UnprocessableEntity => 422,
             ResponseStatusCode::TooEarly => 425,
             ResponseStatusCode::PreconditionRequired => 428,
             ResponseStatusCode::TooManyRequests => 429,
@@ -339,6 +992,54 @@ impl ResponseStatusCode {
             ResponseStatusCode::HttpVersionNotSupported => 505,
         }
     }
+    /// The status for a numeric code, if it's one of these.
+    pub fn from_code(code: i32) -> Option<ResponseStatusCode> {
+        Some(match code {
+            200 => ResponseStatusCode::Ok,
+            201 => ResponseStatusCode::Created,
+            202 => ResponseStatusCode::Accepted,
+            203 => ResponseStatusCode::NonAuthoritativeInformation,
+            204 => ResponseStatusCode::NoContent,
+            205 => ResponseStatusCode::ResetContent,
+            206 => ResponseStatusCode::PartialContent,
+            300 => ResponseStatusCode::MultipleChoices,
+            301 => ResponseStatusCode::MovedPermanently,
+            302 => ResponseStatusCode::Found,
+            303 => ResponseStatusCode::SeeOther,
+            304 => ResponseStatusCode::NotModified,
+            307 => ResponseStatusCode::TemporaryRedirect,
+            308 => ResponseStatusCode::PermanentRedirect,
+            400 => ResponseStatusCode::BadRequest,
+            401 => ResponseStatusCode::Unauthorized,
+            402 => ResponseStatusCode::PaymentRequired,
+            403 => ResponseStatusCode::Forbidden,
+            404 => ResponseStatusCode::NotFound,
+            405 => ResponseStatusCode::MethodNotAllowed,
+            406 => ResponseStatusCode::NotAcceptable,
+            408 => ResponseStatusCode::RequestTimeout,
+            409 => ResponseStatusCode::Conflict,
+            410 => ResponseStatusCode::Gone,
+            411 => ResponseStatusCode::LengthRequired,
+            412 => ResponseStatusCode::PreconditionFailed,
+            413 => ResponseStatusCode::PayloadTooLarge,
+            414 => ResponseStatusCode::UriTooLong,
+            415 => ResponseStatusCode::UnsupportedMediaType,
+            416 => ResponseStatusCode::RangeNotSatisfiable,
+            417 => ResponseStatusCode::ExpectationFailed,
+            418 => ResponseStatusCode::ImATeapot,
+            422 => ResponseStatusCode::UnprocessableEntity,
+            425 => ResponseStatusCode::TooEarly,
+            428 => ResponseStatusCode::PreconditionRequired,
+            429 => ResponseStatusCode::TooManyRequests,
+            500 => ResponseStatusCode::InternalServerError,
+            501 => ResponseStatusCode::NotImplemented,
+            502 => ResponseStatusCode::BadGateway,
+            503 => ResponseStatusCode::ServiceUnavailable,
+            504 => ResponseStatusCode::GatewayTimeout,
+            505 => ResponseStatusCode::HttpVersionNotSupported,
+            _ => return None,
+        })
+    }
     pub fn http_string(&self) -> String {
         match self {
             ResponseStatusCode::Ok => "200 OK".to_string(),
@@ -375,6 +1076,7 @@ impl ResponseStatusCode {
             ResponseStatusCode::RangeNotSatisfiable => "416 Range Not Satisfiable".to_string(),
             ResponseStatusCode::ExpectationFailed => "417 Expectation Failed".to_string(),
             ResponseStatusCode::ImATeapot => "418 I'm a teapot".to_string(),
+            ResponseStatusCode::UnprocessableEntity => "422 Unprocessable Entity".to_string(),
             ResponseStatusCode::TooEarly => "425 Too Early".to_string(),
             ResponseStatusCode::PreconditionRequired => "428 Precondition Required".to_string(),
             ResponseStatusCode::TooManyRequests => "429 Too Many Requests".to_string(),
@@ -397,6 +1099,9 @@ pub enum BodyContents {
     JsonArray(JsonArray),
     UrlEncoded(UrlEncoded),
     PlainText(String),
+    /// The unread body of a route using
+    /// [`crate::server::BodyMode::Streaming`].
+    Stream(BodyReader),
     None,
 }
 impl BodyContents {
@@ -406,25 +1111,42 @@ impl BodyContents {
     const TYPE_LD_JSON: &'static str = "application/ld+json";
     const TYPE_PLAIN_TEXT: &'static str = "text/plain";
 
+    /// Whether bodies of this type are parsed as JSON.
+    pub(crate) fn is_json_type(mime: &str) -> bool {
+        matches!(mime, BodyContents::TYPE_JSON | BodyContents::TYPE_LD_JSON)
+    }
+
+    /// Reads a body of the given type. Text bodies which aren't
+    /// valid UTF-8 are kept as [`BodyContents::Binary`]; use
+    /// [`BodyContents::try_type_from_mime`] to refuse them.
     pub fn type_from_mime(mime: &str, data: Vec<u8>) -> BodyContents {
-        match mime {
+        Self::read_typed(mime, data).unwrap_or_else(BodyContents::Binary)
+    }
+
+    /// Reads a body of the given type, failing with a 400 if a
+    /// text body isn't valid UTF-8.
+    pub fn try_type_from_mime(mime: &str, data: Vec<u8>) -> Result<BodyContents, RouteError> {
+        Self::read_typed(mime, data).map_err(|_| RouteError::bad_request("Body is not valid UTF-8."))
+    }
+
+    /// Reads a body, giving back the data of a text body
+    /// which isn't valid UTF-8.
+    fn read_typed(mime: &str, data: Vec<u8>) -> Result<BodyContents, Vec<u8>> {
+        let text = |data: Vec<u8>| String::from_utf8(data).map_err(|err| err.into_bytes());
+        Ok(match mime {
             BodyContents::TYPE_OCTET_STREAM => BodyContents::Binary(data),
             BodyContents::TYPE_JSON | BodyContents::TYPE_LD_JSON => {
-                let contents_string = String::from_utf8(data).unwrap();
+                let contents_string = text(data)?;
                 if contents_string.starts_with('[') {
                     BodyContents::JsonArray(JsonArray::from_string(&contents_string))
                 } else {
                     BodyContents::JsonObject(JsonObject::from_string(&contents_string))
                 }
             }
-            BodyContents::TYPE_PLAIN_TEXT => {
-                BodyContents::PlainText(String::from_utf8(data).unwrap())
-            }
-            BodyContents::TYPE_URL_ENCODED => {
-                BodyContents::UrlEncoded(UrlEncoded::from_string(String::from_utf8(data).unwrap()))
-            }
+            BodyContents::TYPE_PLAIN_TEXT => BodyContents::PlainText(text(data)?),
+            BodyContents::TYPE_URL_ENCODED => BodyContents::UrlEncoded(UrlEncoded::from_string(text(data)?)),
             _ => BodyContents::Binary(data),
-        }
+        })
     }
 
     pub fn to_json_object(&self) -> Result<&JsonObject, RouteError> {
@@ -451,6 +1173,11 @@ impl BodyContents {
             _ => Err(RouteError::bad_request("Expected JSON array")),
         }
     }
+    /// Reads a JSON Merge Patch body into the fields of `T`
+    /// it changes. See [`crate::PostgresPatchable`].
+    pub fn to_postgres_patch<T: PostgresPatchable>(&self) -> Result<PostgresPatch, RouteError> {
+        T::patch_from_json(&JsonNode::from(self.to_json_object()?))
+    }
     pub fn url_encoded(&self) -> Result<&UrlEncoded, RouteError> {
         match self {
             BodyContents::UrlEncoded(j) => Ok(j),
@@ -463,6 +1190,13 @@ impl BodyContents {
             _ => UrlEncoded::from_string("".to_string()),
         }
     }
+    /// Take the reader of a streamed body.
+    pub fn into_stream(self) -> Result<BodyReader, RouteError> {
+        match self {
+            BodyContents::Stream(reader) => Ok(reader),
+            _ => Err(RouteError::server_error("Route does not stream its body.")),
+        }
+    }
     pub fn as_bytes(self) -> Vec<u8> {
         match self {
             BodyContents::Binary(j) => j,
@@ -471,7 +1205,69 @@ impl BodyContents {
     }
 }
 
-#[derive(Debug)]
+/// Reads a request body as it arrives from the client,
+/// up to its `Content-Length`. Use it with
+/// `tokio::io::AsyncReadExt`.
+pub struct BodyReader {
+    /// Body bytes read along with the headers.
+    buffered: Vec<u8>,
+    position: usize,
+    /// Bytes still to be read from the stream.
+    remaining: usize,
+    stream: Rc<TcpStream>,
+}
+impl BodyReader {
+    pub(crate) fn new(buffered: Vec<u8>, content_length: usize, stream: Rc<TcpStream>) -> BodyReader {
+        BodyReader {
+            remaining: content_length.saturating_sub(buffered.len()),
+            buffered,
+            position: 0,
+            stream,
+        }
+    }
+
+    /// The number of body bytes not yet read.
+    pub fn remaining(&self) -> usize {
+        self.buffered.len() - self.position + self.remaining
+    }
+}
+impl AsyncRead for BodyReader {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.position < this.buffered.len() {
+            let len = buf.remaining().min(this.buffered.len() - this.position);
+            buf.put_slice(&this.buffered[this.position..this.position + len]);
+            this.position += len;
+            return Poll::Ready(Ok(()));
+        }
+        if this.remaining == 0 || buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            ready!(this.stream.poll_read_ready(cx))?;
+            let limit = buf.remaining().min(this.remaining);
+            match this.stream.try_read(buf.initialize_unfilled_to(limit)) {
+                Ok(0) => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+                Ok(read) => {
+                    buf.advance(read);
+                    this.remaining -= read;
+                    return Poll::Ready(Ok(()));
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+        }
+    }
+}
+impl Debug for BodyReader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BodyReader")
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
 #[derive(PartialEq)]
 pub enum RequestType {
     Get,
@@ -521,3 +1317,81 @@ impl Display for RequestType {
         })
     }
 }
+
+/// The HTTP version the client used for a request.
+///
+/// Tusk always responds with `HTTP/1.1` and closes the
+/// connection after each response, which HTTP/1.0 clients
+/// accept. HTTP/1.1 requests without a `Host` header are
+/// refused with a 400.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HttpVersion {
+    Http10,
+    Http11,
+}
+
+impl HttpVersion {
+    const HTTP_10: &'static str = "HTTP/1.0";
+    const HTTP_11: &'static str = "HTTP/1.1";
+
+    /// Parses the version from the request line.
+    /// Unknown or missing versions are treated as `HTTP/1.1`.
+    pub fn version_for_string(version: &str) -> HttpVersion {
+        match version.trim() {
+            HttpVersion::HTTP_10 => HttpVersion::Http10,
+            _ => HttpVersion::Http11,
+        }
+    }
+
+    /// Whether the client is required to send a `Host` header.
+    pub fn requires_host(&self) -> bool {
+        matches!(self, HttpVersion::Http11)
+    }
+}
+impl Display for HttpVersion {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            HttpVersion::Http10 => HttpVersion::HTTP_10,
+            HttpVersion::Http11 => HttpVersion::HTTP_11,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_are_read_with_or_without_a_space() {
+        let request = Request::from_head("GET / HTTP/1.1\r\nHost:example.com\r\nX-Time:  12:30:00 \r\nbroken\r\n\r\n");
+        assert_eq!(request.headers.get("host").map(String::as_str), Some("example.com"));
+        assert_eq!(request.headers.get("x-time").map(String::as_str), Some("12:30:00"));
+        assert_eq!(request.headers.len(), 2);
+    }
+
+    #[test]
+    fn request_targets_are_split_into_host_and_path() {
+        let split = |target: &str, host: Option<&str>, path: &str| {
+            assert_eq!(split_request_target(target), (host.map(str::to_string), path.to_string()), "{}", target);
+        };
+        split("/users?id=1", None, "/users?id=1");
+        split("*", None, "*");
+        split("http://example.com/users?id=1", Some("example.com"), "/users?id=1");
+        split("https://example.com:8443/", Some("example.com:8443"), "/");
+        split("http://example.com", Some("example.com"), "/");
+        split("http://example.com?id=1", Some("example.com"), "/?id=1");
+        split("ftp://example.com/", None, "ftp://example.com/");
+    }
+
+    #[test]
+    fn trailers_are_dropped_for_http_10() {
+        let response = || Response::data(b"body".to_vec()).trailer("Server-Timing", "db;dur=1");
+        let http11 = String::from_utf8(response().into_http(HttpVersion::Http11)).unwrap();
+        assert!(http11.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(http11.ends_with("4\r\nbody\r\n0\r\nServer-Timing: db;dur=1\r\n\r\n"));
+        let http10 = String::from_utf8(response().into_http(HttpVersion::Http10)).unwrap();
+        assert!(http10.contains("Content-Length: 4\r\n"));
+        assert!(!http10.contains("Transfer-Encoding") && !http10.contains("Trailer"));
+        assert!(http10.ends_with("\r\n\r\nbody"));
+    }
+}
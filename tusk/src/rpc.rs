@@ -0,0 +1,74 @@
+use std::fmt::Display;
+
+use brackets::ToJson;
+
+use crate::{json::write_json_string, JsonField, JsonNode, Request, RouteError};
+
+/// Sends the requests of a client generated by `#[rpc(client)]`,
+/// using any HTTP client.
+#[allow(async_fn_in_trait)]
+pub trait RpcTransport {
+    /// Sends a `POST` to `path` with a JSON `body`, returning the
+    /// response's status code and body.
+    async fn post(&self, path: &str, body: String) -> Result<(i32, String), RpcError>;
+}
+
+/// Errors returned by a client generated by `#[rpc(client)]`.
+#[derive(Debug)]
+pub enum RpcError {
+    /// The request couldn't be sent.
+    Transport(String),
+    /// The server responded with an error. Syntax is
+    /// (status code, message).
+    Status(i32, String),
+    /// The response couldn't be read as the method's output.
+    InvalidResponse,
+}
+impl Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcError::Transport(err) => write!(f, "could not send request: {}", err),
+            RpcError::Status(code, message) => write!(f, "{} {}", code, message),
+            RpcError::InvalidResponse => write!(f, "invalid response"),
+        }
+    }
+}
+impl std::error::Error for RpcError {}
+
+/// The JSON object an RPC route's arguments are read from.
+pub fn body(request: &Request) -> Result<JsonNode, RouteError> {
+    Ok(JsonNode::from(request.body.to_json_object()?))
+}
+
+/// Reads a required argument from an RPC request body.
+pub fn arg<T: JsonField>(body: &JsonNode, name: &str) -> Result<T, RouteError> {
+    T::from_json_entry(body, name).ok_or_else(|| RouteError::bad_request(&format!("{} is missing or invalid.", name)))
+}
+
+/// Reads an `Option` argument, which may be missing or `null`.
+pub fn optional_arg<T: JsonField>(body: &JsonNode, name: &str) -> Result<Option<T>, RouteError> {
+    match body.get(name) {
+        None | Some(JsonNode::Null) => Ok(None),
+        Some(_) => arg(body, name).map(Some),
+    }
+}
+
+/// Adds an argument to an RPC request body.
+pub fn write_arg<T: ToJson>(body: &mut String, name: &str, value: &T) {
+    if body.len() > 1 {
+        body.push(',');
+    }
+    write_json_string(body, name);
+    body.push(':');
+    body.push_str(&value.to_json());
+}
+
+/// Reads the output of an RPC method from a response.
+pub fn read_response<T: JsonField>((status, body): (i32, String)) -> Result<T, RpcError> {
+    let json = JsonNode::parse(&body);
+    if !(200..300).contains(&status) {
+        let message = json.ok().and_then(|x| String::from_json_entry(&x, "message")).unwrap_or(body);
+        return Err(RpcError::Status(status, message));
+    }
+    json.ok().and_then(|x| T::from_json_node(&x)).ok_or(RpcError::InvalidResponse)
+}
@@ -0,0 +1,239 @@
+use std::fmt::{Display, Formatter};
+
+use crate::{database::PostgresReadError, DatabaseConnection, PostgresTable};
+
+/// A column a model expects its table to have.
+#[derive(Debug)]
+pub struct ExpectedColumn {
+    pub name: &'static str,
+    /// The `information_schema` data types the field can be
+    /// read from, such as `text` or `integer`. Any type is
+    /// accepted if empty.
+    pub types: &'static [&'static str],
+    /// Whether the field can hold NULL (an `Option`).
+    pub nullable: bool,
+}
+
+/// A trait describing the columns a model reads and writes in
+/// its table, so they can be checked with [`verify`].
+///
+/// This may be derived. Fields read from an expression or a
+/// join are left out, and fields with types Tusk doesn't know
+/// accept any column type.
+pub trait PostgresSchema {
+    fn expected_columns() -> &'static [ExpectedColumn];
+}
+
+/// A difference between a model and its table.
+#[derive(Debug)]
+pub enum SchemaMismatch {
+    /// (Table)
+    MissingTable(String),
+    /// (Table, Column)
+    MissingColumn(String, String),
+    TypeMismatch {
+        table: String,
+        column: String,
+        expected: &'static [&'static str],
+        found: String,
+    },
+    /// The column allows NULL, but the field isn't an `Option`.
+    /// (Table, Column)
+    Nullable(String, String),
+}
+impl Display for SchemaMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaMismatch::MissingTable(table) => write!(f, "table {} does not exist", table),
+            SchemaMismatch::MissingColumn(table, column) => {
+                write!(f, "{}.{} does not exist", table, column)
+            }
+            SchemaMismatch::TypeMismatch { table, column, expected, found } => write!(
+                f,
+                "{}.{} is {}, expected {}",
+                table,
+                column,
+                found,
+                expected.join(" or ")
+            ),
+            SchemaMismatch::Nullable(table, column) => write!(
+                f,
+                "{}.{} allows NULL, but the field is not an Option",
+                table, column
+            ),
+        }
+    }
+}
+
+/// Why a schema check failed.
+#[derive(Debug)]
+pub enum SchemaError {
+    Query(PostgresReadError),
+    Mismatched(Vec<SchemaMismatch>),
+}
+impl From<tokio_postgres::Error> for SchemaError {
+    fn from(value: tokio_postgres::Error) -> Self {
+        SchemaError::Query(value.into())
+    }
+}
+
+/// Compares `T` with its table in `information_schema`, reporting
+/// missing columns, type mismatches and nullable columns read into
+/// fields which can't hold NULL.
+///
+/// Call this at startup or in CI, so drift between models and
+/// the database is found before traffic hits it.
+pub async fn verify<T: PostgresSchema + PostgresTable>(db: &DatabaseConnection) -> Result<(), SchemaError> {
+    let (schema, table) = match T::table_name().split_once('.') {
+        Some((schema, table)) => (Some(schema), table),
+        None => (None, T::table_name()),
+    };
+    let rows = db
+        .query(
+            "SELECT column_name::text, data_type::text, is_nullable = 'YES'
+                FROM information_schema.columns
+                WHERE table_name = $1 AND table_schema = COALESCE($2, current_schema())",
+            &[&table, &schema],
+        )
+        .await?;
+    if rows.is_empty() {
+        return Err(SchemaError::Mismatched(vec![SchemaMismatch::MissingTable(
+            T::table_name().to_string(),
+        )]));
+    }
+
+    let mut mismatches = Vec::new();
+    for expected in T::expected_columns() {
+        let Some(row) = rows.iter().find(|x| x.get::<_, String>(0) == expected.name) else {
+            mismatches.push(SchemaMismatch::MissingColumn(
+                T::table_name().to_string(),
+                expected.name.to_string(),
+            ));
+            continue;
+        };
+        let found: String = row.get(1);
+        if !expected.types.is_empty() && !expected.types.contains(&found.as_str()) {
+            mismatches.push(SchemaMismatch::TypeMismatch {
+                table: T::table_name().to_string(),
+                column: expected.name.to_string(),
+                expected: expected.types,
+                found,
+            });
+        } else if row.get::<_, bool>(2) && !expected.nullable {
+            mismatches.push(SchemaMismatch::Nullable(
+                T::table_name().to_string(),
+                expected.name.to_string(),
+            ));
+        }
+    }
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(SchemaError::Mismatched(mismatches))
+    }
+}
+
+/// Generates Rust models for every table in the current schema,
+/// for adopting Tusk on an existing database. See [`generate_model`].
+pub async fn generate_models(db: &DatabaseConnection) -> Result<String, PostgresReadError> {
+    let tables = db
+        .query(
+            "SELECT table_name::text FROM information_schema.tables
+                WHERE table_schema = current_schema() AND table_type = 'BASE TABLE'
+                ORDER BY table_name",
+            &[],
+        )
+        .await?;
+    let mut output = String::from(
+        "use tusk_rs::{\n    FromPostgres, PostgresJoins, PostgresReadFields, PostgresReadable, PostgresSchema,\n    PostgresTable, PostgresWriteFields, PostgresWriteable, ToJson,\n};\n",
+    );
+    for table in tables {
+        output += "\n";
+        output += &generate_model(db, table.get(0)).await?;
+    }
+    Ok(output)
+}
+
+/// Generates a Rust model for `table`, with the derives needed
+/// to read, write and verify it, and its [`PostgresTable`] impl.
+///
+/// Nullable columns become `Option`s. Columns with types Tusk
+/// can't map, or named after Rust keywords, are left commented
+/// out for you to handle.
+pub async fn generate_model(db: &DatabaseConnection, table: &str) -> Result<String, PostgresReadError> {
+    let columns = db
+        .query(
+            "SELECT column_name::text, data_type::text, is_nullable = 'YES'
+                FROM information_schema.columns
+                WHERE table_name = $1 AND table_schema = current_schema()
+                ORDER BY ordinal_position",
+            &[&table],
+        )
+        .await?;
+    if columns.is_empty() {
+        return Err(PostgresReadError::NoResults);
+    }
+
+    let struct_name = table
+        .split('_')
+        .map(|x| {
+            let mut chars = x.chars();
+            chars
+                .next()
+                .map(|c| c.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect::<String>();
+    let mut output = String::from(
+        "#[derive(Debug, ToJson, FromPostgres, PostgresReadFields, PostgresJoins, PostgresReadable, PostgresWriteFields, PostgresWriteable, PostgresSchema)]\n",
+    );
+    output += &format!("pub struct {} {{\n", struct_name);
+    for column in columns {
+        let name: String = column.get(0);
+        let data_type: String = column.get(1);
+        let nullable: bool = column.get(2);
+        match rust_type(&data_type) {
+            _ if RUST_KEYWORDS.contains(&name.as_str()) => {
+                output += &format!("    // {} ({}) is a Rust keyword\n", name, data_type);
+            }
+            Some(rust_type) if nullable => {
+                output += &format!("    pub {}: Option<{}>,\n", name, rust_type);
+            }
+            Some(rust_type) => output += &format!("    pub {}: {},\n", name, rust_type),
+            None => output += &format!("    // {} has an unsupported type ({})\n", name, data_type),
+        }
+    }
+    output += "}\n";
+    output += &format!(
+        "impl PostgresTable for {} {{\n    fn table_name() -> &'static str {{\n        \"{}\"\n    }}\n}}\n",
+        struct_name, table
+    );
+    Ok(output)
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+    "where", "while",
+];
+
+/// The Rust type for an `information_schema` data type.
+fn rust_type(data_type: &str) -> Option<&'static str> {
+    Some(match data_type {
+        "text" | "character varying" | "character" => "String",
+        "boolean" => "bool",
+        "smallint" => "i16",
+        "integer" => "i32",
+        "bigint" => "i64",
+        "real" => "f32",
+        "double precision" => "f64",
+        "timestamp with time zone" => "tusk_rs::chrono::DateTime<tusk_rs::chrono::Utc>",
+        "timestamp without time zone" => "tusk_rs::chrono::NaiveDateTime",
+        "date" => "tusk_rs::chrono::NaiveDate",
+        "time without time zone" => "tusk_rs::chrono::NaiveTime",
+        "interval" => "tusk_rs::Interval",
+        "bytea" => "Vec<u8>",
+        _ => return None,
+    })
+}
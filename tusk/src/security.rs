@@ -0,0 +1,106 @@
+/// A set of security-related headers applied to
+/// every response. Register it using [`crate::Server::security_headers`].
+///
+/// Headers set by a handler or by [`crate::Route::default_headers`]
+/// are never overwritten, so individual routes can override any
+/// value in the preset.
+pub struct SecurityHeaders {
+    pub strict_transport_security: Option<String>,
+    pub content_type_options: bool,
+    pub frame_options: Option<String>,
+    pub referrer_policy: Option<String>,
+    pub content_security_policy: Option<String>,
+}
+impl SecurityHeaders {
+    /// Creates an empty preset which sends no headers.
+    pub fn new() -> SecurityHeaders {
+        SecurityHeaders {
+            strict_transport_security: None,
+            content_type_options: false,
+            frame_options: None,
+            referrer_policy: None,
+            content_security_policy: None,
+        }
+    }
+
+    /// Creates a strict preset, suitable for APIs.
+    ///
+    /// - `Strict-Transport-Security: max-age=63072000; includeSubDomains`
+    /// - `X-Content-Type-Options: nosniff`
+    /// - `X-Frame-Options: DENY`
+    /// - `Referrer-Policy: no-referrer`
+    /// - `Content-Security-Policy: default-src 'none'; frame-ancestors 'none'`
+    pub fn strict() -> SecurityHeaders {
+        SecurityHeaders {
+            strict_transport_security: Some("max-age=63072000; includeSubDomains".to_string()),
+            content_type_options: true,
+            frame_options: Some("DENY".to_string()),
+            referrer_policy: Some("no-referrer".to_string()),
+            content_security_policy: Some("default-src 'none'; frame-ancestors 'none'".to_string()),
+        }
+    }
+
+    /// Define the `Strict-Transport-Security` value. Can be chained.
+    pub fn hsts<T: AsRef<str>>(mut self, value: T) -> SecurityHeaders {
+        self.strict_transport_security = Some(value.as_ref().to_string());
+        self
+    }
+
+    /// Define whether `X-Content-Type-Options: nosniff` is sent. Can be chained.
+    pub fn content_type_options(mut self, enabled: bool) -> SecurityHeaders {
+        self.content_type_options = enabled;
+        self
+    }
+
+    /// Define the `X-Frame-Options` value. Can be chained.
+    pub fn frame_options<T: AsRef<str>>(mut self, value: T) -> SecurityHeaders {
+        self.frame_options = Some(value.as_ref().to_string());
+        self
+    }
+
+    /// Define the `Referrer-Policy` value. Can be chained.
+    pub fn referrer_policy<T: AsRef<str>>(mut self, value: T) -> SecurityHeaders {
+        self.referrer_policy = Some(value.as_ref().to_string());
+        self
+    }
+
+    /// Define the `Content-Security-Policy` value. Can be chained.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tusk_rs::SecurityHeaders;
+    ///
+    /// SecurityHeaders::strict().content_security_policy("default-src 'self'");
+    /// ```
+    pub fn content_security_policy<T: AsRef<str>>(mut self, value: T) -> SecurityHeaders {
+        self.content_security_policy = Some(value.as_ref().to_string());
+        self
+    }
+
+    /// Converts the preset into header pairs.
+    pub fn headers(&self) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        if let Some(hsts) = &self.strict_transport_security {
+            headers.push(("Strict-Transport-Security".to_string(), hsts.clone()));
+        }
+        if self.content_type_options {
+            headers.push(("X-Content-Type-Options".to_string(), "nosniff".to_string()));
+        }
+        if let Some(frame_options) = &self.frame_options {
+            headers.push(("X-Frame-Options".to_string(), frame_options.clone()));
+        }
+        if let Some(referrer_policy) = &self.referrer_policy {
+            headers.push(("Referrer-Policy".to_string(), referrer_policy.clone()));
+        }
+        if let Some(csp) = &self.content_security_policy {
+            headers.push(("Content-Security-Policy".to_string(), csp.clone()));
+        }
+        headers
+    }
+}
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        SecurityHeaders::new()
+    }
+}
@@ -1,14 +1,64 @@
-use super::{BodyContents, Request, RequestType, Response, ResponseStatusCode, RouteError};
-use crate::DatabaseConnection;
-use crate::{config::DatabaseConfig, database::Database};
-use std::collections::HashMap;
+use super::{BodyContents, BodyReader, HttpVersion, Request, RequestType, Response, ResponseStatusCode, RouteError};
+use crate::hooks::{BudgetAlerts, ConnectionLeaks, LifecycleHooks, ResponseWritten};
+use crate::wellknown::{AcmeChallenges, WellKnown};
+use crate::{AuditLog, CanonicalRedirects, ConnectionLimit, DatabaseConnection, EncryptionKeys, FeatureFlags, IdempotencyStore, LoadShedding, MaintenanceMode, NetworkAcl, ReplayGuard, SecurityHeaders, Services, SignatureVerifier, Tenancy, Warmup};
+use crate::{config::DatabaseConfig, database::Database, json::write_json_string, jsoncheck::{check_strict_json, JsonLimits}};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::cell::{Cell, RefCell};
 use std::future::Future;
+use std::io::IoSlice;
+use std::net::SocketAddr;
 use std::pin::Pin;
+use std::task::Poll;
 use std::rc::Rc;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
 
-use brackets::JsonParseError;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use brackets::{JsonObject, JsonParseError, ToJson};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::OwnedSemaphorePermit;
+
+/// Requests with larger headers are cut off.
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// A connection waiting to be handled: its stream, address, when
+/// it was accepted, and its place under the connection limit.
+type PendingConnection = (TcpStream, SocketAddr, Instant, Option<OwnedSemaphorePermit>);
+
+/// Reusable read buffers, so reading a request doesn't
+/// allocate once the server has warmed up.
+#[derive(Default)]
+struct BufferPool {
+    buffers: RefCell<Vec<Vec<u8>>>,
+}
+impl BufferPool {
+    const MAX_POOLED: usize = 16;
+    /// Buffers which grew past this are dropped instead of kept.
+    const MAX_POOLED_CAPACITY: usize = 1024 * 1024;
+
+    fn take(&self) -> Vec<u8> {
+        self.buffers
+            .borrow_mut()
+            .pop()
+            .unwrap_or_else(|| Vec::with_capacity(READ_CHUNK_SIZE))
+    }
+
+    fn give(&self, mut buffer: Vec<u8>) {
+        let mut buffers = self.buffers.borrow_mut();
+        if buffers.len() < Self::MAX_POOLED && buffer.capacity() <= Self::MAX_POOLED_CAPACITY {
+            buffer.clear();
+            buffers.push(buffer);
+        }
+    }
+}
+
+thread_local! {
+    /// Set on threads created by `Server::start_workers`,
+    /// so their listeners can share a port.
+    static WORKER_THREAD: Cell<bool> = const { Cell::new(false) };
+}
 
 /// The core of Tusk, `Server` is a async/await ready
 /// web server.
@@ -17,13 +67,38 @@ use tokio::net::{TcpListener, TcpStream};
 /// into all routes as the final argument.
 pub struct Server<T, V> {
     routes: RouteStorage<T>,
+    hosts: Vec<(String, RouteStorage<T>)>,
+    route_registry: Option<RouteRegistry<T>>,
     listener: TcpListener,
     database: Database,
     treatment: AsyncTreatmentHandler<T, V>,
-    postfix: Option<fn(Response) -> Response>,
+    postfix: Option<AsyncPostfixHandler>,
     cors_origin: String,
     cors_headers: String,
+    security_headers: Vec<(String, String)>,
+    audit_log: Option<AuditLog>,
+    budget_alerts: Option<BudgetAlerts>,
+    services: Rc<Services>,
+    feature_flags: Option<FeatureFlags>,
+    maintenance: Option<MaintenanceMode>,
+    load_shedding: Option<LoadShedding>,
+    connection_limit: Option<ConnectionLimit>,
+    client_timeout: Option<Duration>,
+    handler_timeout: Option<Duration>,
+    json_limits: Option<JsonLimits>,
+    warmup: Option<Warmup>,
+    network_acl: Option<NetworkAcl>,
+    idempotency: Option<IdempotencyStore>,
+    tenancy: Option<Tenancy>,
+    redirects: Option<CanonicalRedirects>,
+    well_known: WellKnown,
+    modules: Vec<Box<dyn RouteModule<T>>>,
+    apps: Vec<Box<dyn MountedApp<T>>>,
+    shutdown: Option<Pin<Box<dyn Future<Output = ()>>>>,
+    hooks: LifecycleHooks,
+    buffers: BufferPool,
     debugging_enabled: bool,
+    mocking_enabled: bool,
     initialization_data: std::rc::Rc<V>
 }
 impl<T: 'static, V: 'static> Server<T, V> {
@@ -39,20 +114,91 @@ impl<T: 'static, V: 'static> Server<T, V> {
     ) -> Server<T, V> {
         Server {
             routes: RouteStorage::new(),
-            listener: TcpListener::bind(format!("127.0.0.1:{}", port))
-                .await
-                .unwrap(),
+            hosts: Vec::new(),
+            route_registry: None,
+            listener: Self::bind(port).await,
             database: Database::new(database).await.unwrap(),
             treatment,
             postfix: None,
             cors_origin: "*".to_string(),
             cors_headers: "Origin, X-Requested-With, Content-Type, Accept, Authorization"
                 .to_string(),
+            security_headers: Vec::new(),
+            audit_log: None,
+            budget_alerts: None,
+            services: Rc::new(Services::new()),
+            feature_flags: None,
+            maintenance: None,
+            load_shedding: None,
+            connection_limit: None,
+            client_timeout: None,
+            handler_timeout: None,
+            json_limits: None,
+            warmup: None,
+            network_acl: None,
+            idempotency: None,
+            tenancy: None,
+            redirects: None,
+            well_known: WellKnown::default(),
+            modules: Vec::new(),
+            apps: Vec::new(),
+            shutdown: None,
+            hooks: LifecycleHooks::default(),
+            buffers: BufferPool::default(),
             debugging_enabled: false,
+            mocking_enabled: false,
             initialization_data: Rc::new(initialization_data),
         }
     }
 
+    /// Runs several copies of a server, each on its own thread
+    /// with its own single-threaded runtime, so requests are handled
+    /// across cores. `build` is called once on each thread and should
+    /// create and configure the server, but not start it.
+    ///
+    /// Workers share the port using `SO_REUSEPORT`, so the kernel
+    /// balances connections between them. This is only supported
+    /// on Unix. Each worker has its own database pool.
+    ///
+    /// The threads are returned so they can be joined. This should be
+    /// called outside of a Tokio runtime.
+    pub fn start_workers<F, Fut>(workers: usize, build: F) -> Vec<std::thread::JoinHandle<()>>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Server<T, V>>,
+    {
+        let build = Arc::new(build);
+        (0..workers.max(1))
+            .map(|_| {
+                let build = build.clone();
+                std::thread::spawn(move || {
+                    WORKER_THREAD.with(|x| x.set(true));
+                    let runtime = tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .expect("Could not create worker runtime!");
+                    runtime.block_on(async move {
+                        let mut server = build().await;
+                        server.start().await;
+                    });
+                })
+            })
+            .collect()
+    }
+
+    async fn bind(port: i32) -> TcpListener {
+        let address = format!("127.0.0.1:{}", port);
+        #[cfg(unix)]
+        if WORKER_THREAD.with(|x| x.get()) {
+            let socket = tokio::net::TcpSocket::new_v4().unwrap();
+            socket.set_reuseaddr(true).unwrap();
+            socket.set_reuseport(true).unwrap();
+            socket.bind(address.parse().unwrap()).unwrap();
+            return socket.listen(1024).unwrap();
+        }
+        TcpListener::bind(address).await.unwrap()
+    }
+
     /// Enable debugging. This will enable printing verbose information.
     /// This is useful for debugging queries and other issues.
     pub fn enable_debugging(&mut self) {
@@ -64,9 +210,19 @@ impl<T: 'static, V: 'static> Server<T, V> {
         self.debugging_enabled = false
     }
 
+    /// Enable mocking. Routes with an example, set with
+    /// [`Route::example`], respond with it instead of running
+    /// the treatment and handler, and without a database query.
+    /// This lets frontends be built against a server before its
+    /// handlers are. Don't enable this in production.
+    pub fn enable_mocking(&mut self) {
+        self.mocking_enabled = true
+    }
+
     /// Register a [`Route`]. Routes should NOT be registered
     /// after calling `Server::start`, as all routes are sorted
-    /// for peformance when `start` is called.
+    /// for peformance when `start` is called. To change routes
+    /// while running, see [`Server::route_registry`].
     ///
     /// See [`Server::register`] for a better way to register routes.
     pub fn register(&mut self, r: Route<T>) {
@@ -83,25 +239,109 @@ impl<T: 'static, V: 'static> Server<T, V> {
     /// Note that this has no effect on performance, this just
     /// keeps your code organized.
     pub fn module(&mut self, prefix: &str, rs: Vec<Route<T>>) {
-        let mut applied_prefix = if prefix.ends_with('/') {
-            prefix[0..prefix.len()].to_string()
-        } else {
-            prefix.to_string()
-        };
-        applied_prefix = if !applied_prefix.starts_with('/') {
-            format!("/{}", applied_prefix)
-        } else {
-            applied_prefix
-        };
+        // The prefix is normalized once and shared by every route.
+        let applied_prefix = module_prefix(prefix);
         for mut r in rs {
-            r.path = format!("{}{}", applied_prefix, r.path);
+            let mut path = String::with_capacity(applied_prefix.len() + r.path.len());
+            path.push_str(&applied_prefix);
+            path.push_str(&r.path);
+            r.path = path;
             self.routes.add(r);
         }
     }
 
-    /// Add function that can modify all outgoing requests.
+    /// Serve routes from a [`RouteRegistry`], so they can be
+    /// changed while the server is running. Routes registered
+    /// on the server are moved into the registry by `start`.
+    pub fn route_registry(&mut self, registry: RouteRegistry<T>) {
+        self.route_registry = Some(registry);
+    }
+
+    /// Register a [`RouteModule`]'s routes under its prefix. Its
+    /// `on_start` hook runs when the server starts, and its
+    /// `on_shutdown` hook when it stops.
+    pub fn mount<M: RouteModule<T> + 'static>(&mut self, module: M) {
+        self.module(module.prefix(), module.routes());
+        self.modules.push(Box::new(module));
+    }
+
+    /// Serve a [`RouteModule`] only for requests whose `Host` is
+    /// `host`, such as `api.example.com`. Several modules can be
+    /// added for the same host.
+    ///
+    /// Requests to a host added here are only matched against its
+    /// modules; requests to any other host use the server's other
+    /// routes. Its hooks run like those of [`Server::mount`].
+    pub fn host<M: RouteModule<T> + 'static>(&mut self, host: &str, module: M) {
+        let ix = match self.hosts.iter().position(|(x, _)| x.eq_ignore_ascii_case(host)) {
+            Some(ix) => ix,
+            None => {
+                self.hosts.push((host.to_ascii_lowercase(), RouteStorage::new()));
+                self.hosts.len() - 1
+            }
+        };
+        let applied_prefix = module_prefix(module.prefix());
+        for mut r in module.routes() {
+            r.path.insert_str(0, &applied_prefix);
+            self.hosts[ix].1.add(r);
+        }
+        self.modules.push(Box::new(module));
+    }
+
+    /// The routes added with [`Server::host`] for the request's host.
+    fn host_routes(&self, request: &Request) -> Option<&RouteStorage<T>> {
+        let host = request.host()?;
+        self.hosts
+            .iter()
+            .find(|(x, _)| x.eq_ignore_ascii_case(host))
+            .map(|(_, routes)| routes)
+    }
+
+    /// Serve a [`SubApp`] under `prefix`. Its requests run its own
+    /// treatment, with initialization data made from this server's
+    /// by `adapt`. Routes registered on the server take precedence.
+    pub fn mount_app<U: 'static, W: 'static, F: FnOnce(&V) -> W>(
+        &mut self,
+        prefix: &str,
+        app: SubApp<U, W>,
+        adapt: F,
+    ) {
+        let applied_prefix = module_prefix(prefix);
+        let mut routes = app.routes;
+        for mut r in routes.take_all() {
+            r.path.insert_str(0, &applied_prefix);
+            routes.add(r);
+        }
+        routes.prep();
+        self.apps.push(Box::new(Mounted {
+            routes,
+            treatment: app.treatment,
+            initialization_data: Rc::new(adapt(&self.initialization_data)),
+        }));
+    }
+
+    /// Register a module, like [`Server::module`], and apply
+    /// default headers to every response generated by its routes.
+    /// This keeps headers like `Cache-Control` out of each handler.
+    ///
+    /// Headers set by the handler or by [`Route::default_headers`]
+    /// take precedence over the module's headers.
+    pub fn module_with_headers(&mut self, prefix: &str, rs: Vec<Route<T>>, headers: &[(&str, &str)]) {
+        self.module(
+            prefix,
+            rs.into_iter().map(|r| r.default_headers(headers)).collect(),
+        );
+    }
+
+    /// Add an async function that can modify all outgoing responses.
     /// Useful for setting headers.
-    pub fn set_postfix(&mut self, f: fn(Response) -> Response) {
+    ///
+    /// The function receives the original [`Request`] (without its body),
+    /// the [`Response`] produced by the route, and the time elapsed
+    /// since the request was read. It runs for error responses too.
+    ///
+    /// See the `#[postfix]` macro for the easiest way to create one.
+    pub fn set_postfix(&mut self, f: AsyncPostfixHandler) {
         self.postfix = Some(f);
     }
 
@@ -111,170 +351,893 @@ impl<T: 'static, V: 'static> Server<T, V> {
         self.cors_headers = headers.to_string();
     }
 
+    /// Apply a [`SecurityHeaders`] preset to every response.
+    /// Routes can override individual headers by setting them
+    /// in the handler or with [`Route::default_headers`].
+    pub fn security_headers(&mut self, headers: SecurityHeaders) {
+        self.security_headers = headers.headers();
+    }
+
+    /// Register a shared service, such as a cache client or mailer.
+    /// Handlers resolve services by type using [`Request::service`].
+    /// Only one service of each type can be registered.
+    pub fn add_service<S: 'static>(&mut self, service: S) {
+        Rc::make_mut(&mut self.services).insert(service);
+    }
+
+    /// Register [`FeatureFlags`], making them available to
+    /// [`Request::feature`]. The flags are refreshed from the
+    /// database before handling a request once their TTL expires.
+    pub fn feature_flags(&mut self, flags: FeatureFlags) {
+        self.add_service(flags.clone());
+        self.feature_flags = Some(flags);
+    }
+
+    /// Register a [`MaintenanceMode`] switch. While it's on,
+    /// routes which aren't allowed are answered with a 503.
+    /// The switch is also available to handlers as a service.
+    pub fn maintenance_mode(&mut self, mode: MaintenanceMode) {
+        self.add_service(mode.clone());
+        self.maintenance = Some(mode);
+    }
+
+    /// Respond with a 503 when no database connection is free in
+    /// time, instead of waiting. See [`LoadShedding`].
+    pub fn load_shedding(&mut self, shedding: LoadShedding) {
+        self.load_shedding = Some(shedding);
+    }
+
+    /// Cap how many connections are held at once, queueing
+    /// or rejecting the rest with a 503. See [`ConnectionLimit`].
+    pub fn connection_limit(&mut self, limit: ConnectionLimit) {
+        self.connection_limit = Some(limit);
+    }
+
+    /// Limit how long a client may take to send its request, and
+    /// to receive its response. Each worker handles one connection
+    /// at a time while others wait in the listen queue, so a slow
+    /// or stalled client would otherwise hold up every connection
+    /// behind it. Requests which are too slow get a 408.
+    ///
+    /// Streaming bodies are read by the route, so they're only
+    /// limited by the route itself.
+    pub fn client_timeout(&mut self, timeout: Duration) {
+        self.client_timeout = Some(timeout);
+    }
+
+    /// Stop running routes which take longer than `timeout`, and
+    /// answer with a 504. The route is dropped where it's waiting,
+    /// returning its database connection to the pool, and the
+    /// request's [`crate::CancellationToken`] is cancelled so
+    /// work it started can stop too. Routes can set their own
+    /// with [`Route::timeout`].
+    ///
+    /// A connection dropped inside a transaction from
+    /// [`DatabaseConnection::begin`] is closed instead, which rolls
+    /// the transaction back. Timeouts are printed with debugging
+    /// enabled.
+    ///
+    /// Only the treatment and handler are timed, not waiting for
+    /// a database connection or writing the response.
+    pub fn handler_timeout(&mut self, timeout: Duration) {
+        self.handler_timeout = Some(timeout);
+    }
+
+    /// Refuse JSON bodies over the given limits with a 400,
+    /// before they're parsed. See [`JsonLimits`].
+    pub fn json_limits(&mut self, limits: JsonLimits) {
+        self.json_limits = Some(limits);
+    }
+
+    /// Generate and prepare statements before accepting
+    /// connections. See [`Warmup`].
+    pub fn warmup(&mut self, warmup: Warmup) {
+        self.warmup = Some(warmup);
+    }
+
+    /// Replay stored responses to retried requests carrying an
+    /// `Idempotency-Key` header. See [`IdempotencyStore`].
+    pub fn idempotency(&mut self, store: IdempotencyStore) {
+        self.idempotency = Some(store);
+    }
+
+    /// Resolve the tenant of each request and point its database
+    /// connection at it. See [`Tenancy`].
+    pub fn tenancy(&mut self, tenancy: Tenancy) {
+        self.tenancy = Some(tenancy);
+    }
+
+    /// Install the keys used by [`crate::Encrypted`] columns.
+    /// See [`EncryptionKeys`].
+    pub fn encryption_keys(&mut self, keys: EncryptionKeys) {
+        keys.install();
+    }
+
+    /// Filter clients by address. See [`NetworkAcl`].
+    pub fn network_acl(&mut self, acl: NetworkAcl) {
+        self.network_acl = Some(acl);
+    }
+
+    /// Redirect requests to HTTPS and a canonical host. Well-known
+    /// documents and ACME challenges are served without redirecting.
+    pub fn canonical_redirects(&mut self, redirects: CanonicalRedirects) {
+        self.redirects = Some(redirects);
+    }
+
+    /// Serve a document at `/.well-known/{name}`, such as
+    /// `security.txt`, as `text/plain`. These are served without
+    /// a database connection, and even in maintenance mode.
+    pub fn well_known(&mut self, name: &str, contents: &str) {
+        self.well_known
+            .add(name, "text/plain; charset=utf-8", contents.as_bytes().to_vec());
+    }
+
+    /// Serve a JSON document at `/.well-known/{name}`, such as
+    /// `openid-configuration`. See [`Server::well_known`].
+    pub fn well_known_json<S: ToJson>(&mut self, name: &str, document: &S) {
+        self.well_known.add(
+            name,
+            "application/json; charset=utf-8",
+            document.to_json().into_bytes(),
+        );
+    }
+
+    /// Answer ACME HTTP-01 challenges for certificate
+    /// provisioning. See [`AcmeChallenges`].
+    pub fn acme_challenges(&mut self, challenges: AcmeChallenges) {
+        self.well_known.set_challenges(challenges);
+    }
+
+    /// Register an [`AuditLog`]. It is made available to every
+    /// request, so handlers can call [`Request::audit`], and pending
+    /// entries are written after responses are sent.
+    pub fn audit_log(&mut self, log: AuditLog) {
+        self.audit_log = Some(log);
+    }
+
+    /// Track how long connections from the server's pool are
+    /// held, warning about those held too long. See [`ConnectionLeaks`].
+    pub fn connection_leaks(&mut self, leaks: ConnectionLeaks) {
+        self.add_service(leaks.clone());
+        self.database.track_leaks(leaks);
+    }
+
+    /// Count responses over their route's budget. See [`Route::budget`].
+    pub fn budget_alerts(&mut self, alerts: BudgetAlerts) {
+        self.add_service(alerts.clone());
+        self.budget_alerts = Some(alerts);
+    }
+
+    /// Call a function whenever a connection is accepted.
+    pub fn on_connection_open(&mut self, f: fn(SocketAddr)) {
+        self.hooks.connection_open = Some(f);
+    }
+
+    /// Call a function once a request has been read and parsed.
+    pub fn on_request_parsed(&mut self, f: fn(&Request)) {
+        self.hooks.request_parsed = Some(f);
+    }
+
+    /// Call a function once a route has been selected for a request.
+    /// The path of the matched route is provided, or `None` if
+    /// no route matched.
+    pub fn on_handler_selected(&mut self, f: fn(&Request, Option<&str>)) {
+        self.hooks.handler_selected = Some(f);
+    }
+
+    /// Call a function after a response has been written.
+    pub fn on_response_written(&mut self, f: fn(&ResponseWritten)) {
+        self.hooks.response_written = Some(f);
+    }
+
+    /// Call a function when a treatment or route returns an
+    /// error, before it's sent. The request has no body.
+    pub fn on_route_error(&mut self, f: fn(&Request, &RouteError)) {
+        self.hooks.route_error = Some(f);
+    }
+
+    /// Call a function when accepting a connection or writing
+    /// a response fails.
+    pub fn on_connection_error(&mut self, f: fn(Option<SocketAddr>, &std::io::Error)) {
+        self.hooks.connection_error = Some(f);
+    }
+
+    /// Stop accepting connections once `signal` completes, such
+    /// as a shutdown message from another task. The request being
+    /// handled is finished, then modules' `on_shutdown` hooks run
+    /// and [`Server::start`] returns.
+    pub fn shutdown_on<F: Future<Output = ()> + 'static>(&mut self, signal: F) {
+        self.shutdown = Some(Box::pin(signal));
+    }
+
     /// Prepares Tusk for serving applications
-    /// and then begins listening.
+    /// and then begins listening. Runs until stopped
+    /// by [`Server::shutdown_on`].
     pub async fn start(&mut self) {
         self.routes.prep();
+        for (_, routes) in &mut self.hosts {
+            routes.prep();
+        }
+        if let Some(registry) = &self.route_registry {
+            let mut shared = registry.write();
+            for route in self.routes.take_all() {
+                shared.insert(route);
+            }
+        }
+        if let Some(warmup) = &self.warmup {
+            if let Err(err) = warmup.run(&self.database).await {
+                eprintln!("[WARN] Could not warm up statements: {}", err);
+            }
+        }
+        for module in &self.modules {
+            module.on_start(&self.database).await;
+        }
         let default: AsyncRouteHandler<T> =
             Box::new(move |a, b, c| Box::pin(Server::<T,V>::default_error(a, b, c)));
+        // Connections accepted ahead of being handled, when there's
+        // a connection limit.
+        let mut pending = VecDeque::new();
         loop {
-            if let Ok(conn) = self.listener.accept().await {
-                let (mut req_stream, _) = conn;
-                let req_parsed = self.create_request_object(&mut req_stream).await;
-                if req_parsed.request_type == RequestType::Options {
-                    let mut bytes = Vec::new();
-                    let body = self.handle_options();
-                    bytes.append(&mut body.get_header_data());
-                    bytes.append(&mut body.bytes());
-                    _ = req_stream.write(&bytes).await;
-                    continue;
+            if pending.is_empty() {
+                let accepted = match &mut self.shutdown {
+                    Some(signal) => tokio::select! {
+                        accepted = self.listener.accept() => accepted,
+                        _ = signal.as_mut() => break,
+                    },
+                    None => self.listener.accept().await,
+                };
+                match accepted {
+                    Ok((stream, remote_addr)) => self.admit(stream, remote_addr, &mut pending).await,
+                    Err(err) => {
+                        if let Some(hook) = self.hooks.connection_error {
+                            hook(None, &err);
+                        }
+                        continue;
+                    }
                 }
-                let mut matched_path: &AsyncRouteHandler<T> = &default;
-                if let Some(handler) = self
-                    .routes
-                    .handler(&req_parsed.request_type, &req_parsed.path)
+            }
+            if self.connection_limit.is_some() {
+                // Take in the connections which arrived while the
+                // last one was handled, so the limit applies to them.
+                while let Poll::Ready(Ok((stream, remote_addr))) =
+                    std::future::poll_fn(|cx| Poll::Ready(self.listener.poll_accept(cx))).await
                 {
-                    matched_path = &handler.handler;
+                    self.admit(stream, remote_addr, &mut pending).await;
                 }
+            }
+            // The permit is held until the connection is handled.
+            let Some((req_stream, remote_addr, accepted, _permit)) = pending.pop_front() else {
+                continue;
+            };
+            if self
+                .connection_limit
+                .as_ref()
+                .is_some_and(|x| x.expired(accepted.elapsed()))
+            {
+                self.reject_connection(&req_stream, remote_addr).await;
+                continue;
+            }
+            // Shared with the body reader of streaming routes.
+            let req_stream = Rc::new(req_stream);
+            if let Some(hook) = self.hooks.connection_open {
+                hook(remote_addr);
+            }
+            let Some(mut req_parsed) =
+                Self::within(self.client_timeout, self.create_request_object(&req_stream, remote_addr)).await
+            else {
+                let response = RouteError::custom(
+                    "The request took too long to send.",
+                    ResponseStatusCode::RequestTimeout,
+                )
+                .to_response();
+                let mut head = self.buffers.take();
+                let body = response.into_http_parts(HttpVersion::Http11, &mut head);
+                _ = Self::within(self.client_timeout, Self::write_response(&req_stream, &head, &body)).await;
+                self.buffers.give(head);
+                if let Some(hook) = self.hooks.connection_error {
+                    hook(
+                        Some(remote_addr),
+                        &std::io::Error::new(std::io::ErrorKind::TimedOut, "client took too long to send the request"),
+                    );
+                }
+                continue;
+            };
+            if req_parsed.cancellation.is_cancelled() {
+                // The client went away before sending the whole request.
+                self.report_disconnect(remote_addr, "client disconnected while sending the request");
+                continue;
+            }
+            let version = req_parsed.version;
+            if version.requires_host() && !req_parsed.headers.contains_key("host") {
+                let mut head = self.buffers.take();
+                let body = RouteError::bad_request("Missing Host header.")
+                    .to_response()
+                    .into_http_parts(version, &mut head);
+                _ = Self::within(self.client_timeout, Self::write_response(&req_stream, &head, &body)).await;
+                self.buffers.give(head);
+                continue;
+            }
+            if !self.client_allowed(&req_parsed) {
+                // The body was never read, so close once this is sent.
+                let mut head = self.buffers.take();
+                let body = NetworkAcl::forbidden().into_http_parts(version, &mut head);
+                _ = Self::within(self.client_timeout, Self::write_response(&req_stream, &head, &body)).await;
+                self.buffers.give(head);
+                continue;
+            }
+            let started = Instant::now();
+            if let Some(hook) = self.hooks.request_parsed {
+                hook(&req_parsed);
+            }
+            if req_parsed.request_type == RequestType::Options {
+                let mut head = self.buffers.take();
+                let options = match self.host_routes(&req_parsed) {
+                    Some(routes) => self.options_response(routes.allowed_methods(&req_parsed.path)),
+                    None => self.handle_options(&req_parsed.path),
+                };
+                let body = options.into_http_parts(version, &mut head);
+                _ = Self::within(self.client_timeout, Self::write_response(&req_stream, &head, &body)).await;
+                self.buffers.give(head);
+                continue;
+            }
+            let matched_route = match self.host_routes(&req_parsed) {
+                Some(routes) => routes
+                    .handler(&req_parsed.request_type, &req_parsed.path)
+                    .map(|x| SelectedRoute::borrowed(x, &req_parsed)),
+                None => match &self.route_registry {
+                    Some(registry) => registry.select(&req_parsed),
+                    None => self
+                        .routes
+                        .handler(&req_parsed.request_type, &req_parsed.path)
+                        .map(|x| SelectedRoute::borrowed(x, &req_parsed)),
+                }
+                .or_else(|| self.apps.iter().find_map(|x| x.select(&req_parsed))),
+            };
+            if let Some(hook) = self.hooks.handler_selected {
+                hook(&req_parsed, matched_route.as_ref().map(|x| x.path.as_ref()));
+            }
+            // The route's budget, and the route to report if it's exceeded.
+            let budget = matched_route.as_ref().and_then(|route| {
+                Some((route.budget?, format!("{} {}", req_parsed.request_type, route.path)))
+            });
+            let timeout = matched_route.as_ref().and_then(|route| {
+                Some((
+                    route.timeout.or(self.handler_timeout)?,
+                    format!("{} {}", req_parsed.request_type, route.path),
+                ))
+            });
+            let route_path = matched_route.as_ref().map(|route| route.path.clone());
+            let (matched_path, default_headers, content_check, example, replay_guard) = match matched_route {
+                Some(route) => (route.target, route.headers, route.content_check, route.example, route.replay_guard),
+                None => (
+                    RouteTarget::Handler(*default),
+                    Cow::Borrowed(&[] as &[(String, String)]),
+                    Ok(()),
+                    None,
+                    None,
+                ),
+            };
+            let content_check = match req_parsed.body_error.take() {
+                Some(error) => Err(error),
+                None => content_check,
+            };
+            let written_details = self
+                .hooks
+                .response_written
+                .map(|_| (req_parsed.request_type.clone(), req_parsed.path.clone()));
+
+            if let Some(log) = &self.audit_log {
+                req_parsed.extensions.insert(log.clone());
+            }
 
-                let mut req = IncomingRequest {
-                    request: req_parsed,
-                    stream: req_stream,
+            let req_details = self.postfix.as_ref().map(|_| req_parsed.without_body());
+            let cancellation = req_parsed.cancellation.clone();
+            // A streaming body is still arriving, so end of stream
+            // can't be told apart from the end of the upload.
+            let watched = !matches!(req_parsed.body, BodyContents::Stream(_));
+            let initialization_data = self.initialization_data.clone();
+            let error_details = self.hooks.route_error.map(|hook| (hook, req_parsed.without_body()));
+            let report_error = |error: RouteError| {
+                if let Some((hook, req)) = &error_details {
+                    hook(req, &error);
+                }
+                error.to_response()
+            };
+            let early_response = self
+                .well_known
+                .response(&req_parsed)
+                .or_else(|| self.redirects.as_ref().and_then(|x| x.response(&req_parsed)))
+                .or_else(|| {
+                    self.maintenance
+                        .as_ref()
+                        .and_then(|x| x.response(&req_parsed.path, self.feature_flags.as_ref()))
+                });
+            let handled = async {
+                if let Some(response) = early_response {
+                    return response;
+                }
+                if let Err(error) = content_check {
+                    return report_error(error);
+                }
+                if let Some(example) = example.filter(|_| self.mocking_enabled) {
+                    return example.response();
+                }
+                let tenant = match self.tenancy.as_ref().map(|x| x.tenant_for(&req_parsed)) {
+                    Some(Ok(tenant)) => tenant,
+                    Some(Err(error)) => return report_error(error),
+                    None => None,
+                };
+                let connection = match &self.load_shedding {
+                    Some(shedding) => {
+                        match tokio::time::timeout(shedding.max_wait, self.database.get_connection()).await {
+                            Ok(connection) => connection,
+                            Err(_) => return shedding.response(),
+                        }
+                    }
+                    None => self.database.get_connection().await,
                 };
-                let mut bytes = Vec::new();
-                let initialization_data = self.initialization_data.clone();
-                let mut response = match self.database.get_connection().await {
-                    Ok(db_inst) => match (self.treatment)(req.request, db_inst, initialization_data).await {
-                        Ok((treat, req, obj)) => {
-                            let mut body = matched_path(req, obj, treat)
-                                .await
-                                .unwrap_or_else(|x| x.to_response());
-                            if self.postfix.is_some() {
-                                body = self.postfix.unwrap()(body)
+                match connection {
+                    Ok(db_inst) => {
+                        if let Some(path) = &route_path {
+                            db_inst.assign_route(|| format!("{} {}", req_parsed.request_type, path));
+                        }
+                        if let Some(flags) = self.feature_flags.as_ref().filter(|x| x.needs_refresh()) {
+                            if let Err(err) = flags.refresh(&db_inst).await {
+                                if self.debugging_enabled {
+                                    dbg!(err);
+                                }
                             }
-                            body
                         }
-                        Err(error) => error.to_response(),
-                    },
+                        if let Some(tenancy) = &self.tenancy {
+                            if let Err(err) = tenancy.apply(&db_inst, tenant.as_ref()).await {
+                                if self.debugging_enabled {
+                                    dbg!(err);
+                                }
+                                return report_error(RouteError::server_error("Cannot select tenant."));
+                            }
+                        }
+                        if let Some(tenant) = tenant {
+                            req_parsed.extensions.insert(tenant);
+                        }
+                        if let Some(guard) = &replay_guard {
+                            if let Err(error) = guard.check(&db_inst, &req_parsed).await {
+                                return report_error(error);
+                            }
+                        }
+                        let idempotency_key = match &self.idempotency {
+                            Some(store) => match store.begin(&db_inst, &req_parsed).await {
+                                Ok(key) => key,
+                                Err(response) => return response,
+                            },
+                            None => None,
+                        };
+                        let run = async {
+                            match matched_path {
+                                RouteTarget::Handler(handler) => {
+                                    let (treat, req, obj) =
+                                        (self.treatment)(req_parsed, db_inst, initialization_data).await?;
+                                    handler(req, obj, treat).await
+                                }
+                                RouteTarget::Mounted(run) => run(req_parsed, db_inst).await,
+                            }
+                        };
+                        // Dropping the route on timeout releases its connection,
+                        // closing it if a transaction was left open.
+                        let response = match Self::within(timeout.as_ref().map(|(x, _)| *x), run).await {
+                            Some(result) => result.unwrap_or_else(report_error),
+                            None => {
+                                let (timeout, route) = timeout.as_ref().unwrap();
+                                if self.debugging_enabled {
+                                    eprintln!("[WARN] {} timed out after {}ms", route, timeout.as_millis());
+                                }
+                                cancellation.cancel();
+                                report_error(RouteError::custom(
+                                    "The request took too long to handle.",
+                                    ResponseStatusCode::GatewayTimeout,
+                                ))
+                            }
+                        };
+                        if let (Some(store), Some(key)) = (&self.idempotency, idempotency_key) {
+                            // The route's connection is back in the pool by now.
+                            let stored = match self.database.get_connection().await {
+                                Ok(db) => store.finish(&db, &key, &response).await.map_err(|x| x.to_string()),
+                                Err(err) => Err(err.to_string()),
+                            };
+                            if let Err(err) = stored {
+                                if self.debugging_enabled {
+                                    dbg!(err);
+                                }
+                            }
+                        }
+                        response
+                    }
                     Err(err) => {
                         if self.debugging_enabled {
                             dbg!(err);
                         }
-                        RouteError::server_error("Cannot connect to database.").to_response()
+                        report_error(RouteError::server_error("Cannot connect to database."))
                     }
-                };
-                response.apply_cors(&self.cors_origin, &self.cors_headers);
-                bytes.append(&mut response.get_header_data());
-                bytes.append(&mut response.bytes());
-
-                let mut write_bytes = bytes.as_slice();
-                // Write stream
-                loop {
-                    let written_bytes = req.stream.write(write_bytes).await;
-                    if let Ok(wr_byt) = written_bytes {
-                        if wr_byt == write_bytes.len() {
-                            break;
-                        };
-                        write_bytes = &write_bytes[wr_byt..];
-                    } else {
-                        break;
+                }
+            };
+            // Stop working on the request if the client disconnects,
+            // since nobody will read the response. Its connection is
+            // closed if a transaction was left open.
+            let mut response = tokio::select! {
+                biased;
+                response = handled => response,
+                _ = Self::client_gone(&req_stream), if watched => {
+                    cancellation.cancel();
+                    self.report_disconnect(remote_addr, "client disconnected before the response was ready");
+                    continue;
+                }
+            };
+            response.apply_default_headers(&default_headers);
+            response.apply_default_headers(&self.security_headers);
+            if let (Some(postfix), Some(req_details)) = (&self.postfix, req_details) {
+                response = postfix(req_details, response, started.elapsed()).await;
+            }
+            response.apply_cors(&self.cors_origin, &self.cors_headers);
+            let status = response.status.clone();
+            let mut head = self.buffers.take();
+            let body = response.into_http_parts(version, &mut head);
+            // How much was written before a timeout isn't known.
+            let written = Self::within(self.client_timeout, Self::write_response(&req_stream, &head, &body))
+                .await
+                .unwrap_or_else(|| Err((0, std::io::ErrorKind::TimedOut.into())));
+            let (bytes_written, completed) = match written {
+                Ok(written) => (written, true),
+                Err((written, err)) => {
+                    // Usually a broken pipe or reset, when the
+                    // client disconnects partway through.
+                    cancellation.cancel();
+                    if let Some(hook) = self.hooks.connection_error {
+                        hook(Some(remote_addr), &err);
+                    }
+                    (written, false)
+                }
+            };
+            self.buffers.give(head);
+            drop(req_stream);
+            let elapsed = started.elapsed();
+            if let Some((budget, route)) = budget.as_ref().filter(|(budget, _)| elapsed > *budget) {
+                if self.debugging_enabled {
+                    eprintln!(
+                        "[WARN] {} took {}ms, over its {}ms budget",
+                        route,
+                        elapsed.as_millis(),
+                        budget.as_millis()
+                    );
+                }
+                if let Some(alerts) = &self.budget_alerts {
+                    alerts.record(route.clone());
+                }
+            }
+            if let (Some(hook), Some((request_type, path))) =
+                (self.hooks.response_written, &written_details)
+            {
+                hook(&ResponseWritten {
+                    request_type,
+                    path,
+                    remote_addr,
+                    status: &status,
+                    bytes_written,
+                    completed,
+                    elapsed,
+                    budget: budget.as_ref().map(|(budget, _)| *budget),
+                });
+            }
+
+            if let Some(log) = self.audit_log.as_ref().filter(|x| x.should_flush()) {
+                if let Ok(db) = self.database.get_connection().await {
+                    if let Err(err) = log.flush(&db).await {
+                        if self.debugging_enabled {
+                            dbg!(err);
+                        }
                     }
                 }
             }
         }
+        for module in &self.modules {
+            module.on_shutdown(&self.database).await;
+        }
     }
 
-    async fn create_request_object(&self, stream: &mut TcpStream) -> Request {
-        let mut buffer = BufReader::new(stream);
-        let mut headers_content = String::new();
-
-        let mut cur_char: [u8; 1] = [0];
-        let mut whitespace_count = 0;
+    async fn create_request_object(&self, stream: &Rc<TcpStream>, remote_addr: SocketAddr) -> Request {
+        let mut buffer = self.buffers.take();
 
-        // Obtain headers
-        while buffer.read_exact(&mut cur_char).await.is_ok() {
-            let cur_char_val = char::from_u32(cur_char[0] as u32).unwrap();
-            headers_content.push(cur_char_val);
-            if cur_char_val == '\u{a}' || cur_char_val == '\u{d}' {
-                whitespace_count += 1;
-            } else {
-                whitespace_count = 0;
+        // Obtain headers. Reads are done in chunks, so part
+        // of the body may already be in the buffer afterwards.
+        let mut closed = false;
+        let (header_end, body_start) = loop {
+            if let Some(end) = Self::find_header_end(&buffer) {
+                break end;
             }
-            // When we have a blank line, exit.
-            if whitespace_count == 4 {
-                break;
+            if buffer.len() >= MAX_HEADER_SIZE {
+                break (buffer.len(), buffer.len());
+            }
+            if !Self::read_chunk(stream, &mut buffer).await {
+                closed = true;
+                break (buffer.len(), buffer.len());
             }
-        }
-        // Process headers
-        let req: Vec<String> = headers_content
-            .lines()
-            .map(|a| a.to_string())
-            .take_while(|a| !a.is_empty())
-            .collect();
-        let head = &req[0].split(' ').collect::<Vec<&str>>();
-
-        let head_path = head[1].to_string();
-        let path = head_path.split('?').collect::<Vec<&str>>();
-        let wo_query_sect = path[0].to_string();
-
-        let mut created_request = Request {
-            path: if wo_query_sect.ends_with('/') {
-                wo_query_sect[0..wo_query_sect.len() - 1].to_string()
-            } else {
-                wo_query_sect.to_string()
-            },
-            request_type: RequestType::type_for_method(head[0]),
-            query: if let Some(q_d) = path.get(1) {
-                q_d.split('&')
-                    .map(|x| {
-                        let q = x.split('=').collect::<Vec<&str>>();
-                        (q[0].to_string(), q.get(1).unwrap_or(&"").to_string())
-                    })
-                    .collect()
-            } else {
-                HashMap::new()
-            },
-            headers: req[1..]
-                .to_vec()
-                .iter()
-                .map(|a| {
-                    let d: Vec<&str> = a.split(": ").collect();
-                    (d[0].to_string().to_lowercase(), d[1].to_string())
-                })
-                .collect(),
-            body: BodyContents::None,
         };
+        let headers_content = String::from_utf8_lossy(&buffer[..header_end]).into_owned();
+
+        let mut created_request = Request::from_head(&headers_content);
+        created_request.services = self.services.clone();
+        created_request.remote_addr = Some(remote_addr);
+        if !self.client_allowed(&created_request) {
+            // Not worth reading the body of a request that's refused.
+            self.buffers.give(buffer);
+            return created_request;
+        }
 
         if let Some(content_length_str) = created_request.headers.get("content-length") {
             // We have a body.
             let content_len: usize = content_length_str.parse().unwrap_or(0);
-            let mut content: Vec<u8> = Vec::new();
+            let (body_mode, keeps_raw) = self.route_body(&created_request);
+            let keep_raw = keeps_raw || self.idempotency.is_some_and(|x| x.applies(&created_request));
+            if body_mode == BodyMode::Streaming {
+                let body_end = buffer.len().min(body_start + content_len);
+                created_request.body = BodyContents::Stream(BodyReader::new(
+                    buffer[body_start..body_end].to_vec(),
+                    content_len,
+                    stream.clone(),
+                ));
+                if closed {
+                    created_request.cancellation.cancel();
+                }
+                self.buffers.give(buffer);
+                return created_request;
+            }
             // Read body
-            loop {
-                if content.len() == content_len {
+            while buffer.len() - body_start < content_len {
+                if !Self::read_chunk(stream, &mut buffer).await {
+                    closed = true;
                     break;
                 }
-                if buffer.read_exact(&mut cur_char).await.is_ok() {
-                    content.push(cur_char[0]);
+            }
+            let body_end = buffer.len().min(body_start + content_len);
+            let content = buffer[body_start..body_end].to_vec();
+            if keep_raw {
+                created_request.raw_body = Some(content.clone());
+            }
+            let no_charset = match created_request.headers.get("content-type") {
+                Some(content_type) => content_type.split(' ').collect::<Vec<&str>>()[0].replace(';', ""),
+                None => String::new(),
+            };
+            let refused = self
+                .json_limits
+                .filter(|_| BodyContents::is_json_type(&no_charset))
+                .and_then(|limits| limits.check_body(&content).err());
+            let body = match refused {
+                Some(error) => Err(error),
+                None => BodyContents::try_type_from_mime(&no_charset, content),
+            };
+            match body {
+                Ok(body) => created_request.body = body,
+                Err(error) => created_request.body_error = Some(error),
+            }
+        }
+        if closed {
+            created_request.cancellation.cancel();
+        }
+        self.buffers.give(buffer);
+        created_request
+    }
+
+    /// Whether the [`NetworkAcl`] lets the client reach the path.
+    fn client_allowed(&self, request: &Request) -> bool {
+        match (&self.network_acl, request.remote_addr) {
+            (Some(acl), Some(addr)) => acl.allows(addr.ip(), &request.path),
+            _ => true,
+        }
+    }
+
+    /// How the route for a request receives its body, and
+    /// whether it needs the raw bytes. See [`Route::keeps_raw_body`].
+    fn route_body(&self, request: &Request) -> (BodyMode, bool) {
+        let body = |route: &Route<T>| (route.body_mode, route.keeps_raw_body());
+        if let Some(routes) = self.host_routes(request) {
+            return routes
+                .handler(&request.request_type, &request.path)
+                .map_or((BodyMode::Buffered, false), body);
+        }
+        match &self.route_registry {
+            Some(registry) => registry
+                .read()
+                .handler(&request.request_type, &request.path)
+                .map(body),
+            None => self
+                .routes
+                .handler(&request.request_type, &request.path)
+                .map(body),
+        }
+        .or_else(|| self.apps.iter().find_map(|x| x.route_body(request)))
+        .unwrap_or((BodyMode::Buffered, false))
+    }
+
+    /// Resolves once the client closes or resets the connection.
+    /// The request has been read in full by now, so end of stream
+    /// means the client is done. Any data the client sends
+    /// afterwards is ignored.
+    async fn client_gone(stream: &TcpStream) {
+        let mut byte = [0; 1];
+        match stream.peek(&mut byte).await {
+            Ok(0) | Err(_) => {}
+            Ok(_) => std::future::pending().await,
+        }
+    }
+
+    /// Runs `future`, giving up after `timeout` if there is one.
+    async fn within<F: Future>(timeout: Option<Duration>, future: F) -> Option<F::Output> {
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, future).await.ok(),
+            None => Some(future.await),
+        }
+    }
+
+    /// Queues an accepted connection to be handled, unless the
+    /// network ACL denies it or the connection limit is reached.
+    async fn admit(&self, stream: TcpStream, remote_addr: SocketAddr, pending: &mut VecDeque<PendingConnection>) {
+        if self
+            .network_acl
+            .as_ref()
+            .is_some_and(|x| x.denies(remote_addr.ip()))
+        {
+            // Dropping the stream closes the connection unread.
+            return;
+        }
+        let permit = match &self.connection_limit {
+            Some(limit) => match limit.try_acquire() {
+                Some(permit) => Some(permit),
+                None => {
+                    self.reject_connection(&stream, remote_addr).await;
+                    return;
                 }
+            },
+            None => None,
+        };
+        pending.push_back((stream, remote_addr, Instant::now(), permit));
+    }
+
+    /// Answers a connection past the [`ConnectionLimit`] with a 503.
+    /// Its request head is read first, since closing a connection
+    /// with unread data resets it, and the client may never see
+    /// the response.
+    async fn reject_connection(&self, stream: &TcpStream, remote_addr: SocketAddr) {
+        let Some(limit) = &self.connection_limit else {
+            return;
+        };
+        _ = Self::within(Some(ConnectionLimit::DRAIN_TIMEOUT), self.discard_head(stream)).await;
+        let mut head = self.buffers.take();
+        let body = limit.response().into_http_parts(HttpVersion::Http11, &mut head);
+        _ = Self::within(self.client_timeout, Self::write_response(stream, &head, &body)).await;
+        self.buffers.give(head);
+        if let Some(hook) = self.hooks.connection_error {
+            hook(
+                Some(remote_addr),
+                &std::io::Error::new(std::io::ErrorKind::WouldBlock, "connection limit reached"),
+            );
+        }
+    }
+
+    /// Reads a request head, up to [`MAX_HEADER_SIZE`], and
+    /// discards it.
+    async fn discard_head(&self, stream: &TcpStream) {
+        let mut buffer = self.buffers.take();
+        while buffer.len() < MAX_HEADER_SIZE && Self::find_header_end(&buffer).is_none() {
+            if !Self::read_chunk(stream, &mut buffer).await {
+                break;
             }
-            if let Some(content_type) = created_request.headers.get("content-type") {
-                let no_charset = content_type.split(' ').collect::<Vec<&str>>()[0].replace(';', "");
-                created_request.body = BodyContents::type_from_mime(&no_charset, content);
+        }
+        self.buffers.give(buffer);
+    }
+
+    /// Reports a client which disconnected before its
+    /// response could be written.
+    fn report_disconnect(&self, remote_addr: SocketAddr, message: &str) {
+        if let Some(hook) = self.hooks.connection_error {
+            hook(
+                Some(remote_addr),
+                &std::io::Error::new(std::io::ErrorKind::ConnectionAborted, message),
+            );
+        }
+    }
+
+    /// Writes the head and body using vectored writes,
+    /// handling partial writes. Returns the number of bytes
+    /// written, including when the write fails partway through.
+    async fn write_response(
+        stream: &TcpStream,
+        head: &[u8],
+        body: &[u8],
+    ) -> Result<usize, (usize, std::io::Error)> {
+        let total = head.len() + body.len();
+        let mut written = 0;
+        while written < total {
+            let slices = if written < head.len() {
+                [IoSlice::new(&head[written..]), IoSlice::new(body)]
             } else {
-                created_request.body = BodyContents::type_from_mime("", content);
+                [IoSlice::new(&body[written - head.len()..]), IoSlice::new(&[])]
+            };
+            if let Err(err) = stream.writable().await {
+                return Err((written, err));
+            }
+            match stream.try_write_vectored(&slices) {
+                Ok(0) => {
+                    return Err((written, std::io::ErrorKind::WriteZero.into()));
+                }
+                Ok(n) => written += n,
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => continue,
+                Err(err) => return Err((written, err)),
+            }
+        }
+        Ok(written)
+    }
+
+    /// Finds the blank line ending the headers. Returns the end
+    /// of the headers and the start of the body.
+    fn find_header_end(buffer: &[u8]) -> Option<(usize, usize)> {
+        let crlf = buffer.windows(4).position(|x| x == b"\r\n\r\n").map(|ix| (ix, ix + 4));
+        let lf = buffer.windows(2).position(|x| x == b"\n\n").map(|ix| (ix, ix + 2));
+        match (crlf, lf) {
+            (Some(a), Some(b)) => Some(if a.0 <= b.0 { a } else { b }),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Reads the next chunk from the stream into the buffer.
+    /// Returns false once the stream is closed or fails.
+    async fn read_chunk(stream: &TcpStream, buffer: &mut Vec<u8>) -> bool {
+        buffer.reserve(READ_CHUNK_SIZE);
+        loop {
+            if stream.readable().await.is_err() {
+                return false;
+            }
+            match stream.try_read_buf(buffer) {
+                Ok(read) => return read > 0,
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => continue,
+                Err(_) => return false,
             }
         }
-        created_request
     }
 
     async fn default_error(_: Request, _: DatabaseConnection, _: T) -> Result<Response, RouteError> {
         Ok(Response::string("404 not found").status(ResponseStatusCode::NotFound))
     }
 
-    pub fn handle_options(&self) -> Response {
+    /// Responds to an OPTIONS request. For known paths, the
+    /// `Allow` header lists the methods with a registered route.
+    pub fn handle_options(&self, path: &str) -> Response {
+        let mut methods = match &self.route_registry {
+            Some(registry) => registry.read().allowed_methods(path),
+            None => self.routes.allowed_methods(path),
+        };
+        if methods.is_empty() {
+            methods = self
+                .apps
+                .iter()
+                .map(|x| x.allowed_methods(path))
+                .find(|x| !x.is_empty())
+                .unwrap_or_default();
+        }
+        self.options_response(methods)
+    }
+
+    /// An OPTIONS response allowing `methods`.
+    fn options_response(&self, methods: Vec<RequestType>) -> Response {
         let mut r = Response::data(Vec::new());
         r.apply_cors(&self.cors_origin, &self.cors_headers);
+        if !methods.is_empty() {
+            let allow = methods
+                .iter()
+                .chain([&RequestType::Options])
+                .map(|x| x.to_string())
+                .collect::<Vec<String>>()
+                .join(", ");
+            r.headers.insert("Access-Control-Allow-Methods", &allow);
+            r.headers.insert("Allow", allow);
+        }
         r
     }
 }
@@ -291,6 +1254,57 @@ pub struct Route<T> {
     pub path: String,
     pub request_type: RequestType,
     pub handler: AsyncRouteHandler<T>,
+    /// Headers added to every response from this route,
+    /// unless the handler already set them.
+    pub headers: Vec<(String, String)>,
+    /// The content types this route accepts for request
+    /// bodies. Any content type is accepted if empty.
+    pub content_types: Vec<String>,
+    /// Disabled routes are treated as if they weren't
+    /// registered. See [`RouteRegistry::set_enabled`].
+    pub enabled: bool,
+    pub body_mode: BodyMode,
+    /// How long responses are expected to take. Slower
+    /// responses are logged and counted.
+    pub budget: Option<Duration>,
+    /// How long the handler may run before the request is
+    /// answered with a 504. See [`Route::timeout`].
+    pub timeout: Option<Duration>,
+    /// Verifies the signature of request bodies.
+    pub signature: Option<SignatureVerifier>,
+    /// The response sent instead of running the route when
+    /// mocking is enabled. See [`Server::enable_mocking`].
+    pub example: Option<RouteExample>,
+    /// Refuses requests whose nonce was already received.
+    pub replay_guard: Option<ReplayGuard>,
+    /// Checks JSON bodies with [`check_strict_json`].
+    pub strict_json: bool,
+}
+
+/// An example response for a route. See [`Route::example`].
+#[derive(Debug, Clone)]
+pub struct RouteExample {
+    pub status: ResponseStatusCode,
+    /// The JSON body.
+    pub body: String,
+}
+impl RouteExample {
+    pub fn response(&self) -> Response {
+        Response::data(self.body.as_bytes().to_vec())
+            .header("Content-Type", "application/json; charset=utf-8")
+            .status(self.status.clone())
+    }
+}
+
+/// How a route receives its request body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BodyMode {
+    /// The body is read in full and parsed by content type.
+    #[default]
+    Buffered,
+    /// The body is left unread, and the route receives a
+    /// [`BodyContents::Stream`] to read it as it arrives.
+    Streaming,
 }
 impl<T> Route<T> {
     /// A route can be manually created, but it is not
@@ -309,7 +1323,165 @@ impl<T> Route<T> {
             },
             request_type,
             handler,
+            headers: Vec::new(),
+            content_types: Vec::new(),
+            enabled: true,
+            body_mode: BodyMode::Buffered,
+            budget: None,
+            timeout: None,
+            signature: None,
+            example: None,
+            replay_guard: None,
+            strict_json: false,
+        }
+    }
+
+    /// Add default headers to every response from this route.
+    /// Headers the handler sets itself are left untouched,
+    /// and headers already added to this route take precedence.
+    pub fn default_headers(mut self, headers: &[(&str, &str)]) -> Route<T> {
+        for (key, value) in headers {
+            if !self.headers.iter().any(|(k, _)| k.eq_ignore_ascii_case(key)) {
+                self.headers.push((key.to_string(), value.to_string()));
+            }
+        }
+        self
+    }
+
+    /// Only accept request bodies with this content type.
+    /// Can be called several times to accept more types.
+    ///
+    /// Requests with a body of another type are rejected with
+    /// a 415 error listing the accepted types (also as
+    /// `details.supported`), before the treatment or handler run.
+    pub fn accept(mut self, content_type: &str) -> Route<T> {
+        self.content_types.push(content_type.to_lowercase());
+        self
+    }
+
+    /// Define how the route receives its request body, such as
+    /// [`BodyMode::Streaming`] for uploads and proxies. Can be chained.
+    pub fn body_mode(mut self, body_mode: BodyMode) -> Route<T> {
+        self.body_mode = body_mode;
+        self
+    }
+
+    /// Define how long responses are expected to take. Slower
+    /// responses are counted by [`BudgetAlerts`], flagged to
+    /// [`Server::on_response_written`] and printed with debugging
+    /// enabled. Can be chained.
+    pub fn budget(mut self, budget: Duration) -> Route<T> {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Answer with a 504 if the treatment and handler haven't
+    /// finished within `timeout`, overriding
+    /// [`Server::handler_timeout`]. Can be chained.
+    pub fn timeout(mut self, timeout: Duration) -> Route<T> {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Refuse requests without a valid signature with a 401,
+    /// before the treatment or handler run. See
+    /// [`SignatureVerifier`]. Streaming bodies aren't kept, so
+    /// streaming routes should call [`SignatureVerifier::verify`]
+    /// themselves. Can be chained.
+    pub fn verify_signature(mut self, verifier: SignatureVerifier) -> Route<T> {
+        self.signature = Some(verifier);
+        self
+    }
+
+    /// Refuse requests whose nonce was already received with a
+    /// 409, before the treatment or handler run. See
+    /// [`ReplayGuard`]. Can be chained.
+    pub fn prevent_replays(mut self, guard: ReplayGuard) -> Route<T> {
+        self.replay_guard = Some(guard);
+        self
+    }
+
+    /// Refuse JSON bodies which aren't strictly valid with a
+    /// 400, before the treatment or handler run. The error says
+    /// where the problem is, and `details` has its `line`,
+    /// `column` and `offset`. See [`check_strict_json`].
+    /// Can be chained.
+    pub fn strict_json(mut self) -> Route<T> {
+        self.strict_json = true;
+        self
+    }
+
+    /// Define an example response, sent in place of the route's
+    /// own when mocking is enabled. See [`Server::enable_mocking`].
+    /// Can be chained.
+    ///
+    /// ```ignore
+    /// server.register(get_user().example(ResponseStatusCode::Ok, &tusk_json!({
+    ///     "id": "01J0000000000000000000000",
+    ///     "name": "Ada",
+    /// })));
+    /// ```
+    pub fn example<S: ToJson>(mut self, status: ResponseStatusCode, body: &S) -> Route<T> {
+        self.example = Some(RouteExample {
+            status,
+            body: body.to_json(),
+        });
+        self
+    }
+
+    /// Accept JSON request bodies. See [`Route::accept`].
+    pub fn accept_json(self) -> Route<T> {
+        self.accept("application/json").accept("application/ld+json")
+    }
+
+    /// Accept URL encoded form bodies. See [`Route::accept`].
+    pub fn accept_form(self) -> Route<T> {
+        self.accept("application/x-www-form-urlencoded")
+    }
+
+    /// Checks the request's body against the accepted content
+    /// types, the route's signature and strict JSON.
+    fn check_body(&self, request: &Request) -> Result<(), RouteError> {
+        self.check_content_type(request)?;
+        if let Some(verifier) = &self.signature {
+            verifier.verify(request, request.raw_body().unwrap_or_default())?;
+        }
+        if self.strict_json && matches!(request.body, BodyContents::JsonObject(_) | BodyContents::JsonArray(_)) {
+            let raw = std::str::from_utf8(request.raw_body().unwrap_or_default())
+                .map_err(|_| RouteError::bad_request("Body is not valid UTF-8."))?;
+            check_strict_json(raw)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the body must be kept as sent, to verify its
+    /// signature or check it's strict JSON.
+    fn keeps_raw_body(&self) -> bool {
+        self.signature.is_some() || self.strict_json
+    }
+
+    /// Checks the request's body against the accepted content types.
+    fn check_content_type(&self, request: &Request) -> Result<(), RouteError> {
+        if self.content_types.is_empty() || matches!(request.body, BodyContents::None) {
+            return Ok(());
+        }
+        let content_type = request.content_type().map(|x| x.essence()).unwrap_or_default();
+        if self.content_types.contains(&content_type) {
+            return Ok(());
+        }
+        let mut supported = String::from("{\"supported\":[");
+        for (ix, accepted) in self.content_types.iter().enumerate() {
+            if ix > 0 {
+                supported.push(',');
+            }
+            write_json_string(&mut supported, accepted);
         }
+        supported.push_str("]}");
+        Err(RouteError::unsupported_media_type(&format!(
+            "Expected a body of type {}.",
+            self.content_types.join(", ")
+        ))
+        .details(JsonObject::from_string(&supported)))
     }
 }
 impl<T> core::fmt::Debug for Route<T> {
@@ -321,13 +1493,41 @@ impl<T> core::fmt::Debug for Route<T> {
     }
 }
 
+/// A group of routes registered under a prefix, with hooks
+/// to warm caches, verify tables or start jobs when the server
+/// starts, and clean up when it stops. Register it with
+/// [`Server::mount`].
+///
+/// With [`Server::start_workers`], hooks run on every worker.
+pub trait RouteModule<T> {
+    /// The prefix for every route, such as `users`.
+    fn prefix(&self) -> &str;
+
+    fn routes(&self) -> Vec<Route<T>>;
+
+    /// Called by [`Server::start`] before accepting connections.
+    fn on_start<'a>(&'a self, _db: &'a Database) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
+        Box::pin(async {})
+    }
+
+    /// Called once the server stops. See [`Server::shutdown_on`].
+    fn on_shutdown<'a>(&'a self, _db: &'a Database) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
+        Box::pin(async {})
+    }
+}
+
 #[derive(Debug)]
 pub struct IncomingRequest {
     pub request: Request,
     pub stream: TcpStream,
 }
 
-struct RouteStorage<T> {
+/// Routes grouped by method and sorted by path, so a
+/// handler can be found with a binary search.
+///
+/// The server manages this itself; it is public so route
+/// lookup can be benchmarked.
+pub struct RouteStorage<T> {
     routes_get: Vec<Route<T>>,
     routes_post: Vec<Route<T>>,
     routes_put: Vec<Route<T>>,
@@ -337,7 +1537,7 @@ struct RouteStorage<T> {
 }
 
 impl<T> RouteStorage<T> {
-    fn new() -> RouteStorage<T> {
+    pub fn new() -> RouteStorage<T> {
         RouteStorage {
             routes_get: Vec::new(),
             routes_post: Vec::new(),
@@ -348,7 +1548,9 @@ impl<T> RouteStorage<T> {
         }
     }
 
-    fn handler(&self, request_type: &RequestType, path: &String) -> Option<&Route<T>> {
+    /// Find the route for a method and path, falling back
+    /// to routes registered for any method.
+    pub fn handler(&self, request_type: &RequestType, path: &str) -> Option<&Route<T>> {
         let handler_cat = match request_type {
             RequestType::Get => &self.routes_get,
             RequestType::Post => &self.routes_post,
@@ -357,31 +1559,114 @@ impl<T> RouteStorage<T> {
             RequestType::Delete => &self.routes_delete,
             _ => &self.routes_any,
         };
-        if let Ok(handler_ix) = handler_cat.binary_search_by(|a| a.path.cmp(path)) {
-            Some(&handler_cat[handler_ix])
+        let route = if let Ok(handler_ix) = handler_cat.binary_search_by(|a| a.path.as_str().cmp(path)) {
+            &handler_cat[handler_ix]
         } else if !request_type.is_any() {
             let any_ix = self
                 .routes_any
-                .binary_search_by(|a| a.path.cmp(path))
+                .binary_search_by(|a| a.path.as_str().cmp(path))
                 .ok()?;
-            Some(&self.routes_any[any_ix])
+            &self.routes_any[any_ix]
         } else {
-            None
+            return None;
+        };
+        Some(route).filter(|x| x.enabled)
+    }
+
+    /// The methods with a route registered for `path`. If a route
+    /// accepts any method, every supported method is returned.
+    pub fn allowed_methods(&self, path: &str) -> Vec<RequestType> {
+        let has = |routes: &Vec<Route<T>>| {
+            routes
+                .binary_search_by(|a| a.path.as_str().cmp(path))
+                .is_ok_and(|ix| routes[ix].enabled)
+        };
+        if has(&self.routes_any) {
+            return vec![
+                RequestType::Get,
+                RequestType::Post,
+                RequestType::Put,
+                RequestType::Patch,
+                RequestType::Delete,
+            ];
         }
+        [
+            (&self.routes_get, RequestType::Get),
+            (&self.routes_post, RequestType::Post),
+            (&self.routes_put, RequestType::Put),
+            (&self.routes_patch, RequestType::Patch),
+            (&self.routes_delete, RequestType::Delete),
+        ]
+        .into_iter()
+        .filter(|(routes, _)| has(routes))
+        .map(|(_, request_type)| request_type)
+        .collect()
     }
-    fn add(&mut self, route: Route<T>) {
-        let handler_cat = match route.request_type {
+
+    fn category_mut(&mut self, request_type: &RequestType) -> &mut Vec<Route<T>> {
+        match request_type {
             RequestType::Get => &mut self.routes_get,
             RequestType::Post => &mut self.routes_post,
             RequestType::Put => &mut self.routes_put,
             RequestType::Patch => &mut self.routes_patch,
             RequestType::Delete => &mut self.routes_delete,
             _ => &mut self.routes_any,
-        };
-        handler_cat.push(route);
+        }
+    }
+
+    /// Add a route. Call [`RouteStorage::prep`] once all
+    /// routes are added.
+    pub fn add(&mut self, route: Route<T>) {
+        self.category_mut(&route.request_type).push(route);
+    }
+
+    /// Add a route to prepped storage, keeping it sorted. A route
+    /// with the same method and path is replaced and returned.
+    pub fn insert(&mut self, route: Route<T>) -> Option<Route<T>> {
+        let handler_cat = self.category_mut(&route.request_type);
+        match handler_cat.binary_search_by(|a| a.path.cmp(&route.path)) {
+            Ok(ix) => Some(std::mem::replace(&mut handler_cat[ix], route)),
+            Err(ix) => {
+                handler_cat.insert(ix, route);
+                None
+            }
+        }
     }
 
-    fn prep(&mut self) {
+    /// Remove a route from prepped storage.
+    pub fn remove(&mut self, request_type: &RequestType, path: &str) -> Option<Route<T>> {
+        let handler_cat = self.category_mut(request_type);
+        let ix = handler_cat.binary_search_by(|a| a.path.as_str().cmp(path)).ok()?;
+        Some(handler_cat.remove(ix))
+    }
+
+    /// Enable or disable a route in prepped storage. Returns
+    /// false if there's no such route.
+    pub fn set_enabled(&mut self, request_type: &RequestType, path: &str, enabled: bool) -> bool {
+        let handler_cat = self.category_mut(request_type);
+        match handler_cat.binary_search_by(|a| a.path.as_str().cmp(path)) {
+            Ok(ix) => {
+                handler_cat[ix].enabled = enabled;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Remove every route.
+    fn take_all(&mut self) -> Vec<Route<T>> {
+        let mut routes = Vec::new();
+        routes.append(&mut self.routes_get);
+        routes.append(&mut self.routes_post);
+        routes.append(&mut self.routes_put);
+        routes.append(&mut self.routes_patch);
+        routes.append(&mut self.routes_delete);
+        routes.append(&mut self.routes_any);
+        routes
+    }
+
+    /// Sort the routes so they can be searched.
+    pub fn prep(&mut self) {
         self.routes_get.sort_by(|a, b| a.path.cmp(&b.path));
         self.routes_post.sort_by(|a, b| a.path.cmp(&b.path));
         self.routes_put.sort_by(|a, b| a.path.cmp(&b.path));
@@ -391,12 +1676,220 @@ impl<T> RouteStorage<T> {
     }
 }
 
-type AsyncRouteHandler<T> = Box<
-    fn(
-        Request,
-        crate::DatabaseConnection,
-        T,
-    ) -> Pin<Box<dyn Future<Output = Result<Response, RouteError>>>>,
+impl<T> Default for RouteStorage<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Routes which can be added, replaced and disabled while
+/// the server is running, such as for plugins or endpoints
+/// toggled by an admin. Register it with
+/// [`Server::route_registry`]; clones share the same routes,
+/// so one registry can serve every worker.
+///
+/// Lookups take a read lock, so this is slightly slower
+/// than the default, fixed routes.
+pub struct RouteRegistry<T>(Arc<RwLock<RouteStorage<T>>>);
+impl<T> RouteRegistry<T> {
+    pub fn new() -> RouteRegistry<T> {
+        RouteRegistry(Arc::new(RwLock::new(RouteStorage::new())))
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, RouteStorage<T>> {
+        // Routes are only replaced whole, so a poisoned lock still holds valid routes.
+        self.0.read().unwrap_or_else(|x| x.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, RouteStorage<T>> {
+        self.0.write().unwrap_or_else(|x| x.into_inner())
+    }
+
+    /// Add a route, replacing any with the same method and path.
+    pub fn register(&self, route: Route<T>) {
+        self.write().insert(route);
+    }
+
+    /// Add routes under a prefix, like [`Server::module`].
+    pub fn module(&self, prefix: &str, rs: Vec<Route<T>>) {
+        let applied_prefix = module_prefix(prefix);
+        let mut routes = self.write();
+        for mut r in rs {
+            r.path.insert_str(0, &applied_prefix);
+            routes.insert(r);
+        }
+    }
+
+    /// Remove a route, returning whether it existed.
+    pub fn remove(&self, request_type: RequestType, path: &str) -> bool {
+        self.write().remove(&request_type, path).is_some()
+    }
+
+    /// Enable or disable a route. Disabled routes respond
+    /// as if they weren't registered. Returns false if
+    /// there's no such route.
+    pub fn set_enabled(&self, request_type: RequestType, path: &str, enabled: bool) -> bool {
+        self.write().set_enabled(&request_type, path, enabled)
+    }
+
+    /// Find a route, copying what's needed to handle the
+    /// request so the lock isn't held while it's handled.
+    fn select<'a>(&self, request: &Request) -> Option<SelectedRoute<'a, T>> {
+        self.read()
+            .handler(&request.request_type, &request.path)
+            .map(|route| SelectedRoute {
+                target: RouteTarget::Handler(*route.handler),
+                headers: Cow::Owned(route.headers.clone()),
+                path: Cow::Owned(route.path.clone()),
+                content_check: route.check_body(request),
+                budget: route.budget,
+                timeout: route.timeout,
+                example: route.example.clone().map(Cow::Owned),
+                replay_guard: route.replay_guard.clone().map(Cow::Owned),
+            })
+    }
+}
+impl<T> Clone for RouteRegistry<T> {
+    fn clone(&self) -> Self {
+        RouteRegistry(self.0.clone())
+    }
+}
+impl<T> Default for RouteRegistry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The parts of a route needed to handle a request.
+struct SelectedRoute<'a, T> {
+    target: RouteTarget<'a, T>,
+    headers: Cow<'a, [(String, String)]>,
+    path: Cow<'a, str>,
+    content_check: Result<(), RouteError>,
+    budget: Option<Duration>,
+    timeout: Option<Duration>,
+    example: Option<Cow<'a, RouteExample>>,
+    replay_guard: Option<Cow<'a, ReplayGuard>>,
+}
+impl<'a, T> SelectedRoute<'a, T> {
+    fn borrowed(route: &'a Route<T>, request: &Request) -> SelectedRoute<'a, T> {
+        SelectedRoute {
+            target: RouteTarget::Handler(*route.handler),
+            headers: Cow::Borrowed(&route.headers),
+            path: Cow::Borrowed(&route.path),
+            content_check: route.check_body(request),
+            budget: route.budget,
+            timeout: route.timeout,
+            example: route.example.as_ref().map(Cow::Borrowed),
+            replay_guard: route.replay_guard.as_ref().map(Cow::Borrowed),
+        }
+    }
+}
+
+enum RouteTarget<'a, T> {
+    /// A route, run after the server's treatment.
+    Handler(RouteHandlerFn<T>),
+    /// A route of a [`SubApp`], which runs its own treatment.
+    Mounted(MountedHandler<'a>),
+}
+
+/// Routes with their own treatment and initialization data,
+/// so independently developed apps can be served from one
+/// binary. Mount it under a prefix with [`Server::mount_app`].
+pub struct SubApp<U, W> {
+    routes: RouteStorage<U>,
+    treatment: AsyncTreatmentHandler<U, W>,
+}
+impl<U, W> SubApp<U, W> {
+    /// Create an app whose routes receive the output of `treatment`.
+    pub fn new(treatment: AsyncTreatmentHandler<U, W>) -> SubApp<U, W> {
+        SubApp {
+            routes: RouteStorage::new(),
+            treatment,
+        }
+    }
+
+    /// Register a [`Route`], like [`Server::register`].
+    pub fn register(&mut self, r: Route<U>) {
+        self.routes.add(r);
+    }
+
+    /// Register routes under a prefix, like [`Server::module`].
+    pub fn module(&mut self, prefix: &str, rs: Vec<Route<U>>) {
+        let applied_prefix = module_prefix(prefix);
+        for mut r in rs {
+            r.path.insert_str(0, &applied_prefix);
+            self.routes.add(r);
+        }
+    }
+}
+
+/// A [`SubApp`] with its initialization data, with the
+/// route types erased so apps can be stored together.
+trait MountedApp<T> {
+    fn select<'a>(&'a self, request: &Request) -> Option<SelectedRoute<'a, T>>;
+    fn allowed_methods(&self, path: &str) -> Vec<RequestType>;
+    fn route_body(&self, request: &Request) -> Option<(BodyMode, bool)>;
+}
+struct Mounted<U, W> {
+    routes: RouteStorage<U>,
+    treatment: AsyncTreatmentHandler<U, W>,
+    initialization_data: Rc<W>,
+}
+impl<T, U: 'static, W: 'static> MountedApp<T> for Mounted<U, W> {
+    fn select<'a>(&'a self, request: &Request) -> Option<SelectedRoute<'a, T>> {
+        let route = self.routes.handler(&request.request_type, &request.path)?;
+        let handler = *route.handler;
+        Some(SelectedRoute {
+            target: RouteTarget::Mounted(Box::new(move |req, db| {
+                Box::pin(async move {
+                    let (treat, req, db) = (self.treatment)(req, db, self.initialization_data.clone()).await?;
+                    handler(req, db, treat).await
+                })
+            })),
+            headers: Cow::Borrowed(&route.headers),
+            path: Cow::Borrowed(&route.path),
+            content_check: route.check_body(request),
+            budget: route.budget,
+            timeout: route.timeout,
+            example: route.example.as_ref().map(Cow::Borrowed),
+            replay_guard: route.replay_guard.as_ref().map(Cow::Borrowed),
+        })
+    }
+
+    fn allowed_methods(&self, path: &str) -> Vec<RequestType> {
+        self.routes.allowed_methods(path)
+    }
+
+    fn route_body(&self, request: &Request) -> Option<(BodyMode, bool)> {
+        self.routes
+            .handler(&request.request_type, &request.path)
+            .map(|x| (x.body_mode, x.keeps_raw_body()))
+    }
+}
+
+/// Normalizes a module prefix to `/prefix`, or nothing.
+fn module_prefix(prefix: &str) -> String {
+    let trimmed = prefix.trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
+type RouteHandlerFn<T> = fn(
+    Request,
+    crate::DatabaseConnection,
+    T,
+) -> Pin<Box<dyn Future<Output = Result<Response, RouteError>>>>;
+type AsyncRouteHandler<T> = Box<RouteHandlerFn<T>>;
+type MountedHandler<'a> = Box<
+    dyn FnOnce(
+            Request,
+            crate::DatabaseConnection,
+        ) -> Pin<Box<dyn Future<Output = Result<Response, RouteError>> + 'a>>
+        + 'a,
 >;
 type AsyncTreatmentHandler<T, V> = Box<
     fn(
@@ -406,6 +1899,10 @@ type AsyncTreatmentHandler<T, V> = Box<
     ) -> Pin<Box<dyn Future<Output = Result<(T, Request, crate::DatabaseConnection), RouteError>>>>,
 >;
 
+type AsyncPostfixHandler = Box<
+    fn(Request, Response, Duration) -> Pin<Box<dyn Future<Output = Response>>>,
+>;
+
 impl From<JsonParseError> for RouteError {
     fn from(val: JsonParseError) -> Self {
         match val {
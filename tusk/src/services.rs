@@ -0,0 +1,52 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt::{Debug, Formatter},
+    rc::Rc,
+};
+
+use crate::RouteError;
+
+/// A registry of shared services, keyed by type.
+///
+/// Services are registered once using [`crate::Server::add_service`]
+/// and shared by every request. Resolve them in handlers with
+/// [`crate::Request::service`].
+#[derive(Clone, Default)]
+pub struct Services {
+    values: HashMap<TypeId, Rc<dyn Any>>,
+}
+impl Services {
+    /// Create an empty registry.
+    pub fn new() -> Services {
+        Services { values: HashMap::new() }
+    }
+
+    /// Register a service, replacing any service of the same type.
+    pub fn insert<T: 'static>(&mut self, service: T) {
+        self.values.insert(TypeId::of::<T>(), Rc::new(service));
+    }
+
+    /// Get the service of type `T`.
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.values.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    /// Get the service of type `T`, or a 500 error
+    /// if it was never registered.
+    pub fn require<T: 'static>(&self) -> Result<&T, RouteError> {
+        self.get().ok_or_else(|| {
+            RouteError::server_error(&format!(
+                "Service {} is not registered.",
+                std::any::type_name::<T>()
+            ))
+        })
+    }
+}
+impl Debug for Services {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Services")
+            .field("len", &self.values.len())
+            .finish()
+    }
+}
@@ -0,0 +1,141 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{Response, ResponseStatusCode, RouteError};
+
+/// Answers requests with a 503 and `Retry-After` when no
+/// database connection is free within `max_wait`, rather than
+/// queueing them until one is. Register it with
+/// [`crate::Server::load_shedding`].
+///
+/// Clients are told to come back later while the pool is
+/// saturated (or the database is slow to accept connections),
+/// so a backlog doesn't build up behind it.
+#[derive(Debug, Clone)]
+pub struct LoadShedding {
+    pub(crate) max_wait: Duration,
+    retry_after: Duration,
+    message: String,
+}
+impl LoadShedding {
+    /// Shed requests which wait more than 500ms for a
+    /// connection, asking clients to retry after 5 seconds.
+    pub fn new() -> LoadShedding {
+        LoadShedding {
+            max_wait: Duration::from_millis(500),
+            retry_after: Duration::from_secs(5),
+            message: "The service is overloaded.".to_string(),
+        }
+    }
+
+    /// Define how long a request may wait for a database
+    /// connection. Can be chained.
+    pub fn max_wait(mut self, max_wait: Duration) -> LoadShedding {
+        self.max_wait = max_wait;
+        self
+    }
+
+    /// Define the `Retry-After` sent to clients. Can be chained.
+    pub fn retry_after(mut self, retry_after: Duration) -> LoadShedding {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Define the error message sent to clients. Can be chained.
+    pub fn message(mut self, message: &str) -> LoadShedding {
+        self.message = message.to_string();
+        self
+    }
+
+    pub(crate) fn response(&self) -> Response {
+        RouteError::custom(&self.message, ResponseStatusCode::ServiceUnavailable)
+            .to_response()
+            .header("Retry-After", self.retry_after.as_secs().to_string())
+    }
+}
+impl Default for LoadShedding {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Caps how many connections a server holds at once: the one
+/// being handled, and those accepted and queued behind it.
+/// Register it with [`crate::Server::connection_limit`], sharing
+/// clones of one limit between workers to cap the whole process.
+///
+/// With a limit, each worker accepts every connection waiting in
+/// the listen queue before handling the next, so the limit
+/// applies to them rather than the kernel's backlog. Connections
+/// past the limit, or queued longer than `queue_timeout`, have
+/// their request head read and are answered with a 503 and
+/// `Retry-After`, then closed. Rejections are reported to
+/// [`crate::Server::on_connection_error`] as `ErrorKind::WouldBlock`.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimit {
+    permits: Arc<Semaphore>,
+    queue_timeout: Option<Duration>,
+    retry_after: Duration,
+    message: String,
+}
+impl ConnectionLimit {
+    /// How long the request head of a rejected connection is read
+    /// for, so closing it doesn't reset the connection before the
+    /// client has read the 503.
+    pub(crate) const DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+    /// Hold at most `max` connections at once, rejecting others
+    /// and asking clients to retry after 5 seconds.
+    pub fn new(max: usize) -> ConnectionLimit {
+        ConnectionLimit {
+            permits: Arc::new(Semaphore::new(max)),
+            queue_timeout: None,
+            retry_after: Duration::from_secs(5),
+            message: "The service is overloaded.".to_string(),
+        }
+    }
+
+    /// Define how long an accepted connection may wait to be
+    /// handled before it's rejected. By default it waits until
+    /// it's handled. Can be chained.
+    pub fn queue_timeout(mut self, queue_timeout: Duration) -> ConnectionLimit {
+        self.queue_timeout = Some(queue_timeout);
+        self
+    }
+
+    /// Define the `Retry-After` sent to clients. Can be chained.
+    pub fn retry_after(mut self, retry_after: Duration) -> ConnectionLimit {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Define the error message sent to clients. Can be chained.
+    pub fn message(mut self, message: &str) -> ConnectionLimit {
+        self.message = message.to_string();
+        self
+    }
+
+    /// How many more connections can be held right now.
+    pub fn available(&self) -> usize {
+        self.permits.available_permits()
+    }
+
+    /// Room for an accepted connection, held until the permit
+    /// is dropped, or `None` if the limit is reached.
+    pub(crate) fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.permits.clone().try_acquire_owned().ok()
+    }
+
+    /// Whether a connection accepted `waited` ago has been
+    /// queued for too long.
+    pub(crate) fn expired(&self, waited: Duration) -> bool {
+        self.queue_timeout.is_some_and(|x| waited > x)
+    }
+
+    pub(crate) fn response(&self) -> Response {
+        RouteError::custom(&self.message, ResponseStatusCode::ServiceUnavailable)
+            .to_response()
+            .header("Retry-After", self.retry_after.as_secs().to_string())
+    }
+}
@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+
+use crate::{apikey::to_hex, Request, RouteError};
+
+/// Where a [`SignatureVerifier`] finds the time a request was signed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Timestamp {
+    /// Requests aren't timestamped, so replays can't be refused.
+    None,
+    /// In a header of its own, as Unix seconds.
+    Header(String),
+    /// As a `t=` entry of the signature header.
+    InSignature,
+}
+
+/// Verifies HMAC-SHA256 signatures of webhook requests, such as
+/// those sent by Stripe, Slack and GitHub. Add it to a route with
+/// [`crate::Route::verify_signature`], or call [`SignatureVerifier::verify`]
+/// with [`Request::raw_body`] from a handler.
+///
+/// The signed payload is the timestamp and body, joined as
+/// configured with [`SignatureVerifier::payload_format`]. Timestamps
+/// further than the tolerance from now are refused, so captured
+/// requests can't be replayed later.
+///
+/// The signature header may hold several comma-separated entries,
+/// and is accepted if any entry starting with the signature prefix
+/// matches. Signatures are compared in constant time.
+#[derive(Debug, Clone)]
+pub struct SignatureVerifier {
+    secret: Vec<u8>,
+    header: String,
+    timestamp: Timestamp,
+    signature_prefix: String,
+    payload_prefix: String,
+    separator: String,
+    tolerance: Duration,
+}
+impl SignatureVerifier {
+    /// Verify a hex HMAC of the body alone, sent in `header`.
+    pub fn new(secret: &[u8], header: &str) -> SignatureVerifier {
+        SignatureVerifier {
+            secret: secret.to_vec(),
+            header: header.to_lowercase(),
+            timestamp: Timestamp::None,
+            signature_prefix: String::new(),
+            payload_prefix: String::new(),
+            separator: ".".to_string(),
+            tolerance: Duration::from_secs(300),
+        }
+    }
+
+    /// Stripe's `Stripe-Signature` header, signing `{t}.{body}`.
+    pub fn stripe(secret: &str) -> SignatureVerifier {
+        SignatureVerifier::new(secret.as_bytes(), "stripe-signature")
+            .timestamp_in_signature()
+            .signature_prefix("v1=")
+    }
+
+    /// Slack's `X-Slack-Signature` header, signing `v0:{t}:{body}`.
+    pub fn slack(secret: &str) -> SignatureVerifier {
+        SignatureVerifier::new(secret.as_bytes(), "x-slack-signature")
+            .timestamp_header("x-slack-request-timestamp")
+            .signature_prefix("v0=")
+            .payload_format("v0:", ":")
+    }
+
+    /// GitHub's `X-Hub-Signature-256` header. GitHub doesn't
+    /// timestamp requests, so replays can't be refused.
+    pub fn github(secret: &str) -> SignatureVerifier {
+        SignatureVerifier::new(secret.as_bytes(), "x-hub-signature-256").signature_prefix("sha256=")
+    }
+
+    /// Read the signing time, in Unix seconds, from `header`.
+    /// Can be chained.
+    pub fn timestamp_header(mut self, header: &str) -> SignatureVerifier {
+        self.timestamp = Timestamp::Header(header.to_lowercase());
+        self
+    }
+
+    /// Read the signing time from a `t=` entry of the signature
+    /// header. Can be chained.
+    pub fn timestamp_in_signature(mut self) -> SignatureVerifier {
+        self.timestamp = Timestamp::InSignature;
+        self
+    }
+
+    /// Define the text before each signature in the header,
+    /// such as `v1=`. Can be chained.
+    pub fn signature_prefix(mut self, prefix: &str) -> SignatureVerifier {
+        self.signature_prefix = prefix.to_string();
+        self
+    }
+
+    /// Define how the signed payload is built for timestamped
+    /// requests: `{prefix}{timestamp}{separator}{body}`.
+    /// Can be chained.
+    pub fn payload_format(mut self, prefix: &str, separator: &str) -> SignatureVerifier {
+        self.payload_prefix = prefix.to_string();
+        self.separator = separator.to_string();
+        self
+    }
+
+    /// Define how far from now timestamps may be. Defaults
+    /// to 5 minutes. Can be chained.
+    pub fn tolerance(mut self, tolerance: Duration) -> SignatureVerifier {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// The hex signature of a body, signed at `timestamp`
+    /// if requests are timestamped.
+    pub fn signature(&self, timestamp: Option<i64>, body: &[u8]) -> String {
+        let key = PKey::hmac(&self.secret).unwrap();
+        let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+        if let Some(timestamp) = timestamp {
+            let head = format!("{}{}{}", self.payload_prefix, timestamp, self.separator);
+            signer.update(head.as_bytes()).unwrap();
+        }
+        signer.update(body).unwrap();
+        to_hex(&signer.sign_to_vec().unwrap())
+    }
+
+    /// Checks a request's signature against its raw body.
+    /// Failures are 401 errors.
+    pub fn verify(&self, request: &Request, body: &[u8]) -> Result<(), RouteError> {
+        let header = request
+            .headers
+            .get(&self.header)
+            .ok_or_else(|| RouteError::unauthorized("Missing request signature."))?;
+        let entries = header.split(',').map(str::trim);
+        let timestamp = match &self.timestamp {
+            Timestamp::None => None,
+            Timestamp::Header(name) => Some(request.headers.get(name).map(String::as_str)),
+            Timestamp::InSignature => Some(entries.clone().find_map(|x| x.strip_prefix("t="))),
+        };
+        let timestamp = match timestamp {
+            Some(value) => {
+                let timestamp = value
+                    .and_then(|x| x.trim().parse::<i64>().ok())
+                    .ok_or_else(|| RouteError::unauthorized("Missing request timestamp."))?;
+                if Utc::now().timestamp().abs_diff(timestamp) > self.tolerance.as_secs() {
+                    return Err(RouteError::unauthorized("Request timestamp is out of range."));
+                }
+                Some(timestamp)
+            }
+            None => None,
+        };
+        let expected = self.signature(timestamp, body);
+        let matched = entries
+            .filter_map(|x| x.strip_prefix(self.signature_prefix.as_str()))
+            .map(str::to_ascii_lowercase)
+            .any(|x| x.len() == expected.len() && openssl::memcmp::eq(x.as_bytes(), expected.as_bytes()));
+        if matched {
+            Ok(())
+        } else {
+            Err(RouteError::unauthorized("Invalid request signature."))
+        }
+    }
+}
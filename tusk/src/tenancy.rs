@@ -0,0 +1,193 @@
+use crate::{DatabaseConnection, IdTokenValidator, JsonField, JsonNode, PostgresTable, Request, RouteError};
+
+/// The tenant a request was made for, resolved by [`Tenancy`]
+/// and placed in the request's extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant {
+    pub id: String,
+}
+
+/// Where [`Tenancy`] looks for the tenant of a request.
+enum TenantSource {
+    /// The first label of hosts under this domain.
+    Subdomain(String),
+    Header(String),
+    /// A claim of the validated bearer token.
+    Claim(IdTokenValidator, String),
+}
+
+/// Resolves the tenant of each request and isolates tenants in
+/// Postgres. Register it with [`crate::Server::tenancy`].
+///
+/// Sources are tried in the order they're added. Tenant ids may
+/// only contain letters, digits, `-` and `_`, so they're safe in
+/// schema names; other values are ignored.
+///
+/// Before the treatment runs, the tenant id is stored in the
+/// `tusk.tenant_id` setting of the request's connection (empty
+/// without a tenant). Tables of [`PostgresTenantScoped`] models
+/// use it to only show and accept the tenant's rows. With
+/// [`Tenancy::schema_per_tenant`], the `search_path` is switched
+/// to the tenant's schema too.
+///
+/// ```ignore
+/// server.tenancy(Tenancy::new().subdomain("example.com").header("x-tenant").required());
+/// ```
+pub struct Tenancy {
+    sources: Vec<TenantSource>,
+    required: bool,
+    schema_prefix: Option<String>,
+}
+impl Tenancy {
+    /// The connection setting holding the current tenant's id.
+    pub const SETTING: &'static str = "tusk.tenant_id";
+    const MAX_ID_LEN: usize = 63;
+
+    /// Create a resolver without any sources.
+    pub fn new() -> Tenancy {
+        Tenancy {
+            sources: Vec::new(),
+            required: false,
+            schema_prefix: None,
+        }
+    }
+
+    /// Resolve `acme.example.com` to `acme` for a `domain` of
+    /// `example.com`. Can be chained.
+    pub fn subdomain(mut self, domain: &str) -> Tenancy {
+        self.sources
+            .push(TenantSource::Subdomain(domain.trim_start_matches('.').to_lowercase()));
+        self
+    }
+
+    /// Resolve the tenant from a header. Can be chained.
+    pub fn header(mut self, name: &str) -> Tenancy {
+        self.sources.push(TenantSource::Header(name.to_lowercase()));
+        self
+    }
+
+    /// Resolve the tenant from a string or integer claim of the
+    /// `Bearer` token, once `validator` has validated it.
+    /// Can be chained.
+    pub fn claim(mut self, validator: IdTokenValidator, claim: &str) -> Tenancy {
+        self.sources.push(TenantSource::Claim(validator, claim.to_string()));
+        self
+    }
+
+    /// Refuse requests without a tenant with a 400. Can be chained.
+    pub fn required(mut self) -> Tenancy {
+        self.required = true;
+        self
+    }
+
+    /// Give each tenant its own schema, named `prefix` followed by
+    /// the tenant id, which is searched before `public`.
+    /// Can be chained.
+    pub fn schema_per_tenant(mut self, prefix: &str) -> Tenancy {
+        self.schema_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// The tenant of a request, from the first source which has one.
+    pub fn resolve(&self, request: &Request) -> Option<Tenant> {
+        self.sources
+            .iter()
+            .find_map(|source| {
+                let id = match source {
+                    TenantSource::Subdomain(domain) => {
+                        let host = request.host()?.to_lowercase();
+                        let label = host.strip_suffix(domain.as_str())?.strip_suffix('.')?;
+                        (!label.contains('.')).then(|| label.to_string())
+                    }
+                    TenantSource::Header(name) => request.headers.get(name).map(|x| x.trim().to_string()),
+                    TenantSource::Claim(validator, claim) => {
+                        let token = validator.validate(request.bearer_token()?, None).ok()?;
+                        match token.claims.get(claim)? {
+                            JsonNode::String(id) => Some(id.clone()),
+                            id => i64::from_json_node(id).map(|x| x.to_string()),
+                        }
+                    }
+                };
+                id.filter(|x| Self::valid_id(x))
+            })
+            .map(|id| Tenant { id })
+    }
+
+    fn valid_id(id: &str) -> bool {
+        !id.is_empty()
+            && id.len() <= Self::MAX_ID_LEN
+            && id.chars().all(|x| x.is_ascii_alphanumeric() || x == '-' || x == '_')
+    }
+
+    /// Resolves the tenant, or the 400 for a missing one.
+    pub(crate) fn tenant_for(&self, request: &Request) -> Result<Option<Tenant>, RouteError> {
+        match self.resolve(request) {
+            None if self.required => Err(RouteError::bad_request("A tenant is required.")),
+            tenant => Ok(tenant),
+        }
+    }
+
+    /// Points a connection at a tenant. Connections are pooled, so
+    /// this also runs without a tenant to clear the previous one.
+    pub(crate) async fn apply(
+        &self,
+        db: &DatabaseConnection,
+        tenant: Option<&Tenant>,
+    ) -> Result<(), tokio_postgres::Error> {
+        let id = tenant.map(|x| x.id.as_str()).unwrap_or("");
+        match &self.schema_prefix {
+            Some(prefix) => {
+                let search_path = match tenant {
+                    Some(tenant) => format!("\"{}{}\", public", prefix.replace('"', "\"\""), tenant.id),
+                    None => "\"$user\", public".to_string(),
+                };
+                db.set_settings(&[(Self::SETTING, id), ("search_path", &search_path)]).await?;
+            }
+            None => db.set_settings(&[(Self::SETTING, id)]).await?,
+        }
+        Ok(())
+    }
+}
+impl Default for Tenancy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A model whose rows each belong to a tenant. The table is
+/// isolated by a row level security policy, from
+/// [`PostgresTenantScoped::tenant_policy_sql`], which adds an
+/// implicit `tenant_id` predicate to every query on it, and
+/// refuses writes for other tenants.
+///
+/// Superusers and roles with `BYPASSRLS` aren't subject to
+/// policies, so connect as an ordinary role.
+pub trait PostgresTenantScoped: PostgresTable {
+    /// The column holding the tenant id.
+    fn tenant_column() -> &'static str {
+        "tenant_id"
+    }
+
+    /// The statements enabling the policy, for a migration.
+    fn tenant_policy_sql() -> String {
+        let table = Self::table_name();
+        let matches = format!(
+            "{}::text = current_setting('{}', true)",
+            Self::tenant_column(),
+            Tenancy::SETTING
+        );
+        format!(
+            "ALTER TABLE {table} ENABLE ROW LEVEL SECURITY;
+ALTER TABLE {table} FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tusk_tenant ON {table};
+CREATE POLICY tusk_tenant ON {table} USING ({matches}) WITH CHECK ({matches});"
+        )
+    }
+}
+
+impl Request {
+    /// The tenant resolved by [`Tenancy`], if any.
+    pub fn tenant(&self) -> Option<&Tenant> {
+        self.extensions.get::<Tenant>()
+    }
+}
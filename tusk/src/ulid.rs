@@ -0,0 +1,160 @@
+use std::{error::Error, fmt::Display, str::FromStr};
+
+use brackets::ToJson;
+use bytes::{BufMut, BytesMut};
+use chrono::{DateTime, TimeZone, Utc};
+use tokio_postgres::types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
+
+use crate::{FromUrlEncoded, JsonField, JsonNode};
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// A ULID: a 128-bit id beginning with its creation time in
+/// milliseconds, so ids sort in the order they were created.
+///
+/// Stored in Postgres as a binary `uuid` (or `bytea`), and
+/// serialized as its 26 character Crockford base32 form.
+/// Create one with [`new_id`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ulid(pub u128);
+impl Ulid {
+    /// Create an id for the current time.
+    pub fn new() -> Ulid {
+        let millis = Utc::now().timestamp_millis().max(0) as u128 & ((1 << 48) - 1);
+        let mut random = [0u8; 16];
+        openssl::rand::rand_bytes(&mut random[6..]).expect("Could not generate random bytes!");
+        Ulid(millis << 80 | u128::from_be_bytes(random))
+    }
+
+    /// When the id was created.
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        Utc.timestamp_millis_opt((self.0 >> 80) as i64)
+            .single()
+            .unwrap_or_default()
+    }
+
+    pub fn to_bytes(&self) -> [u8; 16] {
+        self.0.to_be_bytes()
+    }
+
+    /// The id in UUID form, such as `01890a5d-ac96-774b-bcce-b302099a8057`.
+    pub fn to_uuid_string(&self) -> String {
+        let hex = format!("{:032x}", self.0);
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        )
+    }
+}
+impl Default for Ulid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Create a new [`Ulid`].
+pub fn new_id() -> Ulid {
+    Ulid::new()
+}
+
+impl Display for Ulid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut output = [0u8; 26];
+        for (ix, c) in output.iter_mut().enumerate() {
+            *c = CROCKFORD[(self.0 >> ((25 - ix) * 5)) as usize & 0x1F];
+        }
+        // Only ASCII is written, so this can't fail.
+        f.write_str(std::str::from_utf8(&output).unwrap())
+    }
+}
+
+/// Why a string couldn't be read as a [`Ulid`].
+#[derive(Debug)]
+pub struct UlidParseError;
+impl Display for UlidParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected a 26 character ULID or a UUID")
+    }
+}
+impl Error for UlidParseError {}
+
+impl FromStr for Ulid {
+    type Err = UlidParseError;
+
+    /// Parses the Crockford base32 form (case-insensitively)
+    /// or the UUID form.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.len() {
+            26 => {
+                // The first character only holds 3 bits.
+                if !matches!(s.as_bytes()[0], b'0'..=b'7') {
+                    return Err(UlidParseError);
+                }
+                let mut value = 0u128;
+                for c in s.bytes() {
+                    let digit = match c.to_ascii_uppercase() {
+                        b'O' => 0,
+                        b'I' | b'L' => 1,
+                        c => CROCKFORD.iter().position(|x| *x == c).ok_or(UlidParseError)? as u128,
+                    };
+                    value = value << 5 | digit;
+                }
+                Ok(Ulid(value))
+            }
+            36 => {
+                let hex = s.split('-').collect::<String>();
+                if hex.len() != 32 || !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+                    return Err(UlidParseError);
+                }
+                u128::from_str_radix(&hex, 16).map(Ulid).map_err(|_| UlidParseError)
+            }
+            _ => Err(UlidParseError),
+        }
+    }
+}
+
+impl ToSql for Ulid {
+    fn to_sql(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        out.put_u128(self.0);
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::UUID || *ty == Type::BYTEA
+    }
+
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for Ulid {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Ulid, Box<dyn Error + Sync + Send>> {
+        let bytes: [u8; 16] = raw.try_into().map_err(|_| "expected 16 bytes")?;
+        Ok(Ulid(u128::from_be_bytes(bytes)))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::UUID || *ty == Type::BYTEA
+    }
+}
+
+impl ToJson for Ulid {
+    fn to_json(&self) -> String {
+        format!("\"{}\"", self)
+    }
+}
+
+impl JsonField for Ulid {
+    fn from_json_node(json: &JsonNode) -> Option<Self> {
+        json.as_str()?.parse().ok()
+    }
+}
+
+impl FromUrlEncoded for Ulid {
+    fn from_url_encoded(data: &str) -> Option<Self> {
+        data.parse().ok()
+    }
+}
@@ -1,5 +1,7 @@
 use std::{collections::{HashMap, BTreeMap}};
 
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime};
+
 #[derive(Debug)]
 pub struct UrlEncoded {
     values: HashMap<String, String>,
@@ -115,11 +117,66 @@ impl FromUrlEncoded for i32 {
         data.parse().ok()
     }
 }
+impl FromUrlEncoded for i64 {
+    fn from_url_encoded(data: &str) -> Option<Self> {
+        data.parse().ok()
+    }
+}
+/// Parses `true`/`false` and `1`/`0`.
+impl FromUrlEncoded for bool {
+    fn from_url_encoded(data: &str) -> Option<Self> {
+        match data {
+            "true" | "1" => Some(true),
+            "false" | "0" => Some(false),
+            _ => None,
+        }
+    }
+}
 impl FromUrlEncoded for f64 {
     fn from_url_encoded(data: &str) -> Option<Self> {
         data.parse().ok()
     }
 }
+/// Parses `2024-01-31`.
+impl FromUrlEncoded for NaiveDate {
+    fn from_url_encoded(data: &str) -> Option<Self> {
+        data.parse().ok()
+    }
+}
+/// Parses `13:45` or `13:45:30.5`.
+impl FromUrlEncoded for NaiveTime {
+    fn from_url_encoded(data: &str) -> Option<Self> {
+        data.parse()
+            .ok()
+            .or_else(|| NaiveTime::parse_from_str(data, "%H:%M").ok())
+    }
+}
+/// Parses RFC 3339 timestamps, such as `2024-01-31T13:45:00+02:00`.
+impl FromUrlEncoded for DateTime<FixedOffset> {
+    fn from_url_encoded(data: &str) -> Option<Self> {
+        DateTime::parse_from_rfc3339(data).ok()
+    }
+}
+
+/// Percent-encodes a value for use in a query string
+/// or `application/x-www-form-urlencoded` body.
+pub fn encode_url_component(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                output.push(b as char)
+            }
+            _ => output.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    output
+}
+
+/// Decodes a percent-encoded query or form value.
+pub(crate) fn decode_url_component(value: &str) -> String {
+    value.to_string().decode_url()
+}
 
 trait UrlEncodedParse {
     fn decode_url(self) -> String;
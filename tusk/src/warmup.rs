@@ -0,0 +1,105 @@
+use crate::{
+    config::PoolMode,
+    query::{insert_statement, select_prefix},
+    Database, DatabaseConnection, PostgresReadable, PostgresTable, PostgresWriteFields,
+};
+
+/// Statements to generate and prepare when the server starts,
+/// so the first request using each one doesn't wait for them.
+/// Register it with [`crate::Server::warmup`].
+///
+/// Statements are generated on every worker, and prepared on
+/// [`Warmup::connections`] connections. Once warmed up, a
+/// statement is prepared once per connection and reused, rather
+/// than parsed on every use. With [`PoolMode::Transaction`],
+/// statements are only generated, since prepared statements
+/// don't outlive a transaction.
+///
+/// ```ignore
+/// server.warmup(
+///     Warmup::new()
+///         .model::<User>()
+///         .model::<Post>()
+///         .select::<User>("WHERE users.id = $1"),
+/// );
+/// ```
+pub struct Warmup {
+    statements: Vec<Box<dyn Fn() -> String>>,
+    connections: usize,
+}
+impl Warmup {
+    pub fn new() -> Warmup {
+        Warmup {
+            statements: Vec::new(),
+            connections: 1,
+        }
+    }
+
+    /// Warm up reading `M`, and inserting it from
+    /// [`crate::PostgresWriteable::write`]. Can be chained.
+    pub fn model<M: PostgresReadable + PostgresTable + PostgresWriteFields + 'static>(self) -> Warmup {
+        let mut warmup = self.read_model::<M>();
+        warmup.statements.push(Box::new(|| {
+            let fields = M::write_fields();
+            DatabaseConnection::insert_query::<M>(&insert_statement(M::table_name(), fields, fields.len()))
+        }));
+        warmup
+    }
+
+    /// Warm up reading `M`, for models which aren't written,
+    /// such as views. Can be chained.
+    pub fn read_model<M: PostgresReadable + PostgresTable + 'static>(mut self) -> Warmup {
+        self.statements.push(Box::new(|| select_prefix::<M>().to_string()));
+        self
+    }
+
+    /// Warm up reading `M` with `condition`, written as it's
+    /// passed to [`DatabaseConnection::select_all`] or
+    /// [`DatabaseConnection::select_one`]. Can be chained.
+    pub fn select<M: PostgresReadable + PostgresTable + 'static>(mut self, condition: &str) -> Warmup {
+        let condition = condition.to_string();
+        self.statements
+            .push(Box::new(move || DatabaseConnection::select_query::<M>(&condition)));
+        self
+    }
+
+    /// Define how many connections statements are prepared on.
+    /// Defaults to 1. Can be chained.
+    pub fn connections(mut self, connections: usize) -> Warmup {
+        self.connections = connections.max(1);
+        self
+    }
+
+    /// The statements to warm up.
+    pub fn statements(&self) -> Vec<String> {
+        self.statements.iter().map(|x| x()).collect()
+    }
+
+    /// Generates and prepares the statements, returning how
+    /// many were prepared.
+    pub async fn run(&self, db: &Database) -> Result<usize, deadpool_postgres::PoolError> {
+        let statements = self.statements();
+        if db.pool_mode() == PoolMode::Transaction {
+            return Ok(0);
+        }
+        let mut connections = Vec::with_capacity(self.connections);
+        for _ in 0..self.connections {
+            connections.push(db.get_connection().await?);
+        }
+        for connection in &connections {
+            for statement in &statements {
+                connection
+                    .prepare_cached(statement)
+                    .await
+                    .map_err(deadpool_postgres::PoolError::Backend)?;
+            }
+        }
+        db.keep_prepared(&statements);
+        Ok(statements.len() * connections.len())
+    }
+}
+impl Default for Warmup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
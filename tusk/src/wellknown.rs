@@ -0,0 +1,78 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use crate::{Request, RequestType, Response};
+
+const PREFIX: &str = "/.well-known/";
+const ACME_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// Documents served under `/.well-known/`, such as
+/// `security.txt`. Add them with [`crate::Server::well_known`].
+#[derive(Default)]
+pub(crate) struct WellKnown {
+    documents: HashMap<String, (&'static str, Vec<u8>)>,
+    challenges: Option<AcmeChallenges>,
+}
+impl WellKnown {
+    pub(crate) fn add(&mut self, name: &str, content_type: &'static str, contents: Vec<u8>) {
+        self.documents
+            .insert(name.trim_matches('/').to_string(), (content_type, contents));
+    }
+
+    pub(crate) fn set_challenges(&mut self, challenges: AcmeChallenges) {
+        self.challenges = Some(challenges);
+    }
+
+    /// The response for a `GET` of a well-known document or a
+    /// pending ACME challenge. These don't need the database,
+    /// so they're answered before the treatment runs.
+    pub(crate) fn response(&self, request: &Request) -> Option<Response> {
+        if request.request_type != RequestType::Get {
+            return None;
+        }
+        if let (Some(challenges), Some(token)) = (&self.challenges, request.path.strip_prefix(ACME_PREFIX)) {
+            return challenges
+                .get(token)
+                .map(|x| Response::string(x).header("Content-Type", "application/octet-stream"));
+        }
+        let (content_type, contents) = self.documents.get(request.path.strip_prefix(PREFIX)?)?;
+        Some(Response::data(contents.clone()).header("Content-Type", *content_type))
+    }
+}
+
+/// Pending ACME HTTP-01 challenges, answered at
+/// `/.well-known/acme-challenge/<token>` with their key
+/// authorization. Clones share the same challenges, so one
+/// can be registered with every worker using
+/// [`crate::Server::acme_challenges`] while a certificate
+/// client adds and removes challenges.
+#[derive(Debug, Clone, Default)]
+pub struct AcmeChallenges(Arc<RwLock<HashMap<String, String>>>);
+impl AcmeChallenges {
+    pub fn new() -> AcmeChallenges {
+        AcmeChallenges::default()
+    }
+
+    /// Answer the challenge for `token` until it's removed.
+    pub fn add(&self, token: &str, key_authorization: &str) {
+        self.0
+            .write()
+            .unwrap_or_else(|x| x.into_inner())
+            .insert(token.to_string(), key_authorization.to_string());
+    }
+
+    pub fn remove(&self, token: &str) {
+        self.0.write().unwrap_or_else(|x| x.into_inner()).remove(token);
+    }
+
+    /// The key authorization for a pending challenge.
+    pub fn get(&self, token: &str) -> Option<String> {
+        self.0
+            .read()
+            .unwrap_or_else(|x| x.into_inner())
+            .get(token)
+            .cloned()
+    }
+}
@@ -0,0 +1,238 @@
+//! These tests need a Postgres server, set with `TUSK_TEST_HOST`,
+//! `TUSK_TEST_USERNAME`, `TUSK_TEST_PASSWORD` and
+//! `TUSK_TEST_DATABASE`. Without `TUSK_TEST_HOST` they're skipped.
+
+use std::time::Duration;
+
+use tusk_rs::{
+    config::DatabaseConfig, database::PostgresWriteError, Database, DatabaseConnection, PostgresOwned, PostgresTable,
+    PostgresWriteRef,
+};
+use tusk_rs_derive::{FromPostgres, PostgresJoins, PostgresReadFields, PostgresReadable};
+
+#[derive(FromPostgres, PostgresReadFields, PostgresJoins, PostgresReadable)]
+struct BatchItem {
+    name: String,
+}
+impl PostgresTable for BatchItem {
+    fn table_name() -> &'static str {
+        "tusk_test_batch"
+    }
+}
+
+/// Reads `tusk_test_batch` with the wrong type for `name`.
+#[derive(FromPostgres, PostgresReadFields, PostgresJoins, PostgresReadable)]
+struct MistypedItem {
+    #[allow(dead_code)]
+    name: i32,
+}
+impl PostgresTable for MistypedItem {
+    fn table_name() -> &'static str {
+        "tusk_test_batch"
+    }
+}
+
+/// Reads `tusk_test_batch` with its kind's label, leaving out
+/// items without a kind.
+#[derive(FromPostgres, PostgresReadFields, PostgresJoins, PostgresReadable)]
+#[tusk(join(table = "tusk_test_kinds", on = "kind = kind"))]
+struct LabelledItem {
+    name: String,
+    #[tusk(from_join = "tusk_test_kinds.label")]
+    label: String,
+}
+impl PostgresTable for LabelledItem {
+    fn table_name() -> &'static str {
+        "tusk_test_batch"
+    }
+}
+
+/// Rows of `tusk_test_owned`, which belong to an owner.
+#[derive(FromPostgres, PostgresReadFields, PostgresJoins, PostgresReadable)]
+struct OwnedItem {
+    name: String,
+    owner: i32,
+}
+impl PostgresTable for OwnedItem {
+    fn table_name() -> &'static str {
+        "tusk_test_owned"
+    }
+}
+impl PostgresOwned for OwnedItem {
+    fn owner_column() -> &'static str {
+        "owner"
+    }
+}
+
+async fn database() -> Option<Database> {
+    database_with(|x| x).await
+}
+
+async fn database_with(configure: impl FnOnce(DatabaseConfig) -> DatabaseConfig) -> Option<Database> {
+    let host = std::env::var("TUSK_TEST_HOST").ok()?;
+    let var = |name: &str, default: &str| std::env::var(name).unwrap_or_else(|_| default.to_string());
+    let config = DatabaseConfig::new()
+        .host(host)
+        .username(var("TUSK_TEST_USERNAME", "postgres"))
+        .password(var("TUSK_TEST_PASSWORD", ""))
+        .database(var("TUSK_TEST_DATABASE", "postgres"))
+        .ssl(false);
+    Some(Database::new(configure(config)).await.expect("could not create pool"))
+}
+
+/// The connection's backend and whether it's in a transaction.
+async fn backend(db: &DatabaseConnection) -> (i32, bool) {
+    let row = &db
+        .query(
+            "SELECT pg_backend_pid(), current_setting('tusk.test_open', true) IS NOT DISTINCT FROM 'yes'",
+            &[],
+        )
+        .await
+        .unwrap()[0];
+    (row.get(0), row.get(1))
+}
+
+async fn open_transaction(db: &DatabaseConnection) {
+    db.begin().await.unwrap();
+    db.query("SELECT set_config('tusk.test_open', 'yes', true)", &[])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn finished_connections_are_reused() {
+    let Some(database) = database().await else {
+        return;
+    };
+    let db = database.get_connection().await.unwrap();
+    let (pid, _) = backend(&db).await;
+    open_transaction(&db).await;
+    db.commit().await.unwrap();
+    drop(db);
+
+    let db = database.get_connection().await.unwrap();
+    assert_eq!(backend(&db).await, (pid, false));
+}
+
+#[tokio::test]
+async fn dropping_inside_a_transaction_rolls_it_back() {
+    let Some(database) = database().await else {
+        return;
+    };
+    let db = database.get_connection().await.unwrap();
+    let (pid, _) = backend(&db).await;
+    // As when a route times out partway through.
+    let route = async move {
+        open_transaction(&db).await;
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        db.commit().await.unwrap();
+    };
+    assert!(tokio::time::timeout(Duration::from_millis(100), route).await.is_err());
+
+    let db = database.get_connection().await.unwrap();
+    let (next_pid, open) = backend(&db).await;
+    assert!(!open);
+    assert_ne!(next_pid, pid);
+}
+
+#[tokio::test]
+async fn select_batched_stays_in_the_open_transaction() {
+    let Some(database) = database().await else {
+        return;
+    };
+    let mut db = database.get_connection().await.unwrap();
+    db.begin().await.unwrap();
+    db.query(
+        "CREATE TABLE tusk_test_batch AS SELECT 'item ' || x AS name FROM generate_series(1, 5) x",
+        &[],
+    )
+    .await
+    .unwrap();
+    let mut names = Vec::new();
+    db.select_batched::<BatchItem, _>("WHERE name <> $1", &[&"item 3"], |batch| {
+        names.extend(batch.drain(..).map(|x| x.name))
+    })
+    .await
+    .unwrap();
+    assert_eq!(names, ["item 1", "item 2", "item 4", "item 5"]);
+    db.rollback().await.unwrap();
+
+    let exists = db
+        .query("SELECT to_regclass('tusk_test_batch') IS NOT NULL", &[])
+        .await
+        .unwrap()[0]
+        .get::<_, bool>(0);
+    assert!(!exists);
+}
+
+#[tokio::test]
+async fn unreadable_written_rows_are_an_error() {
+    let Some(database) = database().await else {
+        return;
+    };
+    let db = database.get_connection().await.unwrap();
+    db.begin().await.unwrap();
+    db.query("CREATE TABLE tusk_test_batch AS SELECT 'item' AS name", &[])
+        .await
+        .unwrap();
+    let updated = db.update_set::<MistypedItem>("name = 'renamed'", &[]).await;
+    assert!(matches!(updated, Err(PostgresWriteError::Conversion(_))));
+    db.rollback().await.unwrap();
+}
+
+#[tokio::test]
+async fn rows_left_out_by_joins_are_counted() {
+    let Some(database) = database().await else {
+        return;
+    };
+    let db = database.get_connection().await.unwrap();
+    db.begin().await.unwrap();
+    db.query(
+        "CREATE TABLE tusk_test_batch AS SELECT * FROM (VALUES ('a', 'x'), ('b', 'x'), ('c', 'y')) items (name, kind)",
+        &[],
+    )
+    .await
+    .unwrap();
+    db.query("CREATE TABLE tusk_test_kinds AS SELECT 'x' AS kind, 'X' AS label", &[])
+        .await
+        .unwrap();
+
+    let nothing = db
+        .delete_returning::<LabelledItem>("WHERE name = 'd'", &[])
+        .await
+        .unwrap();
+    assert_eq!((nothing.rows_affected, nothing.returned.len()), (0, 0));
+    let outcome = db.delete_returning::<LabelledItem>("", &[]).await.unwrap();
+    assert_eq!(outcome.rows_affected, 3);
+    let mut returned = outcome
+        .returned
+        .iter()
+        .map(|x| format!("{} {}", x.name, x.label))
+        .collect::<Vec<_>>();
+    returned.sort();
+    assert_eq!(returned, ["a X", "b X"]);
+    db.rollback().await.unwrap();
+}
+
+#[tokio::test]
+async fn owned_inserts_are_written_for_the_owner() {
+    let Some(database) = database_with(|x| x.enforce_ownership::<OwnedItem>()).await else {
+        return;
+    };
+    let db = database.get_connection().await.unwrap();
+    db.begin().await.unwrap();
+    db.query("CREATE TABLE tusk_test_owned (name text, owner integer)", &[])
+        .await
+        .unwrap();
+    let (name, other_owner) = ("a".to_string(), 2);
+    let write = || PostgresWriteRef {
+        fields: &["name", "owner"],
+        arguments: vec![&name, &other_owner],
+    };
+
+    let unscoped = db.insert_ref::<OwnedItem>(write()).await;
+    assert!(matches!(unscoped, Err(PostgresWriteError::Unscoped(_))));
+    let item = db.owned_by::<OwnedItem>(&1).insert(write()).await.unwrap();
+    assert_eq!((item.name.as_str(), item.owner), ("a", 1));
+    db.rollback().await.unwrap();
+}